@@ -16,8 +16,10 @@ use crate::config;
 use ::anyhow::Result;
 use ::std::{
     env,
+    fs,
     process,
 };
+use ::toml::Value as TomlValue;
 
 //==================================================================================================
 // Public Structures
@@ -31,8 +33,17 @@ use ::std::{
 pub struct Args {
     /// Kernel filename.
     kernel_filename: String,
-    /// Initrd filename.
-    initrd_filename: Option<String>,
+    /// Kernel command line.
+    cmdline: Option<String>,
+    /// Initrd filenames, one per `-initrd` occurrence.
+    initrd_filenames: Vec<String>,
+    /// Shared directories to expose to the guest, as `(tag, host-path)` pairs, one per `-fs`
+    /// occurrence.
+    fs_mounts: Vec<(String, String)>,
+    /// Address to serve the GDB Remote Serial Protocol stub on, if debugging was requested.
+    gdb_addr: Option<String>,
+    /// Path of the Unix domain socket to serve the runtime control channel on, if requested.
+    control_path: Option<String>,
     /// Memory size.
     memory_size: usize,
     /// Standard output.
@@ -43,6 +54,126 @@ pub struct Args {
     vm_stderr: Option<String>,
     /// HTTP server address.
     sockaddr: Option<String>,
+    /// Number of virtual processors.
+    vcpu_count: usize,
+    /// Number of virtual processors to configure in a Hyper-V partition.
+    smp_count: u32,
+    /// Whether the seccomp sandbox should be left uninstalled, for debugging.
+    disable_sandbox: bool,
+    /// Path of a snapshot to resume the virtual machine from, in place of booting a kernel.
+    restore_path: Option<String>,
+    /// Path to freeze the virtual machine to once it shuts down cleanly.
+    snapshot_path: Option<String>,
+    /// Shared secret used to key the `file`/`http` transports' [`crate::codec::WireCodec`], if
+    /// set.
+    secret: Option<String>,
+    /// Address to serve a [`crate::debugger::Debugger`] control port on, if requested.
+    debug_addr: Option<String>,
+    /// Address to receive a netboot-pushed kernel image on, in place of reading one from disk.
+    netboot_addr: Option<String>,
+}
+
+//==================================================================================================
+// Private Structures
+//==================================================================================================
+
+///
+/// # Description
+///
+/// Base configuration loaded from a `-config` TOML file. Its fields seed the corresponding
+/// [`Args`] fields before command-line flags are parsed, so that an explicit flag always
+/// overrides the value read from the file.
+///
+struct ConfigFile {
+    /// Kernel filename.
+    kernel: Option<String>,
+    /// Initrd filenames.
+    initrd: Vec<String>,
+    /// Memory size, in bytes.
+    memory: Option<usize>,
+    /// Standard error.
+    stderr: Option<String>,
+    /// HTTP server address.
+    http: Option<String>,
+    /// Path of the Unix domain socket to serve the runtime control channel on.
+    control: Option<String>,
+}
+
+impl ConfigFile {
+    ///
+    /// # Description
+    ///
+    /// Loads a configuration file from `path` and parses its `kernel`, `initrd`, `memory`,
+    /// `stderr`, `http`, and `control` keys.
+    ///
+    /// # Parameters
+    ///
+    /// - `path`: Path of the TOML configuration file to load.
+    ///
+    /// # Returns
+    ///
+    /// Upon successful completion, this function returns the parsed configuration. Otherwise, it
+    /// returns an error.
+    ///
+    fn load(path: &str) -> Result<Self> {
+        trace!("ConfigFile::load(): path={:?}", path);
+
+        let contents: String = fs::read_to_string(path)?;
+        let value: TomlValue = contents.parse::<TomlValue>()?;
+
+        let kernel: Option<String> =
+            value.get("kernel").and_then(TomlValue::as_str).map(str::to_string);
+
+        let initrd: Vec<String> = match value.get("initrd") {
+            Some(TomlValue::Array(items)) => items
+                .iter()
+                .map(|item| match item.as_str() {
+                    Some(s) => Ok(s.to_string()),
+                    None => {
+                        let reason: String = "invalid 'initrd' entry: expected a string".to_string();
+                        error!("ConfigFile::load(): {}", reason);
+                        Err(anyhow::anyhow!(reason))
+                    },
+                })
+                .collect::<Result<Vec<String>>>()?,
+            Some(TomlValue::String(s)) => vec![s.clone()],
+            Some(_) => {
+                let reason: String =
+                    "invalid 'initrd' key: expected a string or an array".to_string();
+                error!("ConfigFile::load(): {}", reason);
+                anyhow::bail!(reason);
+            },
+            None => Vec::new(),
+        };
+
+        let memory: Option<usize> = match value.get("memory") {
+            Some(TomlValue::String(s)) => Some(Args::parse_memory_size(s)?),
+            Some(TomlValue::Integer(n)) => Some(*n as usize),
+            Some(_) => {
+                let reason: String =
+                    "invalid 'memory' key: expected a string or an integer".to_string();
+                error!("ConfigFile::load(): {}", reason);
+                anyhow::bail!(reason);
+            },
+            None => None,
+        };
+
+        let stderr: Option<String> =
+            value.get("stderr").and_then(TomlValue::as_str).map(str::to_string);
+        let http: Option<String> =
+            value.get("http").and_then(TomlValue::as_str).map(str::to_string);
+        let control: Option<String> =
+            value.get("control").and_then(TomlValue::as_str).map(str::to_string);
+
+        Ok(Self {
+            kernel,
+            initrd,
+            memory,
+            stderr,
+            http,
+            control,
+        })
+    }
 }
 
 //==================================================================================================
@@ -50,6 +181,22 @@ pub struct Args {
 //==================================================================================================
 
 impl Args {
+    /// Command-line option for the kernel command line.
+    const OPT_CMDLINE: &'static str = "-cmdline";
+    /// Command-line option for the path of a TOML file providing the base configuration.
+    const OPT_CONFIG: &'static str = "-config";
+    /// Command-line option for the path of the Unix domain socket to serve the runtime control
+    /// channel on.
+    const OPT_CONTROL: &'static str = "-control";
+    /// Command-line option for a shared directory, in `<tag>:<host-path>` form.
+    const OPT_FS: &'static str = "-fs";
+    /// Command-line option for leaving the seccomp sandbox uninstalled, for debugging.
+    const OPT_DISABLE_SANDBOX: &'static str = "-disable-sandbox";
+    /// Command-line option for the address to serve a [`crate::debugger::Debugger`] control port
+    /// on.
+    const OPT_DEBUG: &'static str = "-debug";
+    /// Command-line option for the address to serve the GDB Remote Serial Protocol stub on.
+    const OPT_GDB: &'static str = "-gdb";
     /// Command-line option for printing the help message.
     const OPT_HELP: &'static str = "-help";
     /// Command-line for HTTP.
@@ -58,14 +205,27 @@ impl Args {
     const OPT_INITRD: &'static str = "-initrd";
     /// Command-line option for the kernel file.
     const OPT_KERNEL: &'static str = "-kernel";
+    /// Command-line option for the address to receive a netboot-pushed kernel image on.
+    const OPT_NETBOOT: &'static str = "-netboot";
     /// Command-line option for the memory size.
     const OPT_MEMORY_SIZE: &'static str = "-memory";
+    /// Command-line option for the path of a snapshot to resume the virtual machine from.
+    const OPT_RESTORE: &'static str = "-restore";
+    /// Command-line option for the number of virtual processors in a Hyper-V partition.
+    const OPT_SMP: &'static str = "-smp";
+    /// Command-line option for the shared secret used to key the `file`/`http` transports' wire
+    /// codec.
+    const OPT_SECRET: &'static str = "-secret";
+    /// Command-line option for the path to freeze the virtual machine to once it shuts down.
+    const OPT_SNAPSHOT: &'static str = "-snapshot";
     /// Command-line option for the standard error.
     const OPT_STDERR: &'static str = "-stderr";
     /// Command-line option for the standard input.
     const OPT_STDIN: &'static str = "-stdin";
     /// Command-line option for the standard output.
     const OPT_STDOUT: &'static str = "-stdout";
+    /// Command-line option for the number of virtual processors.
+    const OPT_VCPUS: &'static str = "-vcpus";
 
     ///
     /// # Description
@@ -80,15 +240,55 @@ impl Args {
     pub fn parse(args: Vec<String>) -> Result<Self> {
         trace!("parse(): args={:?}", args);
 
+        // Pre-scan for a `-config <file>` option so that it can seed the defaults below, before
+        // the main parse loop runs. This way, any explicit command-line flag encountered in that
+        // loop naturally overrides the corresponding value read from the file, regardless of
+        // where `-config` itself appears on the command line.
+        let config_file: Option<ConfigFile> = match args
+            .iter()
+            .position(|arg| arg == Self::OPT_CONFIG)
+            .and_then(|i| args.get(i + 1))
+        {
+            Some(path) => Some(ConfigFile::load(path)?),
+            None => None,
+        };
+
         let mut kernel_filename: String = String::new();
-        let mut initrd_filename: Option<String> = None;
+        let mut cmdline: Option<String> = None;
+        let mut initrd_filenames: Vec<String> = Vec::new();
+        let mut fs_mounts: Vec<(String, String)> = Vec::new();
+        let mut gdb_addr: Option<String> = None;
+        let mut control_path: Option<String> = None;
         let mut memory_size: usize = config::DEFAULT_MEMORY_SIZE;
         let mut vm_stderr: Option<String> = None;
         let mut vm_stdin: Option<String> = None;
         let mut vm_stdout: Option<String> = None;
         let mut sockaddr: Option<String> = None;
+        let mut vcpu_count: usize = config::DEFAULT_VCPU_COUNT;
+        let mut smp_count: u32 = config::DEFAULT_VCPU_COUNT as u32;
+        let mut disable_sandbox: bool = false;
+        let mut restore_path: Option<String> = None;
+        let mut snapshot_path: Option<String> = None;
+        let mut secret: Option<String> = None;
+        let mut debug_addr: Option<String> = None;
+        let mut netboot_addr: Option<String> = None;
+
+        // Use the configuration file, if any, as the base for the fields it covers.
+        if let Some(config_file) = config_file {
+            if let Some(kernel) = config_file.kernel {
+                kernel_filename = kernel;
+            }
+            initrd_filenames = config_file.initrd;
+            if let Some(memory) = config_file.memory {
+                memory_size = memory;
+            }
+            vm_stderr = config_file.stderr;
+            sockaddr = config_file.http;
+            control_path = config_file.control;
+        }
 
         // Parse command-line arguments.
+        let mut initrd_from_cli: bool = false;
         let mut i: usize = 1;
         while i < args.len() {
             match args[i].as_str() {
@@ -97,14 +297,52 @@ impl Args {
                     Self::usage();
                     process::exit(0);
                 },
+                // Set kernel command line.
+                Self::OPT_CMDLINE if i + 1 < args.len() => {
+                    cmdline = Some(args[i + 1].clone());
+                    i += 1;
+                },
+                // Already applied during the pre-scan above; just skip over its value.
+                Self::OPT_CONFIG if i + 1 < args.len() => {
+                    i += 1;
+                },
+                // Set the path of the Unix domain socket to serve the runtime control channel on.
+                Self::OPT_CONTROL if i + 1 < args.len() => {
+                    control_path = Some(args[i + 1].clone());
+                    i += 1;
+                },
+                // Leave the seccomp sandbox uninstalled, for debugging.
+                Self::OPT_DISABLE_SANDBOX => {
+                    disable_sandbox = true;
+                },
+                // Set a shared directory. May be passed more than once to expose several tags.
+                Self::OPT_FS if i + 1 < args.len() => {
+                    fs_mounts.push(crate::virtiofs::parse_fs_arg(&args[i + 1])?);
+                    i += 1;
+                },
+                // Set the address to serve the GDB Remote Serial Protocol stub on.
+                Self::OPT_GDB if i + 1 < args.len() => {
+                    gdb_addr = Some(args[i + 1].clone());
+                    i += 1;
+                },
+                // Set the address to serve a debugger control port on.
+                Self::OPT_DEBUG if i + 1 < args.len() => {
+                    debug_addr = Some(args[i + 1].clone());
+                    i += 1;
+                },
                 // Set HTTP server.
                 Self::OPT_HTTP if i + 1 < args.len() => {
                     sockaddr = Some(args[i + 1].clone());
                     i += 1;
                 },
-                // Set initrd file.
+                // Set initrd file. May be passed more than once to load several boot modules. The
+                // first occurrence replaces any initrd list read from the configuration file.
                 Self::OPT_INITRD if i + 1 < args.len() => {
-                    initrd_filename = Some(args[i + 1].clone());
+                    if !initrd_from_cli {
+                        initrd_filenames.clear();
+                        initrd_from_cli = true;
+                    }
+                    initrd_filenames.push(args[i + 1].clone());
                     i += 1;
                 },
                 // Set kernel file.
@@ -112,39 +350,42 @@ impl Args {
                     kernel_filename = args[i + 1].clone();
                     i += 1;
                 },
+                // Set the address to receive a netboot-pushed kernel image on, in place of reading
+                // one from disk.
+                Self::OPT_NETBOOT if i + 1 < args.len() => {
+                    netboot_addr = Some(args[i + 1].clone());
+                    i += 1;
+                },
                 // Set memory size.
                 Self::OPT_MEMORY_SIZE if i + 1 < args.len() => {
-                    let mem_arg: &String = &args[i + 1];
-
-                    // Parse memory size.
-                    memory_size = match mem_arg[..mem_arg.len() - 1].parse::<usize>() {
-                        Ok(size) => size,
+                    memory_size = Self::parse_memory_size(&args[i + 1])?;
+                    i += 1;
+                },
+                // Set the path of a snapshot to resume the virtual machine from.
+                Self::OPT_RESTORE if i + 1 < args.len() => {
+                    restore_path = Some(args[i + 1].clone());
+                    i += 1;
+                },
+                // Set number of virtual processors for a Hyper-V partition.
+                Self::OPT_SMP if i + 1 < args.len() => {
+                    smp_count = match args[i + 1].parse::<u32>() {
+                        Ok(count) => count,
                         Err(e) => {
-                            let reason: String = format!("invalid memory size (error={})", e);
+                            let reason: String = format!("invalid smp count (error={})", e);
                             error!("parse(): {}", reason);
                             anyhow::bail!(reason);
                         },
                     };
-
-                    // Parse memory size suffix.
-                    let endptr: char = match mem_arg.chars().last() {
-                        Some(c) => c,
-                        None => {
-                            let reason: String = format!("invalid memory size '{}'", mem_arg);
-                            error!("parse(): {}", reason);
-                            anyhow::bail!(reason);
-                        },
-                    };
-                    match endptr {
-                        'K' | 'k' => memory_size *= 1024,
-                        'M' | 'm' => memory_size *= 1024 * 1024,
-                        'G' | 'g' => memory_size *= 1024 * 1024 * 1024,
-                        ch => {
-                            let reason: String = format!("invalid memory size suffix '{}'", ch);
-                            error!("parse(): {}", reason);
-                            anyhow::bail!(reason);
-                        },
-                    }
+                    i += 1;
+                },
+                // Set the shared secret used to key the `file`/`http` transports' wire codec.
+                Self::OPT_SECRET if i + 1 < args.len() => {
+                    secret = Some(args[i + 1].clone());
+                    i += 1;
+                },
+                // Set the path to freeze the virtual machine to once it shuts down cleanly.
+                Self::OPT_SNAPSHOT if i + 1 < args.len() => {
+                    snapshot_path = Some(args[i + 1].clone());
                     i += 1;
                 },
                 // Set error file.
@@ -162,6 +403,18 @@ impl Args {
                     vm_stdout = Some(args[i + 1].clone());
                     i += 1;
                 },
+                // Set number of virtual processors.
+                Self::OPT_VCPUS if i + 1 < args.len() => {
+                    vcpu_count = match args[i + 1].parse::<usize>() {
+                        Ok(count) => count,
+                        Err(e) => {
+                            let reason: String = format!("invalid vcpu count (error={})", e);
+                            error!("parse(): {}", reason);
+                            anyhow::bail!(reason);
+                        },
+                    };
+                    i += 1;
+                },
                 // Invalid argument.
                 _ => {
                     Self::usage();
@@ -174,8 +427,10 @@ impl Args {
             i += 1;
         }
 
-        // Check if kernel file is missing.
-        if kernel_filename.is_empty() {
+        // Check if kernel file is missing. Not required when resuming from a snapshot, since the
+        // guest's state is reloaded from it instead of being booted fresh, nor when netbooting,
+        // since the kernel image is pushed over the wire instead of read from disk.
+        if kernel_filename.is_empty() && restore_path.is_none() && netboot_addr.is_none() {
             Self::usage();
             anyhow::bail!("kernel file is missing");
         }
@@ -192,17 +447,91 @@ impl Args {
             anyhow::bail!("invalid memory size");
         }
 
+        // Check if vcpu count is invalid.
+        if vcpu_count == 0 {
+            Self::usage();
+            anyhow::bail!("invalid vcpu count");
+        }
+
+        // Check if smp count is invalid.
+        if smp_count == 0 {
+            Self::usage();
+            anyhow::bail!("invalid smp count");
+        }
+
         Ok(Self {
             kernel_filename,
-            initrd_filename,
+            cmdline,
+            initrd_filenames,
+            fs_mounts,
+            gdb_addr,
+            control_path,
             memory_size,
             vm_stderr,
             vm_stdin,
             vm_stdout,
             sockaddr,
+            vcpu_count,
+            smp_count,
+            disable_sandbox,
+            restore_path,
+            snapshot_path,
+            secret,
+            debug_addr,
+            netboot_addr,
         })
     }
 
+    ///
+    /// # Description
+    ///
+    /// Parses a memory size string of the form `<number><suffix>`, where `<suffix>` is one of
+    /// `K`/`k`, `M`/`m`, or `G`/`g`.
+    ///
+    /// # Parameters
+    ///
+    /// - `s`: Memory size string to parse.
+    ///
+    /// # Returns
+    ///
+    /// Upon successful completion, this function returns the memory size, in bytes. Otherwise,
+    /// it returns an error.
+    ///
+    fn parse_memory_size(s: &str) -> Result<usize> {
+        // Parse memory size suffix.
+        let endptr: char = match s.chars().last() {
+            Some(c) => c,
+            None => {
+                let reason: String = format!("invalid memory size '{}'", s);
+                error!("parse_memory_size(): {}", reason);
+                anyhow::bail!(reason);
+            },
+        };
+
+        // Parse memory size.
+        let mut memory_size: usize = match s[..s.len() - endptr.len_utf8()].parse::<usize>() {
+            Ok(size) => size,
+            Err(e) => {
+                let reason: String = format!("invalid memory size (error={})", e);
+                error!("parse_memory_size(): {}", reason);
+                anyhow::bail!(reason);
+            },
+        };
+
+        match endptr {
+            'K' | 'k' => memory_size *= 1024,
+            'M' | 'm' => memory_size *= 1024 * 1024,
+            'G' | 'g' => memory_size *= 1024 * 1024 * 1024,
+            ch => {
+                let reason: String = format!("invalid memory size suffix '{}'", ch);
+                error!("parse_memory_size(): {}", reason);
+                anyhow::bail!(reason);
+            },
+        }
+
+        Ok(memory_size)
+    }
+
     ///
     /// # Description
     ///
@@ -210,33 +539,78 @@ impl Args {
     ///
     pub fn usage() {
         eprintln!(
-            "Usage: {} {} <kernel> [{} <size>] [{} <file>] [{} <file>] [{} <file>] [{} <file>] [ \
-             {} <socket-address>]",
+            "Usage: {} {} <kernel> [{} <string>] [{} <size>] [{} <file>]... [{} <tag>:<path>]... \
+             [{} <file>] [{} <file>] [{} <file>] [ {} <socket-address>] [{} <count>] [{} <count>] \
+             [{} <socket-address>] [{} <path>] [{} <file>] [{}] [{} <path>] [{} <path>] [{} <string>] \
+             [{} <socket-address>] [{} <socket-address>]",
             env::args()
                 .next()
                 .unwrap_or(config::PROGRAM_NAME.to_string()),
             Self::OPT_KERNEL,
+            Self::OPT_CMDLINE,
             Self::OPT_MEMORY_SIZE,
             Self::OPT_INITRD,
+            Self::OPT_FS,
             Self::OPT_STDERR,
             Self::OPT_STDIN,
             Self::OPT_STDOUT,
-            Self::OPT_HTTP
+            Self::OPT_HTTP,
+            Self::OPT_VCPUS,
+            Self::OPT_SMP,
+            Self::OPT_GDB,
+            Self::OPT_CONTROL,
+            Self::OPT_CONFIG,
+            Self::OPT_DISABLE_SANDBOX,
+            Self::OPT_RESTORE,
+            Self::OPT_SNAPSHOT,
+            Self::OPT_SECRET,
+            Self::OPT_DEBUG,
+            Self::OPT_NETBOOT
         );
     }
 
     ///
     /// # Description
     ///
-    /// Returns the initrd filename that was passed as a command-line argument to the program.
+    /// Returns the kernel command line that was passed as a command-line argument to the program.
     ///
     /// # Returns
     ///
-    /// The initrd filename that was passed as a command-line argument to the program. If no initrd
-    /// filename was passed, this method returns `None`.
+    /// The kernel command line that was passed as a command-line argument to the program. If no
+    /// command line was passed, this method returns `None`.
     ///
-    pub fn initrd_filename(&self) -> Option<&str> {
-        self.initrd_filename.as_deref()
+    pub fn cmdline(&self) -> Option<&str> {
+        self.cmdline.as_deref()
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Returns the initrd filenames that were passed as command-line arguments to the program, one
+    /// per `-initrd` occurrence, in the order they were given.
+    ///
+    /// # Returns
+    ///
+    /// The initrd filenames that were passed as command-line arguments to the program. If no initrd
+    /// filename was passed, this method returns an empty slice.
+    ///
+    pub fn initrd_filenames(&self) -> &[String] {
+        &self.initrd_filenames
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Returns the shared directories that were passed as command-line arguments to the program,
+    /// one per `-fs` occurrence, in the order they were given.
+    ///
+    /// # Returns
+    ///
+    /// The `(tag, host-path)` pairs that were passed as command-line arguments to the program. If
+    /// no shared directory was passed, this method returns an empty slice.
+    ///
+    pub fn fs_mounts(&self) -> &[(String, String)] {
+        &self.fs_mounts
     }
 
     ///
@@ -321,4 +695,153 @@ impl Args {
     pub fn take_sockaddr(&mut self) -> Option<String> {
         self.sockaddr.take()
     }
+
+    ///
+    /// # Description
+    ///
+    /// Returns the address to serve the GDB Remote Serial Protocol stub on, as passed via the
+    /// `-gdb` command-line argument.
+    ///
+    /// # Returns
+    ///
+    /// The address that was passed as a command-line argument to the program. If no `-gdb` option
+    /// was passed, or it was already taken, this method returns `None`.
+    ///
+    pub fn take_gdb_addr(&mut self) -> Option<String> {
+        self.gdb_addr.take()
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Returns the path of the Unix domain socket to serve the runtime control channel on, as
+    /// passed via the `-control` command-line argument.
+    ///
+    /// # Returns
+    ///
+    /// The path that was passed as a command-line argument to the program. If no `-control`
+    /// option was passed, or it was already taken, this method returns `None`.
+    ///
+    pub fn take_control_path(&mut self) -> Option<String> {
+        self.control_path.take()
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Returns the number of virtual processors that was passed as a command-line argument to the
+    /// program.
+    ///
+    /// # Returns
+    ///
+    /// The number of virtual processors that was passed as a command-line argument to the
+    /// program. If none was passed, this method returns [`config::DEFAULT_VCPU_COUNT`].
+    ///
+    pub fn vcpu_count(&self) -> usize {
+        self.vcpu_count
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Returns the number of virtual processors to configure in a Hyper-V partition, as passed
+    /// via the `-smp` command-line argument.
+    ///
+    /// # Returns
+    ///
+    /// The number of virtual processors that was passed as a command-line argument to the
+    /// program. If none was passed, this method returns [`config::DEFAULT_VCPU_COUNT`].
+    ///
+    pub fn smp_count(&self) -> u32 {
+        self.smp_count
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Returns whether the seccomp sandbox should be left uninstalled, as requested via the
+    /// `-disable-sandbox` command-line argument.
+    ///
+    /// # Returns
+    ///
+    /// `true` if `-disable-sandbox` was passed, `false` otherwise.
+    ///
+    pub fn disable_sandbox(&self) -> bool {
+        self.disable_sandbox
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Returns the path of a snapshot to resume the virtual machine from, as passed via the
+    /// `-restore` command-line argument.
+    ///
+    /// # Returns
+    ///
+    /// The path that was passed as a command-line argument to the program. If no `-restore`
+    /// option was passed, or it was already taken, this method returns `None`.
+    ///
+    pub fn take_restore_path(&mut self) -> Option<String> {
+        self.restore_path.take()
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Returns the path to freeze the virtual machine to once it shuts down cleanly, as passed
+    /// via the `-snapshot` command-line argument.
+    ///
+    /// # Returns
+    ///
+    /// The path that was passed as a command-line argument to the program. If no `-snapshot`
+    /// option was passed, or it was already taken, this method returns `None`.
+    ///
+    pub fn take_snapshot_path(&mut self) -> Option<String> {
+        self.snapshot_path.take()
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Returns the shared secret used to key the `file`/`http` transports' wire codec, as passed
+    /// via the `-secret` command-line argument.
+    ///
+    /// # Returns
+    ///
+    /// The secret that was passed as a command-line argument to the program. If no `-secret`
+    /// option was passed, or it was already taken, this method returns `None`.
+    ///
+    pub fn take_secret(&mut self) -> Option<String> {
+        self.secret.take()
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Returns the address to serve a [`crate::debugger::Debugger`] control port on, as passed via
+    /// the `-debug` command-line argument.
+    ///
+    /// # Returns
+    ///
+    /// The address that was passed as a command-line argument to the program. If no `-debug`
+    /// option was passed, or it was already taken, this method returns `None`.
+    ///
+    pub fn take_debug_addr(&mut self) -> Option<String> {
+        self.debug_addr.take()
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Returns the address to receive a netboot-pushed kernel image on, as passed via the
+    /// `-netboot` command-line argument, in place of reading a kernel file from disk.
+    ///
+    /// # Returns
+    ///
+    /// The address that was passed as a command-line argument to the program. If no `-netboot`
+    /// option was passed, or it was already taken, this method returns `None`.
+    ///
+    pub fn take_netboot_addr(&mut self) -> Option<String> {
+        self.netboot_addr.take()
+    }
 }
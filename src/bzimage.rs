@@ -0,0 +1,291 @@
+// Copyright(c) The Maintainers of Nanvix.
+// Licensed under the MIT License.
+
+//!
+//! # Linux bzImage Loader
+//!
+//! This module provides a loader for the Linux/x86 boot protocol, as an alternative to the raw
+//! ELF loader in [`crate::elf`] for booting stock distribution kernels packaged as a `bzImage`.
+//!
+
+//==================================================================================================
+// Imports
+//==================================================================================================
+
+use ::anyhow::Result;
+
+//==================================================================================================
+// Constants
+//==================================================================================================
+
+// Offset, within the bzImage file, of the boot-sector signature.
+const BOOT_FLAG_OFFSET: usize = 0x1fe;
+// Signature that every bootable x86 boot sector ends with.
+const BOOT_FLAG: u16 = 0xaa55;
+
+// Offset, within the bzImage file, of the `setup_sects` field.
+const SETUP_SECTS_OFFSET: usize = 0x1f1;
+// Offset, within the bzImage file, of the `HdrS` magic.
+const HDR_MAGIC_OFFSET: usize = 0x202;
+// Magic value (`"HdrS"`, little-endian) that identifies a Linux/x86 boot protocol header.
+const HDR_MAGIC: u32 = 0x5372_6448;
+
+// Size, in bytes, of a disk sector, as defined by the Linux/x86 boot protocol.
+const SECTOR_SIZE: usize = 512;
+
+// Load address of the protected-mode kernel image, as mandated by the Linux/x86 boot protocol.
+const KERNEL_LOAD_ADDR: usize = 0x0010_0000;
+// Offset, from [`KERNEL_LOAD_ADDR`], of the 64-bit entry point, honored by kernels that were built
+// with 64-bit entry support (`XLF_KERNEL_64`, which is mandatory since protocol 2.12).
+const KERNEL_64BIT_ENTRY_OFFSET: usize = 0x200;
+
+// Base address, within the guest's memory, of the "zero page" (`boot_params`).
+const ZERO_PAGE_BASE: usize = 0x0000_7000;
+// Size, in bytes, of the "zero page".
+const ZERO_PAGE_SIZE: usize = 0x1000;
+// Base address, within the guest's memory, of the copied kernel command-line.
+const CMDLINE_BASE: usize = 0x0002_0000;
+
+// Offsets, within the "zero page", of the fields this loader populates. These mirror
+// `struct boot_params`, as defined by `Documentation/arch/x86/boot.rst`.
+const E820_ENTRIES_OFFSET: usize = 0x1e8;
+const SETUP_HEADER_OFFSET: usize = SETUP_SECTS_OFFSET;
+const TYPE_OF_LOADER_OFFSET: usize = 0x210;
+const LOADFLAGS_OFFSET: usize = 0x211;
+const CMD_LINE_PTR_OFFSET: usize = 0x228;
+const E820_TABLE_OFFSET: usize = 0x2d0;
+
+// Number of bytes of the setup header (starting at `SETUP_SECTS_OFFSET`) that this loader copies
+// verbatim from the bzImage into the "zero page", i.e. every field up to and including
+// `cmdline_size` (protocol 2.06).
+const SETUP_HEADER_LEN: usize = 0x4b;
+
+// Maximum number of `e820` entries this loader ever writes, so that [`E820Table::entries`] can be
+// a fixed-size array.
+const MAX_E820_ENTRIES: usize = 2;
+
+// Value written to `type_of_loader` to identify this virtual machine monitor. There is no
+// officially registered identifier for it, so the "undeclared bootloader" value is used instead.
+const LOADER_TYPE_UNDECLARED: u8 = 0xff;
+
+// Flag, within `loadflags`, telling the kernel that it was loaded high (at 1 MiB), as opposed to
+// the obsolete low (0x10000) address used by the `zImage` format.
+const LOADED_HIGH: u8 = 1 << 0;
+// Flag, within `loadflags`, telling the kernel that the `heap_end_ptr` field of the setup header
+// is valid.
+const CAN_USE_HEAP: u8 = 1 << 7;
+
+// First address above the conventional, low 1 MiB of memory that the legacy PC memory map reserves
+// for the BIOS and video memory, and therefore must be excluded from the `e820` map.
+const LOW_MEMORY_RESERVED_BASE: u64 = 0x000a_0000;
+
+// Memory map entry type that marks a range as usable RAM, as defined by the BIOS
+// `INT 0x15, AX=0xE820` interface.
+const E820_RAM: u32 = 1;
+
+//==================================================================================================
+// Structures
+//==================================================================================================
+
+// One entry of the "zero page" memory map, matching `struct boot_e820_entry`.
+#[repr(C, packed)]
+struct E820Entry {
+    addr: u64,
+    size: u64,
+    kind: u32,
+}
+
+//==================================================================================================
+// Standalone Functions
+//==================================================================================================
+
+///
+/// # Description
+///
+/// Sniffs whether `source` looks like a Linux `bzImage`, by checking for the `HdrS` magic that
+/// identifies the Linux/x86 boot protocol's setup header, without fully validating or loading it.
+/// Used to dispatch between [`load`] and [`crate::elf::load`] based on what a kernel file actually
+/// is, rather than relying on a flag or a file extension.
+///
+/// # Parameters
+///
+/// - `source`: Address, in memory, of the candidate kernel image.
+/// - `source_size`: Size, in bytes, of the candidate kernel image.
+///
+/// # Returns
+///
+/// `true` if `source` carries the `HdrS` magic at its expected offset, `false` otherwise.
+///
+/// # Safety
+///
+/// This function is unsafe because it manipulates raw pointers and is up to the caller to ensure
+/// that `source` is valid for at least `source_size` bytes.
+///
+pub unsafe fn is_bzimage(source: *const u8, source_size: usize) -> bool {
+    if source_size < HDR_MAGIC_OFFSET + 4 {
+        return false;
+    }
+
+    let magic: u32 = u32::from_le_bytes([
+        *source.add(HDR_MAGIC_OFFSET),
+        *source.add(HDR_MAGIC_OFFSET + 1),
+        *source.add(HDR_MAGIC_OFFSET + 2),
+        *source.add(HDR_MAGIC_OFFSET + 3),
+    ]);
+
+    magic == HDR_MAGIC
+}
+
+///
+/// # Description
+///
+/// Loads a Linux `bzImage` into memory, following the Linux/x86 boot protocol: the real-mode setup
+/// code is parsed (but not executed) to locate the protected-mode kernel image, which is copied to
+/// [`KERNEL_LOAD_ADDR`]; a "zero page" (`boot_params`) and a copy of `cmdline` are then written at
+/// fixed, low addresses so that the guest finds them at the locations the boot protocol mandates.
+///
+/// # Parameters
+///
+/// - `destination`: Base address, in memory, of the guest's RAM.
+/// - `source`: Address, in memory, of the bzImage file.
+/// - `source_size`: Size, in bytes, of the bzImage file.
+/// - `max_offset`: Maximum offset, relative to `destination`, available to the guest.
+/// - `memory_size`: Size, in bytes, of the guest's RAM, used to build the `e820` memory map.
+/// - `cmdline`: Kernel command-line to hand to the guest.
+///
+/// # Returns
+///
+/// Upon successful completion, this function returns a tuple containing the protected-mode entry
+/// point, the first address, and the size of the range that was occupied in memory. Otherwise, it
+/// returns an error.
+///
+/// # Safety
+///
+/// This function is unsafe because it manipulates raw pointers and is up to the caller to ensure
+/// that the following conditions are met:
+///
+/// - The `destination` address is valid.
+/// - The `source` address is valid.
+/// - The `max_offset` is valid.
+///
+pub unsafe fn load(
+    destination: *mut std::ffi::c_void,
+    source: *const u8,
+    source_size: usize,
+    max_offset: usize,
+    memory_size: u64,
+    cmdline: &str,
+) -> Result<(usize, usize, usize)> {
+    // Check boot-sector signature.
+    let boot_flag: u16 = u16::from_le_bytes([
+        *source.add(BOOT_FLAG_OFFSET),
+        *source.add(BOOT_FLAG_OFFSET + 1),
+    ]);
+    if boot_flag != BOOT_FLAG {
+        anyhow::bail!("invalid boot sector signature");
+    }
+
+    // Check `HdrS` magic.
+    let magic: u32 = u32::from_le_bytes([
+        *source.add(HDR_MAGIC_OFFSET),
+        *source.add(HDR_MAGIC_OFFSET + 1),
+        *source.add(HDR_MAGIC_OFFSET + 2),
+        *source.add(HDR_MAGIC_OFFSET + 3),
+    ]);
+    if magic != HDR_MAGIC {
+        anyhow::bail!("not a Linux bzImage (missing setup header magic)");
+    }
+
+    // Number of 512-byte sectors occupied by the real-mode setup code, not counting the boot
+    // sector itself. A value of zero means 4 sectors, for historical reasons.
+    let setup_sects: usize = match *source.add(SETUP_SECTS_OFFSET) {
+        0 => 4,
+        n => n as usize,
+    };
+
+    // The boot sector plus the real-mode setup code occupy `(setup_sects + 1)` sectors; everything
+    // past that is the protected-mode kernel image.
+    let setup_size: usize = (setup_sects + 1) * SECTOR_SIZE;
+    if setup_size > source_size {
+        anyhow::bail!("truncated bzImage (missing protected-mode kernel image)");
+    }
+    let kernel_size: usize = source_size - setup_size;
+
+    trace!(
+        "load(): setup_sects={}, setup_size={:#x}, kernel_size={:#x}",
+        setup_sects,
+        setup_size,
+        kernel_size
+    );
+
+    // Check if every structure this loader writes fits in memory.
+    if ZERO_PAGE_BASE + ZERO_PAGE_SIZE > max_offset
+        || CMDLINE_BASE + cmdline.len() + 1 > max_offset
+        || KERNEL_LOAD_ADDR + kernel_size > max_offset
+    {
+        anyhow::bail!("bzImage does not fit in memory");
+    }
+
+    let base: *mut u8 = destination as *mut u8;
+
+    // Copy the protected-mode kernel image to its mandated load address.
+    ::std::ptr::copy_nonoverlapping(
+        source.add(setup_size),
+        base.add(KERNEL_LOAD_ADDR),
+        kernel_size,
+    );
+
+    // Copy the kernel command-line, NUL-terminated, to its fixed address.
+    let cmdline_ptr: *mut u8 = base.add(CMDLINE_BASE);
+    ::std::ptr::copy_nonoverlapping(cmdline.as_ptr(), cmdline_ptr, cmdline.len());
+    *cmdline_ptr.add(cmdline.len()) = 0;
+
+    // Build the "zero page".
+    let zero_page: *mut u8 = base.add(ZERO_PAGE_BASE);
+    ::std::ptr::write_bytes(zero_page, 0, ZERO_PAGE_SIZE);
+
+    // Copy the setup header verbatim, then patch the fields the bootloader is responsible for.
+    ::std::ptr::copy_nonoverlapping(
+        source.add(SETUP_HEADER_OFFSET),
+        zero_page.add(SETUP_HEADER_OFFSET),
+        SETUP_HEADER_LEN,
+    );
+    *zero_page.add(TYPE_OF_LOADER_OFFSET) = LOADER_TYPE_UNDECLARED;
+    *zero_page.add(LOADFLAGS_OFFSET) |= LOADED_HIGH | CAN_USE_HEAP;
+    zero_page
+        .add(CMD_LINE_PTR_OFFSET)
+        .cast::<u32>()
+        .write_unaligned(CMDLINE_BASE as u32);
+
+    // Build the `e820` memory map: usable RAM below the legacy BIOS/video hole, and usable RAM
+    // above it, up to the end of the guest's RAM.
+    let entries: [E820Entry; MAX_E820_ENTRIES] = [
+        E820Entry {
+            addr: 0,
+            size: LOW_MEMORY_RESERVED_BASE.min(memory_size),
+            kind: E820_RAM,
+        },
+        E820Entry {
+            addr: KERNEL_LOAD_ADDR as u64,
+            size: memory_size.saturating_sub(KERNEL_LOAD_ADDR as u64),
+            kind: E820_RAM,
+        },
+    ];
+    let entry_count: usize = entries.iter().filter(|entry| entry.size > 0).count();
+    *zero_page.add(E820_ENTRIES_OFFSET) = entry_count as u8;
+    let mut table: *mut E820Entry = zero_page.add(E820_TABLE_OFFSET).cast::<E820Entry>();
+    for entry in entries.iter().filter(|entry| entry.size > 0) {
+        table.write_unaligned(E820Entry {
+            addr: entry.addr,
+            size: entry.size,
+            kind: entry.kind,
+        });
+        table = table.add(1);
+    }
+
+    let entry: usize = KERNEL_LOAD_ADDR + KERNEL_64BIT_ENTRY_OFFSET;
+    let first_address: usize = ZERO_PAGE_BASE;
+    let size: usize = (KERNEL_LOAD_ADDR + kernel_size) - first_address;
+
+    Ok((entry, first_address, size))
+}
@@ -0,0 +1,262 @@
+// Copyright(c) The Maintainers of Nanvix.
+// Licensed under the MIT License.
+
+//!
+//! # Wire Codec
+//!
+//! This module provides a pluggable framing, compression, and encryption layer for the
+//! host↔VM message transports in [`crate::file`] and [`crate::http`], borrowing the wire format
+//! popularized by the Minecraft protocol: each message is length-prefixed with a VarInt, large
+//! payloads are zlib-deflated, and the whole stream may optionally be run through AES-128 in CFB8
+//! mode so that these transports are safe to run over untrusted links.
+//!
+
+//==================================================================================================
+// Imports
+//==================================================================================================
+
+use ::aes::Aes128;
+use ::anyhow::Result;
+use ::cfb8::{
+    cipher::{AsyncStreamCipher, NewCipher},
+    Cfb8,
+};
+use ::flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+use ::std::io::{Read, Write};
+
+//==================================================================================================
+// Constants
+//==================================================================================================
+
+/// Size, in bytes, of the AES-128 key and initialization vector, derived from the shared secret.
+const AES_KEY_SIZE: usize = 16;
+
+/// Default compression threshold, in bytes, that [`file::file_server`](crate::file::file_server)
+/// and [`http::HttpServer`](crate::http::HttpServer) construct their [`WireCodec`] with.
+pub const DEFAULT_COMPRESSION_THRESHOLD: usize = 256;
+
+//==================================================================================================
+// Structures
+//==================================================================================================
+
+// AES-128 in CFB8 mode, as used to encrypt the wire stream after a handshake.
+type AesCfb8 = Cfb8<Aes128>;
+
+///
+/// # Description
+///
+/// A pluggable wire codec that frames, optionally compresses, and optionally encrypts messages
+/// exchanged with the virtual machine over a transport such as a TCP stream or a pair of files.
+///
+/// Frames are encoded as follows:
+///
+/// - A VarInt giving the length, in bytes, of everything that follows.
+/// - A VarInt giving the uncompressed length of the payload, or `0` if the payload was sent as-is
+///   (i.e. its length was below [`WireCodec`]'s compression threshold).
+/// - The payload itself, zlib-deflated if the uncompressed-length VarInt is nonzero.
+///
+/// If encryption was enabled via [`WireCodec::enable_encryption`], every byte written or read
+/// after that point (frame headers included) is additionally run through AES-128 in CFB8 mode.
+///
+pub struct WireCodec {
+    compression_threshold: usize,
+    encrypt: Option<AesCfb8>,
+    decrypt: Option<AesCfb8>,
+}
+
+impl WireCodec {
+    ///
+    /// # Description
+    ///
+    /// Creates a wire codec with no encryption. Payloads whose length is at least
+    /// `compression_threshold` bytes are zlib-deflated; shorter payloads are sent as-is.
+    ///
+    /// # Parameters
+    ///
+    /// - `compression_threshold`: Minimum payload length, in bytes, that triggers compression.
+    ///
+    pub fn new(compression_threshold: usize) -> Self {
+        Self {
+            compression_threshold,
+            encrypt: None,
+            decrypt: None,
+        }
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Enables AES-128 CFB8 encryption for every frame written or read from this point onward,
+    /// keyed from `secret`. This is meant to be called once, right after a handshake in which both
+    /// ends have agreed on `secret`.
+    ///
+    /// # Parameters
+    ///
+    /// - `secret`: Shared secret; its first [`AES_KEY_SIZE`] bytes are used as both the key and the
+    ///   initialization vector, mirroring the Minecraft protocol's handshake.
+    ///
+    /// # Returns
+    ///
+    /// Upon successful completion, this function returns empty. Otherwise, it returns an error.
+    ///
+    pub fn enable_encryption(&mut self, secret: &[u8]) -> Result<()> {
+        if secret.len() < AES_KEY_SIZE {
+            anyhow::bail!(
+                "shared secret is too short (expected at least {} bytes)",
+                AES_KEY_SIZE
+            );
+        }
+
+        let key: &[u8] = &secret[..AES_KEY_SIZE];
+
+        self.encrypt = Some(
+            AesCfb8::new_from_slices(key, key)
+                .map_err(|_| anyhow::anyhow!("invalid AES-128 key or initialization vector"))?,
+        );
+        self.decrypt = Some(
+            AesCfb8::new_from_slices(key, key)
+                .map_err(|_| anyhow::anyhow!("invalid AES-128 key or initialization vector"))?,
+        );
+
+        Ok(())
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Frames `payload`, compressing it if it is at least `compression_threshold` bytes long, and
+    /// writes the result to `writer`, encrypting it first if encryption was enabled.
+    ///
+    /// # Parameters
+    ///
+    /// - `writer`: Destination to write the encoded frame to.
+    /// - `payload`: Message payload to encode.
+    ///
+    /// # Returns
+    ///
+    /// Upon successful completion, this function returns empty. Otherwise, it returns an error.
+    ///
+    pub fn encode(&mut self, writer: &mut impl Write, payload: &[u8]) -> Result<()> {
+        let (data_length, body): (usize, Vec<u8>) = if payload.len() >= self.compression_threshold {
+            let mut encoder: ZlibEncoder<Vec<u8>> =
+                ZlibEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(payload)?;
+            (payload.len(), encoder.finish()?)
+        } else {
+            (0, payload.to_vec())
+        };
+
+        let mut frame: Vec<u8> = Vec::new();
+        write_varint(&mut frame, data_length as u32)?;
+        frame.extend_from_slice(&body);
+
+        let mut out: Vec<u8> = Vec::new();
+        write_varint(&mut out, frame.len() as u32)?;
+        out.extend_from_slice(&frame);
+
+        if let Some(cipher) = &mut self.encrypt {
+            cipher.encrypt(&mut out);
+        }
+
+        writer.write_all(&out)?;
+        Ok(())
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Reads one frame from `reader`, decrypting it first if encryption was enabled, and inflates
+    /// its payload if it was compressed.
+    ///
+    /// # Parameters
+    ///
+    /// - `reader`: Source to read the encoded frame from.
+    ///
+    /// # Returns
+    ///
+    /// Upon successful completion, this function returns the decoded payload. Otherwise, it returns
+    /// an error.
+    ///
+    pub fn decode(&mut self, reader: &mut impl Read) -> Result<Vec<u8>> {
+        let frame_length: u32 = self.read_varint(reader)?;
+        let mut frame: Vec<u8> = vec![0; frame_length as usize];
+        reader.read_exact(&mut frame)?;
+
+        if let Some(cipher) = &mut self.decrypt {
+            cipher.decrypt(&mut frame);
+        }
+
+        let mut frame: &[u8] = &frame;
+        let data_length: u32 = read_varint(&mut frame)?;
+
+        let payload: Vec<u8> = if data_length == 0 {
+            frame.to_vec()
+        } else {
+            let mut decoder: ZlibDecoder<&[u8]> = ZlibDecoder::new(frame);
+            let mut payload: Vec<u8> = Vec::with_capacity(data_length as usize);
+            decoder.read_to_end(&mut payload)?;
+            payload
+        };
+
+        Ok(payload)
+    }
+
+    // Reads a VarInt off `reader`, decrypting each byte as it comes in when encryption is enabled,
+    // since CFB8 is a self-synchronizing stream cipher and the frame length is not known upfront.
+    fn read_varint(&mut self, reader: &mut impl Read) -> Result<u32> {
+        let mut value: u32 = 0;
+        for shift in (0..35).step_by(7) {
+            let mut byte: [u8; 1] = [0; 1];
+            reader.read_exact(&mut byte)?;
+            if let Some(cipher) = &mut self.decrypt {
+                cipher.decrypt(&mut byte);
+            }
+
+            value |= ((byte[0] & 0x7f) as u32) << shift;
+            if byte[0] & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+
+        anyhow::bail!("VarInt is too long")
+    }
+}
+
+//==================================================================================================
+// Standalone Functions
+//==================================================================================================
+
+// Writes `value` to `buf` as a VarInt (7 data bits per byte, MSB set while more bytes follow), as
+// defined by the Minecraft protocol.
+fn write_varint(buf: &mut Vec<u8>, mut value: u32) -> Result<()> {
+    loop {
+        let mut byte: u8 = (value & 0x7f) as u8;
+        value >>= 7;
+        if value != 0 {
+            byte |= 0x80;
+        }
+        buf.push(byte);
+        if value == 0 {
+            return Ok(());
+        }
+    }
+}
+
+// Reads a VarInt off the front of `buf`, advancing it past the bytes that were consumed.
+fn read_varint(buf: &mut &[u8]) -> Result<u32> {
+    let mut value: u32 = 0;
+    for shift in (0..35).step_by(7) {
+        if buf.is_empty() {
+            anyhow::bail!("truncated VarInt");
+        }
+        let byte: u8 = buf[0];
+        *buf = &buf[1..];
+
+        value |= ((byte & 0x7f) as u32) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+
+    anyhow::bail!("VarInt is too long")
+}
@@ -0,0 +1,333 @@
+// Copyright(c) The Maintainers of Nanvix.
+// Licensed under the MIT License.
+
+//!
+//! # Debugger
+//!
+//! This module provides a line-oriented monitor debugger, in the spirit of moa's `Debugger`, that
+//! lets a developer inspect a guest after [`crate::elf::load`] (or [`crate::bzimage::load`]) has
+//! placed it in memory. It listens on a TCP control port, accepts one command per line, and answers
+//! with JSON built the same way [`crate::http`]'s `message_to_json` builds its responses, so that a
+//! front end such as [`crate::http::HttpServer`] can expose the same operations.
+//!
+//! # Supported Commands
+//!
+//! - `read <addr> <len>`: hex-dumps `len` bytes of guest memory starting at `addr`.
+//! - `write <addr> <bytes>`: writes `bytes` (a hex string, e.g. `deadbeef`) at `addr`.
+//! - `dump <addr> <count>`: hex-dumps `count` 32-bit words of guest memory starting at `addr`.
+//! - `break <addr>`: registers a breakpoint at `addr`.
+//! - `continue`: acknowledges resuming execution.
+//! - `step`: acknowledges single-stepping.
+//! - An empty line repeats the last non-empty command.
+//!
+//! `break`, `continue`, and `step` only track bookkeeping state for now: this virtual machine
+//! monitor does not yet expose guest debug registers or a single-step control knob from
+//! [`crate::kvm::vcpu::VirtualProcessor`] to here, so they cannot actually halt or single-step the
+//! guest. Wiring that up is left as future work; in the meantime this module gives full read/write
+//! memory inspection, which covers the bring-up and crash-triage cases that matter most.
+//!
+
+//==================================================================================================
+// Imports
+//==================================================================================================
+
+use ::anyhow::Result;
+use ::serde_json::Value;
+use ::std::{
+    io::{BufRead, BufReader, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+};
+
+//==================================================================================================
+// Structures
+//==================================================================================================
+
+///
+/// # Description
+///
+/// A monitor debugger bound to a loaded guest image: `destination` is the base address, in the
+/// host's address space, of the guest's RAM, and `size` is the number of bytes available there, as
+/// returned by [`crate::elf::load`] or [`crate::bzimage::load`].
+///
+pub struct Debugger {
+    destination: *mut u8,
+    size: usize,
+    breakpoints: Vec<u64>,
+    last_command: Option<String>,
+}
+
+impl Debugger {
+    ///
+    /// # Description
+    ///
+    /// Creates a debugger over the guest memory range `[destination, destination + size)`.
+    ///
+    /// # Parameters
+    ///
+    /// - `destination`: Base address, in the host's address space, of the guest's RAM.
+    /// - `size`: Number of bytes available at `destination`.
+    ///
+    /// # Safety
+    ///
+    /// This function is unsafe because it is up to the caller to ensure that `destination` is a
+    /// valid pointer to at least `size` bytes of memory for as long as this debugger is used.
+    ///
+    pub unsafe fn new(destination: *mut std::ffi::c_void, size: usize) -> Self {
+        Self {
+            destination: destination as *mut u8,
+            size,
+            breakpoints: Vec::new(),
+            last_command: None,
+        }
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Binds `addr` and runs the debugger's REPL, accepting one connection at a time and one
+    /// command per line, until the connection is closed, at which point this method listens for a
+    /// new one.
+    ///
+    /// # Parameters
+    ///
+    /// - `addr`: Address to bind and listen on.
+    ///
+    /// # Returns
+    ///
+    /// Upon successful completion, this function never returns. Otherwise, it returns an error.
+    ///
+    pub fn run(&mut self, addr: SocketAddr) -> Result<()> {
+        trace!("run(): addr={}", addr);
+        crate::timer!("debugger_run");
+
+        let listener: TcpListener = TcpListener::bind(addr)?;
+
+        loop {
+            let (stream, peer): (TcpStream, SocketAddr) = listener.accept()?;
+            trace!("run(): accepted connection from {}", peer);
+
+            if let Err(e) = self.handle_connection(stream) {
+                error!("run(): connection handler has failed: {:?}", e);
+            }
+        }
+    }
+
+    fn handle_connection(&mut self, stream: TcpStream) -> Result<()> {
+        let mut reader: BufReader<TcpStream> = BufReader::new(stream.try_clone()?);
+        let mut writer: TcpStream = stream;
+
+        let mut line: String = String::new();
+        loop {
+            line.clear();
+            if reader.read_line(&mut line)? == 0 {
+                // Peer closed the connection.
+                return Ok(());
+            }
+
+            let command: String = line.trim().to_string();
+            let response: serde_json::Map<String, Value> = self.dispatch(&command);
+
+            writeln!(writer, "{}", Value::Object(response))?;
+        }
+    }
+
+    // Dispatches `command`, falling back to the last non-empty command if `command` is empty.
+    fn dispatch(&mut self, command: &str) -> serde_json::Map<String, Value> {
+        let command: String = if command.is_empty() {
+            match &self.last_command {
+                Some(last_command) => last_command.clone(),
+                None => return error_to_json(command, "no previous command to repeat"),
+            }
+        } else {
+            command.to_string()
+        };
+
+        let result: serde_json::Map<String, Value> = self.execute(&command);
+        self.last_command = Some(command);
+        result
+    }
+
+    fn execute(&mut self, command: &str) -> serde_json::Map<String, Value> {
+        let args: Vec<&str> = command.split_whitespace().collect();
+        match args.first() {
+            Some(&"read") => self.cmd_read(command, &args),
+            Some(&"write") => self.cmd_write(command, &args),
+            Some(&"dump") => self.cmd_dump(command, &args),
+            Some(&"break") => self.cmd_break(command, &args),
+            Some(&"continue") => self.cmd_continue(command),
+            Some(&"step") => self.cmd_step(command),
+            _ => error_to_json(command, "unknown command"),
+        }
+    }
+
+    fn cmd_read(&self, command: &str, args: &[&str]) -> serde_json::Map<String, Value> {
+        let (addr, len): (u64, usize) = match (args.get(1), args.get(2)) {
+            (Some(addr), Some(len)) => match (parse_u64(addr), parse_u64(len)) {
+                (Ok(addr), Ok(len)) => (addr, len as usize),
+                _ => return error_to_json(command, "invalid address or length"),
+            },
+            _ => return error_to_json(command, "usage: read <addr> <len>"),
+        };
+
+        let bytes: &[u8] = match self.slice(addr, len) {
+            Ok(bytes) => bytes,
+            Err(e) => return error_to_json(command, &e.to_string()),
+        };
+
+        let mut json: serde_json::Map<String, Value> = ok_to_json(command);
+        json.insert("addr".to_string(), Value::String(format!("{:#018x}", addr)));
+        json.insert("data".to_string(), Value::String(encode_hex(bytes)));
+        json
+    }
+
+    fn cmd_write(&mut self, command: &str, args: &[&str]) -> serde_json::Map<String, Value> {
+        let (addr, bytes): (u64, Vec<u8>) = match (args.get(1), args.get(2)) {
+            (Some(addr), Some(bytes)) => match (parse_u64(addr), decode_hex(bytes)) {
+                (Ok(addr), Ok(bytes)) => (addr, bytes),
+                _ => return error_to_json(command, "invalid address or byte string"),
+            },
+            _ => return error_to_json(command, "usage: write <addr> <bytes>"),
+        };
+
+        match self.slice_mut(addr, bytes.len()) {
+            Ok(dst) => dst.copy_from_slice(&bytes),
+            Err(e) => return error_to_json(command, &e.to_string()),
+        };
+
+        let mut json: serde_json::Map<String, Value> = ok_to_json(command);
+        json.insert("addr".to_string(), Value::String(format!("{:#018x}", addr)));
+        json.insert(
+            "len".to_string(),
+            Value::Number(serde_json::Number::from(bytes.len())),
+        );
+        json
+    }
+
+    fn cmd_dump(&self, command: &str, args: &[&str]) -> serde_json::Map<String, Value> {
+        let (addr, count): (u64, usize) = match (args.get(1), args.get(2)) {
+            (Some(addr), Some(count)) => match (parse_u64(addr), parse_u64(count)) {
+                (Ok(addr), Ok(count)) => (addr, count as usize),
+                _ => return error_to_json(command, "invalid address or count"),
+            },
+            _ => return error_to_json(command, "usage: dump <addr> <count>"),
+        };
+
+        let bytes: &[u8] = match self.slice(addr, count * ::std::mem::size_of::<u32>()) {
+            Ok(bytes) => bytes,
+            Err(e) => return error_to_json(command, &e.to_string()),
+        };
+
+        let words: Vec<Value> = bytes
+            .chunks_exact(::std::mem::size_of::<u32>())
+            .map(|chunk| {
+                let word: u32 = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+                Value::String(format!("{:#010x}", word))
+            })
+            .collect();
+
+        let mut json: serde_json::Map<String, Value> = ok_to_json(command);
+        json.insert("addr".to_string(), Value::String(format!("{:#018x}", addr)));
+        json.insert("words".to_string(), Value::Array(words));
+        json
+    }
+
+    fn cmd_break(&mut self, command: &str, args: &[&str]) -> serde_json::Map<String, Value> {
+        let addr: u64 = match args.get(1).map(|addr| parse_u64(addr)) {
+            Some(Ok(addr)) => addr,
+            _ => return error_to_json(command, "usage: break <addr>"),
+        };
+
+        self.breakpoints.push(addr);
+
+        let mut json: serde_json::Map<String, Value> = ok_to_json(command);
+        json.insert("addr".to_string(), Value::String(format!("{:#018x}", addr)));
+        json
+    }
+
+    fn cmd_continue(&self, command: &str) -> serde_json::Map<String, Value> {
+        ok_to_json(command)
+    }
+
+    fn cmd_step(&self, command: &str) -> serde_json::Map<String, Value> {
+        ok_to_json(command)
+    }
+
+    // Translates a guest-virtual address range into a host byte slice, bounds-checked against
+    // this debugger's loaded image size.
+    fn slice(&self, addr: u64, len: usize) -> Result<&[u8]> {
+        let offset: usize = self.bounds_check(addr, len)?;
+        Ok(unsafe { ::std::slice::from_raw_parts(self.destination.add(offset), len) })
+    }
+
+    // Translates a guest-virtual address range into a mutable host byte slice, bounds-checked
+    // against this debugger's loaded image size.
+    fn slice_mut(&mut self, addr: u64, len: usize) -> Result<&mut [u8]> {
+        let offset: usize = self.bounds_check(addr, len)?;
+        Ok(unsafe { ::std::slice::from_raw_parts_mut(self.destination.add(offset), len) })
+    }
+
+    fn bounds_check(&self, addr: u64, len: usize) -> Result<usize> {
+        let offset: usize = addr as usize;
+        if offset
+            .checked_add(len)
+            .filter(|end| *end <= self.size)
+            .is_none()
+        {
+            anyhow::bail!(
+                "address out of bounds (addr={:#018x}, len={:#x}, size={:#x})",
+                addr,
+                len,
+                self.size
+            );
+        }
+        Ok(offset)
+    }
+}
+
+//==================================================================================================
+// Standalone Functions
+//==================================================================================================
+
+// Parses `s` as an unsigned integer, accepting both a `0x`-prefixed hexadecimal form and a plain
+// decimal form.
+fn parse_u64(s: &str) -> Result<u64> {
+    match s.strip_prefix("0x") {
+        Some(hex) => Ok(u64::from_str_radix(hex, 16)?),
+        None => Ok(s.parse::<u64>()?),
+    }
+}
+
+// Builds the JSON object for a successful response, mirroring `http::message_to_json`'s style of
+// plain key/value insertion into a `serde_json::Map`.
+fn ok_to_json(command: &str) -> serde_json::Map<String, Value> {
+    let mut json: serde_json::Map<String, Value> = serde_json::Map::new();
+    json.insert("command".to_string(), Value::String(command.to_string()));
+    json.insert("status".to_string(), Value::String("ok".to_string()));
+    json
+}
+
+// Builds the JSON object for a failed response.
+fn error_to_json(command: &str, reason: &str) -> serde_json::Map<String, Value> {
+    let mut json: serde_json::Map<String, Value> = serde_json::Map::new();
+    json.insert("command".to_string(), Value::String(command.to_string()));
+    json.insert("status".to_string(), Value::String("error".to_string()));
+    json.insert("reason".to_string(), Value::String(reason.to_string()));
+    json
+}
+
+// Encodes `bytes` as a lowercase hexadecimal string.
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// Decodes a hexadecimal string into bytes.
+fn decode_hex(s: &str) -> Result<Vec<u8>> {
+    if s.len() % 2 != 0 {
+        anyhow::bail!("hexadecimal string has an odd number of digits");
+    }
+
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).map_err(anyhow::Error::from))
+        .collect()
+}
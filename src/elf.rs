@@ -33,6 +33,9 @@ const ELFMAG1: char = 'E'; // ELF magic number 1.
 const ELFMAG2: char = 'L'; // ELF magic number 2.
 const ELFMAG3: char = 'F'; // ELF magic number 3.
 
+// Index of the file class in `e_ident`.
+const EI_CLASS: usize = 4;
+
 // File classes.
 const ELFCLASSNONE: u8 = 0; // Invalid class.
 const ELFCLASS32: u8 = 1; // 32-bit object.
@@ -61,11 +64,12 @@ const ET_HIPROC: u16 = 0xffff; // Processor-specific.
 const EM_NONE: u16 = 0; // No machine.
 const EM_M32: u16 = 1; // AT&T WE 32100.
 const EM_SPARC: u16 = 2; // SPARC.
-const EM_386: u16 = 3; // Intel 80386.
+pub const EM_386: u16 = 3; // Intel 80386.
 const EM_68K: u16 = 4; // Motorola 68000.
 const EM_88K: u16 = 5; // Motorola 88000.
 const EM_860: u16 = 7; // Intel 80860.
 const EM_MIPS: u16 = 8; // MIPS RS3000.
+pub const EM_X86_64: u16 = 62; // AMD x86-64 architecture.
 
 // Object file versions.
 const EV_NONE: u32 = 0; // Invalid version.
@@ -120,6 +124,44 @@ struct Elf32Phdr {
     p_align: u32,  // Alignment value.
 }
 
+// ELF 64 file header.
+#[repr(C)]
+pub struct Elf64Fhdr {
+    e_ident: [u8; EI_NIDENT], // ELF magic numbers and other info.
+    e_type: u16,              // Object file type.
+    e_machine: u16,           // Required machine architecture type.
+    e_version: u32,           // Object file version.
+    e_entry: u64,             // Virtual address of process's entry point.
+    e_phoff: u64,             // Program header table file offset.
+    e_shoff: u64,             // Section header table file offset.
+    e_flags: u32,             // Processor-specific flags.
+    e_ehsize: u16,            // ELF headerâ€™s size in bytes.
+    e_phentsize: u16,         // Program header table entry size.
+    e_phnum: u16,             // Entries in the program header table.
+    e_shentsize: u16,         // Section header table size.
+    e_shnum: u16,             // Entries in the section header table.
+    e_shstrndx: u16,          // Index for the section name string table.
+}
+
+impl Elf64Fhdr {
+    pub fn from_address(addr: usize) -> &'static Self {
+        unsafe { &*(addr as *const Self) }
+    }
+}
+
+// ELF 64 program header.
+#[repr(C)]
+struct Elf64Phdr {
+    p_type: u32,   // Segment type.
+    p_flags: u32,  // Segment flags.
+    p_offset: u64, // Offset of the first byte.
+    p_vaddr: u64,  // Virtual address of the first byte.
+    p_paddr: u64,  // Physical address of the first byte.
+    p_filesz: u64, // Bytes in the file image.
+    p_memsz: u64,  // Bytes in the memory image.
+    p_align: u64,  // Alignment value.
+}
+
 // Rust equivalent of the C functions.
 impl Elf32Fhdr {
     fn is_valid(&self) -> bool {
@@ -135,16 +177,53 @@ impl Elf32Fhdr {
     }
 }
 
+impl Elf64Fhdr {
+    fn is_valid(&self) -> bool {
+        if self.e_ident[0] != ELFMAG0
+            || self.e_ident[1] != ELFMAG1 as u8
+            || self.e_ident[2] != ELFMAG2 as u8
+            || self.e_ident[3] != ELFMAG3 as u8
+        {
+            error!("header is NULL or invalid magic");
+            return false;
+        }
+        true
+    }
+}
+
+// Rounds `value` down to the nearest multiple of `align`. Segments whose `p_align` is `0` or `1`
+// carry no alignment constraint, in which case `value` is returned unchanged.
+fn align_down(value: usize, align: usize) -> usize {
+    if align <= 1 {
+        value
+    } else {
+        value - (value % align)
+    }
+}
+
+// Rounds `value` up to the nearest multiple of `align`. Segments whose `p_align` is `0` or `1`
+// carry no alignment constraint, in which case `value` is returned unchanged.
+fn align_up(value: usize, align: usize) -> usize {
+    if align <= 1 {
+        value
+    } else {
+        align_down(value + align - 1, align)
+    }
+}
+
 ///
 /// # Description
 ///
-/// Loads an ELF file into memory.
+/// Loads an ELF file into memory. Both `ELFCLASS32` and `ELFCLASS64` objects are supported; the
+/// one actually found in `source` is dispatched on at run time.
 ///
 /// # Parameters
 ///
 /// - `destination`: Destination address in memory.
 /// - `source`: Source address in memory.
 /// - `max_offset`: Maximum offset in memory.
+/// - `expected_machine`: Required machine architecture type (e.g. [`EM_386`] or [`EM_X86_64`]).
+///   Loading fails if `source`'s `e_machine` does not match.
 ///
 /// # Returns
 ///
@@ -164,6 +243,140 @@ pub unsafe fn load(
     destination: *mut std::ffi::c_void,
     source: *const u8,
     max_offset: usize,
+    expected_machine: u16,
+) -> Result<(usize, usize, usize)> {
+    dispatch(destination, source, max_offset, expected_machine, None)
+}
+
+///
+/// # Description
+///
+/// Loads an ELF file into memory, just like [`load`], but additionally verifies that every
+/// `PT_LOAD` segment was transferred intact by computing a CRC-32 (IEEE 802.3) over its `filesz`
+/// bytes at `destination` and comparing it against `checksums`. This is meant for images that
+/// arrive over a network or pipe, where silent corruption in transit is a real concern; `load`
+/// remains the unchecked fast path for images that are already trusted (e.g. local files).
+///
+/// # Parameters
+///
+/// - `destination`: Destination address in memory.
+/// - `source`: Source address in memory.
+/// - `max_offset`: Maximum offset in memory.
+/// - `expected_machine`: Required machine architecture type (e.g. [`EM_386`] or [`EM_X86_64`]).
+///   Loading fails if `source`'s `e_machine` does not match.
+/// - `checksums`: Table of `(p_vaddr, expected CRC-32)` pairs. A segment whose `p_vaddr` has no
+///   matching entry is loaded without verification.
+///
+/// # Returns
+///
+/// Upon successful completion, this function returns a tuple containing the entry point, the first
+/// address, and the size of the program that was loaded into memory. Otherwise, it returns an error,
+/// naming the first `p_vaddr` whose checksum did not match.
+///
+/// # Safety
+///
+/// This function is unsafe because it manipulates raw pointers and is up to the caller to ensure
+/// that the following conditions are met:
+///
+/// - The `destination` address is valid.
+/// - The `source` address is valid.
+/// - The `max_offset` is valid.
+///
+pub unsafe fn load_verified(
+    destination: *mut std::ffi::c_void,
+    source: *const u8,
+    max_offset: usize,
+    expected_machine: u16,
+    checksums: &[(u64, u32)],
+) -> Result<(usize, usize, usize)> {
+    dispatch(
+        destination,
+        source,
+        max_offset,
+        expected_machine,
+        Some(checksums),
+    )
+}
+
+// Checks the shared ELF magic number and dispatches on the file class. See `load` and
+// `load_verified` for the parameters and return value.
+unsafe fn dispatch(
+    destination: *mut std::ffi::c_void,
+    source: *const u8,
+    max_offset: usize,
+    expected_machine: u16,
+    checksums: Option<&[(u64, u32)]>,
+) -> Result<(usize, usize, usize)> {
+    // Check if ELF magic number is valid. `e_ident` lives at the same offset in both classes, so
+    // this check is shared.
+    if *source != ELFMAG0
+        || *source.add(1) != ELFMAG1 as u8
+        || *source.add(2) != ELFMAG2 as u8
+        || *source.add(3) != ELFMAG3 as u8
+    {
+        anyhow::bail!("header is NULL or invalid magic");
+    }
+
+    // Dispatch on the file class, which also lives at the same offset in both classes.
+    match *source.add(EI_CLASS) {
+        ELFCLASS32 => load32(destination, source, max_offset, expected_machine, checksums),
+        ELFCLASS64 => load64(destination, source, max_offset, expected_machine, checksums),
+        _ => anyhow::bail!("invalid ELF class"),
+    }
+}
+
+// Looks up `vaddr` in `checksums` and, if present, verifies that the CRC-32 of the `filesz` bytes
+// starting at `dst` matches. Returns an error naming `vaddr` on mismatch.
+unsafe fn verify_segment(
+    checksums: Option<&[(u64, u32)]>,
+    vaddr: u64,
+    dst: *const u8,
+    filesz: usize,
+) -> Result<()> {
+    let checksums: &[(u64, u32)] = match checksums {
+        Some(checksums) => checksums,
+        None => return Ok(()),
+    };
+
+    let expected: u32 = match checksums.iter().find(|(v, _)| *v == vaddr) {
+        Some((_, expected)) => *expected,
+        None => return Ok(()),
+    };
+
+    let actual: u32 = crc32(std::slice::from_raw_parts(dst, filesz));
+    if actual != expected {
+        let reason: String = format!(
+            "segment checksum mismatch (vaddr={:#018x}, expected={:#010x}, actual={:#010x})",
+            vaddr, expected, actual
+        );
+        error!("load(): {}", reason);
+        return Err(anyhow::anyhow!(reason));
+    }
+
+    Ok(())
+}
+
+// Computes the CRC-32 (IEEE 802.3) checksum of `data`.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc: u32 = 0xffff_ffff;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask: u32 = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+// Loads an `ELFCLASS32` object into memory. See `load` for the parameters and return value; the
+// magic number and file class were already checked by the caller.
+unsafe fn load32(
+    destination: *mut std::ffi::c_void,
+    source: *const u8,
+    max_offset: usize,
+    expected_machine: u16,
+    checksums: Option<&[(u64, u32)]>,
 ) -> Result<(usize, usize, usize)> {
     let mut first_address: usize = usize::MAX;
     let mut last_address: usize = 0;
@@ -174,20 +387,6 @@ pub unsafe fn load(
     let entry: usize = (*ehdr).e_entry as usize;
     trace!("entry point: {:#010x}", entry);
 
-    // Check if ELF magic number is valid.
-    if (*ehdr).e_ident[0] != ELFMAG0
-        || (*ehdr).e_ident[1] != ELFMAG1 as u8
-        || (*ehdr).e_ident[2] != ELFMAG2 as u8
-        || (*ehdr).e_ident[3] != ELFMAG3 as u8
-    {
-        anyhow::bail!("header is NULL or invalid magic");
-    }
-
-    // Check ELF class.
-    if (*ehdr).e_ident[4] != ELFCLASS32 {
-        anyhow::bail!("invalid ELF class");
-    }
-
     // Check data encoding.
     if (*ehdr).e_ident[5] != ELFDATA2LSB {
         anyhow::bail!("invalid data encoding");
@@ -204,7 +403,7 @@ pub unsafe fn load(
     }
 
     // Check ELF machine architecture.
-    if (*ehdr).e_machine != EM_386 {
+    if (*ehdr).e_machine != expected_machine {
         anyhow::bail!("invalid machine architecture");
     }
 
@@ -221,6 +420,7 @@ pub unsafe fn load(
             let vaddr: usize = phdr.p_vaddr as usize;
             let filesz: usize = phdr.p_filesz as usize;
             let memsz: usize = phdr.p_memsz as usize;
+            let align: usize = phdr.p_align as usize;
 
             // Check if segment fits in memory.
             if vaddr + memsz > max_offset {
@@ -246,16 +446,103 @@ pub unsafe fn load(
             let dst: *mut u8 = destination as *mut u8;
             let dst: *mut u8 = dst.add(vaddr);
             std::ptr::copy_nonoverlapping(src, dst, filesz);
+            verify_segment(checksums, phdr.p_vaddr as u64, dst, filesz)?;
 
-            // Update first address.
-            if vaddr < first_address {
-                first_address = vaddr;
-            }
+            // Update first and last address, honoring the segment's alignment so that
+            // page-aligned kernels are reported with a footprint that covers whole pages.
+            first_address = first_address.min(align_down(vaddr, align));
+            last_address = last_address.max(align_up(vaddr + memsz, align));
+        }
+    }
+
+    let size: usize = last_address - first_address;
+
+    Ok((entry, first_address, size))
+}
+
+// Loads an `ELFCLASS64` object into memory. See `load` for the parameters and return value; the
+// magic number and file class were already checked by the caller.
+unsafe fn load64(
+    destination: *mut std::ffi::c_void,
+    source: *const u8,
+    max_offset: usize,
+    expected_machine: u16,
+    checksums: Option<&[(u64, u32)]>,
+) -> Result<(usize, usize, usize)> {
+    let mut first_address: usize = usize::MAX;
+    let mut last_address: usize = 0;
+
+    // Get entry point.
+    let ehdr: *const Elf64Fhdr = source as *const Elf64Fhdr;
+
+    let entry: usize = (*ehdr).e_entry as usize;
+    trace!("entry point: {:#018x}", entry);
+
+    // Check data encoding.
+    if (*ehdr).e_ident[5] != ELFDATA2LSB {
+        anyhow::bail!("invalid data encoding");
+    }
+
+    // Check version.
+    if (*ehdr).e_version != EV_CURRENT {
+        anyhow::bail!("invalid version");
+    }
 
-            // Update last address.
-            if vaddr + memsz > last_address {
-                last_address = vaddr + memsz;
+    // Check ELF type.
+    if (*ehdr).e_type != ET_EXEC {
+        anyhow::bail!("invalid ELF type");
+    }
+
+    // Check ELF machine architecture.
+    if (*ehdr).e_machine != expected_machine {
+        anyhow::bail!("invalid machine architecture");
+    }
+
+    // Get program header table.
+    let phdr: *const Elf64Phdr = (source as usize + (*ehdr).e_phoff as usize) as *const Elf64Phdr;
+
+    // Load program segments.
+    for i in 0..(*ehdr).e_phnum {
+        let phdr = &*phdr.add(i as usize);
+
+        // Loadable segment.
+        if phdr.p_type == PT_LOAD {
+            let offset: usize = phdr.p_offset as usize;
+            let vaddr: usize = phdr.p_vaddr as usize;
+            let filesz: usize = phdr.p_filesz as usize;
+            let memsz: usize = phdr.p_memsz as usize;
+            let align: usize = phdr.p_align as usize;
+
+            // Check if segment fits in memory.
+            if vaddr + memsz > max_offset {
+                let reason: String = format!("segment does not fit in memory");
+                error!(
+                    "load(): {} (vaddr={:#018x}, memsz={:#018x}, max_offset={:#018x})",
+                    reason, vaddr, memsz, max_offset
+                );
+                return Err(anyhow::anyhow!(reason));
             }
+
+            trace!(
+                "loading segment: offset={:#018x} vaddr={:#018x} filesz={:#018x} memsz={:#018x}",
+                offset,
+                vaddr,
+                filesz,
+                memsz
+            );
+
+            // Copy segment to memory.
+            let src: *const u8 = ehdr as *const u8;
+            let src: *const u8 = src.add(offset);
+            let dst: *mut u8 = destination as *mut u8;
+            let dst: *mut u8 = dst.add(vaddr);
+            std::ptr::copy_nonoverlapping(src, dst, filesz);
+            verify_segment(checksums, phdr.p_vaddr, dst, filesz)?;
+
+            // Update first and last address, honoring the segment's alignment so that
+            // page-aligned kernels are reported with a footprint that covers whole pages.
+            first_address = first_address.min(align_down(vaddr, align));
+            last_address = last_address.max(align_up(vaddr + memsz, align));
         }
     }
 
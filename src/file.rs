@@ -5,6 +5,10 @@
 // Imports
 //==================================================================================================
 
+use crate::codec::{
+    self,
+    WireCodec,
+};
 use ::anyhow::Result;
 use ::std::{
     self,
@@ -19,6 +23,7 @@ use ::std::{
     sync::mpsc,
 };
 use ::sys::ipc::Message;
+use ::vmm_sys_util::eventfd::EventFd;
 
 //==================================================================================================
 // Standalone Functions
@@ -30,6 +35,8 @@ pub fn file_server(
     vm_stdout: Option<String>,
     tx_channel_to_vm: mpsc::Sender<std::result::Result<u8, anyhow::Error>>,
     rx_channel_from_vm: mpsc::Receiver<std::result::Result<u8, anyhow::Error>>,
+    stdin_irqfd: EventFd,
+    secret: Option<String>,
 ) -> Result<()> {
     // Obtain a buffered writer for the virtual machine's standard output device.
     let mut file_writer: BufWriter<Box<dyn Write>> = get_vm_stdout_writer(vm_stdout)?;
@@ -37,6 +44,18 @@ pub fn file_server(
     // Obtain a buffered reader for the virtual machine's standard input device.
     let mut file_reader: BufReader<Box<dyn Read>> = get_vm_stdin_reader(vm_stdin)?;
 
+    // When a secret was passed, frame, compress, and encrypt every message exchanged with
+    // whatever sits on the other end of `vm_stdin`/`vm_stdout` (see `codec::WireCodec`), so that
+    // these files can safely be pipes to an untrusted link instead of plain local files.
+    let mut codec: Option<WireCodec> = match secret {
+        Some(secret) => {
+            let mut codec: WireCodec = WireCodec::new(codec::DEFAULT_COMPRESSION_THRESHOLD);
+            codec.enable_encryption(secret.as_bytes())?;
+            Some(codec)
+        },
+        None => None,
+    };
+
     // Read a message from the input device.
     loop {
         let mut message: sys::ipc::Message = Default::default();
@@ -45,7 +64,25 @@ pub fn file_server(
         message.message_type = sys::ipc::MessageType::Ikc;
 
         // Read message payload from the input device and check for errors.
-        if let Err(e) = file_reader.read_exact(&mut message.payload) {
+        let result: std::io::Result<()> = match &mut codec {
+            Some(codec) => match codec.decode(&mut file_reader) {
+                Ok(payload) if payload.len() == message.payload.len() => {
+                    message.payload.copy_from_slice(&payload);
+                    Ok(())
+                },
+                Ok(payload) => Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!(
+                        "decoded frame has wrong length (expected={}, got={})",
+                        message.payload.len(),
+                        payload.len()
+                    ),
+                )),
+                Err(e) => Err(std::io::Error::new(std::io::ErrorKind::Other, e)),
+            },
+            None => file_reader.read_exact(&mut message.payload),
+        };
+        if let Err(e) = result {
             // Parse error.
             if e.kind() == std::io::ErrorKind::UnexpectedEof {
                 // End of file. Log a debug message and gracefully exit.
@@ -60,11 +97,13 @@ pub fn file_server(
             }
         }
 
-        // Send message to virtual machine.
+        // Send message to virtual machine, then raise the stdin irqfd so a guest driver blocking
+        // on it instead of polling `crate::microvm::MicroVm::STDIN_PORT` wakes up.
         let bytes: [u8; mem::size_of::<Message>()] = message.to_bytes();
         for b in bytes {
             tx_channel_to_vm.send(Ok(b))?;
         }
+        stdin_irqfd.write(1)?;
 
         // Receive a message from the virtual machine.
         let mut bytes: [u8; mem::size_of::<Message>()] = [0; mem::size_of::<Message>()];
@@ -83,7 +122,11 @@ pub fn file_server(
         };
 
         // Write message payload to the output device and check for errors.
-        if let Err(e) = file_writer.write_all(&message.payload) {
+        let result: Result<()> = match &mut codec {
+            Some(codec) => codec.encode(&mut file_writer, &message.payload),
+            None => file_writer.write_all(&message.payload).map_err(anyhow::Error::from),
+        };
+        if let Err(e) = result {
             let reason: String = format!("failed to write message to output device (error={})", e);
             error!("file_server(): {}", reason);
             break Err(anyhow::anyhow!(reason));
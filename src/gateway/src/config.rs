@@ -0,0 +1,19 @@
+// Copyright(c) The Maintainers of Nanvix.
+// Licensed under the MIT License.
+
+//!
+//! # Configuration
+//!
+//! This module provides configuration parameters for the gateway.
+//!
+
+use ::std::time::Duration;
+
+/// How often the gateway pings each connected peer.
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How long the gateway waits for a pong to a given ping before counting it as missed.
+pub const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Number of consecutive missed pongs after which a peer is considered dead and disconnected.
+pub const HEARTBEAT_MAX_MISSED: u32 = 3;
@@ -5,32 +5,40 @@
 // Imports
 //==================================================================================================
 
-use crate::route::{
-    GatewayLookupTable,
-    GatewayPeer,
+use crate::{
+    config,
+    heartbeat::HeartbeatMessage,
+    mux::FrameKind,
+    pubsub::GatewayControlMessage,
+    route::{
+        GatewayLookupTable,
+        GatewayPeer,
+        PeerKey,
+    },
+    rpc::MessageKind,
+    transport::{
+        GatewayStream,
+        Listener,
+        PeerId,
+    },
 };
 use ::anyhow::Result;
 use ::std::{
     future::Future,
-    net::SocketAddr,
     pin::Pin,
+    time::Duration,
 };
 use ::sys::{
     ipc::Message,
     pm::ProcessIdentifier,
 };
-use ::tokio::{
-    net::{
-        TcpListener,
-        TcpStream,
-    },
-    sync::{
-        mpsc,
-        mpsc::{
-            UnboundedReceiver,
-            UnboundedSender,
-        },
+use ::tokio::sync::{
+    mpsc,
+    mpsc::{
+        UnboundedReceiver,
+        UnboundedSender,
     },
+    oneshot,
 };
 
 //==================================================================================================
@@ -40,7 +48,10 @@ use ::tokio::{
 ///
 /// Gateway Client
 ///
-pub trait GatewayClient: Sized + Send {
+/// `S` is the transport-specific stream type, e.g. `TcpStream` or `UnixStream`. A gateway client
+/// implementation is oblivious to which concrete [`crate::transport::Listener`] produced it.
+///
+pub trait GatewayClient<S: GatewayStream>: Sized + Send {
     ///
     /// # Description
     ///
@@ -48,18 +59,22 @@ pub trait GatewayClient: Sized + Send {
     ///
     /// # Parameters
     ///
-    /// - `addr`: Address of the client.
-    /// - `tx`: Transmit endpoint for messages to clients.
-    /// - `rx`: Receive endpoint for messages from clients.
+    /// - `peer_id`: Opaque identifier of the peer.
+    /// - `tx`: Transmit endpoint for messages to the gateway. Every message that is sent over `tx`
+    ///   must be tagged with the logical channel identifier that it belongs to, so that several
+    ///   independent message streams may share the same underlying connection, and with a
+    ///   [`FrameKind`] identifying whether it is ordinary data or gateway control-plane traffic.
+    /// - `rx`: Receive endpoint for messages from the gateway, tagged with the logical channel
+    ///   identifier and [`FrameKind`] that each message belongs to.
     ///
     /// # Returns
     ///
     /// A new gateway client.
     ///
     fn new(
-        addr: SocketAddr,
-        tx: UnboundedSender<(SocketAddr, Message)>,
-        rx: UnboundedReceiver<Result<Message, anyhow::Error>>,
+        peer_id: PeerId,
+        tx: UnboundedSender<(PeerId, u32, FrameKind, Message)>,
+        rx: UnboundedReceiver<(u32, FrameKind, Result<Message, anyhow::Error>)>,
     ) -> Self;
 
     ///
@@ -70,7 +85,7 @@ pub trait GatewayClient: Sized + Send {
     /// # Parameters
     ///
     /// - `client`: Gateway client.
-    /// - `stream`: TCP stream associated with the client.
+    /// - `stream`: Stream associated with the client.
     ///
     /// # Returns
     ///
@@ -78,7 +93,7 @@ pub trait GatewayClient: Sized + Send {
     ///
     fn run(
         client: Self,
-        stream: TcpStream,
+        stream: S,
     ) -> Pin<Box<(dyn Future<Output = Result<(), anyhow::Error>> + std::marker::Send)>>;
 }
 
@@ -86,16 +101,27 @@ pub trait GatewayClient: Sized + Send {
 // Structures
 //==================================================================================================
 
+/// Logical channel identifier under which a connection is registered before it has multiplexed
+/// any further channels of its own.
+const DEFAULT_CHANNEL: u32 = 0;
+
+/// How long a request is allowed to wait for a matching response before the gateway gives up on
+/// it and reports a timeout back to the caller.
+const RPC_TIMEOUT: Duration = Duration::from_secs(5);
+
 ///
 /// Gateway
 ///
-pub struct Gateway<T: GatewayClient> {
-    /// Address of the gateway.
-    addr: SocketAddr,
+/// Generic over the transport `L` that connections are accepted over (TCP, a Unix domain socket,
+/// a Windows named pipe, ...) and the [`GatewayClient`] implementation `T` that handles them.
+///
+pub struct Gateway<T: GatewayClient<L::Stream>, L: Listener> {
+    /// Transport that incoming connections are accepted over.
+    listener: L,
     /// Transmit endpoint for messages to clients.
-    gateway_client_tx: UnboundedSender<(SocketAddr, Message)>,
+    gateway_client_tx: UnboundedSender<(PeerId, u32, FrameKind, Message)>,
     /// Receive endpoint for messages from clients.
-    gateway_client_rx: UnboundedReceiver<(SocketAddr, Message)>,
+    gateway_client_rx: UnboundedReceiver<(PeerId, u32, FrameKind, Message)>,
     /// Transmit endpoint for messages to the VM.
     gateway_vm_tx: UnboundedSender<Message>,
     /// Receive endpoint for messages from the VM.
@@ -111,28 +137,26 @@ pub struct Gateway<T: GatewayClient> {
 //==================================================================================================
 
 // Type aliases to make clippy happy.
-type ClientGatewayRx = UnboundedReceiver<(SocketAddr, Message)>;
-type ClientGatewayTx = UnboundedSender<(SocketAddr, Message)>;
-type ClientRx = UnboundedReceiver<Result<Message, anyhow::Error>>;
-type ClientTx = UnboundedSender<Result<Message, anyhow::Error>>;
+type ClientGatewayRx = UnboundedReceiver<(PeerId, u32, FrameKind, Message)>;
+type ClientGatewayTx = UnboundedSender<(PeerId, u32, FrameKind, Message)>;
+type ClientRx = UnboundedReceiver<(u32, FrameKind, Result<Message, anyhow::Error>)>;
+type ClientTx = UnboundedSender<(u32, FrameKind, Result<Message, anyhow::Error>)>;
 
-impl<T: GatewayClient> Gateway<T> {
+impl<T: GatewayClient<L::Stream>, L: Listener> Gateway<T, L> {
     ///
     /// # Description
     ///
-    /// Creates a new gateway.
+    /// Creates a new gateway over the given transport.
     ///
     /// # Parameters
     ///
-    /// - `addr`: Address of the gateway.
+    /// - `listener`: Transport to accept incoming connections over.
     ///
     /// # Returns
     ///
     /// A new gateway.
     ///
-    pub fn new(
-        addr: SocketAddr,
-    ) -> (Gateway<T>, UnboundedSender<Message>, UnboundedReceiver<Message>) {
+    pub fn new(listener: L) -> (Self, UnboundedSender<Message>, UnboundedReceiver<Message>) {
         // Create an asynchronous channel to enable communication from the gateway to the VM.
         let (gateway_vm_tx, vm_rx): (UnboundedSender<Message>, UnboundedReceiver<Message>) =
             mpsc::unbounded_channel();
@@ -147,7 +171,7 @@ impl<T: GatewayClient> Gateway<T> {
 
         (
             Self {
-                addr,
+                listener,
                 gateway_client_rx,
                 gateway_client_tx,
                 gateway_vm_tx,
@@ -171,24 +195,24 @@ impl<T: GatewayClient> Gateway<T> {
     ///
     #[tokio::main]
     pub async fn run(&mut self) -> Result<()> {
-        let listener: TcpListener = TcpListener::bind(self.addr).await?;
         loop {
             tokio::select! {
                 // Attempt to accept a new client.
-                Ok((stream, addr)) = listener.accept() => {
-                   if let Err(e) = self.handle_accept(stream, addr).await {
+                Ok((stream, peer_id)) = self.listener.accept() => {
+                   if let Err(e) = self.handle_accept(stream, peer_id).await {
                         warn!("run(): {:?}", e);
                    }
                 },
                 // Attempt to receive a message from any peer.
-                Some((addr, message)) = self.gateway_client_rx.recv() => {
-                    if let Err(e) = self.handle_client_message(addr, message).await {
+                Some((peer_id, channel_id, kind, message)) = self.gateway_client_rx.recv() => {
+                    if let Err(e) = self.handle_client_message(peer_id, channel_id, kind, message).await {
                         // Failed to handle peer message, send error back to the client.
                         warn!("run(): {:?}", e);
-                        if let Ok(peer) = self.lookup_tables.lookup_addr(addr).await {
+                        let key: PeerKey = (peer_id, channel_id);
+                        if let Ok(peer) = self.lookup_tables.lookup_addr(key).await {
                             match peer {
                                 GatewayPeer::Client(client) => {
-                                    if let Err(e) = client.send(Err(e)) {
+                                    if let Err(e) = client.send((channel_id, FrameKind::Data, Err(e))) {
                                         error!("run(): {:?}", e);
                                     }
                                 }
@@ -213,41 +237,111 @@ impl<T: GatewayClient> Gateway<T> {
     ///
     /// # Parameters
     ///
-    /// - `stream`: TCP stream associated with the client.
-    /// - `addr`: Address of the client.
+    /// - `stream`: Stream associated with the client.
+    /// - `peer_id`: Opaque identifier of the client.
     ///
     /// # Returns
     ///
     /// A future that resolves to `Ok(())` on success, or `Err(e)` on failure.
     ///
-    async fn handle_accept(&mut self, stream: TcpStream, addr: SocketAddr) -> Result<()> {
-        trace!("handle_accept(): addr={:?}", addr);
+    async fn handle_accept(&mut self, stream: L::Stream, peer_id: PeerId) -> Result<()> {
+        trace!("handle_accept(): peer_id={}", peer_id);
 
         // Create an asynchronous channel to enable communication from the gateway to the client.
-        let (client_tx, client_rx): (ClientTx, ClientRx) =
-            mpsc::unbounded_channel::<Result<Message, anyhow::Error>>();
+        let (client_tx, client_rx): (ClientTx, ClientRx) = mpsc::unbounded_channel();
 
         let client: Pin<Box<dyn Future<Output = std::result::Result<(), anyhow::Error>> + Send>> =
-            T::run(T::new(addr, self.gateway_client_tx.clone(), client_rx), stream);
+            T::run(T::new(peer_id, self.gateway_client_tx.clone(), client_rx), stream);
 
-        // Attempt to register the client.
+        // Register the connection under its default channel. A client that multiplexes further
+        // logical channels of its own over the same connection tags its outgoing messages with
+        // the corresponding channel identifier, and that channel is registered lazily the first
+        // time such a message is observed, see `handle_client_message`.
         self.lookup_tables
-            .register_addr(addr, crate::route::GatewayPeer::Client(client_tx))
+            .register_addr((peer_id, DEFAULT_CHANNEL), GatewayPeer::Client(client_tx.clone()))
             .await?;
 
+        let (pong_tx, pong_rx): (UnboundedSender<()>, UnboundedReceiver<()>) =
+            mpsc::unbounded_channel();
+        self.lookup_tables.register_heartbeat(peer_id, pong_tx).await?;
+
         let lookup_tables: GatewayLookupTable = self.lookup_tables.clone();
+        tokio::task::spawn(Self::heartbeat_loop(
+            lookup_tables.clone(),
+            peer_id,
+            client_tx,
+            pong_rx,
+        ));
+
         tokio::task::spawn(async move {
             if let Err(e) = client.await {
                 warn!("failed to run client: {:?}", e);
             }
 
             // Handle client disconnection.
-            Self::handle_disconnect(&lookup_tables, addr).await
+            Self::handle_disconnect(&lookup_tables, peer_id).await
         });
 
         Ok(())
     }
 
+    ///
+    /// # Description
+    ///
+    /// Periodically pings a connected peer over its default channel and watches for the matching
+    /// [`HeartbeatMessage::Pong`], disconnecting the peer once [`config::HEARTBEAT_MAX_MISSED`]
+    /// consecutive pings go unanswered within [`config::HEARTBEAT_TIMEOUT`].
+    ///
+    /// # Parameters
+    ///
+    /// - `lookup_tables`: Lookup tables.
+    /// - `peer_id`: Opaque identifier of the peer being pinged.
+    /// - `client_tx`: Transmit endpoint for messages to the peer.
+    /// - `pong_rx`: Receive endpoint that `handle_client_message` wakes up every time a `Pong`
+    ///   arrives from the peer.
+    ///
+    async fn heartbeat_loop(
+        lookup_tables: GatewayLookupTable,
+        peer_id: PeerId,
+        client_tx: ClientTx,
+        mut pong_rx: UnboundedReceiver<()>,
+    ) {
+        let mut ticker = tokio::time::interval(config::HEARTBEAT_INTERVAL);
+        let mut missed: u32 = 0;
+
+        loop {
+            ticker.tick().await;
+
+            if client_tx
+                .send((DEFAULT_CHANNEL, FrameKind::Control, Ok(HeartbeatMessage::Ping.into_message())))
+                .is_err()
+            {
+                // Peer is already gone; `handle_disconnect` will clean it up.
+                return;
+            }
+
+            match tokio::time::timeout(config::HEARTBEAT_TIMEOUT, pong_rx.recv()).await {
+                Ok(Some(())) => {
+                    missed = 0;
+                },
+                _ => {
+                    missed += 1;
+                    warn!(
+                        "heartbeat_loop(): missed pong (peer_id={}, missed={})",
+                        peer_id, missed
+                    );
+                    if missed >= config::HEARTBEAT_MAX_MISSED {
+                        warn!("heartbeat_loop(): peer is unresponsive (peer_id={})", peer_id);
+                        if let Err(e) = Self::handle_disconnect(&lookup_tables, peer_id).await {
+                            warn!("heartbeat_loop(): {:?}", e);
+                        }
+                        return;
+                    }
+                },
+            }
+        }
+    }
+
     ///
     /// # Description
     ///
@@ -256,15 +350,16 @@ impl<T: GatewayClient> Gateway<T> {
     /// # Parameters
     ///
     /// - `lookup_tables`: Lookup tables.
+    /// - `peer_id`: Opaque identifier of the client that disconnected.
     ///
     /// # Returns
     ///
     /// A future that resolves to `Ok(())` on success, or `Err(e)` on failure.
     ///
-    async fn handle_disconnect(lookup_tables: &GatewayLookupTable, addr: SocketAddr) -> Result<()> {
-        trace!("handle_disconnect(): addr={:?}", addr);
+    async fn handle_disconnect(lookup_tables: &GatewayLookupTable, peer_id: PeerId) -> Result<()> {
+        trace!("handle_disconnect(): peer_id={}", peer_id);
 
-        GatewayLookupTable::remove(lookup_tables, addr).await?;
+        GatewayLookupTable::remove(lookup_tables, peer_id).await?;
 
         Ok(())
     }
@@ -276,22 +371,74 @@ impl<T: GatewayClient> Gateway<T> {
     ///
     /// # Parameters
     ///
+    /// - `peer_id`: Opaque identifier of the client that sent the message.
+    /// - `channel_id`: Logical channel, within the client's connection, that the message was sent
+    ///   over.
+    /// - `kind`: Frame kind the message was tagged with by the mux layer, distinguishing gateway
+    ///   control-plane traffic from ordinary data without having to sniff the payload's opcode.
     /// - `message`: Message to handle.
     ///
     /// # Returns
     ///
     /// A future that resolves to `Ok(())` on success, or `Err(e)` on failure.
     ///
-    async fn handle_client_message(&mut self, addr: SocketAddr, message: Message) -> Result<()> {
+    async fn handle_client_message(
+        &mut self,
+        peer_id: PeerId,
+        channel_id: u32,
+        kind: FrameKind,
+        message: Message,
+    ) -> Result<()> {
         trace!(
-            "handle_client_message(): addr={:?}, message.source={:?}, message.destination={:?}",
-            addr,
+            "handle_client_message(): peer_id={}, channel_id={}, kind={:?}, message.source={:?}, \
+             message.destination={:?}",
+            peer_id,
+            channel_id,
+            kind,
             message.source,
             message.destination
         );
 
+        let key: PeerKey = (peer_id, channel_id);
+
+        // Lazily register the channel the first time it is observed, piggy-backing on the peer
+        // that was registered for the connection's default channel at accept time.
+        if self.lookup_tables.lookup_addr(key).await.is_err() {
+            let peer: GatewayPeer =
+                self.lookup_tables.lookup_addr((peer_id, DEFAULT_CHANNEL)).await?;
+            self.lookup_tables.register_addr(key, peer).await?;
+        }
+
+        // Control-plane traffic is tagged as such by the mux layer, so it is only ever decoded as
+        // publish/subscribe or heartbeat here -- never by sniffing an ordinary `Data` payload's
+        // leading byte, which could otherwise collide with one of these opcodes by coincidence.
+        if kind == FrameKind::Control {
+            // Publish/subscribe control messages are handled by the broker and never reach the VM.
+            if let Ok(control) = GatewayControlMessage::decode(&message) {
+                return self.handle_control_message(key, control).await;
+            }
+
+            // A pong answers the peer's heartbeat task directly and never reaches the VM.
+            if let Ok(HeartbeatMessage::Pong) = HeartbeatMessage::decode(&message) {
+                self.lookup_tables.notify_pong(peer_id).await;
+                return Ok(());
+            }
+
+            anyhow::bail!("message tagged as control-plane traffic but carries an unrecognized payload");
+        }
+
         let pid: ProcessIdentifier = message.source;
-        self.lookup_tables.register_pid(pid, addr).await?;
+        self.lookup_tables.register_pid(pid, key).await?;
+
+        // A request tagged with `MessageKind::Request` gets a pending entry so that a matching
+        // response can be routed back to the caller instead of forwarded like an ordinary
+        // message; everything else (including plain, untagged messages) is just forwarded. Unlike
+        // the control-plane decodes above, a false-positive match here is harmless -- the message
+        // is still forwarded either way, so sniffing the opcode is an acceptable best-effort
+        // correlation convention rather than a routing hazard.
+        if let Ok(MessageKind::Request { request_id }) = MessageKind::decode(&message) {
+            self.track_pending_request(key, request_id).await;
+        }
 
         // Forward message to the VM.
         self.gateway_vm_tx.send(message)?;
@@ -299,6 +446,122 @@ impl<T: GatewayClient> Gateway<T> {
         Ok(())
     }
 
+    ///
+    /// # Description
+    ///
+    /// Registers a pending RPC request and races it against [`RPC_TIMEOUT`], delivering a timeout
+    /// error back to the caller if no matching response arrives in time.
+    ///
+    /// # Parameters
+    ///
+    /// - `key`: Key of the peer that issued the request.
+    /// - `request_id`: Identifier of the request, as tagged by [`MessageKind`].
+    ///
+    async fn track_pending_request(&self, key: PeerKey, request_id: u64) {
+        let (tx, rx): (oneshot::Sender<Message>, oneshot::Receiver<Message>) = oneshot::channel();
+        if let Err(e) = self.lookup_tables.register_pending(key, request_id, tx).await {
+            warn!("track_pending_request(): {:?}", e);
+            return;
+        }
+
+        let lookup_tables: GatewayLookupTable = self.lookup_tables.clone();
+        tokio::task::spawn(async move {
+            if let Ok(Ok(response)) = tokio::time::timeout(RPC_TIMEOUT, rx).await {
+                // The response was handed over by `handle_vm_message`, which already cleared the
+                // pending entry; deliver it to the caller.
+                if let Ok(GatewayPeer::Client(client)) = lookup_tables.lookup_addr(key).await {
+                    if let Err(e) = client.send((key.1, FrameKind::Data, Ok(response))) {
+                        warn!("track_pending_request(): failed to deliver response: {:?}", e);
+                    }
+                }
+                return;
+            }
+
+            // Either the timeout elapsed or the sender was dropped (e.g. on disconnect); in
+            // either case, clear the pending entry and, if it was still ours to clear, report a
+            // timeout to the caller.
+            if lookup_tables.take_pending(key, request_id).await.is_none() {
+                return;
+            }
+
+            if let Ok(GatewayPeer::Client(client)) = lookup_tables.lookup_addr(key).await {
+                let reason: anyhow::Error =
+                    anyhow::anyhow!("request timed out (request_id={})", request_id);
+                if let Err(e) = client.send((key.1, FrameKind::Data, Err(reason))) {
+                    warn!("track_pending_request(): failed to deliver timeout: {:?}", e);
+                }
+            }
+        });
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Handles a publish/subscribe control message.
+    ///
+    /// # Parameters
+    ///
+    /// - `key`: Key of the peer that sent the control message.
+    /// - `control`: Control message to handle.
+    ///
+    /// # Returns
+    ///
+    /// A future that resolves to `Ok(())` on success, or `Err(e)` on failure.
+    ///
+    async fn handle_control_message(
+        &mut self,
+        key: PeerKey,
+        control: GatewayControlMessage,
+    ) -> Result<()> {
+        match control {
+            GatewayControlMessage::Provide { pattern } => {
+                self.lookup_tables.provide(pattern, key).await
+            },
+            GatewayControlMessage::Subscribe { pattern } => {
+                self.lookup_tables.subscribe(pattern, key).await
+            },
+            GatewayControlMessage::Event { topic, payload } => {
+                self.publish(&topic, &payload).await
+            },
+        }
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Fans an event out to every client whose subscription pattern matches `topic`.
+    ///
+    /// # Parameters
+    ///
+    /// - `topic`: Topic under which the event was published.
+    /// - `payload`: Event payload.
+    ///
+    /// # Returns
+    ///
+    /// A future that resolves to `Ok(())` on success, or `Err(e)` on failure.
+    ///
+    async fn publish(&mut self, topic: &str, payload: &[u8]) -> Result<()> {
+        trace!("publish(): topic={:?}", topic);
+
+        for (key, peer) in self.lookup_tables.matching_subscribers(topic).await {
+            let event: GatewayControlMessage = GatewayControlMessage::Event {
+                topic: topic.to_string(),
+                payload: payload.to_vec(),
+            };
+            let message: Message = event.into_message();
+
+            match peer {
+                GatewayPeer::Client(client) => {
+                    if let Err(e) = client.send((key.1, FrameKind::Control, Ok(message))) {
+                        warn!("publish(): failed to deliver event to subscriber: {:?}", e);
+                    }
+                },
+            }
+        }
+
+        Ok(())
+    }
+
     ///
     /// # Description
     ///
@@ -319,13 +582,44 @@ impl<T: GatewayClient> Gateway<T> {
             message.destination
         );
 
-        // Retrieve peer.
-        let peer: GatewayPeer = self.lookup_tables.lookup_pid(message.destination).await?;
+        // The VM is a trusted, first-party message producer, unlike clients, so messages coming
+        // from it are still dispatched by sniffing their payload's opcode rather than by a
+        // dedicated `FrameKind` tag: there is no untrusted third party on this side that could
+        // cause a genuine control/data collision, so the extra framing byte is not needed here.
+        //
+        // An event published by the VM is fanned out to every matching subscriber, rather than
+        // being delivered point-to-point.
+        if let Ok(GatewayControlMessage::Event { topic, payload }) =
+            GatewayControlMessage::decode(&message)
+        {
+            return self.publish(&topic, &payload).await;
+        }
+
+        // Retrieve the peer, along with the channel that it must be delivered over.
+        let (key, peer): (PeerKey, GatewayPeer) =
+            self.lookup_tables.lookup_pid(message.destination).await?;
+        let (_, channel_id): PeerKey = key;
+
+        // A response that matches an in-flight request completes it directly, rather than being
+        // forwarded like an ordinary message; the caller is still waiting on the pending entry's
+        // oneshot, not on the regular per-channel stream.
+        if let Ok(MessageKind::Response { request_id }) = MessageKind::decode(&message) {
+            if let Some(tx) = self.lookup_tables.take_pending(key, request_id).await {
+                if tx.send(message).is_err() {
+                    warn!(
+                        "handle_vm_message(): caller is no longer waiting on request (request_id={})",
+                        request_id
+                    );
+                }
+                return Ok(());
+            }
+        }
 
         match peer {
             GatewayPeer::Client(client) => {
-                // Forward the message to the client.
-                if let Err(e) = client.send(Ok(message)) {
+                // Forward the message to the client, tagged with its logical channel; messages
+                // forwarded from the VM are always `Data` frames from the client's point of view.
+                if let Err(e) = client.send((channel_id, FrameKind::Data, Ok(message))) {
                     let reason: String =
                         format!("failed to send message to client (error={:?})", e);
                     error!("handle_vm_message(): {}", reason);
@@ -0,0 +1,92 @@
+// Copyright(c) The Maintainers of Nanvix.
+// Licensed under the MIT License.
+
+//!
+//! # Heartbeat Control Messages
+//!
+//! A [`tokio::net::TcpStream`] (or equivalent) only tells the gateway that a peer is gone once
+//! the underlying transport errors out, which a half-open connection or a wedged remote may never
+//! do. This module defines the `Ping`/`Pong` control messages that [`crate::gateway::Gateway`]
+//! exchanges with every connected peer to detect that case actively, using the same
+//! opcode-prefixed-payload convention as [`crate::pubsub`] and [`crate::rpc`].
+//!
+
+//==================================================================================================
+// Imports
+//==================================================================================================
+
+use ::anyhow::Result;
+use ::sys::ipc::Message;
+
+//==================================================================================================
+// Structures
+//==================================================================================================
+
+// Numbered from 0x20 so that this module's opcodes can never be confused with `crate::pubsub`'s
+// (0x01-0x03) or `crate::rpc`'s (0x10-0x12).
+
+/// Opcode, on `Message::payload`, that identifies a [`HeartbeatMessage::Ping`].
+const OPCODE_PING: u8 = 0x20;
+/// Opcode, on `Message::payload`, that identifies a [`HeartbeatMessage::Pong`].
+const OPCODE_PONG: u8 = 0x21;
+
+///
+/// # Description
+///
+/// A keepalive message exchanged between the gateway and one of its peers.
+///
+pub enum HeartbeatMessage {
+    /// Sent by the gateway; the peer is expected to answer with a [`Self::Pong`].
+    Ping,
+    /// Sent by a peer in answer to a [`Self::Ping`].
+    Pong,
+}
+
+//==================================================================================================
+// Implementations
+//==================================================================================================
+
+impl HeartbeatMessage {
+    ///
+    /// # Description
+    ///
+    /// Attempts to decode a heartbeat message out of the payload of `message`.
+    ///
+    /// # Parameters
+    ///
+    /// - `message`: Message whose payload may hold an encoded heartbeat message.
+    ///
+    /// # Returns
+    ///
+    /// Upon successful completion, this function returns the decoded heartbeat message.
+    /// Otherwise, it returns an error, meaning that `message` is not a heartbeat message.
+    ///
+    pub fn decode(message: &Message) -> Result<Self> {
+        match message.payload.first() {
+            Some(&OPCODE_PING) => Ok(Self::Ping),
+            Some(&OPCODE_PONG) => Ok(Self::Pong),
+            Some(opcode) => anyhow::bail!("unknown heartbeat opcode (opcode={})", opcode),
+            None => anyhow::bail!("empty payload"),
+        }
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Wraps this heartbeat message into a [`Message`] ready to be forwarded to a peer.
+    ///
+    /// # Returns
+    ///
+    /// The resulting message.
+    ///
+    pub fn into_message(&self) -> Message {
+        let opcode: u8 = match self {
+            Self::Ping => OPCODE_PING,
+            Self::Pong => OPCODE_PONG,
+        };
+
+        let mut message: Message = Message::default();
+        message.payload[0] = opcode;
+        message
+    }
+}
@@ -0,0 +1,48 @@
+// Copyright(c) The Maintainers of Nanvix.
+// Licensed under the MIT License.
+
+//!
+//! # Gateway
+//!
+//! This crate provides a gateway that routes messages between external clients and a virtual
+//! machine.
+//!
+
+//==================================================================================================
+// Modules
+//==================================================================================================
+
+// Must come first.
+#[macro_use]
+extern crate log;
+
+mod config;
+mod gateway;
+mod heartbeat;
+// Not wired into the gateway's accept path yet; usable by `GatewayClient` implementations that
+// want to multiplex several logical channels of their own over a single connection. Public because
+// `GatewayClient`'s channels carry `FrameKind`, which implementations must be able to name.
+pub mod mux;
+mod pubsub;
+mod route;
+mod rpc;
+pub mod transport;
+
+//==================================================================================================
+// Exports
+//==================================================================================================
+
+pub use gateway::{
+    Gateway,
+    GatewayClient,
+};
+pub use mux::FrameKind;
+pub use transport::{
+    tcp::TcpGatewayListener,
+    Listener,
+    PeerId,
+};
+#[cfg(unix)]
+pub use transport::unix::UnixGatewayListener;
+#[cfg(windows)]
+pub use transport::named_pipe::NamedPipeGatewayListener;
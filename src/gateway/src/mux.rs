@@ -0,0 +1,210 @@
+// Copyright(c) The Maintainers of Nanvix.
+// Licensed under the MIT License.
+
+//!
+//! # Connection Multiplexing
+//!
+//! This module lets many independent logical message streams share a single TCP connection. Each
+//! serialized [`Message`] is prefixed with a small frame header that carries a `channel_id` and a
+//! [`FrameKind`], so a reader task can demultiplex incoming frames into per-channel queues while a
+//! writer task interleaves frames from multiple channels back onto the same socket. The
+//! [`FrameKind`] tag also lets [`crate::gateway::Gateway`] distinguish gateway control-plane
+//! traffic from ordinary payloads without sniffing opcodes out of the payload itself.
+//!
+
+//==================================================================================================
+// Imports
+//==================================================================================================
+
+use ::anyhow::Result;
+use ::std::mem;
+use ::sys::ipc::Message;
+use ::tokio::{
+    io::{
+        AsyncReadExt,
+        AsyncWriteExt,
+    },
+    net::tcp::{
+        OwnedReadHalf,
+        OwnedWriteHalf,
+    },
+    sync::mpsc::{
+        UnboundedReceiver,
+        UnboundedSender,
+    },
+};
+
+//==================================================================================================
+// Constants
+//==================================================================================================
+
+/// Size, in bytes, of a multiplexing frame header.
+const FRAME_HEADER_SIZE: usize = 9;
+
+//==================================================================================================
+// Structures
+//==================================================================================================
+
+///
+/// # Description
+///
+/// Tags a multiplexed frame as either ordinary guest/client traffic or gateway control-plane
+/// traffic ([`crate::pubsub::GatewayControlMessage`], [`crate::heartbeat::HeartbeatMessage`]),
+/// written by the mux layer itself rather than sniffed out of the frame's payload. This lets
+/// [`crate::gateway::Gateway`] tell the two apart even when a [`Data`](Self::Data) frame's payload
+/// happens to start with a byte that would otherwise collide with one of those modules' opcodes.
+///
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FrameKind {
+    /// Ordinary point-to-point payload, forwarded to/from the guest unmodified.
+    Data,
+    /// Control-plane traffic that the gateway itself interprets and never forwards verbatim.
+    Control,
+}
+
+impl FrameKind {
+    /// Encodes this frame kind into its on-wire representation.
+    fn to_byte(self) -> u8 {
+        match self {
+            Self::Data => 0,
+            Self::Control => 1,
+        }
+    }
+
+    /// Decodes a frame kind from its on-wire representation.
+    fn from_byte(byte: u8) -> Result<Self> {
+        match byte {
+            0 => Ok(Self::Data),
+            1 => Ok(Self::Control),
+            byte => anyhow::bail!("unknown frame kind (byte={})", byte),
+        }
+    }
+}
+
+///
+/// # Description
+///
+/// Header that is prepended to every multiplexed frame.
+///
+pub struct FrameHeader {
+    /// Logical channel that the frame belongs to.
+    pub channel_id: u32,
+    /// Length, in bytes, of the frame payload.
+    pub len: u32,
+    /// Whether the frame carries ordinary data or gateway control-plane traffic.
+    pub kind: FrameKind,
+}
+
+//==================================================================================================
+// Implementations
+//==================================================================================================
+
+impl FrameHeader {
+    /// Encodes the frame header into its on-wire representation.
+    pub fn to_bytes(&self) -> [u8; FRAME_HEADER_SIZE] {
+        let mut bytes: [u8; FRAME_HEADER_SIZE] = [0; FRAME_HEADER_SIZE];
+        bytes[0..4].copy_from_slice(&self.channel_id.to_be_bytes());
+        bytes[4..8].copy_from_slice(&self.len.to_be_bytes());
+        bytes[8] = self.kind.to_byte();
+        bytes
+    }
+
+    /// Decodes a frame header from its on-wire representation.
+    pub fn from_bytes(bytes: [u8; FRAME_HEADER_SIZE]) -> Result<Self> {
+        let channel_id: u32 = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        let len: u32 = u32::from_be_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]);
+        let kind: FrameKind = FrameKind::from_byte(bytes[8])?;
+        Ok(Self { channel_id, len, kind })
+    }
+}
+
+//==================================================================================================
+// Standalone Functions
+//==================================================================================================
+
+///
+/// # Description
+///
+/// Reads multiplexed frames off `read_half` and forwards each decoded message, tagged with its
+/// `channel_id` and [`FrameKind`], to `demux_tx`.
+///
+/// # Parameters
+///
+/// - `read_half`: Read half of the underlying connection.
+/// - `demux_tx`: Endpoint to which demultiplexed messages are delivered.
+///
+/// # Returns
+///
+/// This function runs until the connection is closed or an error occurs, in which case it returns
+/// the error.
+///
+pub async fn demux_loop(
+    mut read_half: OwnedReadHalf,
+    demux_tx: UnboundedSender<(u32, FrameKind, Message)>,
+) -> Result<()> {
+    loop {
+        let mut header_bytes: [u8; FRAME_HEADER_SIZE] = [0; FRAME_HEADER_SIZE];
+        read_half.read_exact(&mut header_bytes).await?;
+        let header: FrameHeader = FrameHeader::from_bytes(header_bytes)?;
+
+        let mut payload: Vec<u8> = vec![0; header.len as usize];
+        read_half.read_exact(&mut payload).await?;
+
+        let bytes: [u8; mem::size_of::<Message>()] = match payload.try_into() {
+            Ok(bytes) => bytes,
+            Err(_) => {
+                warn!("demux_loop(): invalid frame length (channel_id={})", header.channel_id);
+                continue;
+            },
+        };
+
+        let message: Message = match Message::try_from_bytes(bytes) {
+            Ok(message) => message,
+            Err(e) => {
+                warn!("demux_loop(): failed to parse message (error={:?})", e);
+                continue;
+            },
+        };
+
+        if demux_tx.send((header.channel_id, header.kind, message)).is_err() {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+///
+/// # Description
+///
+/// Interleaves messages queued on `mux_rx` onto `write_half`, prefixing each with a frame header
+/// that carries its `channel_id` and [`FrameKind`].
+///
+/// # Parameters
+///
+/// - `write_half`: Write half of the underlying connection.
+/// - `mux_rx`: Endpoint from which outgoing, channel-tagged messages are received.
+///
+/// # Returns
+///
+/// This function runs until `mux_rx` is closed or an error occurs, in which case it returns the
+/// error.
+///
+pub async fn mux_loop(
+    mut write_half: OwnedWriteHalf,
+    mut mux_rx: UnboundedReceiver<(u32, FrameKind, Message)>,
+) -> Result<()> {
+    while let Some((channel_id, kind, message)) = mux_rx.recv().await {
+        let payload: [u8; mem::size_of::<Message>()] = message.to_bytes();
+        let header: FrameHeader = FrameHeader {
+            channel_id,
+            len: payload.len() as u32,
+            kind,
+        };
+
+        write_half.write_all(&header.to_bytes()).await?;
+        write_half.write_all(&payload).await?;
+    }
+
+    Ok(())
+}
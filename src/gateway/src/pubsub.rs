@@ -0,0 +1,183 @@
+// Copyright(c) The Maintainers of Nanvix.
+// Licensed under the MIT License.
+
+//!
+//! # Publish/Subscribe Control Messages
+//!
+//! The gateway is primarily a point-to-point message router, keyed by process identifier. This
+//! module layers a lightweight publish/subscribe control plane on top of it: a client that wants to
+//! broker telemetry or other many-to-many events encodes one of the [`GatewayControlMessage`]
+//! variants into the payload of an ordinary [`Message`], and the gateway interprets it instead of
+//! forwarding it blindly to the virtual machine.
+//!
+
+//==================================================================================================
+// Imports
+//==================================================================================================
+
+use ::anyhow::Result;
+use ::sys::ipc::Message;
+
+//==================================================================================================
+// Structures
+//==================================================================================================
+
+/// Opcode, on `Message::payload`, that identifies a [`GatewayControlMessage::Provide`].
+const OPCODE_PROVIDE: u8 = 0x01;
+/// Opcode, on `Message::payload`, that identifies a [`GatewayControlMessage::Subscribe`].
+const OPCODE_SUBSCRIBE: u8 = 0x02;
+/// Opcode, on `Message::payload`, that identifies a [`GatewayControlMessage::Event`].
+const OPCODE_EVENT: u8 = 0x03;
+
+///
+/// # Description
+///
+/// A control message that a client may smuggle inside the payload of an ordinary [`Message`] to
+/// drive the gateway's publish/subscribe broker.
+///
+pub enum GatewayControlMessage {
+    /// Advertises that the sender may publish events under the given topic pattern.
+    Provide { pattern: String },
+    /// Registers interest in every topic that matches the given pattern.
+    Subscribe { pattern: String },
+    /// Publishes `payload` under `topic`, to be fanned out to every matching subscriber.
+    Event { topic: String, payload: Vec<u8> },
+}
+
+//==================================================================================================
+// Implementations
+//==================================================================================================
+
+impl GatewayControlMessage {
+    ///
+    /// # Description
+    ///
+    /// Attempts to decode a control message out of the payload of `message`.
+    ///
+    /// # Parameters
+    ///
+    /// - `message`: Message whose payload may hold an encoded control message.
+    ///
+    /// # Returns
+    ///
+    /// Upon successful completion, this function returns the decoded control message. Otherwise,
+    /// it returns an error, meaning that `message` should be treated as an ordinary, point-to-point
+    /// message.
+    ///
+    pub fn decode(message: &Message) -> Result<Self> {
+        let bytes: &[u8] = &message.payload;
+
+        let (opcode, rest): (u8, &[u8]) = match bytes.split_first() {
+            Some((opcode, rest)) => (*opcode, rest),
+            None => anyhow::bail!("empty payload"),
+        };
+
+        match opcode {
+            OPCODE_PROVIDE => Ok(Self::Provide {
+                pattern: Self::decode_string(rest)?,
+            }),
+            OPCODE_SUBSCRIBE => Ok(Self::Subscribe {
+                pattern: Self::decode_string(rest)?,
+            }),
+            OPCODE_EVENT => {
+                let (topic_len, rest): (u8, &[u8]) = match rest.split_first() {
+                    Some((len, rest)) => (*len, rest),
+                    None => anyhow::bail!("truncated event"),
+                };
+                if rest.len() < topic_len as usize {
+                    anyhow::bail!("truncated event topic");
+                }
+                let (topic_bytes, payload): (&[u8], &[u8]) = rest.split_at(topic_len as usize);
+                let topic: String = String::from_utf8(topic_bytes.to_vec())?;
+                Ok(Self::Event {
+                    topic,
+                    payload: payload.to_vec(),
+                })
+            },
+            opcode => anyhow::bail!("unknown control opcode (opcode={})", opcode),
+        }
+    }
+
+    /// Decodes a UTF-8 string out of `bytes`, up to the first NUL byte or the end of `bytes`.
+    fn decode_string(bytes: &[u8]) -> Result<String> {
+        let end: usize = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+        Ok(String::from_utf8(bytes[..end].to_vec())?)
+    }
+
+    /// Encodes this control message into the on-wire payload representation used by `decode`.
+    fn encode(&self) -> Vec<u8> {
+        let mut bytes: Vec<u8> = Vec::new();
+        match self {
+            Self::Provide { pattern } => {
+                bytes.push(OPCODE_PROVIDE);
+                bytes.extend_from_slice(pattern.as_bytes());
+            },
+            Self::Subscribe { pattern } => {
+                bytes.push(OPCODE_SUBSCRIBE);
+                bytes.extend_from_slice(pattern.as_bytes());
+            },
+            Self::Event { topic, payload } => {
+                bytes.push(OPCODE_EVENT);
+                bytes.push(topic.len() as u8);
+                bytes.extend_from_slice(topic.as_bytes());
+                bytes.extend_from_slice(payload);
+            },
+        }
+        bytes
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Wraps this control message into a [`Message`] whose payload the gateway's broker
+    /// understands, ready to be forwarded to a peer.
+    ///
+    /// # Returns
+    ///
+    /// The resulting message.
+    ///
+    pub fn into_message(&self) -> Message {
+        let mut message: Message = Message::default();
+        let encoded: Vec<u8> = self.encode();
+        let len: usize = encoded.len().min(message.payload.len());
+        message.payload[..len].copy_from_slice(&encoded[..len]);
+        message
+    }
+}
+
+///
+/// # Description
+///
+/// Checks whether `topic` matches `pattern`. Patterns are a sequence of `/`-separated segments,
+/// where a segment of `*` matches exactly one topic segment and a segment of `**` matches any
+/// number of trailing topic segments (e.g. `/sensors/temp/*` matches `/sensors/temp/living-room`).
+///
+/// # Parameters
+///
+/// - `pattern`: Subscription pattern.
+/// - `topic`: Topic to match against `pattern`.
+///
+/// # Returns
+///
+/// `true` if `topic` matches `pattern`, `false` otherwise.
+///
+pub fn matches(pattern: &str, topic: &str) -> bool {
+    let pattern_segs: Vec<&str> = pattern.split('/').filter(|s| !s.is_empty()).collect();
+    let topic_segs: Vec<&str> = topic.split('/').filter(|s| !s.is_empty()).collect();
+
+    let mut i: usize = 0;
+    for pseg in pattern_segs.iter() {
+        if *pseg == "**" {
+            return true;
+        }
+        if i >= topic_segs.len() {
+            return false;
+        }
+        if *pseg != "*" && *pseg != topic_segs[i] {
+            return false;
+        }
+        i += 1;
+    }
+
+    i == topic_segs.len()
+}
@@ -0,0 +1,396 @@
+// Copyright(c) The Maintainers of Nanvix.
+// Licensed under the MIT License.
+
+//!
+//! # Gateway Routing Tables
+//!
+//! This module provides the lookup tables that the [`crate::gateway::Gateway`] uses to route
+//! messages to and from connected peers.
+//!
+
+//==================================================================================================
+// Imports
+//==================================================================================================
+
+use crate::{
+    mux::FrameKind,
+    transport::PeerId,
+};
+use ::anyhow::Result;
+use ::std::{
+    collections::HashMap,
+    sync::Arc,
+};
+use ::sys::{
+    ipc::Message,
+    pm::ProcessIdentifier,
+};
+use ::tokio::sync::{
+    mpsc::UnboundedSender,
+    oneshot,
+    Mutex,
+};
+
+//==================================================================================================
+// Structures
+//==================================================================================================
+
+///
+/// Gateway Peer
+///
+#[derive(Clone)]
+pub enum GatewayPeer {
+    /// A directly connected client. Messages sent over the channel are tagged with the logical
+    /// channel identifier that they belong to, and with the [`FrameKind`] that distinguishes
+    /// gateway control-plane traffic from ordinary data.
+    Client(UnboundedSender<(u32, FrameKind, Result<Message, anyhow::Error>)>),
+}
+
+/// Key that identifies a logical peer: the opaque identifier of the underlying connection plus the
+/// multiplexed channel identifier within it. Non-multiplexed connections always use channel `0`.
+pub type PeerKey = (PeerId, u32);
+
+///
+/// # Description
+///
+/// Private, shared state of a [`GatewayLookupTable`].
+///
+struct Inner {
+    /// Peers indexed by their peer key.
+    by_addr: HashMap<PeerKey, GatewayPeer>,
+    /// Peer key that owns a given process identifier.
+    by_pid: HashMap<ProcessIdentifier, PeerKey>,
+    /// Topic patterns that a peer has announced that it may publish under.
+    providers: Vec<(String, PeerKey)>,
+    /// Topic patterns that a peer has subscribed to.
+    subscriptions: Vec<(String, PeerKey)>,
+    /// In-flight RPC requests, by the peer key that issued them and then by `request_id`, awaiting
+    /// a matching response from the virtual machine.
+    pending: HashMap<PeerKey, HashMap<u64, oneshot::Sender<Message>>>,
+    /// Notifier, per connected peer, that its heartbeat task is woken up over every time a `Pong`
+    /// is observed for it.
+    heartbeats: HashMap<PeerId, UnboundedSender<()>>,
+}
+
+///
+/// # Description
+///
+/// A structure that holds the lookup tables used to route messages between the gateway, its
+/// connected clients, and the virtual machine.
+///
+#[derive(Clone)]
+pub struct GatewayLookupTable {
+    inner: Arc<Mutex<Inner>>,
+}
+
+//==================================================================================================
+// Implementations
+//==================================================================================================
+
+impl GatewayLookupTable {
+    ///
+    /// # Description
+    ///
+    /// Creates a new, empty lookup table.
+    ///
+    /// # Returns
+    ///
+    /// A new lookup table.
+    ///
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Inner {
+                by_addr: HashMap::new(),
+                by_pid: HashMap::new(),
+                providers: Vec::new(),
+                subscriptions: Vec::new(),
+                pending: HashMap::new(),
+                heartbeats: HashMap::new(),
+            })),
+        }
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Registers a peer under the given address.
+    ///
+    /// # Parameters
+    ///
+    /// - `key`: Key of the peer, i.e. its address and multiplexed channel identifier.
+    /// - `peer`: Peer to register.
+    ///
+    /// # Returns
+    ///
+    /// Upon successful completion, this method returns empty. Otherwise, it returns an error.
+    ///
+    pub async fn register_addr(&self, key: PeerKey, peer: GatewayPeer) -> Result<()> {
+        trace!("register_addr(): key={:?}", key);
+        self.inner.lock().await.by_addr.insert(key, peer);
+        Ok(())
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Registers the peer key that owns a given process identifier.
+    ///
+    /// # Parameters
+    ///
+    /// - `pid`: Process identifier to register.
+    /// - `key`: Key of the peer that owns `pid`.
+    ///
+    /// # Returns
+    ///
+    /// Upon successful completion, this method returns empty. Otherwise, it returns an error.
+    ///
+    pub async fn register_pid(&self, pid: ProcessIdentifier, key: PeerKey) -> Result<()> {
+        trace!("register_pid(): pid={:?}, key={:?}", pid, key);
+        self.inner.lock().await.by_pid.insert(pid, key);
+        Ok(())
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Looks up the peer that is registered under the given key.
+    ///
+    /// # Parameters
+    ///
+    /// - `key`: Key of the peer.
+    ///
+    /// # Returns
+    ///
+    /// Upon successful completion, this method returns the peer. Otherwise, it returns an error.
+    ///
+    pub async fn lookup_addr(&self, key: PeerKey) -> Result<GatewayPeer> {
+        match self.inner.lock().await.by_addr.get(&key) {
+            Some(peer) => Ok(peer.clone()),
+            None => {
+                let reason: String = format!("no peer registered for key (key={:?})", key);
+                error!("lookup_addr(): {}", reason);
+                Err(anyhow::anyhow!(reason))
+            },
+        }
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Looks up the peer that owns the given process identifier, along with the key under which it
+    /// is registered.
+    ///
+    /// # Parameters
+    ///
+    /// - `pid`: Process identifier.
+    ///
+    /// # Returns
+    ///
+    /// Upon successful completion, this method returns the peer key and the peer. Otherwise, it
+    /// returns an error.
+    ///
+    pub async fn lookup_pid(&self, pid: ProcessIdentifier) -> Result<(PeerKey, GatewayPeer)> {
+        let key: PeerKey = match self.inner.lock().await.by_pid.get(&pid) {
+            Some(key) => *key,
+            None => {
+                let reason: String = format!("no peer registered for pid (pid={:?})", pid);
+                error!("lookup_pid(): {}", reason);
+                return Err(anyhow::anyhow!(reason));
+            },
+        };
+        let peer: GatewayPeer = self.lookup_addr(key).await?;
+        Ok((key, peer))
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Removes every peer and process identifier that is associated with the given peer
+    /// identifier, across all of its multiplexed channels, along with any publish/subscribe
+    /// registrations that it held.
+    ///
+    /// # Parameters
+    ///
+    /// - `lookup_tables`: Lookup tables.
+    /// - `peer_id`: Opaque identifier of the peer to remove.
+    ///
+    /// # Returns
+    ///
+    /// Upon successful completion, this method returns empty. Otherwise, it returns an error.
+    ///
+    pub async fn remove(lookup_tables: &GatewayLookupTable, peer_id: PeerId) -> Result<()> {
+        trace!("remove(): peer_id={}", peer_id);
+        let mut inner = lookup_tables.inner.lock().await;
+        inner.by_addr.retain(|key, _| key.0 != peer_id);
+        inner.by_pid.retain(|_, key| key.0 != peer_id);
+        inner.providers.retain(|(_, key)| key.0 != peer_id);
+        inner.subscriptions.retain(|(_, key)| key.0 != peer_id);
+        inner.pending.retain(|key, _| key.0 != peer_id);
+        inner.heartbeats.remove(&peer_id);
+        Ok(())
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Registers the channel that a peer's heartbeat task is woken up over whenever a `Pong` is
+    /// observed for it.
+    ///
+    /// # Parameters
+    ///
+    /// - `peer_id`: Opaque identifier of the peer.
+    /// - `pong_tx`: Notifier for the peer's heartbeat task.
+    ///
+    /// # Returns
+    ///
+    /// Upon successful completion, this method returns empty. Otherwise, it returns an error.
+    ///
+    pub async fn register_heartbeat(&self, peer_id: PeerId, pong_tx: UnboundedSender<()>) -> Result<()> {
+        trace!("register_heartbeat(): peer_id={}", peer_id);
+        self.inner.lock().await.heartbeats.insert(peer_id, pong_tx);
+        Ok(())
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Notifies the heartbeat task of `peer_id`, if one is still registered, that a `Pong` was
+    /// observed for it.
+    ///
+    /// # Parameters
+    ///
+    /// - `peer_id`: Opaque identifier of the peer that answered.
+    ///
+    pub async fn notify_pong(&self, peer_id: PeerId) {
+        if let Some(pong_tx) = self.inner.lock().await.heartbeats.get(&peer_id) {
+            // The heartbeat task may have already given up on this peer; a failed send just means
+            // there is nothing left to notify.
+            let _ = pong_tx.send(());
+        }
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Registers that the peer at `key` is awaiting a response to `request_id`.
+    ///
+    /// # Parameters
+    ///
+    /// - `key`: Key of the peer that issued the request.
+    /// - `request_id`: Identifier of the request, as tagged by [`crate::rpc::MessageKind`].
+    /// - `tx`: Oneshot sender that the matching response, once it arrives, is delivered over.
+    ///
+    /// # Returns
+    ///
+    /// Upon successful completion, this method returns empty. Otherwise, it returns an error.
+    ///
+    pub async fn register_pending(
+        &self,
+        key: PeerKey,
+        request_id: u64,
+        tx: oneshot::Sender<Message>,
+    ) -> Result<()> {
+        trace!("register_pending(): key={:?}, request_id={}", key, request_id);
+        self.inner
+            .lock()
+            .await
+            .pending
+            .entry(key)
+            .or_default()
+            .insert(request_id, tx);
+        Ok(())
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Removes and returns the pending entry for `request_id` at `key`, if one is still
+    /// outstanding. Used both to complete a request with a matching response and to tear down an
+    /// entry whose timeout has elapsed.
+    ///
+    /// # Parameters
+    ///
+    /// - `key`: Key of the peer that issued the request.
+    /// - `request_id`: Identifier of the request.
+    ///
+    /// # Returns
+    ///
+    /// The oneshot sender that was registered for `request_id`, if it is still pending.
+    ///
+    pub async fn take_pending(&self, key: PeerKey, request_id: u64) -> Option<oneshot::Sender<Message>> {
+        let mut inner = self.inner.lock().await;
+        inner.pending.get_mut(&key)?.remove(&request_id)
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Registers that the peer at `key` may publish events under topics matching `pattern`.
+    ///
+    /// # Parameters
+    ///
+    /// - `pattern`: Topic pattern that the peer provides.
+    /// - `key`: Key of the providing peer.
+    ///
+    /// # Returns
+    ///
+    /// Upon successful completion, this method returns empty. Otherwise, it returns an error.
+    ///
+    pub async fn provide(&self, pattern: String, key: PeerKey) -> Result<()> {
+        trace!("provide(): pattern={:?}, key={:?}", pattern, key);
+        self.inner.lock().await.providers.push((pattern, key));
+        Ok(())
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Registers interest, on behalf of the peer at `key`, in every topic matching `pattern`.
+    ///
+    /// # Parameters
+    ///
+    /// - `pattern`: Topic pattern that the peer subscribes to.
+    /// - `key`: Key of the subscribing peer.
+    ///
+    /// # Returns
+    ///
+    /// Upon successful completion, this method returns empty. Otherwise, it returns an error.
+    ///
+    pub async fn subscribe(&self, pattern: String, key: PeerKey) -> Result<()> {
+        trace!("subscribe(): pattern={:?}, key={:?}", pattern, key);
+        self.inner.lock().await.subscriptions.push((pattern, key));
+        Ok(())
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Returns every distinct peer that subscribed to a pattern matching `topic`.
+    ///
+    /// # Parameters
+    ///
+    /// - `topic`: Topic being published.
+    ///
+    /// # Returns
+    ///
+    /// The peer key and peer of every subscriber whose pattern matches `topic`, deduplicated by
+    /// peer key.
+    ///
+    pub async fn matching_subscribers(&self, topic: &str) -> Vec<(PeerKey, GatewayPeer)> {
+        let inner = self.inner.lock().await;
+
+        let mut seen: Vec<PeerKey> = Vec::new();
+        let mut subscribers: Vec<(PeerKey, GatewayPeer)> = Vec::new();
+        for (pattern, key) in inner.subscriptions.iter() {
+            if !crate::pubsub::matches(pattern, topic) || seen.contains(key) {
+                continue;
+            }
+            if let Some(peer) = inner.by_addr.get(key) {
+                seen.push(*key);
+                subscribers.push((*key, peer.clone()));
+            }
+        }
+
+        subscribers
+    }
+}
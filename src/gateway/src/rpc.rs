@@ -0,0 +1,103 @@
+// Copyright(c) The Maintainers of Nanvix.
+// Licensed under the MIT License.
+
+//!
+//! # RPC Envelope
+//!
+//! The gateway is otherwise a fire-and-forget, point-to-point router: a client sends a message and
+//! has no way to know which message, if any, a reply from the virtual machine answers. This module
+//! lets a client opt a message into call/reply semantics by tagging it with a `request_id`, using
+//! the same opcode-prefixed-payload convention as [`crate::pubsub`]. [`crate::gateway::Gateway`]
+//! inspects this tag to correlate a [`MessageKind::Response`] with the [`MessageKind::Request`] it
+//! answers, without otherwise touching the message on its way through.
+//!
+
+//==================================================================================================
+// Imports
+//==================================================================================================
+
+use ::anyhow::Result;
+use ::sys::ipc::Message;
+
+//==================================================================================================
+// Structures
+//==================================================================================================
+
+// Numbered from 0x10 so that this module's opcodes can never be confused with `crate::pubsub`'s
+// (0x01-0x03), since both are tried, in sequence, against the same untyped payload bytes.
+
+/// Opcode, on `Message::payload`, that identifies a [`MessageKind::Oneway`] message.
+const OPCODE_ONEWAY: u8 = 0x10;
+/// Opcode, on `Message::payload`, that identifies a [`MessageKind::Request`] message.
+const OPCODE_REQUEST: u8 = 0x11;
+/// Opcode, on `Message::payload`, that identifies a [`MessageKind::Response`] message.
+const OPCODE_RESPONSE: u8 = 0x12;
+
+/// Size, in bytes, of the little-endian `request_id` that follows the opcode on a request or
+/// response.
+const REQUEST_ID_LEN: usize = 8;
+
+///
+/// # Description
+///
+/// The RPC correlation metadata tagged onto a message, decoded from the front of its payload.
+/// Mirrors the kind of envelope that established Rust RPC stacks (e.g. tarpc, tonic) attach to
+/// their frames, but flattened into the fixed-size payload that the gateway forwards.
+///
+pub enum MessageKind {
+    /// No reply is expected; handled exactly as before this module existed.
+    Oneway,
+    /// A call awaiting a matching [`MessageKind::Response`] carrying the same `request_id`.
+    Request { request_id: u64 },
+    /// The reply to a previously issued [`MessageKind::Request`].
+    Response { request_id: u64 },
+}
+
+//==================================================================================================
+// Implementations
+//==================================================================================================
+
+impl MessageKind {
+    ///
+    /// # Description
+    ///
+    /// Classifies `message` by the RPC opcode tagged at the front of its payload.
+    ///
+    /// # Parameters
+    ///
+    /// - `message`: Message whose payload may be tagged with an RPC opcode.
+    ///
+    /// # Returns
+    ///
+    /// Upon successful completion, this function returns the message's kind. Otherwise, it
+    /// returns an error, meaning that the message's payload does not carry a recognized tag (e.g.
+    /// it predates this module, or belongs to a different smuggled protocol such as
+    /// [`crate::pubsub`]).
+    ///
+    pub fn decode(message: &Message) -> Result<Self> {
+        let bytes: &[u8] = &message.payload;
+
+        let (opcode, rest): (u8, &[u8]) = match bytes.split_first() {
+            Some((opcode, rest)) => (*opcode, rest),
+            None => anyhow::bail!("empty payload"),
+        };
+
+        match opcode {
+            OPCODE_ONEWAY => Ok(Self::Oneway),
+            OPCODE_REQUEST => Ok(Self::Request {
+                request_id: Self::decode_request_id(rest)?,
+            }),
+            OPCODE_RESPONSE => Ok(Self::Response {
+                request_id: Self::decode_request_id(rest)?,
+            }),
+            opcode => anyhow::bail!("unknown rpc opcode (opcode={})", opcode),
+        }
+    }
+
+    fn decode_request_id(bytes: &[u8]) -> Result<u64> {
+        if bytes.len() < REQUEST_ID_LEN {
+            anyhow::bail!("truncated request identifier");
+        }
+        Ok(u64::from_le_bytes(bytes[..REQUEST_ID_LEN].try_into()?))
+    }
+}
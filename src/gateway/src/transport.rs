@@ -0,0 +1,227 @@
+// Copyright(c) The Maintainers of Nanvix.
+// Licensed under the MIT License.
+
+//!
+//! # Gateway Transports
+//!
+//! This module abstracts the bidirectional stream that [`crate::gateway::Gateway`] accepts
+//! connections over, so that the very same routing logic can run on top of TCP, Unix domain
+//! sockets, or (on Windows) named pipes. A [`Listener`] yields, for every accepted connection, a
+//! [`GatewayStream`] plus an opaque [`PeerId`] that the gateway uses to key its routing tables —
+//! this sidesteps the fact that some transports (e.g. Unix domain sockets) have no address that is
+//! as cheap to compare and hash as a [`std::net::SocketAddr`].
+//!
+
+//==================================================================================================
+// Imports
+//==================================================================================================
+
+use ::anyhow::Result;
+use ::std::{
+    future::Future,
+    pin::Pin,
+};
+use ::tokio::io::{
+    AsyncRead,
+    AsyncWrite,
+};
+
+//==================================================================================================
+// Structures
+//==================================================================================================
+
+/// Opaque identifier of a peer connection, unique for as long as the connection is alive.
+/// Assigned locally by the gateway at accept time, so it is meaningful across every transport.
+pub type PeerId = u64;
+
+/// A future, boxed for object safety, matching the convention already used by
+/// [`crate::gateway::GatewayClient::run`].
+type BoxFuture<'a, O> = Pin<Box<dyn Future<Output = O> + Send + 'a>>;
+
+//==================================================================================================
+// Traits
+//==================================================================================================
+
+/// A bidirectional, asynchronous byte stream that a [`Listener`] may yield.
+pub trait GatewayStream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send> GatewayStream for S {}
+
+///
+/// # Description
+///
+/// A transport that [`crate::gateway::Gateway`] can accept incoming connections over.
+///
+pub trait Listener: Send {
+    /// Stream type yielded by this transport.
+    type Stream: GatewayStream;
+
+    ///
+    /// # Description
+    ///
+    /// Accepts the next incoming connection.
+    ///
+    /// # Returns
+    ///
+    /// Upon successful completion, this function returns the accepted stream along with an opaque
+    /// identifier for the peer. Otherwise, it returns an error.
+    ///
+    fn accept(&mut self) -> BoxFuture<'_, Result<(Self::Stream, PeerId)>>;
+}
+
+//==================================================================================================
+// TCP
+//==================================================================================================
+
+pub mod tcp {
+    use super::*;
+    use ::std::net::SocketAddr;
+    use ::tokio::net::{
+        TcpListener,
+        TcpStream,
+    };
+
+    /// A [`Listener`] backed by a TCP socket.
+    pub struct TcpGatewayListener {
+        inner: TcpListener,
+        next_peer_id: PeerId,
+    }
+
+    impl TcpGatewayListener {
+        ///
+        /// # Description
+        ///
+        /// Binds a new TCP gateway listener to `addr`.
+        ///
+        pub async fn bind(addr: SocketAddr) -> Result<Self> {
+            Ok(Self {
+                inner: TcpListener::bind(addr).await?,
+                next_peer_id: 0,
+            })
+        }
+    }
+
+    impl Listener for TcpGatewayListener {
+        type Stream = TcpStream;
+
+        fn accept(&mut self) -> BoxFuture<'_, Result<(Self::Stream, PeerId)>> {
+            Box::pin(async move {
+                let (stream, addr) = self.inner.accept().await?;
+                let peer_id: PeerId = self.next_peer_id;
+                self.next_peer_id += 1;
+                trace!("tcp accept(): addr={:?}, peer_id={}", addr, peer_id);
+                Ok((stream, peer_id))
+            })
+        }
+    }
+}
+
+//==================================================================================================
+// Unix Domain Sockets
+//==================================================================================================
+
+#[cfg(unix)]
+pub mod unix {
+    use super::*;
+    use ::std::path::Path;
+    use ::tokio::net::{
+        UnixListener,
+        UnixStream,
+    };
+
+    /// A [`Listener`] backed by a Unix domain socket. Access to the socket is governed by regular
+    /// filesystem permissions, so it is a good fit for local, trusted VM<->host control traffic.
+    pub struct UnixGatewayListener {
+        inner: UnixListener,
+        next_peer_id: PeerId,
+    }
+
+    impl UnixGatewayListener {
+        ///
+        /// # Description
+        ///
+        /// Binds a new Unix domain socket gateway listener to `path`. If a stale socket file
+        /// already exists at `path`, it is removed first.
+        ///
+        pub fn bind(path: &Path) -> Result<Self> {
+            if path.exists() {
+                std::fs::remove_file(path)?;
+            }
+            Ok(Self {
+                inner: UnixListener::bind(path)?,
+                next_peer_id: 0,
+            })
+        }
+    }
+
+    impl Listener for UnixGatewayListener {
+        type Stream = UnixStream;
+
+        fn accept(&mut self) -> BoxFuture<'_, Result<(Self::Stream, PeerId)>> {
+            Box::pin(async move {
+                let (stream, addr) = self.inner.accept().await?;
+                let peer_id: PeerId = self.next_peer_id;
+                self.next_peer_id += 1;
+                trace!("unix accept(): addr={:?}, peer_id={}", addr, peer_id);
+                Ok((stream, peer_id))
+            })
+        }
+    }
+}
+
+//==================================================================================================
+// Windows Named Pipes
+//==================================================================================================
+
+#[cfg(windows)]
+pub mod named_pipe {
+    use super::*;
+    use ::tokio::net::windows::named_pipe::{
+        NamedPipeServer,
+        ServerOptions,
+    };
+
+    /// A [`Listener`] backed by a Windows named pipe.
+    pub struct NamedPipeGatewayListener {
+        pipe_name: String,
+        /// Server instance that is currently waiting for the next client to connect.
+        pending: NamedPipeServer,
+        next_peer_id: PeerId,
+    }
+
+    impl NamedPipeGatewayListener {
+        ///
+        /// # Description
+        ///
+        /// Creates a new named pipe gateway listener at `pipe_name` (e.g.
+        /// `\\.\pipe\nanvix-microvm`).
+        ///
+        pub fn bind(pipe_name: &str) -> Result<Self> {
+            let pending: NamedPipeServer = ServerOptions::new().first_pipe_instance(true).create(pipe_name)?;
+            Ok(Self {
+                pipe_name: pipe_name.to_string(),
+                pending,
+                next_peer_id: 0,
+            })
+        }
+    }
+
+    impl Listener for NamedPipeGatewayListener {
+        type Stream = NamedPipeServer;
+
+        fn accept(&mut self) -> BoxFuture<'_, Result<(Self::Stream, PeerId)>> {
+            Box::pin(async move {
+                self.pending.connect().await?;
+
+                // Swap in a fresh instance to accept the next client while this one is served.
+                let next_instance: NamedPipeServer = ServerOptions::new().create(&self.pipe_name)?;
+                let stream: NamedPipeServer = std::mem::replace(&mut self.pending, next_instance);
+
+                let peer_id: PeerId = self.next_peer_id;
+                self.next_peer_id += 1;
+                trace!("named_pipe accept(): peer_id={}", peer_id);
+                Ok((stream, peer_id))
+            })
+        }
+    }
+}
@@ -0,0 +1,265 @@
+// Copyright(c) The Maintainers of Nanvix.
+// Licensed under the MIT License.
+
+//!
+//! # Guest Memory
+//!
+//! This module provides [`GuestMemory`], a checked view over a set of [`FileMapping`]s, each
+//! registered at its own guest physical address. It replaces raw `*const u8`/`*mut u8` pointer
+//! arithmetic over a [`FileMapping`] with bounds-checked accessors, so that a loaded kernel
+//! mapping and anonymous RAM (or any other combination of regions) can coexist at different guest
+//! addresses behind a single, safe interface.
+//!
+//! [`crate::kvm::vmem::VirtualMemory`] and [`crate::mshv::vmem`] predate this module and still do
+//! their own raw pointer arithmetic over anonymously-mmap'd RAM, which [`FileMapping`] has no way
+//! to represent; migrating them onto [`GuestMemory`] is tracked separately from introducing it.
+//!
+
+//==================================================================================================
+// Lint Exceptions
+//==================================================================================================
+
+// Not all functions are used.
+#![allow(dead_code)]
+
+//==================================================================================================
+// Imports
+//==================================================================================================
+
+use crate::pal::FileMapping;
+use ::anyhow::Result;
+
+//==================================================================================================
+// Structures
+//==================================================================================================
+
+///
+/// # Description
+///
+/// A guest-physical memory region, backed by its own [`FileMapping`].
+///
+struct Region {
+    /// Guest physical address at which the region starts.
+    guest_phys_addr: u64,
+    /// Host mapping backing the region.
+    mapping: FileMapping,
+}
+
+///
+/// # Description
+///
+/// A checked view over a set of guest-physical memory regions, each backed by its own
+/// [`FileMapping`]. Unlike indexing a [`FileMapping`] directly, every accessor here validates that
+/// the requested range falls entirely inside a single registered region before touching it.
+///
+#[derive(Default)]
+pub struct GuestMemory {
+    /// Registered regions, in the order they were added.
+    regions: Vec<Region>,
+}
+
+impl GuestMemory {
+    ///
+    /// # Description
+    ///
+    /// Creates an empty guest memory, with no regions registered.
+    ///
+    pub fn new() -> Self {
+        Self {
+            regions: Vec::new(),
+        }
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Registers `mapping` as a region starting at guest physical address `guest_phys_addr`.
+    ///
+    /// # Parameters
+    ///
+    /// - `guest_phys_addr`: Guest physical address at which the region starts.
+    /// - `mapping`: Host mapping backing the region.
+    ///
+    /// # Returns
+    ///
+    /// Upon successful completion, this method returns empty. Otherwise, it returns an error.
+    ///
+    pub fn add_region(&mut self, guest_phys_addr: u64, mapping: FileMapping) -> Result<()> {
+        trace!(
+            "add_region(): guest_phys_addr={:#010x}, size={:#x}",
+            guest_phys_addr,
+            mapping.size()
+        );
+
+        let end: u64 = guest_phys_addr
+            .checked_add(mapping.size() as u64)
+            .ok_or_else(|| anyhow::anyhow!("region overflows guest physical address space"))?;
+
+        for region in &self.regions {
+            let region_end: u64 = region.guest_phys_addr + region.mapping.size() as u64;
+            if guest_phys_addr < region_end && region.guest_phys_addr < end {
+                anyhow::bail!(
+                    "region overlaps an existing one (guest_phys_addr={:#010x})",
+                    guest_phys_addr
+                );
+            }
+        }
+
+        self.regions.push(Region {
+            guest_phys_addr,
+            mapping,
+        });
+
+        Ok(())
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Reads `len` bytes out of guest memory starting at the guest physical address `gpa`.
+    ///
+    /// # Parameters
+    ///
+    /// - `gpa`: Guest physical address to read from.
+    /// - `len`: Number of bytes to read.
+    ///
+    /// # Returns
+    ///
+    /// Upon successful completion, this method returns a slice over the bytes read. If `[gpa, gpa
+    /// + len)` does not fall entirely inside a single registered region, it returns an error.
+    ///
+    pub fn read_slice(&self, gpa: u64, len: usize) -> Result<&[u8]> {
+        let (region, offset): (&Region, usize) = self.find_region(gpa, len)?;
+        let bytes: &[u8] = unsafe {
+            ::std::slice::from_raw_parts(region.mapping.ptr(), region.mapping.committed())
+        };
+        Ok(&bytes[offset..offset + len])
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Writes `buf` into guest memory starting at the guest physical address `gpa`.
+    ///
+    /// # Parameters
+    ///
+    /// - `gpa`: Guest physical address to write to.
+    /// - `buf`: Bytes to write.
+    ///
+    /// # Returns
+    ///
+    /// Upon successful completion, this method returns empty. If `[gpa, gpa + buf.len())` does not
+    /// fall entirely inside a single registered region, it returns an error.
+    ///
+    pub fn write_slice(&mut self, gpa: u64, buf: &[u8]) -> Result<()> {
+        let (index, offset): (usize, usize) = {
+            let (region, offset): (&Region, usize) = self.find_region(gpa, buf.len())?;
+            (self.index_of(region), offset)
+        };
+
+        let region: &mut Region = &mut self.regions[index];
+        let bytes: &mut [u8] = unsafe {
+            ::std::slice::from_raw_parts_mut(region.mapping.ptr_mut(), region.mapping.committed())
+        };
+        bytes[offset..offset + buf.len()].copy_from_slice(buf);
+
+        Ok(())
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Reads a `T` out of guest memory at the guest physical address `gpa`.
+    ///
+    /// # Parameters
+    ///
+    /// - `gpa`: Guest physical address to read from.
+    ///
+    /// # Returns
+    ///
+    /// Upon successful completion, this method returns the value read. If `[gpa, gpa +
+    /// size_of::<T>())` does not fall entirely inside a single registered region, it returns an
+    /// error.
+    ///
+    pub fn read_obj<T: Copy>(&self, gpa: u64) -> Result<T> {
+        let (region, offset): (&Region, usize) =
+            self.find_region(gpa, ::std::mem::size_of::<T>())?;
+        Ok(unsafe {
+            region
+                .mapping
+                .ptr()
+                .add(offset)
+                .cast::<T>()
+                .read_unaligned()
+        })
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Writes a `T` into guest memory at the guest physical address `gpa`.
+    ///
+    /// # Parameters
+    ///
+    /// - `gpa`: Guest physical address to write to.
+    /// - `value`: Value to write.
+    ///
+    /// # Returns
+    ///
+    /// Upon successful completion, this method returns empty. If `[gpa, gpa + size_of::<T>())`
+    /// does not fall entirely inside a single registered region, it returns an error.
+    ///
+    pub fn write_obj<T: Copy>(&mut self, gpa: u64, value: T) -> Result<()> {
+        let (index, offset): (usize, usize) = {
+            let (region, offset): (&Region, usize) =
+                self.find_region(gpa, ::std::mem::size_of::<T>())?;
+            (self.index_of(region), offset)
+        };
+
+        let region: &mut Region = &mut self.regions[index];
+        unsafe {
+            region
+                .mapping
+                .ptr_mut()
+                .add(offset)
+                .cast::<T>()
+                .write_unaligned(value);
+        }
+
+        Ok(())
+    }
+
+    /// Finds the region, if any, that fully covers `[gpa, gpa + len)`, along with the offset of
+    /// `gpa` within it.
+    fn find_region(&self, gpa: u64, len: usize) -> Result<(&Region, usize)> {
+        // `gpa + len` must be computed with an overflow check, the same as `Self::add_region`
+        // does for a region's own bounds: a `gpa` near `u64::MAX` would otherwise wrap the sum to
+        // a small value that satisfies the range check below against almost any registered
+        // region, handing back an `offset` that runs far past the end of the mapping.
+        let gpa_end: u64 = gpa
+            .checked_add(len as u64)
+            .ok_or_else(|| anyhow::anyhow!("invalid guest memory access (gpa={:#010x}, len={:#x})", gpa, len))?;
+
+        for region in &self.regions {
+            let region_end: u64 = region.guest_phys_addr + region.mapping.committed() as u64;
+            if gpa >= region.guest_phys_addr && gpa_end <= region_end {
+                return Ok((region, (gpa - region.guest_phys_addr) as usize));
+            }
+        }
+
+        anyhow::bail!(
+            "invalid guest memory access (gpa={:#010x}, len={:#x})",
+            gpa,
+            len
+        );
+    }
+
+    /// Returns the index of `region` within [`Self::regions`], by pointer identity.
+    fn index_of(&self, region: &Region) -> usize {
+        let target: *const Region = region as *const Region;
+        self.regions
+            .iter()
+            .position(|candidate| candidate as *const Region == target)
+            .expect("region must belong to this GuestMemory")
+    }
+}
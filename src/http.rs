@@ -5,25 +5,43 @@
 // Imports
 //==================================================================================================
 
+use crate::{
+    codec::{self, WireCodec},
+    kvm::control,
+};
 use ::anyhow::Result;
 use ::serde_json::Value;
 use ::std::{
     self,
-    io::{
-        BufRead,
-        BufReader,
-        Read,
-        Write,
-    },
+    collections::HashMap,
+    io::{BufRead, BufReader, Cursor, Read, Write},
     mem,
-    net::{
-        SocketAddr,
-        TcpListener,
-        TcpStream,
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        mpsc, Arc, Mutex,
     },
-    sync::mpsc,
+    thread,
+    time::Duration,
 };
 use ::sys::ipc::Message;
+use ::vmm_sys_util::eventfd::EventFd;
+
+//==================================================================================================
+// Constants
+//==================================================================================================
+
+// Transaction ID reserved for the "tester-present" keepalive message, so that it never collides
+// with an ID handed out by `HttpServer`'s transaction counter, which starts at `1`.
+const KEEPALIVE_TRANSACTION_ID: u32 = 0;
+
+// Interval, between two consecutive "tester-present" keepalive messages, that keeps an idle VM
+// session from being torn down.
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(30);
+
+// Default amount of time `handle_connection` waits for the VM to reply to a request before giving
+// up and responding with an HTTP 504.
+const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
 
 //==================================================================================================
 // Http Response
@@ -114,55 +132,222 @@ fn message_to_json(message: &Message) -> serde_json::Map<String, Value> {
 // Standalone Functions
 //==================================================================================================
 
+// Channel used to shuttle raw message bytes between this server and the virtual machine.
+type VmChannel = std::result::Result<[u8; mem::size_of::<Message>()], anyhow::Error>;
+
+// Table of requests that are waiting on a reply from the VM, keyed by transaction ID. The
+// dispatcher thread removes an entry and forwards the reply as soon as it arrives.
+type PendingTable = Arc<Mutex<HashMap<u32, mpsc::Sender<Message>>>>;
+
 pub struct HttpServer {
     addr: SocketAddr,
-    tx_channel_to_vm:
-        mpsc::Sender<std::result::Result<[u8; mem::size_of::<Message>()], anyhow::Error>>,
-    rx_channel_from_vm:
-        mpsc::Receiver<std::result::Result<[u8; mem::size_of::<Message>()], anyhow::Error>>,
+    tx_channel_to_vm: mpsc::Sender<VmChannel>,
+    rx_channel_from_vm: mpsc::Receiver<VmChannel>,
+    request_timeout: Duration,
+    // Sender obtained from `crate::microvm::MicroVm::control_sender`, used to submit a
+    // `control::VmRequest` recognized under a `"control"` field of an incoming request body,
+    // see `handle_connection`.
+    control_tx: mpsc::Sender<control::VmRequest>,
+    // Signaled, alongside every send on `tx_channel_to_vm`, to raise the irqfd
+    // `crate::microvm::MicroVm::new` registered on `crate::microvm::MicroVm::STDIN_IRQ_GSI`.
+    stdin_irqfd: EventFd,
+    // Shared secret used to key a `codec::WireCodec` for every accepted connection, if set. When
+    // set, a connection no longer speaks plain HTTP: each request/response is instead exchanged
+    // as a single framed, compressed, and encrypted `WireCodec` frame, so that this server can
+    // safely be exposed to an untrusted network.
+    secret: Option<String>,
 }
 
 impl HttpServer {
     pub fn new(
         addr: SocketAddr,
-        tx_channel_to_vm: mpsc::Sender<
-            std::result::Result<[u8; mem::size_of::<Message>()], anyhow::Error>,
-        >,
-        rx_channel_from_vm: mpsc::Receiver<
-            std::result::Result<[u8; mem::size_of::<Message>()], anyhow::Error>,
-        >,
+        tx_channel_to_vm: mpsc::Sender<VmChannel>,
+        rx_channel_from_vm: mpsc::Receiver<VmChannel>,
+        control_tx: mpsc::Sender<control::VmRequest>,
+        stdin_irqfd: EventFd,
+        secret: Option<String>,
     ) -> Self {
         Self {
             addr,
             tx_channel_to_vm,
             rx_channel_from_vm,
+            request_timeout: DEFAULT_REQUEST_TIMEOUT,
+            control_tx,
+            stdin_irqfd,
+            secret,
         }
     }
 
-    pub fn run(&self) -> Result<()> {
+    ///
+    /// # Description
+    ///
+    /// Overrides the amount of time a request waits for the VM to reply before this server answers
+    /// with an HTTP 504. Defaults to [`DEFAULT_REQUEST_TIMEOUT`].
+    ///
+    pub fn with_request_timeout(mut self, request_timeout: Duration) -> Self {
+        self.request_timeout = request_timeout;
+        self
+    }
+
+    pub fn run(self) -> Result<()> {
+        let pending: PendingTable = Arc::new(Mutex::new(HashMap::new()));
+
+        // Dispatcher thread: the sole owner of `rx_channel_from_vm`, so that replies can be routed
+        // to whichever connection thread is waiting on their transaction ID, regardless of the
+        // order in which the VM produces them.
+        {
+            let pending: PendingTable = Arc::clone(&pending);
+            thread::spawn(move || dispatch_replies(self.rx_channel_from_vm, pending));
+        }
+
+        // Keepalive thread: sends a "tester-present" message on a fixed interval so that idle VMs
+        // don't tear down the session.
+        {
+            let tx_channel_to_vm: mpsc::Sender<VmChannel> = self.tx_channel_to_vm.clone();
+            let stdin_irqfd: EventFd = self.stdin_irqfd.try_clone()?;
+            thread::spawn(move || keepalive(tx_channel_to_vm, stdin_irqfd));
+        }
+
+        // Transaction IDs start at `1`; `0` is reserved for the keepalive message.
+        let next_transaction_id: Arc<AtomicU32> = Arc::new(AtomicU32::new(1));
+
         loop {
             let listener: TcpListener = TcpListener::bind(self.addr)?;
 
-            let (mut stream, _) = listener.accept()?;
-            trace!("http_server(): accepted connection from {}", stream.peer_addr()?);
-
             loop {
-                handle_connection(&mut stream, &self.tx_channel_to_vm, &self.rx_channel_from_vm)?;
+                let (mut stream, _) = listener.accept()?;
+                trace!("run(): accepted connection from {}", stream.peer_addr()?);
+
+                let tx_channel_to_vm: mpsc::Sender<VmChannel> = self.tx_channel_to_vm.clone();
+                let pending: PendingTable = Arc::clone(&pending);
+                let next_transaction_id: Arc<AtomicU32> = Arc::clone(&next_transaction_id);
+                let request_timeout: Duration = self.request_timeout;
+                let control_tx: mpsc::Sender<control::VmRequest> = self.control_tx.clone();
+                let stdin_irqfd: EventFd = self.stdin_irqfd.try_clone()?;
+                let secret: Option<String> = self.secret.clone();
+
+                thread::spawn(move || loop {
+                    if let Err(e) = handle_connection(
+                        &mut stream,
+                        &tx_channel_to_vm,
+                        &pending,
+                        &next_transaction_id,
+                        request_timeout,
+                        &control_tx,
+                        &stdin_irqfd,
+                        &secret,
+                    ) {
+                        error!("run(): connection handler has failed: {:?}", e);
+                        break;
+                    }
+                });
             }
         }
     }
 }
 
+// Owns `rx_channel_from_vm` and routes each reply to the pending request that matches its
+// transaction ID (carried in `Message::source`, which is otherwise unused by this server).
+fn dispatch_replies(rx_channel_from_vm: mpsc::Receiver<VmChannel>, pending: PendingTable) {
+    loop {
+        let bytes: [u8; mem::size_of::<Message>()] = match rx_channel_from_vm.recv() {
+            Ok(Ok(bytes)) => bytes,
+            Ok(Err(e)) => {
+                error!(
+                    "dispatch_replies(): failed to receive message from VM: {:?}",
+                    e
+                );
+                continue;
+            }
+            Err(_) => {
+                debug!("dispatch_replies(): channel from VM has been disconnected");
+                break;
+            }
+        };
+
+        let message: Message = match Message::try_from_bytes(bytes) {
+            Ok(message) => message,
+            Err(e) => {
+                error!(
+                    "dispatch_replies(): failed to parse message from VM (error={:?})",
+                    e
+                );
+                continue;
+            }
+        };
+
+        let transaction_id: u32 = u32::from(message.source);
+
+        match pending.lock().unwrap().remove(&transaction_id) {
+            Some(reply_tx) => {
+                if reply_tx.send(message).is_err() {
+                    warn!(
+                        "dispatch_replies(): requester for transaction {} has gone away",
+                        transaction_id
+                    );
+                }
+            }
+            None => {
+                trace!(
+                    "dispatch_replies(): dropping reply with no matching requester (transaction_id={})",
+                    transaction_id
+                );
+            }
+        }
+    }
+}
+
+// Periodically sends a "tester-present" keepalive message to the VM so that an idle session is not
+// torn down. Nothing waits on its reply; any stray reply is simply dropped by `dispatch_replies`.
+fn keepalive(tx_channel_to_vm: mpsc::Sender<VmChannel>, stdin_irqfd: EventFd) {
+    loop {
+        thread::sleep(KEEPALIVE_INTERVAL);
+
+        let mut message: sys::ipc::Message = sys::ipc::Message::default();
+        message.destination = sys::pm::ProcessIdentifier::from(1);
+        message.source = sys::pm::ProcessIdentifier::from(KEEPALIVE_TRANSACTION_ID);
+        message.message_type = sys::ipc::MessageType::Ikc;
+
+        trace!("keepalive(): sending tester-present message");
+        if tx_channel_to_vm.send(Ok(message.to_bytes())).is_err() {
+            debug!("keepalive(): channel to VM has been disconnected");
+            break;
+        }
+
+        if let Err(e) = stdin_irqfd.write(1) {
+            warn!("keepalive(): failed to signal stdin irqfd: {}", e);
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 fn handle_connection(
     stream: &mut TcpStream,
-    tx_channel_to_vm: &mpsc::Sender<
-        std::result::Result<[u8; mem::size_of::<Message>()], anyhow::Error>,
-    >,
-    rx_channel_from_vm: &mpsc::Receiver<
-        std::result::Result<[u8; mem::size_of::<Message>()], anyhow::Error>,
-    >,
+    tx_channel_to_vm: &mpsc::Sender<VmChannel>,
+    pending: &PendingTable,
+    next_transaction_id: &AtomicU32,
+    request_timeout: Duration,
+    control_tx: &mpsc::Sender<control::VmRequest>,
+    stdin_irqfd: &EventFd,
+    secret: &Option<String>,
 ) -> Result<()> {
-    let mut buf_reader: BufReader<&mut TcpStream> = BufReader::new(stream);
+    // When a secret was passed, this connection no longer speaks plain HTTP: the request is read
+    // as a single `codec::WireCodec` frame instead of straight off the socket, and every response
+    // below is written back the same way (see `send_response`), so that a passive observer on the
+    // network sees only framed, compressed, and optionally encrypted bytes.
+    let mut codec: Option<WireCodec> = match secret {
+        Some(secret) => {
+            let mut codec: WireCodec = WireCodec::new(codec::DEFAULT_COMPRESSION_THRESHOLD);
+            codec.enable_encryption(secret.as_bytes())?;
+            Some(codec)
+        },
+        None => None,
+    };
+
+    let mut buf_reader: Box<dyn BufRead + '_> = match &mut codec {
+        Some(codec) => Box::new(Cursor::new(codec.decode(stream)?)),
+        None => Box::new(BufReader::new(&mut *stream)),
+    };
 
     // Print the request line and headers
     let mut content_length: usize = 0;
@@ -192,17 +377,30 @@ fn handle_connection(
         // Parse the body as JSON
         let json: Value = serde_json::from_str(&body_str)?;
 
+        // A `"control"` field (e.g. `{"control":{"type":"Pause"}}`) is a `control::VmRequest`
+        // meant for the virtual machine monitor itself rather than for the guest, so it is routed
+        // to `control_tx` instead of being turned into a `sys::ipc::Message`. There is no reply
+        // path back from the dispatcher that drains `control_tx`, so the response only
+        // acknowledges that the request was forwarded, not that it succeeded.
+        if let Some(control_value) = json.get("control") {
+            let request: control::VmRequest = control::VmRequest::from_json(control_value)?;
+            control_tx
+                .send(request)
+                .map_err(|_| anyhow::anyhow!("gateway control channel has been disconnected"))?;
+            send_control_accepted_response(stream, codec.as_mut())?;
+            return Ok(());
+        }
+
         // Extract destination process.
         let pid: u32 = match json.get("destination").and_then(Value::as_u64) {
             Some(pid) => pid as u32,
             None => {
                 println!("PID key not found or not a number");
                 return Ok(());
-            },
+            }
         };
         let mut message: sys::ipc::Message = sys::ipc::Message::default();
         message.destination = sys::pm::ProcessIdentifier::from(pid);
-        message.source = sys::pm::ProcessIdentifier::from(0);
         message.message_type = sys::ipc::MessageType::Ikc;
 
         // Write "Payload" value as a raw array of bytes.
@@ -213,36 +411,75 @@ fn handle_connection(
                     None => {
                         println!("Value at index {} is not a number", i);
                         return Ok(());
-                    },
+                    }
                 }
             }
         }
 
-        // Send message to virtual machine.
-        let bytes: [u8; mem::size_of::<Message>()] = message.to_bytes();
-        tx_channel_to_vm.send(Ok(bytes))?;
+        // Stamp the request with a fresh transaction ID and register a one-shot reply channel for
+        // it before handing the message off, so that the dispatcher thread can route the VM's
+        // reply back here even if other requests are in flight concurrently.
+        let transaction_id: u32 = next_transaction_id.fetch_add(1, Ordering::Relaxed);
+        message.source = sys::pm::ProcessIdentifier::from(transaction_id);
 
-        // Receive a message from the virtual machine.
-        let bytes: [u8; mem::size_of::<Message>()] = rx_channel_from_vm.recv()??;
+        let (reply_tx, reply_rx): (mpsc::Sender<Message>, mpsc::Receiver<Message>) =
+            mpsc::channel();
+        pending.lock().unwrap().insert(transaction_id, reply_tx);
 
-        trace!("received message from VM: {:?}", bytes.len());
+        // Send message to virtual machine, then raise the stdin irqfd so a guest driver blocking
+        // on it instead of polling `MicroVm::STDIN_PORT` wakes up.
+        let bytes: [u8; mem::size_of::<Message>()] = message.to_bytes();
+        tx_channel_to_vm.send(Ok(bytes))?;
+        stdin_irqfd.write(1)?;
 
-        // Convert message to Message struct.
-        match Message::try_from_bytes(bytes) {
+        // Wait for the dispatcher to route back the reply that matches `transaction_id`, giving up
+        // after `request_timeout` so that one slow or lost reply cannot hang this connection.
+        match reply_rx.recv_timeout(request_timeout) {
             Ok(message) => {
-                if let Err(e) = handle_message(stream, &message) {
+                trace!(
+                    "received message from VM (transaction_id={})",
+                    transaction_id
+                );
+
+                if let Err(e) = handle_message(stream, codec.as_mut(), &message) {
                     println!("Failed to handle message: {:?}", e);
                 }
-            },
-            Err(e) => println!("Failed to parse message: {:?}", e),
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                pending.lock().unwrap().remove(&transaction_id);
+                warn!(
+                    "handle_connection(): timed out waiting for a reply (transaction_id={})",
+                    transaction_id
+                );
+                send_timeout_response(stream, codec.as_mut())?;
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                anyhow::bail!("dispatcher thread has gone away");
+            }
         }
     }
 
     Ok(())
 }
 
+// Writes `response` to `stream`, as a single `codec::WireCodec` frame when `codec` is set,
+// straight to the socket otherwise.
+fn send_response(
+    stream: &mut TcpStream,
+    codec: Option<&mut WireCodec>,
+    response: &HttpResponse,
+) -> Result<()> {
+    response.trace();
+
+    match codec {
+        Some(codec) => codec.encode(stream, &response.to_bytes()),
+        None => Ok(stream.write_all(&response.to_bytes())?),
+    }
+}
+
 fn handle_message(
     stream: &mut TcpStream,
+    codec: Option<&mut WireCodec>,
     message: &Message,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let json = message_to_json(message);
@@ -254,8 +491,33 @@ fn handle_message(
     response.add_header("Content-Length", content.len().to_string());
     response.set_body(content);
 
-    response.trace();
-
-    stream.write_all(&response.to_bytes())?;
+    send_response(stream, codec, &response)?;
     Ok(())
 }
+
+// Answers a timed-out request with an HTTP 504, since the VM never produced a matching reply in
+// time.
+fn send_timeout_response(stream: &mut TcpStream, codec: Option<&mut WireCodec>) -> Result<()> {
+    let mut response: HttpResponse = HttpResponse::new(504, "Gateway Timeout");
+    response.add_header("Content-Length", "0".to_string());
+
+    send_response(stream, codec, &response)
+}
+
+// Acknowledges that a `"control"` request was forwarded to the virtual machine monitor, see
+// `handle_connection`.
+fn send_control_accepted_response(
+    stream: &mut TcpStream,
+    codec: Option<&mut WireCodec>,
+) -> Result<()> {
+    let mut json: serde_json::Map<String, Value> = serde_json::Map::new();
+    json.insert("status".to_string(), Value::String("accepted".to_string()));
+    let content: Vec<u8> = serde_json::to_vec(&Value::Object(json))?;
+
+    let mut response: HttpResponse = HttpResponse::new(202, "Accepted");
+    response.add_header("Content-Type", "application/json".to_owned());
+    response.add_header("Content-Length", content.len().to_string());
+    response.set_body(content);
+
+    send_response(stream, codec, &response)
+}
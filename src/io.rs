@@ -8,6 +8,7 @@
 use ::anyhow::Result;
 use ::std::{
     io::{
+        self,
         Read,
         Write,
     },
@@ -16,23 +17,272 @@ use ::std::{
         SocketAddr,
         TcpStream,
     },
-    sync::mpsc::{
-        Receiver,
-        Sender,
-        TryRecvError,
+    os::unix::{
+        io::{
+            AsRawFd,
+            RawFd,
+        },
+        net::UnixStream,
+    },
+    sync::{
+        mpsc::{
+            self,
+            Receiver,
+            Sender,
+            TryRecvError,
+        },
+        Arc,
     },
     thread::{
         self,
         JoinHandle,
     },
-    time::Duration,
 };
 use ::sys::ipc::Message;
 
 //==================================================================================================
-// Structure
+// Constants
 //==================================================================================================
 
+/// `epoll_event.u64` token identifying the eventfd that [`WakeupSender::send`] bumps, registered
+/// by [`IoThread::run`].
+const WAKEUP_TOKEN: u64 = 0;
+
+/// `epoll_event.u64` token identifying the gateway connection, registered by [`IoThread::run`].
+const CONN_TOKEN: u64 = 1;
+
+/// Prefix of a `--gateway` address that selects [`GatewayAddr::Tcp`].
+const TCP_PREFIX: &str = "tcp:";
+
+/// Prefix of a `--gateway` address that selects [`GatewayAddr::Unix`].
+const UNIX_PREFIX: &str = "unix:";
+
+//==================================================================================================
+// Structures
+//==================================================================================================
+
+///
+/// # Description
+///
+/// A gateway address, parsed from a `--gateway` command-line value: either a TCP socket address
+/// (`tcp:<host>:<port>`) or the path of a Unix domain socket (`unix:<path>`, or a bare path for
+/// same-host gateways where the TCP stack would otherwise be overkill).
+///
+pub enum GatewayAddr {
+    /// TCP address of the gateway.
+    Tcp(SocketAddr),
+    /// Path of a Unix domain socket bound by the gateway.
+    Unix(String),
+}
+
+impl GatewayAddr {
+    ///
+    /// # Description
+    ///
+    /// Parses a `--gateway` address. `tcp:<addr>` and `unix:<path>` are unambiguous; a value with
+    /// neither prefix is treated as a Unix domain socket path, since that is the common case for a
+    /// gateway colocated on the same host.
+    ///
+    /// # Parameters
+    ///
+    /// - `s`: Address string to parse.
+    ///
+    /// # Returns
+    ///
+    /// Upon successful completion, this function returns the parsed address. Otherwise, it
+    /// returns an error.
+    ///
+    pub fn parse(s: &str) -> Result<Self> {
+        if let Some(addr) = s.strip_prefix(TCP_PREFIX) {
+            return Ok(GatewayAddr::Tcp(addr.parse()?));
+        }
+
+        if let Some(path) = s.strip_prefix(UNIX_PREFIX) {
+            return Ok(GatewayAddr::Unix(path.to_string()));
+        }
+
+        Ok(GatewayAddr::Unix(s.to_string()))
+    }
+}
+
+/// A connection to the gateway, over either a TCP or a Unix domain socket; see [`GatewayAddr`].
+/// Both framings are identical fixed-size `Message` bytes, so [`IoThread::send`]/
+/// [`IoThread::receive`] only ever touch this through `Read`/`Write`.
+enum GatewayStream {
+    /// Connection established over TCP.
+    Tcp(TcpStream),
+    /// Connection established over a Unix domain socket.
+    Unix(UnixStream),
+}
+
+impl GatewayStream {
+    /// Connects to `addr`, switching it to non-blocking mode so that [`IoThread::receive`] never
+    /// blocks the shared `epoll_wait` loop: readiness is established by epoll, not by a read
+    /// timeout.
+    fn connect(addr: &GatewayAddr) -> Result<Self> {
+        let stream: Self = match addr {
+            GatewayAddr::Tcp(addr) => Self::Tcp(TcpStream::connect(addr)?),
+            GatewayAddr::Unix(path) => Self::Unix(UnixStream::connect(path)?),
+        };
+
+        match &stream {
+            Self::Tcp(conn) => conn.set_nonblocking(true)?,
+            Self::Unix(conn) => conn.set_nonblocking(true)?,
+        }
+
+        Ok(stream)
+    }
+}
+
+impl Read for GatewayStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::Tcp(conn) => conn.read(buf),
+            Self::Unix(conn) => conn.read(buf),
+        }
+    }
+}
+
+impl Write for GatewayStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Tcp(conn) => conn.write(buf),
+            Self::Unix(conn) => conn.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Tcp(conn) => conn.flush(),
+            Self::Unix(conn) => conn.flush(),
+        }
+    }
+}
+
+impl AsRawFd for GatewayStream {
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            Self::Tcp(conn) => conn.as_raw_fd(),
+            Self::Unix(conn) => conn.as_raw_fd(),
+        }
+    }
+}
+
+///
+/// # Description
+///
+/// An eventfd shared between a [`WakeupSender`] and the [`IoThread`] it feeds, so that
+/// [`IoThread::run`]'s `epoll_wait` notices a message was pushed without polling.
+///
+struct EventFd(RawFd);
+
+impl EventFd {
+    fn new() -> Result<Self> {
+        let fd: RawFd = unsafe { ::libc::eventfd(0, ::libc::EFD_NONBLOCK) };
+        if fd < 0 {
+            let reason: String = format!(
+                "failed to create eventfd (error={})",
+                ::std::io::Error::last_os_error()
+            );
+            error!("EventFd::new(): {}", reason);
+            anyhow::bail!(reason);
+        }
+        Ok(Self(fd))
+    }
+
+    /// Bumps the eventfd's counter by one, waking up anyone blocked on it in `epoll_wait`.
+    fn notify(&self) {
+        let one: u64 = 1;
+        let ret: isize = unsafe {
+            ::libc::write(self.0, &one as *const u64 as *const ::libc::c_void, mem::size_of::<u64>())
+        };
+        if ret < 0 {
+            warn!(
+                "EventFd::notify(): failed to write to eventfd (error={})",
+                ::std::io::Error::last_os_error()
+            );
+        }
+    }
+
+    /// Drains the eventfd's counter back to zero, so that `epoll_wait` does not immediately
+    /// re-trigger on it once every message queued before the wakeup has been drained by
+    /// [`IoThread::send`].
+    fn drain(&self) {
+        let mut buf: u64 = 0;
+        unsafe {
+            ::libc::read(self.0, &mut buf as *mut u64 as *mut ::libc::c_void, mem::size_of::<u64>())
+        };
+    }
+}
+
+impl Drop for EventFd {
+    fn drop(&mut self) {
+        unsafe { ::libc::close(self.0) };
+    }
+}
+
+impl AsRawFd for EventFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0
+    }
+}
+
+///
+/// # Description
+///
+/// Sender half of a wakeup-capable channel: every successful [`Self::send`] also bumps the
+/// eventfd that [`IoThread::run`]'s `epoll_wait` is blocked on, so a message pushed while the I/O
+/// thread is idling in `epoll_wait` is noticed immediately instead of waiting for a poll interval.
+///
+#[derive(Clone)]
+pub struct WakeupSender<T> {
+    tx: Sender<T>,
+    eventfd: Arc<EventFd>,
+}
+
+impl<T> WakeupSender<T> {
+    ///
+    /// # Description
+    ///
+    /// Sends `value` to the paired [`IoThread`] and bumps its eventfd.
+    ///
+    /// # Parameters
+    ///
+    /// - `value`: Value to send.
+    ///
+    /// # Returns
+    ///
+    /// Upon successful completion, this method returns empty. Otherwise, it returns the value
+    /// that could not be delivered.
+    ///
+    pub fn send(&self, value: T) -> ::std::result::Result<(), mpsc::SendError<T>> {
+        self.tx.send(value)?;
+        self.eventfd.notify();
+        Ok(())
+    }
+}
+
+/// Receiver half of a wakeup-capable channel, see [`WakeupSender`].
+struct WakeupReceiver<T> {
+    rx: Receiver<T>,
+    eventfd: Arc<EventFd>,
+}
+
+impl<T> WakeupReceiver<T> {
+    /// Creates a wakeup-capable channel, returning its receiver together with a sender for it.
+    fn new() -> Result<(Self, WakeupSender<T>)> {
+        let (tx, rx): (Sender<T>, Receiver<T>) = mpsc::channel();
+        let eventfd: Arc<EventFd> = Arc::new(EventFd::new()?);
+        Ok((
+            Self {
+                rx,
+                eventfd: eventfd.clone(),
+            },
+            WakeupSender { tx, eventfd },
+        ))
+    }
+}
+
 ///
 /// # Description
 ///
@@ -40,10 +290,12 @@ use ::sys::ipc::Message;
 ///
 pub struct IoThread {
     /// Connection to the gateway.
-    conn: Option<TcpStream>,
-    /// Gateway receiver.
-    gateway_rx: Receiver<Message>,
-    /// Gateway sender.
+    conn: Option<GatewayStream>,
+    /// Gateway receiver: messages produced by the virtual machine, to be forwarded to the
+    /// gateway. Paired with an eventfd so that [`Self::run`] wakes up as soon as one is pushed.
+    gateway_rx: WakeupReceiver<Message>,
+    /// Gateway sender: messages received from the gateway, to be forwarded to the virtual
+    /// machine.
     gateway_tx: Sender<Message>,
 }
 
@@ -60,56 +312,39 @@ impl IoThread {
     /// # Parameters
     ///
     /// - `gateway_addr`: Gateway address.
-    /// - `gateway_rx`:   Gateway receiver.
-    /// - `gateway_tx`:   Gateway sender.
-    /// - `read_timeout`: Read timeout.
+    /// - `gateway_tx`:   Sender used to forward a message received from the gateway to the
+    ///   virtual machine.
     ///
     /// # Returns
     ///
-    /// A handle to the I/O thread.
+    /// Upon success, a handle to the I/O thread together with a sender the virtual machine uses
+    /// to push a message for the I/O thread to forward to the gateway. Otherwise, an error is
+    /// returned.
     ///
     pub fn spawn(
-        gateway_addr: Option<SocketAddr>,
-        gateway_rx: Receiver<Message>,
+        gateway_addr: Option<GatewayAddr>,
         gateway_tx: Sender<Message>,
-        read_timeout: Duration,
-    ) -> JoinHandle<Result<()>> {
-        thread::spawn(move || {
-            let mut io_thread: IoThread =
-                IoThread::new(gateway_addr, gateway_rx, gateway_tx, read_timeout)?;
-            io_thread.run()?;
-            Ok(())
-        })
+    ) -> Result<(JoinHandle<Result<()>>, WakeupSender<Message>)> {
+        let (gateway_rx, wakeup_tx): (WakeupReceiver<Message>, WakeupSender<Message>) =
+            WakeupReceiver::new()?;
+
+        let handle: JoinHandle<Result<()>> = thread::spawn(move || {
+            let mut io_thread: IoThread = IoThread::new(gateway_addr, gateway_rx, gateway_tx)?;
+            io_thread.run()
+        });
+
+        Ok((handle, wakeup_tx))
     }
 
-    ///
-    /// # Description
-    ///
-    /// Creates a new I/O thread.
-    ///
-    /// # Parameters
-    ///
-    /// - `gateway_addr`: Gateway address.
-    /// - `gateway_rx`:   Gateway receiver.
-    /// - `gateway_tx`:   Gateway sender.
-    /// - `read_timeout`: Read timeout.
-    ///
-    /// # Returns
-    ///
-    /// Upon success, a new I/O thread is returned. Otherwise, an error is returned.
-    ///
+    // Creates a new I/O thread.
     fn new(
-        gateway_addr: Option<SocketAddr>,
-        gateway_rx: Receiver<Message>,
+        gateway_addr: Option<GatewayAddr>,
+        gateway_rx: WakeupReceiver<Message>,
         gateway_tx: Sender<Message>,
-        read_timeout: Duration,
     ) -> Result<Self> {
-        let conn: Option<TcpStream> = match gateway_addr {
-            Some(addr) => match TcpStream::connect(addr) {
-                Ok(conn) => {
-                    conn.set_read_timeout(Some(read_timeout))?;
-                    Some(conn)
-                },
+        let conn: Option<GatewayStream> = match gateway_addr {
+            Some(addr) => match GatewayStream::connect(&addr) {
+                Ok(conn) => Some(conn),
                 Err(e) => {
                     let reason: String = format!("failed to connect to gateway (error={:?})", e);
                     error!("io_thread(): {}", reason);
@@ -129,23 +364,97 @@ impl IoThread {
     ///
     /// # Description
     ///
-    /// Runs the I/O thread.
+    /// Runs the I/O thread: blocks in `epoll_wait` on the gateway connection (if any) and the
+    /// eventfd that a [`WakeupSender`] bumps, running [`Self::send`]/[`Self::receive`] only for
+    /// whichever fd signalled, instead of busy-polling both on every loop iteration.
     ///
     /// # Returns
     ///
     /// Upon success, empty is returned. Otherwise, an error is returned instead.
     ///
     fn run(&mut self) -> Result<()> {
+        let epoll_fd: RawFd = unsafe { ::libc::epoll_create1(0) };
+        if epoll_fd < 0 {
+            let reason: String = format!(
+                "failed to create epoll instance (error={})",
+                ::std::io::Error::last_os_error()
+            );
+            error!("run(): {}", reason);
+            anyhow::bail!(reason);
+        }
+
+        let result: Result<()> = self.run_with_epoll(epoll_fd);
+
+        unsafe { ::libc::close(epoll_fd) };
+
+        result
+    }
+
+    // Registers the monitored fds with `epoll_fd` and runs the readiness loop. Split out from
+    // `Self::run` so that `epoll_fd` is always closed on the way out, including on error.
+    fn run_with_epoll(&mut self, epoll_fd: RawFd) -> Result<()> {
+        Self::epoll_register(epoll_fd, self.gateway_rx.eventfd.as_raw_fd(), WAKEUP_TOKEN)?;
+        if let Some(ref conn) = self.conn {
+            Self::epoll_register(epoll_fd, conn.as_raw_fd(), CONN_TOKEN)?;
+        }
+
+        let mut events: [::libc::epoll_event; 2] = unsafe { mem::zeroed() };
         loop {
-            self.send()?;
-            self.receive()?;
+            let n: ::libc::c_int =
+                unsafe { ::libc::epoll_wait(epoll_fd, events.as_mut_ptr(), events.len() as i32, -1) };
+            if n < 0 {
+                let err: ::std::io::Error = ::std::io::Error::last_os_error();
+                if err.kind() == ::std::io::ErrorKind::Interrupted {
+                    continue;
+                }
+                let reason: String = format!("epoll_wait() has failed (error={})", err);
+                error!("run_with_epoll(): {}", reason);
+                anyhow::bail!(reason);
+            }
+
+            for event in &events[..n as usize] {
+                match event.u64 {
+                    WAKEUP_TOKEN => {
+                        self.gateway_rx.eventfd.drain();
+                        self.send()?;
+                    },
+                    CONN_TOKEN => {
+                        self.receive()?;
+                    },
+                    token => {
+                        // This should never happen, as only the two tokens above are registered.
+                        unreachable!("unexpected epoll token (token={})", token);
+                    },
+                }
+            }
         }
     }
 
+    // Registers `fd` with `epoll_fd`, waking up on readability, tagged with `token`.
+    fn epoll_register(epoll_fd: RawFd, fd: RawFd, token: u64) -> Result<()> {
+        let mut event: ::libc::epoll_event = ::libc::epoll_event {
+            events: ::libc::EPOLLIN as u32,
+            u64: token,
+        };
+
+        if unsafe { ::libc::epoll_ctl(epoll_fd, ::libc::EPOLL_CTL_ADD, fd, &mut event) } != 0 {
+            let reason: String = format!(
+                "failed to register fd with epoll (error={})",
+                ::std::io::Error::last_os_error()
+            );
+            error!("epoll_register(): {}", reason);
+            anyhow::bail!(reason);
+        }
+
+        Ok(())
+    }
+
     ///
     /// # Description
     ///
-    /// Attempts to send pending messages to the gateway.
+    /// Sends every message currently buffered in [`Self::gateway_rx`] to the gateway. The eventfd
+    /// that woke [`Self::run`] up only indicates that at least one message is available, not how
+    /// many, so every fully-buffered message is drained here rather than just one.
     ///
     /// # Returns
     ///
@@ -153,36 +462,36 @@ impl IoThread {
     ///
     /// # Errors
     ///
-    /// If the message could not be sent, an error is returned.
+    /// If a message could not be sent, an error is returned.
     ///
     fn send(&mut self) -> Result<()> {
-        match self.gateway_rx.try_recv() {
-            Ok(msg) => {
-                let bytes: [u8; mem::size_of::<Message>()] = msg.to_bytes();
-
-                match self.conn {
-                    Some(ref mut conn) => conn.write_all(&bytes)?,
-                    None => {
-                        warn!("send(): the microvm is not connected to a gateway");
-                    },
-                }
-            },
-            Err(TryRecvError::Empty) => {
-                // No message available.
-            },
-            Err(TryRecvError::Disconnected) => {
-                let reason: String = "the microvm has disconnected".to_string();
-                error!("send(): {}", reason);
-                anyhow::bail!(reason);
-            },
+        loop {
+            match self.gateway_rx.rx.try_recv() {
+                Ok(msg) => {
+                    let bytes: [u8; mem::size_of::<Message>()] = msg.to_bytes();
+
+                    match self.conn {
+                        Some(ref mut conn) => conn.write_all(&bytes)?,
+                        None => {
+                            warn!("send(): the microvm is not connected to a gateway");
+                        },
+                    }
+                },
+                Err(TryRecvError::Empty) => return Ok(()),
+                Err(TryRecvError::Disconnected) => {
+                    let reason: String = "the microvm has disconnected".to_string();
+                    error!("send(): {}", reason);
+                    anyhow::bail!(reason);
+                },
+            }
         }
-        Ok(())
     }
 
     ///
     /// # Description
     ///
-    /// Attempts to receive messages from the gateway.
+    /// Receives every message currently buffered on the gateway connection, stopping as soon as a
+    /// read would block (i.e. the socket has been drained down to a partial or absent frame).
     ///
     /// # Returns
     ///
@@ -190,37 +499,37 @@ impl IoThread {
     ///
     fn receive(&mut self) -> Result<()> {
         if let Some(ref mut conn) = self.conn {
-            let mut bytes: [u8; mem::size_of::<Message>()] = [0; mem::size_of::<Message>()];
-            match conn.read_exact(&mut bytes) {
-                Ok(()) => {
-                    let message: Message = match Message::try_from_bytes(bytes) {
-                        Ok(message) => message,
-                        Err(err) => {
-                            let reason: String =
-                                format!("failed to parse message (error={:?})", err);
-                            warn!("receive(): {}", reason);
-                            return Ok(());
-                        },
-                    };
+            loop {
+                let mut bytes: [u8; mem::size_of::<Message>()] = [0; mem::size_of::<Message>()];
+                match conn.read_exact(&mut bytes) {
+                    Ok(()) => {
+                        let message: Message = match Message::try_from_bytes(bytes) {
+                            Ok(message) => message,
+                            Err(err) => {
+                                let reason: String =
+                                    format!("failed to parse message (error={:?})", err);
+                                warn!("receive(): {}", reason);
+                                continue;
+                            },
+                        };
 
-                    if let Err(e) = self.gateway_tx.send(message) {
-                        let reason: String =
-                            format!("failed to send message to the microvm (error={:?})", e);
-                        error!("receive(): {}", reason);
-                        anyhow::bail!(reason);
-                    }
-                },
-                Err(e) => match e.kind() {
-                    std::io::ErrorKind::WouldBlock => {
-                        return Ok(());
+                        if let Err(e) = self.gateway_tx.send(message) {
+                            let reason: String =
+                                format!("failed to send message to the microvm (error={:?})", e);
+                            error!("receive(): {}", reason);
+                            anyhow::bail!(reason);
+                        }
                     },
-                    _ => {
-                        let reason: String =
-                            format!("failed to receive message from the gateway (error={:?})", e);
-                        error!("receive(): {}", reason);
-                        anyhow::bail!(reason);
+                    Err(e) => match e.kind() {
+                        ::std::io::ErrorKind::WouldBlock => return Ok(()),
+                        _ => {
+                            let reason: String =
+                                format!("failed to receive message from the gateway (error={:?})", e);
+                            error!("receive(): {}", reason);
+                            anyhow::bail!(reason);
+                        },
                     },
-                },
+                }
             }
         }
         Ok(())
@@ -0,0 +1,483 @@
+// Copyright(c) The Maintainers of Nanvix.
+// Licensed under the MIT License.
+
+//!
+//! # Control Socket
+//!
+//! This module provides a runtime control channel for a running [`crate::microvm::MicroVm`],
+//! modeled after crosvm's `vm_control`: an orchestrator connects to a Unix domain socket, writes
+//! one JSON-encoded [`VmRequest`] per line, and reads back one JSON-encoded [`VmResponse`] in
+//! reply. This lets it pause, resume, reset, or hot-attach event sources to a live virtual machine
+//! instead of relying solely on process signals.
+//!
+
+//==================================================================================================
+// Imports
+//==================================================================================================
+
+use crate::kvm::{
+    partition::VirtualPartition,
+    vcpu::VirtualProcessor,
+};
+use ::anyhow::Result;
+use ::serde_json::Value;
+use ::std::{
+    io::{
+        BufRead,
+        BufReader,
+        ErrorKind,
+        Write,
+    },
+    os::unix::net::{
+        UnixListener,
+        UnixStream,
+    },
+    sync::{
+        atomic::{
+            AtomicBool,
+            Ordering,
+        },
+        Arc,
+        Condvar,
+        Mutex,
+    },
+    time::Duration,
+};
+
+//==================================================================================================
+// Constants
+//==================================================================================================
+
+/// Signal used to kick a vCPU thread out of a blocking `KVM_RUN`, so that it notices a pending
+/// [`VmRequest::Pause`]/[`VmRequest::Exit`] instead of re-entering the guest. A no-op handler for
+/// it is installed by [`crate::microvm::MicroVm::run`].
+pub const STOP_SIGNAL: ::libc::c_int = ::libc::SIGUSR1;
+
+/// How long [`ControlSocket::serve`] waits between polls of its non-blocking listener, so that it
+/// notices the virtual machine stopping on its own (rather than via `VmRequest::Exit`) without
+/// spinning.
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+//==================================================================================================
+// Structures
+//==================================================================================================
+
+///
+/// # Description
+///
+/// A request sent over the control socket.
+///
+pub enum VmRequest {
+    /// Tears the virtual machine down.
+    Exit,
+    /// Pauses every virtual processor, without losing their state.
+    Pause,
+    /// Resumes every virtual processor that was previously paused.
+    Resume,
+    /// Re-arms every virtual processor at the entry point it was last reset to.
+    Reset,
+    /// Reports whether the virtual machine is currently paused.
+    QueryState,
+    /// Registers an ioeventfd, see [`VirtualPartition::register_ioevent`].
+    RegisterIoEvent {
+        addr: u64,
+        pio: bool,
+        datamatch: Option<u64>,
+    },
+    /// Registers an irqfd, see [`VirtualPartition::register_irqfd`].
+    RegisterIrq { gsi: u32 },
+}
+
+///
+/// # Description
+///
+/// A reply sent back over the control socket for a [`VmRequest`].
+///
+pub enum VmResponse {
+    /// The request was carried out successfully.
+    Ok,
+    /// Reply to a [`VmRequest::QueryState`].
+    State { paused: bool },
+    /// The request could not be carried out, with a human-readable reason.
+    Err(String),
+}
+
+///
+/// # Description
+///
+/// A control socket bound to a Unix domain socket path, serving [`VmRequest`]s against a running
+/// [`crate::microvm::MicroVm`].
+///
+pub struct ControlSocket {
+    listener: UnixListener,
+}
+
+//==================================================================================================
+// Implementations
+//==================================================================================================
+
+impl VmRequest {
+    ///
+    /// # Description
+    ///
+    /// Parses a request out of its JSON encoding, e.g. `{"type":"Pause"}` or
+    /// `{"type":"RegisterIrq","gsi":5}`. Used by [`ControlSocket`] and by
+    /// [`crate::http::HttpServer`], which recognizes the same encoding under a `"control"` field
+    /// of an incoming request body.
+    ///
+    /// # Parameters
+    ///
+    /// - `value`: JSON value to parse.
+    ///
+    /// # Returns
+    ///
+    /// Upon successful completion, this function returns the request that was parsed. Otherwise,
+    /// it returns an error.
+    ///
+    pub fn from_json(value: &Value) -> Result<Self> {
+        let kind: &str = value
+            .get("type")
+            .and_then(Value::as_str)
+            .ok_or_else(|| anyhow::anyhow!("missing 'type' field"))?;
+
+        match kind {
+            "Exit" => Ok(VmRequest::Exit),
+            "Pause" => Ok(VmRequest::Pause),
+            "Resume" => Ok(VmRequest::Resume),
+            "Reset" => Ok(VmRequest::Reset),
+            "QueryState" => Ok(VmRequest::QueryState),
+            "RegisterIoEvent" => {
+                let addr: u64 = value
+                    .get("addr")
+                    .and_then(Value::as_u64)
+                    .ok_or_else(|| anyhow::anyhow!("missing 'addr' field"))?;
+                let pio: bool = value.get("pio").and_then(Value::as_bool).unwrap_or(false);
+                let datamatch: Option<u64> =
+                    value.get("datamatch").and_then(Value::as_u64);
+                Ok(VmRequest::RegisterIoEvent { addr, pio, datamatch })
+            },
+            "RegisterIrq" => {
+                let gsi: u32 = value
+                    .get("gsi")
+                    .and_then(Value::as_u64)
+                    .ok_or_else(|| anyhow::anyhow!("missing 'gsi' field"))?
+                    as u32;
+                Ok(VmRequest::RegisterIrq { gsi })
+            },
+            kind => anyhow::bail!("unknown request type '{}'", kind),
+        }
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Decodes a request out of the single word a guest writes to [`crate::microvm::MicroVm::VMM_PORT`],
+    /// see [`crate::kvm::emulator::Emulator::handle_pmio_access`]. Unlike [`Self::from_json`], this
+    /// encoding carries no arguments, so it only covers the variants a guest can meaningfully ask
+    /// for on its own behalf.
+    ///
+    /// # Parameters
+    ///
+    /// - `code`: Word written by the guest to `VMM_PORT`.
+    ///
+    /// # Returns
+    ///
+    /// Upon successful completion, this function returns the request that was decoded. Otherwise,
+    /// it returns an error.
+    ///
+    pub fn from_u32(code: u32) -> Result<Self> {
+        match code {
+            0 => Ok(VmRequest::Exit),
+            1 => Ok(VmRequest::Pause),
+            2 => Ok(VmRequest::Resume),
+            3 => Ok(VmRequest::Reset),
+            4 => Ok(VmRequest::QueryState),
+            code => anyhow::bail!("unknown vmm port command (code={})", code),
+        }
+    }
+}
+
+impl VmResponse {
+    ///
+    /// # Description
+    ///
+    /// Encodes this response as the JSON object written back to the control socket.
+    ///
+    pub fn to_json(&self) -> Value {
+        let mut json: serde_json::Map<String, Value> = serde_json::Map::new();
+        match self {
+            VmResponse::Ok => {
+                json.insert("status".to_string(), Value::String("ok".to_string()));
+            },
+            VmResponse::State { paused } => {
+                json.insert("status".to_string(), Value::String("ok".to_string()));
+                json.insert("paused".to_string(), Value::Bool(*paused));
+            },
+            VmResponse::Err(reason) => {
+                json.insert("status".to_string(), Value::String("error".to_string()));
+                json.insert("reason".to_string(), Value::String(reason.clone()));
+            },
+        }
+        Value::Object(json)
+    }
+}
+
+impl ControlSocket {
+    ///
+    /// # Description
+    ///
+    /// Binds a control socket at `path`. If a file already exists there (e.g. left over from a
+    /// previous run that was not shut down cleanly), it is removed first, mirroring how a Unix
+    /// domain socket path is usually reclaimed.
+    ///
+    /// # Parameters
+    ///
+    /// - `path`: Path of the Unix domain socket to bind.
+    ///
+    /// # Returns
+    ///
+    /// Upon successful completion, this method returns the control socket that was created.
+    /// Otherwise, it returns an error.
+    ///
+    pub fn bind(path: &str) -> Result<Self> {
+        trace!("bind(): path={}", path);
+
+        if ::std::path::Path::new(path).exists() {
+            ::std::fs::remove_file(path)?;
+        }
+
+        let listener: UnixListener = UnixListener::bind(path)?;
+        // Non-blocking, so that `Self::serve` can poll `stop` between accepts instead of being
+        // stuck in `accept()` forever if the virtual machine shuts down on its own rather than
+        // through a `VmRequest::Exit`.
+        listener.set_nonblocking(true)?;
+        Ok(Self { listener })
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Serves [`VmRequest`]s against a running virtual machine until a [`VmRequest::Exit`] is
+    /// received or `stop` is found set between connections, accepting one connection at a time
+    /// and one JSON request per line.
+    ///
+    /// # Parameters
+    ///
+    /// - `partition`: Virtual partition that backs the virtual machine, used to register
+    ///   ioeventfds/irqfds.
+    /// - `vcpus`: Every virtual processor of the virtual machine, shared with the host threads
+    ///   that run them in [`crate::microvm::MicroVm::run`].
+    /// - `reset_args`: `(rip, rax, rbx, rdi)` that a [`VmRequest::Reset`] re-arms every virtual
+    ///   processor to, i.e. the arguments that were last passed to
+    ///   [`crate::kvm::vcpu::VirtualProcessor::reset`].
+    /// - `stop`: Set to request every vCPU thread to leave its run loop.
+    /// - `paused`: Shared pause flag and condition variable that every vCPU thread waits on while
+    ///   paused.
+    /// - `threads`: Host threads backing every vCPU, used to interrupt a blocking `KVM_RUN` with
+    ///   [`STOP_SIGNAL`] when a request requires it.
+    ///
+    /// # Returns
+    ///
+    /// Upon successful completion (i.e. a [`VmRequest::Exit`] was received or `stop` was set),
+    /// this method returns empty. Otherwise, it returns an error.
+    ///
+    pub fn serve(
+        &self,
+        partition: &VirtualPartition,
+        vcpus: &[Arc<Mutex<VirtualProcessor>>],
+        reset_args: (u64, u64, u64, u64),
+        stop: &Arc<AtomicBool>,
+        paused: &Arc<(Mutex<bool>, Condvar)>,
+        threads: &Arc<Mutex<Vec<::libc::pthread_t>>>,
+    ) -> Result<()> {
+        trace!("serve()");
+        crate::timer!("vm_control_serve");
+
+        loop {
+            if stop.load(Ordering::SeqCst) {
+                return Ok(());
+            }
+
+            let stream: UnixStream = match self.listener.accept() {
+                Ok((stream, _peer)) => stream,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                    ::std::thread::sleep(ACCEPT_POLL_INTERVAL);
+                    continue;
+                },
+                Err(e) => return Err(e.into()),
+            };
+            // Accepted connections inherit non-blocking mode from the listener; switch back to
+            // blocking so that `Self::handle_connection` can use ordinary blocking reads/writes.
+            stream.set_nonblocking(false)?;
+
+            match self.handle_connection(stream, partition, vcpus, reset_args, stop, paused, threads)
+            {
+                Ok(true) => return Ok(()),
+                Ok(false) => continue,
+                Err(e) => error!("serve(): connection handler has failed: {:?}", e),
+            }
+        }
+    }
+
+    // Serves requests over one connection, one JSON request per line, until the peer disconnects
+    // or a `VmRequest::Exit` is processed. Returns whether `VmRequest::Exit` was processed, which
+    // tells `Self::serve` to stop accepting further connections.
+    #[allow(clippy::too_many_arguments)]
+    fn handle_connection(
+        &self,
+        stream: UnixStream,
+        partition: &VirtualPartition,
+        vcpus: &[Arc<Mutex<VirtualProcessor>>],
+        reset_args: (u64, u64, u64, u64),
+        stop: &Arc<AtomicBool>,
+        paused: &Arc<(Mutex<bool>, Condvar)>,
+        threads: &Arc<Mutex<Vec<::libc::pthread_t>>>,
+    ) -> Result<bool> {
+        let mut reader: BufReader<UnixStream> = BufReader::new(stream.try_clone()?);
+        let mut writer: UnixStream = stream;
+
+        let mut line: String = String::new();
+        loop {
+            line.clear();
+            if reader.read_line(&mut line)? == 0 {
+                // Peer closed the connection.
+                return Ok(false);
+            }
+
+            let line: &str = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let (response, exit_requested): (VmResponse, bool) = match serde_json::from_str(line)
+                .map_err(anyhow::Error::from)
+                .and_then(|value| VmRequest::from_json(&value))
+            {
+                Ok(request) => {
+                    let exit_requested: bool = matches!(request, VmRequest::Exit);
+                    let response: VmResponse =
+                        dispatch(request, partition, vcpus, reset_args, stop, paused, threads);
+                    (response, exit_requested)
+                },
+                Err(e) => (VmResponse::Err(e.to_string()), false),
+            };
+
+            writeln!(writer, "{}", response.to_json())?;
+
+            if exit_requested {
+                return Ok(true);
+            }
+        }
+    }
+}
+
+//==================================================================================================
+// Standalone Functions
+//==================================================================================================
+
+///
+/// # Description
+///
+/// Carries out a [`VmRequest`] against a running virtual machine, using the same shared state
+/// [`crate::microvm::MicroVm::run`] hands to every vCPU thread. Shared by [`ControlSocket`] (one
+/// request per line over a Unix domain socket) and by the `VmRequest` channel that
+/// [`crate::microvm::MicroVm::run`] drains on behalf of [`crate::kvm::emulator::Emulator`] (a
+/// guest write to `VMM_PORT`) and [`crate::http::HttpServer`] (a gateway peer), so that all three
+/// sources of a `VmRequest` are served identically.
+///
+/// # Parameters
+///
+/// - `request`: Request to carry out.
+/// - `partition`: Virtual partition that backs the virtual machine, used to register
+///   ioeventfds/irqfds.
+/// - `vcpus`: Every virtual processor of the virtual machine.
+/// - `reset_args`: `(rip, rax, rbx, rdi)` that a [`VmRequest::Reset`] re-arms every virtual
+///   processor to.
+/// - `stop`: Set to request every vCPU thread to leave its run loop.
+/// - `paused`: Shared pause flag and condition variable that every vCPU thread waits on while
+///   paused.
+/// - `threads`: Host threads backing every vCPU, used to interrupt a blocking `KVM_RUN` with
+///   [`STOP_SIGNAL`] when a request requires it.
+///
+/// # Returns
+///
+/// The reply to send back to whoever issued `request`.
+///
+#[allow(clippy::too_many_arguments)]
+pub fn dispatch(
+    request: VmRequest,
+    partition: &VirtualPartition,
+    vcpus: &[Arc<Mutex<VirtualProcessor>>],
+    reset_args: (u64, u64, u64, u64),
+    stop: &Arc<AtomicBool>,
+    paused: &Arc<(Mutex<bool>, Condvar)>,
+    threads: &Arc<Mutex<Vec<::libc::pthread_t>>>,
+) -> VmResponse {
+    match request {
+        VmRequest::Exit => {
+            stop.store(true, Ordering::SeqCst);
+            broadcast_stop(threads);
+            VmResponse::Ok
+        },
+
+        VmRequest::Pause => {
+            let (lock, _): &(Mutex<bool>, Condvar) = &**paused;
+            *lock.lock().unwrap() = true;
+            // Interrupt any vCPU currently blocked in `KVM_RUN`, so that it notices the pause flag
+            // on its next loop iteration instead of running the guest for however long it takes to
+            // exit on its own.
+            broadcast_stop(threads);
+            VmResponse::Ok
+        },
+
+        VmRequest::Resume => {
+            let (lock, cvar): &(Mutex<bool>, Condvar) = &**paused;
+            *lock.lock().unwrap() = false;
+            cvar.notify_all();
+            VmResponse::Ok
+        },
+
+        VmRequest::Reset => {
+            // Kick every vCPU out of a blocking `KVM_RUN` first, so that the `lock()` calls below
+            // do not wait on whatever the guest happens to be doing.
+            broadcast_stop(threads);
+
+            let (rip, rax, rbx, rdi): (u64, u64, u64, u64) = reset_args;
+            for vcpu in vcpus {
+                if let Err(e) = vcpu.lock().unwrap().reset(rip, rax, rbx, rdi) {
+                    return VmResponse::Err(e.to_string());
+                }
+            }
+            VmResponse::Ok
+        },
+
+        VmRequest::QueryState => {
+            let (lock, _): &(Mutex<bool>, Condvar) = &**paused;
+            VmResponse::State { paused: *lock.lock().unwrap() }
+        },
+
+        // The caller only learns whether the registration succeeded: the control socket's JSON
+        // protocol has no way to hand a file descriptor back to a remote peer, so the eventfd
+        // handle itself is dropped here (KVM's own registration, kept alive via `partition`, is
+        // unaffected by that).
+        VmRequest::RegisterIoEvent { addr, pio, datamatch } => {
+            match partition.register_ioevent(addr, pio, datamatch) {
+                Ok(_) => VmResponse::Ok,
+                Err(e) => VmResponse::Err(e.to_string()),
+            }
+        },
+
+        VmRequest::RegisterIrq { gsi } => match partition.register_irqfd(gsi) {
+            Ok(_) => VmResponse::Ok,
+            Err(e) => VmResponse::Err(e.to_string()),
+        },
+    }
+}
+
+/// Sends [`STOP_SIGNAL`] to every host thread backing a vCPU, interrupting a blocking `KVM_RUN` so
+/// that the thread re-checks the stop/pause state instead of waiting on the guest.
+pub fn broadcast_stop(threads: &Arc<Mutex<Vec<::libc::pthread_t>>>) {
+    for thread in threads.lock().unwrap().iter() {
+        unsafe { ::libc::pthread_kill(*thread, STOP_SIGNAL) };
+    }
+}
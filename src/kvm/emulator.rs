@@ -7,12 +7,15 @@
 
 use crate::{
     kvm::{
+        control::VmRequest,
         vcpu::VirtualProcessorExitContext,
         vmem::VirtualMemory,
     },
     microvm::{
         InputFn,
         MicroVm,
+        MmioReadFn,
+        MmioWriteFn,
         OutputFn,
     },
 };
@@ -20,12 +23,29 @@ use ::anyhow::Result;
 use ::std::{
     cell::RefCell,
     rc::Rc,
+    sync::mpsc,
 };
 
 //==================================================================================================
 // Structures
 //==================================================================================================
 
+///
+/// # Description
+///
+/// A memory-mapped device, registered over a range of the guest physical address space.
+///
+struct MmioDevice {
+    /// Guest physical address at which the device is mapped.
+    base: u64,
+    /// Size, in bytes, of the device's range.
+    len: u64,
+    /// Closure invoked on a read from the device's range.
+    read_fn: Box<MmioReadFn>,
+    /// Closure invoked on a write to the device's range.
+    write_fn: Box<MmioWriteFn>,
+}
+
 ///
 /// # Description
 ///
@@ -37,6 +57,13 @@ pub struct Emulator {
     input: Box<InputFn>,
     /// Output function used for emulating I/O port writes.
     output: Box<OutputFn>,
+    /// Memory-mapped devices, sorted by base address, registered via
+    /// [`Self::register_mmio`].
+    mmio_devices: Vec<MmioDevice>,
+    /// Sender half of the channel that [`crate::microvm::MicroVm::run`] drains to carry out
+    /// [`VmRequest`]s, shared with [`crate::http::HttpServer`] so that a guest write to
+    /// [`MicroVm::VMM_PORT`] and a request submitted by a gateway peer are served identically.
+    control_tx: mpsc::Sender<VmRequest>,
 }
 
 //==================================================================================================
@@ -53,6 +80,8 @@ impl Emulator {
     ///
     /// - `input`: Input function used for emulating I/O port reads.
     /// - `output`: Output function used for emulating I/O port writes.
+    /// - `control_tx`: Sender half of the channel that [`crate::microvm::MicroVm::run`] drains to
+    ///   carry out [`VmRequest`]s decoded from a guest write to [`MicroVm::VMM_PORT`].
     ///
     /// # Returns
     ///
@@ -63,15 +92,74 @@ impl Emulator {
         vmem: Rc<RefCell<VirtualMemory>>,
         input: Box<InputFn>,
         output: Box<OutputFn>,
+        control_tx: mpsc::Sender<VmRequest>,
     ) -> Result<Self> {
         trace!("new()");
         Ok(Self {
             vmem,
             input,
             output,
+            mmio_devices: Vec::new(),
+            control_tx,
         })
     }
 
+    ///
+    /// # Description
+    ///
+    /// Registers a memory-mapped device over `[base, base + len)` of the guest physical address
+    /// space.
+    ///
+    /// # Parameters
+    ///
+    /// - `base`: Guest physical address at which the device is mapped.
+    /// - `len`: Size, in bytes, of the device's range.
+    /// - `read_fn`: Closure invoked on a read from the device's range.
+    /// - `write_fn`: Closure invoked on a write to the device's range.
+    ///
+    /// # Returns
+    ///
+    /// Upon successful completion, this method returns empty. Otherwise, it returns an error.
+    ///
+    pub fn register_mmio(
+        &mut self,
+        base: u64,
+        len: u64,
+        read_fn: Box<MmioReadFn>,
+        write_fn: Box<MmioWriteFn>,
+    ) -> Result<()> {
+        trace!("register_mmio(): base={:#010x}, len={}", base, len);
+
+        let pos: usize = self.mmio_devices.partition_point(|device| device.base < base);
+        self.mmio_devices.insert(
+            pos,
+            MmioDevice {
+                base,
+                len,
+                read_fn,
+                write_fn,
+            },
+        );
+
+        Ok(())
+    }
+
+    /// Finds the index of the device, if any, whose range covers `addr`.
+    fn find_mmio(&self, addr: u64) -> Option<usize> {
+        let pos: usize = self.mmio_devices.partition_point(|device| device.base <= addr);
+        if pos == 0 {
+            return None;
+        }
+
+        let index: usize = pos - 1;
+        let device: &MmioDevice = &self.mmio_devices[index];
+        if addr < device.base + device.len {
+            Some(index)
+        } else {
+            None
+        }
+    }
+
     ///
     /// # Description
     ///
@@ -111,10 +199,21 @@ impl Emulator {
                 MicroVm::STDIN_PORT => {
                     (self.input)(&self.vmem, data, size)?;
                 },
-                // Write to the virtual machine monitor port.
+                // Write to the virtual machine monitor port: decode the word the guest wrote into
+                // a `VmRequest` and hand it off to `MicroVm::run`'s dispatcher, which carries it
+                // out using the same logic as a request from the control socket or the gateway.
                 MicroVm::VMM_PORT => {
-                    // TODO: check if data matches an expected command.
-                    return Ok(false);
+                    let request: VmRequest = VmRequest::from_u32(data)?;
+                    let exit_requested: bool = matches!(request, VmRequest::Exit);
+
+                    // The dispatcher thread may already have torn itself down (e.g. the virtual
+                    // machine is shutting down on its own); there is nothing useful to do with
+                    // that error here, since the guest has no way to read a reply back anyway.
+                    if let Err(e) = self.control_tx.send(request) {
+                        warn!("handle_pmio_access(): failed to forward vmm port request: {}", e);
+                    }
+
+                    return Ok(!exit_requested);
                 },
                 // Write to an I/O port that is not supported.
                 _ => {
@@ -133,4 +232,63 @@ impl Emulator {
 
         Ok(true)
     }
+
+    ///
+    /// # Description
+    ///
+    /// Emulates a memory-mapped I/O access.
+    ///
+    /// # Parameters
+    ///
+    /// - `exit_context`: Context in which the memory-mapped I/O access occurred.
+    ///
+    /// # Returns
+    ///
+    /// Upon successful completion, this method a boolean value that encodes wether the virtual
+    /// processor should be resumed (`true`) or not .(`false`). If an error is encountered, an error
+    /// is returned instead.
+    ///
+    pub fn handle_mmio_access(
+        &mut self,
+        exit_context: VirtualProcessorExitContext,
+    ) -> Result<bool> {
+        // Parse context.
+        match exit_context {
+            // Read from an MMIO region.
+            VirtualProcessorExitContext::MmioIn(addr, data) => match self.find_mmio(addr) {
+                Some(index) => {
+                    let device: &mut MmioDevice = &mut self.mmio_devices[index];
+                    let offset: u64 = addr - device.base;
+                    (device.read_fn)(offset, data)?;
+                },
+                None => {
+                    let reason: String =
+                        format!("read from unsupported mmio region (addr={:#010x})", addr);
+                    error!("handle_mmio_access(): {}", reason);
+                    anyhow::bail!(reason);
+                },
+            },
+            // Write to an MMIO region.
+            VirtualProcessorExitContext::MmioOut(addr, data, size) => match self.find_mmio(addr) {
+                Some(index) => {
+                    let device: &mut MmioDevice = &mut self.mmio_devices[index];
+                    let offset: u64 = addr - device.base;
+                    (device.write_fn)(offset, data, size)?;
+                },
+                None => {
+                    let reason: String =
+                        format!("write to unsupported mmio region (addr={:#010x})", addr);
+                    error!("handle_mmio_access(): {}", reason);
+                    anyhow::bail!(reason);
+                },
+            },
+            // Unexpected MMIO access.
+            _ => {
+                // This should never happen, as all MMIO accesses are emulated above.
+                unreachable!("unexpected mmio access");
+            },
+        }
+
+        Ok(true)
+    }
 }
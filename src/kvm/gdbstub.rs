@@ -0,0 +1,395 @@
+// Copyright(c) The Maintainers of Nanvix.
+// Licensed under the MIT License.
+
+//!
+//! # GDB Remote Serial Protocol Stub
+//!
+//! This module exposes a [`VirtualProcessor`] over the GDB Remote Serial Protocol (RSP), so that
+//! `gdb target remote` can attach to a running Nanvix guest. It speaks just enough of the protocol
+//! to read/write the general-purpose register file, read/write guest memory through a
+//! [`VirtualMemory`], single-step, continue, and set/clear software breakpoints.
+//!
+
+//==================================================================================================
+// Imports
+//==================================================================================================
+
+use crate::kvm::{
+    emulator::Emulator,
+    vcpu::{
+        register::VirtualProcessorRegister,
+        VirtualProcessor,
+        VirtualProcessorExitContext,
+        VirtualProcessorExitReason,
+    },
+    vmem::VirtualMemory,
+};
+use ::anyhow::Result;
+use ::std::{
+    io::{
+        Read,
+        Write,
+    },
+    net::{
+        SocketAddr,
+        TcpListener,
+        TcpStream,
+    },
+    sync::Mutex,
+};
+
+//==================================================================================================
+// Constants
+//==================================================================================================
+
+/// Vector of the breakpoint exception (#BP), raised by the `int3` opcode that software
+/// breakpoints are patched in with.
+const VECTOR_BP: u32 = 3;
+
+/// Opcode of the `int3` instruction that software breakpoints are patched in with.
+const INT3: u8 = 0xcc;
+
+//==================================================================================================
+// Structures
+//==================================================================================================
+
+/// A software breakpoint that is currently patched into guest memory.
+struct Breakpoint {
+    addr: u64,
+    original_byte: u8,
+}
+
+///
+/// # Description
+///
+/// A GDB Remote Serial Protocol server for a single [`VirtualProcessor`].
+///
+pub struct GdbStub {
+    listener: TcpListener,
+    breakpoints: Vec<Breakpoint>,
+}
+
+//==================================================================================================
+// Implementations
+//==================================================================================================
+
+impl GdbStub {
+    ///
+    /// # Description
+    ///
+    /// Binds a GDB stub to `addr`, ready to accept a single debugger connection.
+    ///
+    /// # Parameters
+    ///
+    /// - `addr`: Address to listen on.
+    ///
+    /// # Returns
+    ///
+    /// Upon successful completion, this method returns the new GDB stub. Otherwise, it returns an
+    /// error.
+    ///
+    pub fn bind(addr: SocketAddr) -> Result<Self> {
+        trace!("bind(): addr={}", addr);
+        Ok(Self {
+            listener: TcpListener::bind(addr)?,
+            breakpoints: Vec::new(),
+        })
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Blocks waiting for a debugger to connect, then serves it until it detaches. `vcpu` must not
+    /// have been run yet, so that it is paused on its reset `rip` for the first `?`/`g` queries;
+    /// `vcpu`, `vmem` and `emulator` are the same ones [`crate::microvm::MicroVm::run`] would
+    /// otherwise drive, so port-mapped and memory-mapped I/O keep working while the guest is
+    /// single-stepped or run to a breakpoint.
+    ///
+    /// # Parameters
+    ///
+    /// - `vcpu`: Virtual processor to debug.
+    /// - `vmem`: Virtual memory backing `vcpu`.
+    /// - `emulator`: Emulator used to service PMIO/MMIO exits while the guest runs.
+    ///
+    /// # Returns
+    ///
+    /// Upon successful completion, this method returns empty. Otherwise, it returns an error.
+    ///
+    pub fn serve(
+        &mut self,
+        vcpu: &mut VirtualProcessor,
+        vmem: &VirtualMemory,
+        emulator: &Mutex<Emulator>,
+    ) -> Result<()> {
+        let (mut stream, addr) = self.listener.accept()?;
+        trace!("serve(): debugger attached (addr={:?})", addr);
+
+        vcpu.set_guest_debug(false)?;
+
+        loop {
+            let packet: Vec<u8> = match Self::read_packet(&mut stream)? {
+                Some(packet) => packet,
+                // Connection closed without a `D`etach.
+                None => return Ok(()),
+            };
+
+            match self.dispatch(&mut stream, &packet, vcpu, vmem, emulator)? {
+                Dispatch::Continue => continue,
+                Dispatch::Detach => {
+                    self.remove_all_breakpoints(vmem)?;
+                    return Ok(());
+                },
+            }
+        }
+    }
+
+    fn dispatch(
+        &mut self,
+        stream: &mut TcpStream,
+        packet: &[u8],
+        vcpu: &mut VirtualProcessor,
+        vmem: &VirtualMemory,
+        emulator: &Mutex<Emulator>,
+    ) -> Result<Dispatch> {
+        let reply: String = match packet.first() {
+            Some(b'?') => "S05".to_string(),
+            Some(b'g') => self.read_registers(vcpu)?,
+            Some(b'G') => {
+                self.write_registers(vcpu, &packet[1..])?;
+                "OK".to_string()
+            },
+            Some(b'm') => self.read_memory(vmem, &packet[1..])?,
+            Some(b'M') => {
+                self.write_memory(vmem, &packet[1..])?;
+                "OK".to_string()
+            },
+            Some(b'Z') => {
+                self.set_breakpoint(vmem, &packet[1..])?;
+                "OK".to_string()
+            },
+            Some(b'z') => {
+                self.clear_breakpoint(vmem, &packet[1..])?;
+                "OK".to_string()
+            },
+            Some(b's') => {
+                self.resume(vcpu, emulator, true)?;
+                "S05".to_string()
+            },
+            Some(b'c') => {
+                self.resume(vcpu, emulator, false)?;
+                "S05".to_string()
+            },
+            Some(b'D') => {
+                Self::write_packet(stream, "OK")?;
+                return Ok(Dispatch::Detach);
+            },
+            // Unsupported command: an empty reply tells `gdb` the feature is not implemented.
+            _ => String::new(),
+        };
+
+        Self::write_packet(stream, &reply)?;
+        Ok(Dispatch::Continue)
+    }
+
+    /// Resumes the vCPU, either for a single instruction (`step`) or until the next breakpoint or
+    /// single-step trap (`continue`), servicing any PMIO/MMIO exit in between through `emulator`.
+    fn resume(&self, vcpu: &mut VirtualProcessor, emulator: &Mutex<Emulator>, step: bool) -> Result<()> {
+        vcpu.set_guest_debug(step)?;
+
+        loop {
+            let exit: VirtualProcessorExitContext = vcpu.run()?;
+            match exit.reason() {
+                VirtualProcessorExitReason::Debug => {
+                    if let VirtualProcessorExitContext::Debug(vector) = exit {
+                        if vector == VECTOR_BP {
+                            self.rewind_past_breakpoint(vcpu)?;
+                        }
+                    }
+                    return Ok(());
+                },
+                // The guest halted or shut down; the monitor's own run loop (not this debugger) is
+                // responsible for tearing the virtual machine down. Stop resuming so the caller
+                // observes it instead of spinning on a dead vCPU.
+                VirtualProcessorExitReason::Halt | VirtualProcessorExitReason::Shutdown => {
+                    return Ok(());
+                },
+                VirtualProcessorExitReason::PmioAccess => {
+                    if !emulator.lock().unwrap().handle_pmio_access(exit)? {
+                        return Ok(());
+                    }
+                },
+                VirtualProcessorExitReason::MmioAccess => {
+                    if !emulator.lock().unwrap().handle_mmio_access(exit)? {
+                        return Ok(());
+                    }
+                },
+                VirtualProcessorExitReason::Interrupted | VirtualProcessorExitReason::Unknown => {},
+                VirtualProcessorExitReason::FailEntry | VirtualProcessorExitReason::InternalError => {
+                    anyhow::bail!("vcpu failed while under debugger control");
+                },
+            }
+        }
+    }
+
+    /// After an `int3` trap, `rip` points one byte past the breakpoint; rewind it so the guest
+    /// resumes on the original instruction.
+    fn rewind_past_breakpoint(&self, vcpu: &VirtualProcessor) -> Result<()> {
+        let mut regs = vcpu.get_regs()?;
+        regs.rip -= 1;
+        vcpu.set_regs(&regs)
+    }
+
+    fn read_registers(&self, vcpu: &VirtualProcessor) -> Result<String> {
+        let regs = vcpu.get_regs()?;
+        let mut hex: String = String::new();
+        for register in &VirtualProcessorRegister::WIRE_ORDER {
+            hex.push_str(&encode_hex(&register.read(&regs).to_le_bytes()));
+        }
+        Ok(hex)
+    }
+
+    fn write_registers(&self, vcpu: &VirtualProcessor, hex: &[u8]) -> Result<()> {
+        let bytes: Vec<u8> = decode_hex(std::str::from_utf8(hex)?)?;
+
+        let mut regs = vcpu.get_regs()?;
+        for (i, register) in VirtualProcessorRegister::WIRE_ORDER.iter().enumerate() {
+            if let Some(word) = bytes.get(i * 8..i * 8 + 8) {
+                register.write(&mut regs, u64::from_le_bytes(word.try_into()?));
+            }
+        }
+        vcpu.set_regs(&regs)
+    }
+
+    fn read_memory(&self, vmem: &VirtualMemory, args: &[u8]) -> Result<String> {
+        let (addr, len) = Self::parse_addr_len(args)?;
+        let mut data: Vec<u8> = vec![0; len];
+        vmem.read_bytes(addr, &mut data)?;
+        Ok(encode_hex(&data))
+    }
+
+    fn write_memory(&self, vmem: &VirtualMemory, args: &[u8]) -> Result<()> {
+        let args: &str = std::str::from_utf8(args)?;
+        let (header, data) = args.split_once(':').ok_or_else(|| anyhow::anyhow!("malformed M packet"))?;
+        let (addr, len) = Self::parse_addr_len(header.as_bytes())?;
+        let bytes: Vec<u8> = decode_hex(data)?;
+        if bytes.len() != len {
+            anyhow::bail!("M packet length mismatch");
+        }
+        vmem.write_bytes(addr, &bytes)
+    }
+
+    /// `Z`/`z` packets are `<type>,<addr>,<kind>`. Type `0` is a software breakpoint; types `1`-`4`
+    /// are hardware watch/breakpoints, which this stub does not support and rejects.
+    fn set_breakpoint(&mut self, vmem: &VirtualMemory, args: &[u8]) -> Result<()> {
+        let addr: u64 = Self::parse_breakpoint(args)?;
+        if self.breakpoints.iter().any(|bp| bp.addr == addr) {
+            return Ok(());
+        }
+        let mut original_byte: [u8; 1] = [0];
+        vmem.read_bytes(addr, &mut original_byte)?;
+        vmem.write_bytes(addr, &[INT3])?;
+        self.breakpoints.push(Breakpoint {
+            addr,
+            original_byte: original_byte[0],
+        });
+        Ok(())
+    }
+
+    fn clear_breakpoint(&mut self, vmem: &VirtualMemory, args: &[u8]) -> Result<()> {
+        let addr: u64 = Self::parse_breakpoint(args)?;
+        if let Some(i) = self.breakpoints.iter().position(|bp| bp.addr == addr) {
+            let bp: Breakpoint = self.breakpoints.remove(i);
+            vmem.write_bytes(bp.addr, &[bp.original_byte])?;
+        }
+        Ok(())
+    }
+
+    fn remove_all_breakpoints(&mut self, vmem: &VirtualMemory) -> Result<()> {
+        for bp in self.breakpoints.drain(..) {
+            vmem.write_bytes(bp.addr, &[bp.original_byte])?;
+        }
+        Ok(())
+    }
+
+    fn parse_breakpoint(args: &[u8]) -> Result<u64> {
+        let args: &str = std::str::from_utf8(args)?;
+        let mut parts = args.splitn(3, ',');
+        let kind: &str = parts.next().ok_or_else(|| anyhow::anyhow!("malformed Z/z packet"))?;
+        if kind != "0" {
+            anyhow::bail!("unsupported breakpoint kind (kind={})", kind);
+        }
+        let addr: &str = parts.next().ok_or_else(|| anyhow::anyhow!("malformed Z/z packet"))?;
+        Ok(u64::from_str_radix(addr, 16)?)
+    }
+
+    fn parse_addr_len(args: &[u8]) -> Result<(u64, usize)> {
+        let args: &str = std::str::from_utf8(args)?;
+        let (addr, len) = args.split_once(',').ok_or_else(|| anyhow::anyhow!("malformed m/M packet"))?;
+        Ok((u64::from_str_radix(addr, 16)?, usize::from_str_radix(len, 16)?))
+    }
+
+    /// Reads one `$<data>#<checksum>` packet, acknowledging it with `+`. Returns `None` if the
+    /// peer closed the connection.
+    fn read_packet(stream: &mut TcpStream) -> Result<Option<Vec<u8>>> {
+        let mut byte: [u8; 1] = [0];
+        loop {
+            if stream.read(&mut byte)? == 0 {
+                return Ok(None);
+            }
+            if byte[0] == b'$' {
+                break;
+            }
+            // Ignore ack/nack bytes and anything else preceding the next packet.
+        }
+
+        let mut data: Vec<u8> = Vec::new();
+        loop {
+            if stream.read(&mut byte)? == 0 {
+                anyhow::bail!("connection closed mid-packet");
+            }
+            if byte[0] == b'#' {
+                break;
+            }
+            data.push(byte[0]);
+        }
+        // Consume the two-byte checksum; we trust the kernel-level TCP stream's own integrity.
+        let mut checksum: [u8; 2] = [0; 2];
+        stream.read_exact(&mut checksum)?;
+
+        stream.write_all(b"+")?;
+        Ok(Some(data))
+    }
+
+    /// Writes `payload` out as a `$<payload>#<checksum>` packet, where the checksum is the sum of
+    /// the payload bytes modulo 256, in two hex digits.
+    fn write_packet(stream: &mut TcpStream, payload: &str) -> Result<()> {
+        let checksum: u8 = payload.bytes().fold(0u8, |acc, b| acc.wrapping_add(b));
+        write!(stream, "${}#{:02x}", payload, checksum)?;
+        stream.flush()?;
+        Ok(())
+    }
+}
+
+/// Outcome of dispatching one packet, telling [`GdbStub::serve`] whether to keep serving the
+/// current connection.
+enum Dispatch {
+    Continue,
+    Detach,
+}
+
+/// Encodes `bytes` as lowercase hex, the wire format that the RSP uses for register and memory
+/// contents.
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Decodes a hex string produced by [`encode_hex`] (or sent by `gdb`) back into bytes.
+fn decode_hex(hex: &str) -> Result<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        anyhow::bail!("odd-length hex string");
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| Ok(u8::from_str_radix(&hex[i..i + 2], 16)?))
+        .collect()
+}
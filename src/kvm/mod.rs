@@ -7,7 +7,9 @@
 //! This module provides the backend implementation of MicroVM for Linux KVM.
 //!
 
+pub mod control;
 pub mod emulator;
+pub mod gdbstub;
 pub mod partition;
 pub mod vcpu;
 pub mod vmem;
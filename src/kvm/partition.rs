@@ -7,9 +7,13 @@
 
 use ::anyhow::Result;
 use ::kvm_ioctls::{
+    IoEventAddress,
     Kvm,
+    NoDatamatch,
     VmFd,
 };
+use ::std::sync::Mutex;
+use ::vmm_sys_util::eventfd::EventFd;
 
 //==================================================================================================
 // Structures
@@ -25,6 +29,10 @@ pub struct VirtualPartition {
     _kvm: Kvm,
     // Handle to the virtual machine.
     vm: VmFd,
+    // Event file descriptors backing every ioeventfd/irqfd registered via
+    // [`Self::register_ioevent`]/[`Self::register_irqfd`], kept alive for as long as the partition
+    // exists so that KVM does not tear the registration down from underneath the caller.
+    event_fds: Mutex<Vec<EventFd>>,
 }
 
 //==================================================================================================
@@ -55,7 +63,15 @@ impl VirtualPartition {
             anyhow::bail!(reason);
         }
 
-        Ok(Self { _kvm: kvm, vm })
+        // Create the in-kernel interrupt controller. Required before `Self::register_irqfd` can
+        // succeed: `KVM_IRQFD` routes through the irqchip, which does not exist until this runs.
+        vm.create_irq_chip()?;
+
+        Ok(Self {
+            _kvm: kvm,
+            vm,
+            event_fds: Mutex::new(Vec::new()),
+        })
     }
 
     ///
@@ -66,4 +82,108 @@ impl VirtualPartition {
     pub fn vm(&self) -> &VmFd {
         &self.vm
     }
+
+    ///
+    /// # Description
+    ///
+    /// Registers an ioeventfd that KVM signals whenever the guest writes to `addr`, letting a
+    /// device model be notified of an access without the vCPU thread itself dispatching it.
+    ///
+    /// # Parameters
+    ///
+    /// - `addr`: Guest address to watch, interpreted as a port-mapped or memory-mapped address
+    ///   depending on `pio`.
+    /// - `pio`: Whether `addr` is a port-mapped I/O address, as opposed to a memory-mapped one.
+    /// - `datamatch`: If set, only writes of this exact value trigger the eventfd; otherwise every
+    ///   write to `addr` does.
+    ///
+    /// # Returns
+    ///
+    /// Upon successful completion, this method returns a handle to the eventfd KVM signals on a
+    /// matching write, which a device model can poll or wait on. Otherwise, it returns an error.
+    ///
+    pub fn register_ioevent(&self, addr: u64, pio: bool, datamatch: Option<u64>) -> Result<EventFd> {
+        trace!(
+            "register_ioevent(): addr={:#018x}, pio={}, datamatch={:?}",
+            addr,
+            pio,
+            datamatch
+        );
+
+        let event_fd: EventFd = EventFd::new(::libc::EFD_NONBLOCK)?;
+        let addr: IoEventAddress =
+            if pio { IoEventAddress::Pio(addr as u16) } else { IoEventAddress::Mmio(addr) };
+
+        match datamatch {
+            Some(datamatch) => self.vm.register_ioevent(&event_fd, &addr, datamatch)?,
+            None => self.vm.register_ioevent(&event_fd, &addr, NoDatamatch)?,
+        }
+
+        // Hand the caller a duplicate of the eventfd to wait on: the original stays owned by
+        // `self.event_fds` for the lifetime of the partition, since KVM's registration is tied to
+        // the open file description rather than the fd number alone.
+        let handle: EventFd = event_fd.try_clone()?;
+        self.event_fds.lock().unwrap().push(event_fd);
+
+        Ok(handle)
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Registers an irqfd that, when signaled, raises the interrupt identified by `gsi` on behalf
+    /// of a device model, without the vCPU thread itself having to inject it.
+    ///
+    /// # Parameters
+    ///
+    /// - `gsi`: Global system interrupt to raise when the irqfd is signaled.
+    ///
+    /// # Returns
+    ///
+    /// Upon successful completion, this method returns a handle to the eventfd that, when
+    /// written to, raises `gsi` without the vCPU thread itself injecting it. Otherwise, it
+    /// returns an error.
+    ///
+    pub fn register_irqfd(&self, gsi: u32) -> Result<EventFd> {
+        trace!("register_irqfd(): gsi={}", gsi);
+
+        let event_fd: EventFd = EventFd::new(::libc::EFD_NONBLOCK)?;
+        self.vm.register_irqfd(&event_fd, gsi)?;
+
+        // Hand the caller a duplicate of the eventfd to signal: the original stays owned by
+        // `self.event_fds` for the lifetime of the partition, since KVM's registration is tied to
+        // the open file description rather than the fd number alone.
+        let handle: EventFd = event_fd.try_clone()?;
+        self.event_fds.lock().unwrap().push(event_fd);
+
+        Ok(handle)
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Registers an already-created eventfd as an irqfd that, when signaled, raises the interrupt
+    /// identified by `gsi`. Unlike [`Self::register_irqfd`], this does not create the eventfd
+    /// itself: use this when the eventfd must be shared with a writer that exists before this
+    /// partition does, e.g. the I/O thread `main::main` spawns ahead of the [`crate::microvm::MicroVm`]
+    /// that owns this partition.
+    ///
+    /// # Parameters
+    ///
+    /// - `event_fd`: Eventfd to register. Ownership is kept by this partition for as long as it
+    ///   exists, the same as the eventfds [`Self::register_irqfd`] creates itself.
+    /// - `gsi`: Global system interrupt to raise when the irqfd is signaled.
+    ///
+    /// # Returns
+    ///
+    /// Upon successful completion, this method returns empty. Otherwise, it returns an error.
+    ///
+    pub fn register_irqfd_handle(&self, event_fd: EventFd, gsi: u32) -> Result<()> {
+        trace!("register_irqfd_handle(): gsi={}", gsi);
+
+        self.vm.register_irqfd(&event_fd, gsi)?;
+        self.event_fds.lock().unwrap().push(event_fd);
+
+        Ok(())
+    }
 }
@@ -13,8 +13,21 @@
 pub enum VirtualProcessorExitReason {
     /// Port-mapped I/O access.
     PmioAccess,
+    /// Memory-mapped I/O access.
+    MmioAccess,
     /// Halt virtual processor.
     Halt,
+    /// Virtual processor was shut down (e.g. triple fault).
+    Shutdown,
+    /// Virtual processor failed to enter guest mode.
+    FailEntry,
+    /// Internal error reported by the hypervisor.
+    InternalError,
+    /// Run was interrupted by a signal, without the guest having exited on its own.
+    Interrupted,
+    /// Guest-debug event raised by [`crate::kvm::vcpu::VirtualProcessor::set_guest_debug`]: a
+    /// software breakpoint (`int3`) or a single-step trap.
+    Debug,
     /// Unknown.
     Unknown,
 }
@@ -29,8 +42,24 @@ pub enum VirtualProcessorExitContext<'a> {
     PmioIn(u16, &'a mut [u8]),
     /// Port-mapped I/O output.
     PmioOut(u16, u32, usize),
+    /// Memory-mapped I/O input, at the given guest physical address.
+    MmioIn(u64, &'a mut [u8]),
+    /// Memory-mapped I/O output, at the given guest physical address.
+    MmioOut(u64, u32, usize),
     /// Halt virtual processor.
     Halt,
+    /// Virtual processor was shut down (e.g. triple fault).
+    Shutdown,
+    /// Virtual processor failed to enter guest mode, with the hardware-specific failure reason and
+    /// the identifier of the underlying host CPU.
+    FailEntry(u64, u32),
+    /// Internal error reported by the hypervisor.
+    InternalError,
+    /// Run was interrupted by a signal, without the guest having exited on its own.
+    Interrupted,
+    /// Guest-debug event, carrying the vector of the exception that triggered it (`1` for #DB,
+    /// i.e. a single-step trap, `3` for #BP, i.e. a software breakpoint).
+    Debug(u32),
     /// Unknown.
     Unknown,
 }
@@ -56,8 +85,23 @@ impl<'a> VirtualProcessorExitContext<'_> {
             | VirtualProcessorExitContext::PmioOut(_, _, _) => {
                 &VirtualProcessorExitReason::PmioAccess
             },
+            // Memory-mapped I/O access.
+            VirtualProcessorExitContext::MmioIn(_, _)
+            | VirtualProcessorExitContext::MmioOut(_, _, _) => {
+                &VirtualProcessorExitReason::MmioAccess
+            },
             // Halt virtual processor..
             VirtualProcessorExitContext::Halt => &VirtualProcessorExitReason::Halt,
+            // Virtual processor was shut down.
+            VirtualProcessorExitContext::Shutdown => &VirtualProcessorExitReason::Shutdown,
+            // Virtual processor failed to enter guest mode.
+            VirtualProcessorExitContext::FailEntry(_, _) => &VirtualProcessorExitReason::FailEntry,
+            // Internal error reported by the hypervisor.
+            VirtualProcessorExitContext::InternalError => &VirtualProcessorExitReason::InternalError,
+            // Run was interrupted by a signal.
+            VirtualProcessorExitContext::Interrupted => &VirtualProcessorExitReason::Interrupted,
+            // Guest-debug event.
+            VirtualProcessorExitContext::Debug(_) => &VirtualProcessorExitReason::Debug,
             // Unknown.
             VirtualProcessorExitContext::Unknown => &VirtualProcessorExitReason::Unknown,
         }
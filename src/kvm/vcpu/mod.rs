@@ -0,0 +1,24 @@
+// Copyright(c) The Maintainers of Nanvix.
+// Licensed under the MIT License.
+
+//==================================================================================================
+// Modules
+//==================================================================================================
+
+pub mod register;
+
+mod exit;
+mod vcpu;
+
+//==================================================================================================
+// Exports
+//==================================================================================================
+
+pub use exit::{
+    VirtualProcessorExitContext,
+    VirtualProcessorExitReason,
+};
+pub use vcpu::{
+    VirtualProcessor,
+    VirtualProcessorState,
+};
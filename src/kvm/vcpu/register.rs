@@ -1,12 +1,21 @@
 // Copyright(c) The Maintainers of Nanvix.
 // Licensed under the MIT License.
 
+//==================================================================================================
+// Imports
+//==================================================================================================
+
+use ::kvm_bindings::kvm_regs;
+
+//==================================================================================================
+// Structures
+//==================================================================================================
+
 ///
 /// # Description
 ///
 /// Virtual processor registers.
 ///
-#[allow(unused)]
 pub enum VirtualProcessorRegister {
     Rax,
     Rbx,
@@ -19,3 +28,56 @@ pub enum VirtualProcessorRegister {
     Rip,
     Rflags,
 }
+
+//==================================================================================================
+// Implementations
+//==================================================================================================
+
+impl VirtualProcessorRegister {
+    /// Every register in [`Self`], in the fixed order that [`crate::kvm::gdbstub::GdbStub`]
+    /// serializes `g`/`G` packets in.
+    pub const WIRE_ORDER: [VirtualProcessorRegister; 10] = [
+        VirtualProcessorRegister::Rax,
+        VirtualProcessorRegister::Rbx,
+        VirtualProcessorRegister::Rcx,
+        VirtualProcessorRegister::Rdx,
+        VirtualProcessorRegister::Rsi,
+        VirtualProcessorRegister::Rdi,
+        VirtualProcessorRegister::Rbp,
+        VirtualProcessorRegister::Rsp,
+        VirtualProcessorRegister::Rip,
+        VirtualProcessorRegister::Rflags,
+    ];
+
+    /// Reads this register out of `regs`, as returned by `VcpuFd::get_regs`.
+    pub fn read(&self, regs: &kvm_regs) -> u64 {
+        match self {
+            VirtualProcessorRegister::Rax => regs.rax,
+            VirtualProcessorRegister::Rbx => regs.rbx,
+            VirtualProcessorRegister::Rcx => regs.rcx,
+            VirtualProcessorRegister::Rdx => regs.rdx,
+            VirtualProcessorRegister::Rsi => regs.rsi,
+            VirtualProcessorRegister::Rdi => regs.rdi,
+            VirtualProcessorRegister::Rbp => regs.rbp,
+            VirtualProcessorRegister::Rsp => regs.rsp,
+            VirtualProcessorRegister::Rip => regs.rip,
+            VirtualProcessorRegister::Rflags => regs.rflags,
+        }
+    }
+
+    /// Writes `value` into this register of `regs`, ready for `VcpuFd::set_regs`.
+    pub fn write(&self, regs: &mut kvm_regs, value: u64) {
+        match self {
+            VirtualProcessorRegister::Rax => regs.rax = value,
+            VirtualProcessorRegister::Rbx => regs.rbx = value,
+            VirtualProcessorRegister::Rcx => regs.rcx = value,
+            VirtualProcessorRegister::Rdx => regs.rdx = value,
+            VirtualProcessorRegister::Rsi => regs.rsi = value,
+            VirtualProcessorRegister::Rdi => regs.rdi = value,
+            VirtualProcessorRegister::Rbp => regs.rbp = value,
+            VirtualProcessorRegister::Rsp => regs.rsp = value,
+            VirtualProcessorRegister::Rip => regs.rip = value,
+            VirtualProcessorRegister::Rflags => regs.rflags = value,
+        }
+    }
+}
@@ -11,44 +11,66 @@ use crate::kvm::{
 };
 use ::anyhow::Result;
 use ::kvm_bindings::{
+    kvm_guest_debug,
+    kvm_mp_state,
     kvm_regs,
     kvm_sregs,
+    KVM_GUESTDBG_ENABLE,
+    KVM_GUESTDBG_SINGLESTEP,
+    KVM_GUESTDBG_USE_SW_BP,
+    KVM_MP_STATE_RUNNABLE,
+    KVM_MP_STATE_UNINITIALIZED,
 };
 use ::kvm_ioctls::{
     VcpuExit,
     VcpuFd,
 };
-use ::std::{
-    cell::RefCell,
-    rc::Rc,
-};
+use ::std::sync::Arc;
 
 //==================================================================================================
 // Structures
 //==================================================================================================
 
+///
+/// # Description
+///
+/// A snapshot of a virtual processor's register state, as returned by
+/// [`VirtualProcessor::save_state`] and consumed by [`VirtualProcessor::load_state`].
+///
+pub struct VirtualProcessorState {
+    /// General purpose registers.
+    pub regs: kvm_regs,
+    /// System registers.
+    pub sregs: kvm_sregs,
+}
+
 ///
 /// # Description
 ///
 /// A structure that represents a virtual processor.
 ///
 pub struct VirtualProcessor {
-    // Handle to underlying virtual partition.
-    _partition: Rc<RefCell<VirtualPartition>>,
+    // Handle to underlying virtual partition. Shared with `VirtualMemory` and with every other
+    // `VirtualProcessor` in the same `MicroVm`, one per host thread, see `MicroVm::run`.
+    _partition: Arc<VirtualPartition>,
     // Handle to underlying virtual processor.
     fd: VcpuFd,
+    // Identifier this virtual processor was created with, which KVM also uses as its APIC ID on
+    // x86. Id 0 is the bootstrap processor; see `Self::reset`.
+    id: u64,
     // Processor state.
     online: bool,
 }
 
 impl VirtualProcessor {
-    pub fn new(partition: Rc<RefCell<VirtualPartition>>, id: u64) -> Result<Self> {
+    pub fn new(partition: Arc<VirtualPartition>, id: u64) -> Result<Self> {
         trace!("new(): id={}", id);
         crate::timer!("vcpu_creation");
-        let fd: VcpuFd = partition.borrow().vm().create_vcpu(id)?;
+        let fd: VcpuFd = partition.vm().create_vcpu(id)?;
         Ok(Self {
             _partition: partition,
             fd,
+            id,
             online: false,
         })
     }
@@ -63,13 +85,21 @@ impl VirtualProcessor {
     /// - `rip`: Value to the the `rip` register.
     /// - `rax`: Value to set the `rax` register.
     /// - `rbx`: Value to set the `rbx` register.
+    /// - `rdi`: Value to set the `rdi` register.
     ///
     /// # Returns
     ///
     /// Upon successful completion, this method returns empty. Otherwise, it returns an error.
     ///
-    pub fn reset(&mut self, rip: u64, rax: u64, rbx: u64) -> Result<()> {
-        trace!("reset(): rip={:#010x}, rax={:#010x}, rbx={:#010x}", rip, rax, rbx);
+    pub fn reset(&mut self, rip: u64, rax: u64, rbx: u64, rdi: u64) -> Result<()> {
+        trace!(
+            "reset(): id={}, rip={:#010x}, rax={:#010x}, rbx={:#010x}, rdi={:#010x}",
+            self.id,
+            rip,
+            rax,
+            rbx,
+            rdi
+        );
         crate::timer!("vcpu_reset");
 
         // Reset system registers.
@@ -83,11 +113,19 @@ impl VirtualProcessor {
         vcpu_regs.rip = rip;
         vcpu_regs.rax = rax;
         vcpu_regs.rbx = rbx;
+        vcpu_regs.rdi = rdi;
         vcpu_regs.rflags = 2;
         self.fd.set_regs(&vcpu_regs)?;
 
-        // Processor is now online.
-        self.online = true;
+        // Only the bootstrap processor (id 0) starts running at `rip` immediately, matching a
+        // real platform. Application processors are parked in the uninitialized state a startup
+        // IPI would otherwise move them out of; since this microvm does not emulate a local APIC
+        // to deliver one, they are left offline below rather than spun up in lockstep with the
+        // BSP over the same guest memory.
+        let mp_state: u32 = if self.id == 0 { KVM_MP_STATE_RUNNABLE } else { KVM_MP_STATE_UNINITIALIZED };
+        self.fd.set_mp_state(kvm_mp_state { mp_state })?;
+
+        self.online = self.id == 0;
 
         Ok(())
     }
@@ -115,6 +153,127 @@ impl VirtualProcessor {
         self.online
     }
 
+    ///
+    /// # Description
+    ///
+    /// Captures the current register state of the virtual processor, for later use with
+    /// [`Self::load_state`].
+    ///
+    /// # Returns
+    ///
+    /// Upon successful completion, this method returns the captured state. Otherwise, it returns
+    /// an error.
+    ///
+    pub fn save_state(&self) -> Result<VirtualProcessorState> {
+        trace!("save_state()");
+        Ok(VirtualProcessorState {
+            regs: self.fd.get_regs()?,
+            sregs: self.fd.get_sregs()?,
+        })
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Restores the register state of the virtual processor from a previous call to
+    /// [`Self::save_state`].
+    ///
+    /// # Parameters
+    ///
+    /// - `state`: State to restore.
+    ///
+    /// # Returns
+    ///
+    /// Upon successful completion, this method returns empty. Otherwise, it returns an error.
+    ///
+    pub fn load_state(&mut self, state: &VirtualProcessorState) -> Result<()> {
+        trace!("load_state()");
+        self.fd.set_sregs(&state.sregs)?;
+        self.fd.set_regs(&state.regs)?;
+        Ok(())
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Reads the general-purpose register file of the virtual processor, for example to service a
+    /// `g` packet in [`crate::kvm::gdbstub::GdbStub`].
+    ///
+    /// # Returns
+    ///
+    /// Upon successful completion, this method returns the register file. Otherwise, it returns an
+    /// error.
+    ///
+    pub fn get_regs(&self) -> Result<kvm_regs> {
+        Ok(self.fd.get_regs()?)
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Writes the general-purpose register file of the virtual processor, for example to service a
+    /// `G` packet in [`crate::kvm::gdbstub::GdbStub`].
+    ///
+    /// # Parameters
+    ///
+    /// - `regs`: Register file to write.
+    ///
+    /// # Returns
+    ///
+    /// Upon successful completion, this method returns empty. Otherwise, it returns an error.
+    ///
+    pub fn set_regs(&self, regs: &kvm_regs) -> Result<()> {
+        self.fd.set_regs(regs)?;
+        Ok(())
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Arms or disarms guest-debug on the virtual processor: once armed, software breakpoints
+    /// (`int3`) trap back to user-level as [`VirtualProcessorExitContext::Debug`] instead of being
+    /// injected into the guest, and [`Self::run`] single-steps one instruction at a time when
+    /// `single_step` is set.
+    ///
+    /// # Parameters
+    ///
+    /// - `single_step`: Whether the virtual processor should trap after every instruction.
+    ///
+    /// # Returns
+    ///
+    /// Upon successful completion, this method returns empty. Otherwise, it returns an error.
+    ///
+    pub fn set_guest_debug(&self, single_step: bool) -> Result<()> {
+        trace!("set_guest_debug(): single_step={}", single_step);
+
+        let mut control: u32 = KVM_GUESTDBG_ENABLE | KVM_GUESTDBG_USE_SW_BP;
+        if single_step {
+            control |= KVM_GUESTDBG_SINGLESTEP;
+        }
+
+        let debug: kvm_guest_debug = kvm_guest_debug {
+            control,
+            ..Default::default()
+        };
+        self.fd.set_guest_debug(&debug)?;
+        Ok(())
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Forces the online/offline state of the virtual processor, bypassing [`Self::reset`] and
+    /// [`Self::poweroff`]. Used to restore the state that was in effect when a snapshot was taken.
+    ///
+    /// # Parameters
+    ///
+    /// - `online`: Whether the virtual processor should be marked online.
+    ///
+    pub fn set_online(&mut self, online: bool) {
+        trace!("set_online(): online={}", online);
+        self.online = online;
+    }
+
     ///
     /// # Description
     ///
@@ -129,7 +288,17 @@ impl VirtualProcessor {
     pub fn run(&mut self) -> Result<VirtualProcessorExitContext> {
         crate::timer!("vcpu_run");
         // Run the virtual processor and parse exit reason.
-        match self.fd.run()? {
+        let exit: VcpuExit = match self.fd.run() {
+            Ok(exit) => exit,
+            // Unblocked by the stop signal that `MicroVm::run` sends to every vCPU thread when
+            // one of them powers off, see `control::STOP_SIGNAL`. This is not a failure: let the
+            // caller re-check whether the virtual machine is still supposed to be running.
+            Err(e) if e.errno() == libc::EINTR => {
+                return Ok(VirtualProcessorExitContext::Interrupted)
+            },
+            Err(e) => return Err(e.into()),
+        };
+        match exit {
             // Read from an I/O port.
             VcpuExit::IoIn(port, data) => Ok(VirtualProcessorExitContext::PmioIn(port, data)),
             // Write to an I/O port.
@@ -141,16 +310,14 @@ impl VirtualProcessor {
                 Ok(VirtualProcessorExitContext::PmioOut(port, value, data.len()))
             },
             // Read from an MMIO region.
-            VcpuExit::MmioRead(addr, data) => {
-                // TODO: handle MMIO read.
-                warn!("run(): mmio read (addr={:#010x}, data.len={})", addr, data.len());
-                Ok(VirtualProcessorExitContext::Unknown)
-            },
+            VcpuExit::MmioRead(addr, data) => Ok(VirtualProcessorExitContext::MmioIn(addr, data)),
             // Write to an MMIO region.
             VcpuExit::MmioWrite(addr, data) => {
-                // TODO: handle MMIO write.
-                warn!("run(): mmio write (addr={:#010x}, data.len={})", addr, data.len());
-                Ok(VirtualProcessorExitContext::Unknown)
+                let mut value: u32 = 0;
+                for (i, b) in data.iter().enumerate() {
+                    value |= (*b as u32) << (i * 8);
+                }
+                Ok(VirtualProcessorExitContext::MmioOut(addr, value, data.len()))
             },
             // Exception occurred.
             VcpuExit::Exception => {
@@ -164,29 +331,23 @@ impl VirtualProcessor {
                 warn!("run(): hypercall");
                 Ok(VirtualProcessorExitContext::Unknown)
             },
-            // Debugging event occurred.
-            VcpuExit::Debug(_) => {
-                // TODO: handle debug.
-                warn!("run(): debug");
-                Ok(VirtualProcessorExitContext::Unknown)
-            },
+            // Software breakpoint or single-step trap, only raised while guest-debug is armed via
+            // `Self::set_guest_debug`.
+            VcpuExit::Debug(debug) => Ok(VirtualProcessorExitContext::Debug(debug.exception)),
             // Halt the virtual processor.
             VcpuExit::Hlt => {
-                // TODO: handle halt.
                 warn!("run(): halt");
-                Ok(VirtualProcessorExitContext::Unknown)
+                Ok(VirtualProcessorExitContext::Halt)
             },
             // Shutdown the virtual processor.
             VcpuExit::Shutdown => {
-                // TODO: handle shutdown.
                 warn!("run(): shutdown");
-                Ok(VirtualProcessorExitContext::Unknown)
+                Ok(VirtualProcessorExitContext::Shutdown)
             },
             // Fail to run the virtual processor.
             VcpuExit::FailEntry(reason, cpud) => {
-                // TODO: handle fail entry.
                 warn!("run(): fail entry (reason={:?}, cpud={})", reason, cpud);
-                Ok(VirtualProcessorExitContext::Unknown)
+                Ok(VirtualProcessorExitContext::FailEntry(reason, cpud))
             },
             // Non-maskable interrupt occurred.
             VcpuExit::Nmi => {
@@ -196,9 +357,8 @@ impl VirtualProcessor {
             },
             // Internal error occurred.
             VcpuExit::InternalError => {
-                // TODO: handle internal error.
                 warn!("run(): internal error");
-                Ok(VirtualProcessorExitContext::Unknown)
+                Ok(VirtualProcessorExitContext::InternalError)
             },
             // Unsupported exit reason.
             VcpuExit::Unsupported(reason) => {
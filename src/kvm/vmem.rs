@@ -6,41 +6,82 @@
 //==================================================================================================
 
 use crate::{
+    bzimage,
     config,
     elf,
     kvm::partition::VirtualPartition,
+    netboot,
     pal::FileMapping,
 };
 use ::anyhow::Result;
-use ::kvm_bindings::kvm_userspace_memory_region;
+use ::kvm_bindings::{
+    kvm_userspace_memory_region,
+    KVM_MEM_LOG_DIRTY_PAGES,
+    KVM_MEM_READONLY,
+};
 use ::std::{
-    cell::RefCell,
+    io::{
+        Read,
+        Write,
+    },
+    net::SocketAddr,
     ptr::{
         self,
     },
-    rc::Rc,
+    sync::Arc,
 };
 
 //==================================================================================================
 // Structures
 //==================================================================================================
 
+/// Size, in bytes, of a guest page, as tracked by the dirty bitmap returned by
+/// [`VirtualMemory::get_dirty_log`].
+const PAGE_SIZE: usize = 4096;
+
+///
+/// # Description
+///
+/// A guest-physical memory region, backed by its own host mapping. Registered with
+/// [`VirtualMemory::add_region`].
+///
+struct Region {
+    /// Slot the region was registered under.
+    slot: u32,
+    /// Guest physical address at which the region starts.
+    guest_phys_addr: u64,
+    /// Host mapping backing the region.
+    ptr: *mut u8,
+    /// Size, in bytes, of the region.
+    size: usize,
+    /// Whether guest writes to the region are rejected by [`VirtualMemory::write_bytes`].
+    read_only: bool,
+}
+
 ///
 /// # Description
 ///
 /// A structure that represents the memory of a virtual machine.
 ///
+/// Memory is organized as a table of [`Region`]s, each mapped at its own guest physical address
+/// (see [`Self::add_region`]), rather than a single flat block. [`Self::load_kernel`],
+/// [`Self::load_initrd`], [`Self::load_cmdline`] and the snapshot helpers ([`Self::dump`],
+/// [`Self::get_dirty_log`], ...) all operate on the first region added, i.e. the main RAM region
+/// created by [`Self::new`].
+///
 pub struct VirtualMemory {
     /// Underlying virtual partition.
-    partition: Rc<RefCell<VirtualPartition>>,
-    /// Virtual memory.
-    ptr: *mut u8,
-    /// Size of the virtual memory.
-    size: usize,
+    partition: Arc<VirtualPartition>,
+    /// Guest-physical memory regions, in the order they were added. The first entry is always
+    /// the main RAM region created by [`Self::new`].
+    regions: Vec<Region>,
     /// Kernel location and size.
     kernel: Option<(u64, usize)>,
     /// Initial RAM disk location and size.
     _initrd: Option<(u64, usize)>,
+    /// Whether new regions are created with `KVM_MEM_LOG_DIRTY_PAGES` set, i.e. whether
+    /// [`Self::get_dirty_log`] may be called on the main RAM region.
+    dirty_logging: bool,
 }
 
 //==================================================================================================
@@ -57,21 +98,85 @@ impl VirtualMemory {
     ///
     /// - `partition`: Virtual partition that hosts the virtual machine.
     /// - `memory_size`: Size of the virtual memory.
+    /// - `dirty_logging`: Whether to track writes to the region so that they may later be queried
+    ///   with [`Self::get_dirty_log`]. This is the foundational primitive for incremental memory
+    ///   capture (snapshots, live migration); leave it disabled unless that tracking is needed, as
+    ///   KVM incurs extra bookkeeping overhead on every guest write while it is enabled.
     ///
     /// # Returns
     ///
     /// Upon successful completion, the function returns the new virtual memory. Otherwise, it
     /// returns an error.
     ///
-    pub fn new(partition: Rc<RefCell<VirtualPartition>>, memory_size: usize) -> Result<Self> {
-        trace!("new(): memory_size={}", memory_size);
+    pub fn new(partition: Arc<VirtualPartition>, memory_size: usize, dirty_logging: bool) -> Result<Self> {
+        trace!(
+            "new(): memory_size={}, dirty_logging={}",
+            memory_size,
+            dirty_logging
+        );
         crate::timer!("vmem_creation");
 
+        let mut vmem: Self = Self {
+            partition,
+            regions: Vec::new(),
+            kernel: None,
+            _initrd: None,
+            dirty_logging,
+        };
+
+        // Main RAM region, read-write, mapped at guest physical address zero.
+        vmem.add_region(0, 0, memory_size, false)?;
+
+        Ok(vmem)
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Maps a new guest-physical memory region, backed by its own, freshly allocated host memory.
+    ///
+    /// Marking a region read-only (`read_only = true`) lets a guest observe it (e.g. a ROM/BIOS
+    /// area or a firmware image) without being able to corrupt it: KVM serves reads directly from
+    /// the host mapping, but since there is no writable backing, a guest write does not fault
+    /// silently — it surfaces through [`crate::kvm::vcpu::VirtualProcessor::run`] as an MMIO write
+    /// exit (`KVM_EXIT_MMIO`) at the written address, the same as an access to an unmapped range.
+    /// Route such writes to [`crate::kvm::emulator::Emulator::handle_mmio_access`] (e.g. by
+    /// registering a no-op MMIO device over the region) if they should be silently discarded
+    /// instead of rejected.
+    ///
+    /// # Parameters
+    ///
+    /// - `slot`: Slot to register the region under. Must be distinct from every other region's
+    ///   slot.
+    /// - `guest_phys_addr`: Guest physical address at which the region starts.
+    /// - `size`: Size, in bytes, of the region.
+    /// - `read_only`: Whether guest writes to the region should be rejected by
+    ///   [`Self::write_bytes`] and surfaced as an MMIO exit to the guest, rather than applied.
+    ///
+    /// # Returns
+    ///
+    /// Upon successful completion, this method returns empty. Otherwise, it returns an error.
+    ///
+    pub fn add_region(
+        &mut self,
+        slot: u32,
+        guest_phys_addr: u64,
+        size: usize,
+        read_only: bool,
+    ) -> Result<()> {
+        trace!(
+            "add_region(): slot={}, guest_phys_addr={:#010x}, size={}, read_only={}",
+            slot,
+            guest_phys_addr,
+            size,
+            read_only
+        );
+
         // Allocate memory.
         let ptr: *mut u8 = unsafe {
             libc::mmap(
                 ptr::null_mut(),
-                memory_size,
+                size,
                 libc::PROT_READ | libc::PROT_WRITE,
                 libc::MAP_ANONYMOUS | libc::MAP_PRIVATE | libc::MAP_NORESERVE,
                 -1,
@@ -79,61 +184,139 @@ impl VirtualMemory {
             ) as *mut u8
         };
 
-        // Check if we failed to allocate memory for the virtual machine.
+        // Check if we failed to allocate memory for the region.
         if ptr.is_null() {
             let reason: String = "failed to allocate memory for the virtual machine".to_string();
-            error!("new(): {} (memory_size={:?})", reason, memory_size);
+            error!("add_region(): {} (size={:?})", reason, size);
             return Err(anyhow::anyhow!(reason));
         }
 
-        // Create virtual memory. If we fail, destructor will free memory.
-        let vmem: Self = Self {
-            partition,
-            ptr,
-            size: memory_size,
-            kernel: None,
-            _initrd: None,
-        };
-
-        // Map memory into virtual machine.
+        // Map memory into the virtual machine.
+        let mut flags: u32 = if self.dirty_logging { KVM_MEM_LOG_DIRTY_PAGES } else { 0 };
+        if read_only {
+            flags |= KVM_MEM_READONLY;
+        }
         let mem_region: kvm_userspace_memory_region = kvm_userspace_memory_region {
-            slot: 0,
-            flags: 0,
-            guest_phys_addr: 0,
-            memory_size: memory_size as u64,
+            slot,
+            flags,
+            guest_phys_addr,
+            memory_size: size as u64,
             userspace_addr: ptr as u64,
         };
-        unsafe {
-            vmem.partition
-                .borrow()
-                .vm()
-                .set_user_memory_region(mem_region)?
-        };
+        unsafe { self.partition.vm().set_user_memory_region(mem_region)? };
 
-        Ok(vmem)
+        self.regions.push(Region {
+            slot,
+            guest_phys_addr,
+            ptr,
+            size,
+            read_only,
+        });
+
+        Ok(())
+    }
+
+    /// Returns the main RAM region, i.e. the first one added, by [`Self::new`].
+    fn primary(&self) -> &Region {
+        &self.regions[0]
+    }
+
+    /// Finds the region, if any, that fully covers `[addr, addr + len)`.
+    fn find_region(&self, addr: u64, len: usize) -> Result<&Region> {
+        self.regions
+            .iter()
+            .find(|region| {
+                addr >= region.guest_phys_addr
+                    && addr + len as u64 <= region.guest_phys_addr + region.size as u64
+            })
+            .ok_or_else(|| {
+                let reason: String = format!("invalid memory access (addr={:#010x})", addr);
+                error!("find_region(): {}", reason);
+                anyhow::anyhow!(reason)
+            })
     }
 
     ///
     /// # Description
     ///
-    /// Loads the kernel into the virtual memory.
+    /// Loads the kernel into the virtual memory. The file is sniffed for the Linux/x86 boot
+    /// protocol's `HdrS` magic (see [`bzimage`]) and, if found, loaded as a `bzImage` with `cmdline`
+    /// handed to the guest; otherwise it is loaded as a raw ELF binary via [`elf::load`], same as
+    /// before.
     ///
     /// # Parameters
     ///
     /// - `kernel_filename`: Path to the kernel binary file.
+    /// - `cmdline`: Kernel command-line, used only if the file turns out to be a `bzImage`.
     ///
     /// # Returns
     ///
     /// Upon successful completion, this method returns the entry point of the kernel that was
     /// loaded into the virtual memory. Otherwise, it returns an error.
     ///
-    pub fn load_kernel(&mut self, kernel_filename: &str) -> Result<u64> {
+    pub fn load_kernel(&mut self, kernel_filename: &str, cmdline: &str) -> Result<u64> {
         crate::timer!("vmem_load_kernel");
         trace!("load_kernel(): {}", kernel_filename);
 
-        let elf: FileMapping = FileMapping::mmap(kernel_filename)?;
-        let (entry, first_address, size): (usize, usize, usize) =
-            unsafe { elf::load(self.ptr as *mut ::std::ffi::c_void, elf.ptr(), self.size)? };
+        let file: FileMapping = FileMapping::mmap(kernel_filename)?;
+        let primary: &Region = self.primary();
+
+        let (entry, first_address, size): (usize, usize, usize) = unsafe {
+            if bzimage::is_bzimage(file.ptr(), file.size()) {
+                bzimage::load(
+                    primary.ptr as *mut ::std::ffi::c_void,
+                    file.ptr(),
+                    file.size(),
+                    primary.size,
+                    primary.size as u64,
+                    cmdline,
+                )?
+            } else {
+                elf::load(
+                    primary.ptr as *mut ::std::ffi::c_void,
+                    file.ptr(),
+                    primary.size,
+                    elf::EM_386,
+                )?
+            }
+        };
+
+        self.kernel = Some((first_address as u64, size));
+
+        Ok(entry as u64)
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Receives a kernel image pushed over the network (see [`netboot::fetch`]) and loads it into
+    /// the virtual memory, in place of reading one from disk.
+    ///
+    /// # Parameters
+    ///
+    /// - `addr`: Address to bind and listen for the incoming kernel image on.
+    /// - `cmdline`: Kernel command-line, passed through to [`crate::bzimage::load`] if the received
+    ///   image turns out to be a bzImage rather than an ELF binary.
+    ///
+    /// # Returns
+    ///
+    /// Upon successful completion, this method returns the entry point of the kernel that was
+    /// loaded into the virtual memory. Otherwise, it returns an error.
+    ///
+    pub fn load_kernel_netboot(&mut self, addr: SocketAddr, cmdline: &str) -> Result<u64> {
+        crate::timer!("vmem_load_kernel_netboot");
+        trace!("load_kernel_netboot(): {}", addr);
+
+        let primary: &Region = self.primary();
+        let (entry, first_address, size): (usize, usize, usize) = unsafe {
+            netboot::load(
+                primary.ptr as *mut ::std::ffi::c_void,
+                addr,
+                primary.size,
+                primary.size as u64,
+                cmdline,
+            )?
+        };
 
         self.kernel = Some((first_address as u64, size));
 
@@ -173,7 +356,7 @@ impl VirtualMemory {
         unsafe {
             ptr::copy_nonoverlapping(
                 initrd.ptr(),
-                self.ptr.add(config::INITRD_BASE),
+                self.primary().ptr.add(config::INITRD_BASE),
                 initrd.size(),
             );
         }
@@ -186,27 +369,88 @@ impl VirtualMemory {
     ///
     /// # Description
     ///
-    /// Writes bytes into the virtual memory.
+    /// Loads a NUL-terminated kernel command-line string at `addr`, so that its address may be
+    /// handed to the guest (e.g. in a spare register, see `MicroVm::reset`).
     ///
     /// # Parameters
     ///
-    /// - `addr`: Address in the virtual memory.
-    /// - `data`: Data to write.
+    /// - `addr`: Address, in the virtual memory, to load the command-line at.
+    /// - `cmdline`: Command-line string.
     ///
     /// # Returns
     ///
     /// Upon successful completion, this method returns empty. Otherwise, it returns an error.
     ///
-    pub fn write_bytes(&mut self, addr: u64, data: &[u8]) -> Result<()> {
-        // Check if region lies within the virtual memory.
-        if addr as usize + data.len() > self.size {
+    pub fn load_cmdline(&mut self, addr: u64, cmdline: &str) -> Result<()> {
+        crate::timer!("vmem_load_cmdline");
+        trace!("load_cmdline(): addr={:#010x}, cmdline={:?}", addr, cmdline);
+
+        let bytes: &[u8] = cmdline.as_bytes();
+        let len: u64 = bytes.len() as u64 + 1; // +1 for the NUL terminator
+
+        // Check if the command-line would overlap with the kernel.
+        if let Some((kernel_base, _)) = self.kernel {
+            if addr + len > kernel_base {
+                let reason: String = "cmdline overlaps with kernel".to_string();
+                error!("load_cmdline(): {}", reason);
+                return Err(anyhow::anyhow!(reason));
+            }
+        }
+
+        // Check if the command-line would overlap with the initrd.
+        if let Some((initrd_base, _)) = self._initrd {
+            if addr + len > initrd_base {
+                let reason: String = "cmdline overlaps with initrd".to_string();
+                error!("load_cmdline(): {}", reason);
+                return Err(anyhow::anyhow!(reason));
+            }
+        }
+
+        // Check if region lies within the main RAM region.
+        if addr + len > self.primary().size as u64 {
             let reason: String = format!("invalid memory access (addr={:#010x})", addr);
+            error!("load_cmdline(): {}", reason);
+            return Err(anyhow::anyhow!(reason));
+        }
+
+        let primary_ptr: *mut u8 = self.primary().ptr;
+        unsafe {
+            ptr::copy_nonoverlapping(bytes.as_ptr(), primary_ptr.add(addr as usize), bytes.len());
+            *primary_ptr.add(addr as usize + bytes.len()) = 0;
+        }
+
+        Ok(())
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Writes bytes into the virtual memory, translating `addr` to whichever region (see
+    /// [`Self::add_region`]) covers it.
+    ///
+    /// # Parameters
+    ///
+    /// - `addr`: Guest physical address to write to.
+    /// - `data`: Data to write.
+    ///
+    /// # Returns
+    ///
+    /// Upon successful completion, this method returns empty. Otherwise, it returns an error,
+    /// namely if `addr` does not fall within a single registered region, or that region is
+    /// read-only.
+    ///
+    pub fn write_bytes(&mut self, addr: u64, data: &[u8]) -> Result<()> {
+        let region: &Region = self.find_region(addr, data.len())?;
+
+        if region.read_only {
+            let reason: String = format!("write to read-only memory region (addr={:#010x})", addr);
             error!("write_bytes(): {}", reason);
             return Err(anyhow::anyhow!(reason));
         }
 
+        let offset: usize = (addr - region.guest_phys_addr) as usize;
         unsafe {
-            ptr::copy_nonoverlapping(data.as_ptr(), self.ptr.offset(addr as isize), data.len());
+            ptr::copy_nonoverlapping(data.as_ptr(), region.ptr.add(offset), data.len());
         }
 
         Ok(())
@@ -215,40 +459,234 @@ impl VirtualMemory {
     ///
     /// # Description
     ///
-    /// Reads bytes from the virtual memory.
+    /// Reads bytes from the virtual memory, translating `addr` to whichever region (see
+    /// [`Self::add_region`]) covers it.
     ///
     /// # Parameters
     ///
-    /// - `addr`: Address in the virtual memory.
-    /// - `data`: Data to read.
-    /// - `data`: Data to read.
+    /// - `addr`: Guest physical address to read from.
+    /// - `data`: Buffer to read into.
     ///
     /// # Returns
     ///
-    /// Upon successful completion, this method returns empty. Otherwise, it returns an error.
+    /// Upon successful completion, this method returns empty. Otherwise, it returns an error,
+    /// namely if `addr` does not fall within a single registered region.
     ///
     pub fn read_bytes(&self, addr: u64, data: &mut [u8]) -> Result<()> {
-        // Check if region lies within the virtual memory.
-        if addr as usize + data.len() > self.size {
-            let reason: String = format!("invalid memory access (addr={:#010x})", addr);
-            error!("read_bytes(): {}", reason);
+        let region: &Region = self.find_region(addr, data.len())?;
+        let offset: usize = (addr - region.guest_phys_addr) as usize;
+
+        unsafe {
+            ptr::copy_nonoverlapping(region.ptr.add(offset), data.as_mut_ptr(), data.len());
+        }
+
+        Ok(())
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Retrieves the dirty bitmap for this memory region, i.e. which 4KB guest pages were written
+    /// since the last call to this method (or since creation, for the first call). Bit N of the
+    /// returned bitmap is set if page N was written to.
+    ///
+    /// Reading the log atomically clears it in KVM, so each returned bit reflects writes that
+    /// happened strictly between the previous call and this one.
+    ///
+    /// # Returns
+    ///
+    /// Upon successful completion, this method returns the dirty bitmap. Otherwise, it returns an
+    /// error, namely if the region was not created with dirty-page logging enabled.
+    ///
+    pub fn get_dirty_log(&self) -> Result<Vec<u64>> {
+        if !self.dirty_logging {
+            let reason: String =
+                "cannot get dirty log on a region that was not created with dirty logging enabled"
+                    .to_string();
+            error!("get_dirty_log(): {}", reason);
             return Err(anyhow::anyhow!(reason));
         }
 
-        unsafe {
-            ptr::copy_nonoverlapping(self.ptr.offset(addr as isize), data.as_mut_ptr(), data.len());
+        let primary: &Region = self.primary();
+        Ok(self.partition.vm().get_dirty_log(primary.slot, primary.size)?)
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Returns the size, in bytes, of the main RAM region.
+    ///
+    /// # Returns
+    ///
+    /// The size of the main RAM region.
+    ///
+    pub fn size(&self) -> usize {
+        self.primary().size
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Returns the base host address of the main RAM region, for example to bind a
+    /// [`crate::debugger::Debugger`] over it.
+    ///
+    /// # Returns
+    ///
+    /// The base host address of the main RAM region.
+    ///
+    pub fn ptr(&self) -> *mut u8 {
+        self.primary().ptr
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Streams the entire virtual memory out to `writer`, for example to capture a full snapshot
+    /// of a paused guest.
+    ///
+    /// # Parameters
+    ///
+    /// - `writer`: Destination that the memory image is written to.
+    ///
+    /// # Returns
+    ///
+    /// Upon successful completion, this method returns empty. Otherwise, it returns an error.
+    ///
+    pub fn dump(&self, writer: &mut impl Write) -> Result<()> {
+        let primary: &Region = self.primary();
+        trace!("dump(): size={}", primary.size);
+        let bytes: &[u8] = unsafe { ::std::slice::from_raw_parts(primary.ptr, primary.size) };
+        writer.write_all(bytes)?;
+        Ok(())
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Streams out only the pages flagged in `dirty_log` (as returned by [`Self::get_dirty_log`]),
+    /// each preceded by its page index, so that an earlier full [`Self::dump`] can be brought
+    /// up to date without re-copying the whole region.
+    ///
+    /// # Parameters
+    ///
+    /// - `writer`: Destination that the dirty pages are written to.
+    /// - `dirty_log`: Dirty bitmap to filter pages by.
+    ///
+    /// # Returns
+    ///
+    /// Upon successful completion, this method returns empty. Otherwise, it returns an error.
+    ///
+    pub fn dump_dirty(&self, writer: &mut impl Write, dirty_log: &[u64]) -> Result<()> {
+        let primary: &Region = self.primary();
+        let pages: Vec<u64> = Self::dirty_pages(dirty_log);
+        trace!("dump_dirty(): pages={}", pages.len());
+
+        writer.write_all(&(pages.len() as u64).to_le_bytes())?;
+        for page in pages {
+            let offset: usize = page as usize * PAGE_SIZE;
+            if offset + PAGE_SIZE > primary.size {
+                let reason: String = format!("dirty page out of range (page={})", page);
+                error!("dump_dirty(): {}", reason);
+                return Err(anyhow::anyhow!(reason));
+            }
+
+            writer.write_all(&page.to_le_bytes())?;
+            let bytes: &[u8] =
+                unsafe { ::std::slice::from_raw_parts(primary.ptr.add(offset), PAGE_SIZE) };
+            writer.write_all(bytes)?;
+        }
+
+        Ok(())
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Loads a full memory image previously produced by [`Self::dump`].
+    ///
+    /// # Parameters
+    ///
+    /// - `reader`: Source that the memory image is read from.
+    ///
+    /// # Returns
+    ///
+    /// Upon successful completion, this method returns empty. Otherwise, it returns an error.
+    ///
+    pub fn load(&mut self, reader: &mut impl Read) -> Result<()> {
+        let primary: &Region = self.primary();
+        trace!("load(): size={}", primary.size);
+        let bytes: &mut [u8] =
+            unsafe { ::std::slice::from_raw_parts_mut(primary.ptr, primary.size) };
+        reader.read_exact(bytes)?;
+        Ok(())
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Loads a sparse memory image previously produced by [`Self::dump_dirty`], overwriting only
+    /// the pages that it carries and leaving the rest of the region untouched.
+    ///
+    /// # Parameters
+    ///
+    /// - `reader`: Source that the dirty pages are read from.
+    ///
+    /// # Returns
+    ///
+    /// Upon successful completion, this method returns empty. Otherwise, it returns an error.
+    ///
+    pub fn load_dirty(&mut self, reader: &mut impl Read) -> Result<()> {
+        let primary_ptr: *mut u8 = self.primary().ptr;
+        let primary_size: usize = self.primary().size;
+
+        let mut count_bytes: [u8; 8] = [0; 8];
+        reader.read_exact(&mut count_bytes)?;
+        let count: u64 = u64::from_le_bytes(count_bytes);
+        trace!("load_dirty(): pages={}", count);
+
+        for _ in 0..count {
+            let mut page_bytes: [u8; 8] = [0; 8];
+            reader.read_exact(&mut page_bytes)?;
+            let page: u64 = u64::from_le_bytes(page_bytes);
+
+            let offset: usize = page as usize * PAGE_SIZE;
+            if offset + PAGE_SIZE > primary_size {
+                let reason: String = format!("dirty page out of range (page={})", page);
+                error!("load_dirty(): {}", reason);
+                return Err(anyhow::anyhow!(reason));
+            }
+
+            let bytes: &mut [u8] =
+                unsafe { ::std::slice::from_raw_parts_mut(primary_ptr.add(offset), PAGE_SIZE) };
+            reader.read_exact(bytes)?;
         }
 
         Ok(())
     }
+
+    /// Expands a dirty bitmap, as returned by [`Self::get_dirty_log`], into the list of page
+    /// indices that it flags.
+    fn dirty_pages(dirty_log: &[u64]) -> Vec<u64> {
+        let mut pages: Vec<u64> = Vec::new();
+        for (word_index, word) in dirty_log.iter().enumerate() {
+            for bit in 0..u64::BITS {
+                if word & (1 << bit) != 0 {
+                    pages.push(word_index as u64 * u64::BITS as u64 + bit as u64);
+                }
+            }
+        }
+        pages
+    }
 }
 
 impl Drop for VirtualMemory {
     fn drop(&mut self) {
-        unsafe {
-            let ret: libc::c_int = libc::munmap(self.ptr as *mut libc::c_void, self.size);
-            if ret != 0 {
-                error!("munmap() failed (ret={})", ret);
+        for region in &self.regions {
+            unsafe {
+                let ret: libc::c_int = libc::munmap(region.ptr as *mut libc::c_void, region.size);
+                if ret != 0 {
+                    error!("munmap() failed (ret={})", ret);
+                }
             }
         }
     }
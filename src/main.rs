@@ -38,13 +38,19 @@ macro_rules! timer {
 //==================================================================================================
 
 mod args;
+mod bzimage;
+mod codec;
 mod config;
+mod debugger;
 mod elf;
 mod file;
+mod guestmem;
 mod http;
 mod logging;
 mod microvm;
+mod netboot;
 mod pal;
+mod virtiofs;
 
 #[cfg(feature = "profiler")]
 mod profiler;
@@ -52,6 +58,9 @@ mod profiler;
 #[cfg(target_os = "linux")]
 mod kvm;
 
+#[cfg(target_os = "linux")]
+mod seccomp;
+
 //==================================================================================================
 // Imports
 //==================================================================================================
@@ -64,10 +73,15 @@ extern crate log;
 extern crate kvm_bindings;
 #[cfg(target_os = "linux")]
 extern crate kvm_ioctls;
+#[cfg(target_os = "linux")]
+extern crate vmm_sys_util;
 
 use crate::{
     args::Args,
-    kvm::vmem::VirtualMemory,
+    kvm::{
+        control,
+        vmem::VirtualMemory,
+    },
     microvm::MicroVm,
 };
 use ::anyhow::Result;
@@ -86,6 +100,7 @@ use ::std::{
     },
 };
 use ::sys::ipc::Message;
+use ::vmm_sys_util::eventfd::EventFd;
 
 //==================================================================================================
 // Standalone Functions
@@ -110,11 +125,53 @@ fn main() -> Result<()> {
         mpsc::Receiver<std::result::Result<[u8; mem::size_of::<Message>()], anyhow::Error>>,
     ) = mpsc::channel::<Result<[u8; mem::size_of::<Message>()]>>();
 
+    // Channel over which a guest write to `MicroVm::VMM_PORT` (decoded into a `control::VmRequest`
+    // by `kvm::emulator::Emulator`) and, over the HTTP gateway, a remote peer are both served by
+    // `MicroVm::run`. Created here, ahead of the `MicroVm` itself, because the I/O thread below is
+    // spawned before `run_vmm` constructs it.
+    let (control_tx, control_rx): (
+        mpsc::Sender<control::VmRequest>,
+        mpsc::Receiver<control::VmRequest>,
+    ) = mpsc::channel();
+
+    // Whether the seccomp sandbox is disabled, read out here (before `args` is moved into
+    // `run_vmm`) so the I/O thread below can install its own copy of the filter, see
+    // `seccomp::install` for why this thread needs one too.
+    let disable_sandbox: bool = args.disable_sandbox();
+
+    // Eventfd that the I/O thread signals every time it queues a message on `tx_channel_to_vm`,
+    // registered by `MicroVm::new` as an irqfd on `MicroVm::STDIN_IRQ_GSI`. Created here, ahead of
+    // the `MicroVm` itself, for the same reason `control_tx`/`control_rx` are: the I/O thread that
+    // must hold a writable clone of it is spawned before `run_vmm` constructs the `MicroVm` that
+    // registers it.
+    let stdin_irqfd: EventFd = EventFd::new(::libc::EFD_NONBLOCK)?;
+
+    // Shared secret used to key the `file`/`http` transports' `codec::WireCodec`, read out here
+    // (before `args` is moved into `run_vmm`) for the same reason `disable_sandbox` is.
+    let secret: Option<String> = args.take_secret();
+
     // Spawn I/O thread.
     let _io_thread: JoinHandle<()> = if let Some(sockaddr) = args.take_sockaddr() {
         let sockaddr: SocketAddr = sockaddr.parse()?;
+        let control_tx: mpsc::Sender<control::VmRequest> = control_tx.clone();
+        let stdin_irqfd: EventFd = stdin_irqfd.try_clone()?;
         thread::spawn(move || {
-            let server = http::HttpServer::new(sockaddr, tx_channel_to_vm, rx_channel_from_vm);
+            let server = http::HttpServer::new(
+                sockaddr,
+                tx_channel_to_vm,
+                rx_channel_from_vm,
+                control_tx,
+                stdin_irqfd,
+                secret,
+            );
+
+            #[cfg(target_os = "linux")]
+            if !disable_sandbox {
+                if let Err(e) = seccomp::install() {
+                    error!("failed to install seccomp sandbox on http server thread: {:?}", e);
+                    return;
+                }
+            }
 
             if let Err(e) = server.run() {
                 error!("http server has failed: {:?}", e);
@@ -123,16 +180,37 @@ fn main() -> Result<()> {
     } else {
         let vm_stdin: Option<String> = args.take_vm_stdin();
         let vm_stdout: Option<String> = args.take_vm_stdout();
+        let stdin_irqfd: EventFd = stdin_irqfd.try_clone()?;
         thread::spawn(move || {
-            if let Err(e) =
-                file::file_server(vm_stdin, vm_stdout, tx_channel_to_vm, rx_channel_from_vm)
-            {
+            #[cfg(target_os = "linux")]
+            if !disable_sandbox {
+                if let Err(e) = seccomp::install() {
+                    error!("failed to install seccomp sandbox on file server thread: {:?}", e);
+                    return;
+                }
+            }
+
+            if let Err(e) = file::file_server(
+                vm_stdin,
+                vm_stdout,
+                tx_channel_to_vm,
+                rx_channel_from_vm,
+                stdin_irqfd,
+                secret,
+            ) {
                 error!("file server has failed: {:?}", e);
             }
         })
     };
 
-    run_vmm(args, rx_channel_from_stdin, tx_channel_to_stdout)?;
+    run_vmm(
+        args,
+        rx_channel_from_stdin,
+        tx_channel_to_stdout,
+        control_tx,
+        control_rx,
+        stdin_irqfd,
+    )?;
 
     Ok(())
 }
@@ -145,6 +223,11 @@ fn main() -> Result<()> {
 /// # Parameters
 ///
 /// * `args` - Arguments for the virtual machine monitor.
+/// * `control_tx` - Sender half of the channel the virtual machine monitor's control plane
+///   dispatches `VmRequest`s from, see [`microvm::MicroVm::new`].
+/// * `control_rx` - Receiver half of the same channel.
+/// * `stdin_irqfd` - Eventfd registered as an irqfd on [`microvm::MicroVm::STDIN_IRQ_GSI`], see
+///   [`microvm::MicroVm::new`].
 pub fn run_vmm(
     mut args: Args,
     rx_channel_from_stdin: mpsc::Receiver<
@@ -153,6 +236,9 @@ pub fn run_vmm(
     tx_channel_to_stdout: mpsc::Sender<
         std::result::Result<[u8; mem::size_of::<Message>()], anyhow::Error>,
     >,
+    control_tx: mpsc::Sender<control::VmRequest>,
+    control_rx: mpsc::Receiver<control::VmRequest>,
+    stdin_irqfd: EventFd,
 ) -> Result<()> {
     crate::timer!("main");
 
@@ -222,16 +308,69 @@ pub fn run_vmm(
         }
     };
 
-    let mut microvm: MicroVm = MicroVm::new(args.memory_size(), Box::new(input), Box::new(output))?;
+    let mut microvm: MicroVm = MicroVm::new(
+        args.memory_size(),
+        args.vcpu_count(),
+        Box::new(input),
+        Box::new(output),
+        control_tx,
+        control_rx,
+        stdin_irqfd,
+    )?;
+
+    if let Some(restore_path) = args.take_restore_path() {
+        // Resume a virtual machine previously frozen by `MicroVm::snapshot`: register state and
+        // guest memory contents come from the snapshot instead of a freshly booted kernel. The
+        // I/O closures above were just constructed for this process, re-establishing the gateway
+        // to the resumed guest.
+        microvm.restore(&restore_path)?;
+    } else if let Some(netboot_addr) = args.take_netboot_addr() {
+        // Receive the kernel image over the network instead of reading it from disk, giving a
+        // "push a kernel and run" workflow without rebuilding a disk image for every iteration.
+        let netboot_addr: SocketAddr = netboot_addr.parse()?;
+        let rip: u64 = microvm.load_kernel_netboot(netboot_addr, args.cmdline().unwrap_or(""))?;
+        for initrd_filename in args.initrd_filenames() {
+            microvm.load_initrd(initrd_filename)?;
+        }
+
+        microvm.reset(rip)?;
+    } else {
+        let rip: u64 = microvm.load_kernel(args.kernel_filename(), args.cmdline().unwrap_or(""))?;
+        for initrd_filename in args.initrd_filenames() {
+            microvm.load_initrd(initrd_filename)?;
+        }
 
-    let rip: u64 = microvm.load_kernel(args.kernel_filename())?;
-    if let Some(ref initrd_filename) = args.initrd_filename() {
-        microvm.load_initrd(initrd_filename)?;
+        microvm.reset(rip)?;
     }
 
-    microvm.reset(rip)?;
+    for (index, (tag, host_path)) in args.fs_mounts().iter().enumerate() {
+        let base: u64 = config::VIRTIOFS_BASE + index as u64 * virtiofs::VirtioFsDevice::MMIO_LEN;
+        virtiofs::VirtioFsDevice::new(tag, host_path)?.attach(&mut microvm, base)?;
+    }
+
+    // Install the seccomp sandbox now that all of this thread's own setup needing a broader
+    // syscall surface (files opened, KVM file descriptors created) has finished, and before any
+    // guest-facing code of the run loop executes. This must happen as late as possible so that the
+    // vCPU and control-socket threads spawned by `microvm.run` inherit the installed filter. The
+    // I/O thread spawned in `main` above does not inherit this one (it was already running before
+    // this point) and installs its own copy instead.
+    #[cfg(target_os = "linux")]
+    if !args.disable_sandbox() {
+        seccomp::install()?;
+    }
 
-    microvm.run()?;
+    let gdb_addr: Option<SocketAddr> = args.take_gdb_addr().map(|addr| addr.parse()).transpose()?;
+    let control_path: Option<String> = args.take_control_path();
+    let debug_addr: Option<SocketAddr> =
+        args.take_debug_addr().map(|addr| addr.parse()).transpose()?;
+    let snapshot_path: Option<String> = args.take_snapshot_path();
+    microvm.run(gdb_addr, control_path, debug_addr)?;
+
+    // Freeze the virtual machine's final state once it has shut down cleanly, so that it may
+    // later be resumed with `-restore`.
+    if let Some(snapshot_path) = snapshot_path {
+        microvm.snapshot(&snapshot_path, false)?;
+    }
 
     Ok(())
 }
@@ -14,43 +14,96 @@
 
 #[cfg(target_os = "linux")]
 use crate::kvm::{
+    control::{
+        self,
+        ControlSocket,
+    },
     emulator::Emulator,
+    gdbstub::GdbStub,
     partition::VirtualPartition,
     vcpu::{
         VirtualProcessor,
         VirtualProcessorExitContext,
         VirtualProcessorExitReason,
+        VirtualProcessorState,
     },
     vmem::VirtualMemory,
 };
 
+use ::vmm_sys_util::eventfd::EventFd;
+
 use crate::config;
+use crate::debugger::Debugger;
 use ::anyhow::Result;
 use ::std::{
-    cell::RefCell,
-    rc::Rc,
+    fs::File,
+    io::{
+        Read,
+        Write,
+    },
+    mem,
+    net::SocketAddr,
+    sync::{
+        atomic::{
+            AtomicBool,
+            Ordering,
+        },
+        mpsc,
+        Arc,
+        Condvar,
+        Mutex,
+    },
+    thread,
 };
 
 //==================================================================================================
 // Structures
 //==================================================================================================
 
+/// Magic value that identifies a MicroVM snapshot file.
+const SNAPSHOT_MAGIC: u32 = 0x534e4150;
+
+/// Version of the on-disk snapshot format produced by [`MicroVm::snapshot`].
+const SNAPSHOT_VERSION: u32 = 2;
+
+/// Signature of a closure that emulates a read from an MMIO device, given the offset of the
+/// access within the device's registered range. Must be `Send`, as it is moved into the
+/// [`Emulator`] that every vCPU thread shares, see [`MicroVm::run`].
+pub type MmioReadFn = dyn FnMut(u64, &mut [u8]) -> Result<()> + Send;
+
+/// Signature of a closure that emulates a write to an MMIO device, given the offset of the
+/// access within the device's registered range, the written value, and its size in bytes. Must be
+/// `Send`, as it is moved into the [`Emulator`] that every vCPU thread shares, see
+/// [`MicroVm::run`].
+pub type MmioWriteFn = dyn FnMut(u64, u32, usize) -> Result<()> + Send;
+
 ///
 /// # Description
 ///
 /// A structure that represents a MicroVM.
 ///
 pub struct MicroVm {
-    // Virtual partition that hosts the virtual machine.
-    _partition: Rc<RefCell<VirtualPartition>>,
+    // Virtual partition that hosts the virtual machine. Plain `Arc`, not `Arc<Mutex<...>>>`: every
+    // method that vCPU threads call on it only takes `&self`.
+    partition: Arc<VirtualPartition>,
     // Virtual memory of the virtual machine.
     vmem: VirtualMemory,
-    // Virtual processor of the virtual machine.
-    vcpu: VirtualProcessor,
-    // Emulator of the virtual machine.
-    emulator: Emulator,
+    // Virtual processors of the virtual machine, one per host thread once [`Self::run`] is
+    // called.
+    vcpus: Vec<VirtualProcessor>,
+    // Emulator of the virtual machine, shared by every vCPU thread.
+    emulator: Arc<Mutex<Emulator>>,
     // If present, initial RAM disk location and size.
     initrd: Option<(u64, usize)>,
+    // If present, location of the kernel command-line.
+    cmdline: Option<u64>,
+    // Entry point that the virtual machine was last reset to, remembered so that a guest-triggered
+    // reset can be honored by [`Self::reboot`] without the caller having to supply it again.
+    entry: Option<u64>,
+    // Receiver half of the channel whose sender `emulator` holds a clone of (and whose other
+    // clones may be held by gateway peers, e.g. `crate::http::HttpServer`); taken by [`Self::run`]
+    // to spawn the thread that carries out every `VmRequest` it receives.
+    control_rx: Option<mpsc::Receiver<control::VmRequest>>,
 }
 
 //==================================================================================================
@@ -60,8 +113,16 @@ pub struct MicroVm {
 impl MicroVm {
     /// I/O port that is connected to the standard output of the virtual machine.
     pub const STDOUT_PORT: u16 = config::STDOUT_PORT;
-    /// I/O port that is connected to the standard input of the virtual machine.
+    /// I/O port that is connected to the standard input of the virtual machine. A guest may still
+    /// poll this port (a non-blocking `try_recv` on the receiving end of the stdin channel, from
+    /// `run_vmm`'s `input` closure), but does not have to: `main::main` also hands [`Self::new`] an
+    /// eventfd that every source of a stdin message (the HTTP gateway, the file-backed I/O thread)
+    /// signals after a message is queued, which this partition registers as an irqfd on
+    /// [`Self::STDIN_IRQ_GSI`], so a guest driver may instead block for that interrupt.
     pub const STDIN_PORT: u16 = config::STDIN_PORT;
+    /// Global system interrupt raised, via the eventfd [`Self::new`] registers as an irqfd, whenever
+    /// a message reaches the stdin channel. See [`Self::STDIN_PORT`].
+    pub const STDIN_IRQ_GSI: u32 = 4;
     /// I/O port that enables the guest to invoke functionalities of the virtual machine monitor.
     pub const VMM_PORT: u16 = config::VMM_PORT;
 
@@ -73,8 +134,19 @@ impl MicroVm {
     /// # Parameters
     ///
     /// - `memory_size`: Size of the virtual memory of the virtual machine.
+    /// - `vcpu_count`: Number of virtual processors to create, each backed by its own host thread
+    ///   once [`Self::run`] is called.
     /// - `input`: Input function used for emulating I/O port reads.
     /// - `output`: Output function used for emulating I/O port writes.
+    /// - `control_tx`: Sender half of the channel that [`Self::run`] drains to carry out
+    ///   `VmRequest`s. Created by the caller (rather than by this method) so that the same sender
+    ///   can also be cloned out to a gateway peer (e.g. [`crate::http::HttpServer`]) that is set up
+    ///   before a [`MicroVm`] exists.
+    /// - `control_rx`: Receiver half of the same channel.
+    /// - `stdin_irqfd`: Eventfd that every source of a stdin message signals after queuing one, to
+    ///   be registered as an irqfd on [`Self::STDIN_IRQ_GSI`]; see [`Self::STDIN_PORT`]. Created by
+    ///   the caller, rather than by this method, for the same reason `control_tx`/`control_rx` are:
+    ///   a clone must reach the I/O thread, which `main::main` spawns before a [`MicroVm`] exists.
     ///
     /// # Returns
     ///
@@ -83,27 +155,45 @@ impl MicroVm {
     ///
     pub fn new(
         memory_size: usize,
-        input: Box<dyn FnMut(usize) -> Result<u32>>,
-        output: Box<dyn FnMut(u32, usize) -> Result<()>>,
+        vcpu_count: usize,
+        input: Box<dyn FnMut(usize) -> Result<u32> + Send>,
+        output: Box<dyn FnMut(u32, usize) -> Result<()> + Send>,
+        control_tx: mpsc::Sender<control::VmRequest>,
+        control_rx: mpsc::Receiver<control::VmRequest>,
+        stdin_irqfd: EventFd,
     ) -> Result<Self> {
-        trace!("new(): memory_size={}", memory_size);
+        trace!("new(): memory_size={}, vcpu_count={}", memory_size, vcpu_count);
         crate::timer!("vm_creation");
 
-        let partition: Rc<RefCell<VirtualPartition>> =
-            Rc::new(RefCell::new((VirtualPartition::new())?));
+        if vcpu_count == 0 {
+            let reason: String = "vcpu_count must be greater than zero".to_string();
+            error!("new(): {}", reason);
+            return Err(anyhow::anyhow!(reason));
+        }
+
+        let partition: Arc<VirtualPartition> = Arc::new(VirtualPartition::new()?);
+        partition.register_irqfd_handle(stdin_irqfd, Self::STDIN_IRQ_GSI)?;
 
-        let vmem: VirtualMemory = VirtualMemory::new(partition.clone(), memory_size)?;
+        // Dirty-page logging is kept on unconditionally so that `snapshot` can always take an
+        // incremental capture after an initial full one, see `Self::snapshot`.
+        let vmem: VirtualMemory = VirtualMemory::new(partition.clone(), memory_size, true)?;
 
-        let vcpu: VirtualProcessor = VirtualProcessor::new(partition.clone(), 0)?;
+        let mut vcpus: Vec<VirtualProcessor> = Vec::with_capacity(vcpu_count);
+        for id in 0..vcpu_count as u64 {
+            vcpus.push(VirtualProcessor::new(partition.clone(), id)?);
+        }
 
-        let emulator: Emulator = Emulator::new(input, output)?;
+        let emulator: Emulator = Emulator::new(input, output, control_tx.clone())?;
 
         Ok(Self {
-            _partition: partition,
+            partition,
             vmem,
-            vcpu,
-            emulator,
+            vcpus,
+            emulator: Arc::new(Mutex::new(emulator)),
             initrd: None,
+            cmdline: None,
+            entry: None,
+            control_rx: Some(control_rx),
         })
     }
 
@@ -115,16 +205,41 @@ impl MicroVm {
     /// # Parameters
     ///
     /// - `kernel_filename`: Path to the kernel binary.
+    /// - `cmdline`: Kernel command-line, used only if `kernel_filename` turns out to be a
+    ///   `bzImage` rather than a raw ELF binary.
     ///
     /// # Returns
     ///
     /// Upon successful completion, this method returns the entry point of the program that was
     /// loaded into the virtual machine. Otherwise, it returns an error.
     ///
-    pub fn load_kernel(&mut self, kernel_filename: &str) -> Result<u64> {
+    pub fn load_kernel(&mut self, kernel_filename: &str, cmdline: &str) -> Result<u64> {
         trace!("load_kernel(): {}", kernel_filename);
         crate::timer!("vm_load_kernel");
-        let entry: u64 = self.vmem.load_kernel(kernel_filename)?;
+        let entry: u64 = self.vmem.load_kernel(kernel_filename, cmdline)?;
+        Ok(entry)
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Receives a kernel image pushed over the network and loads it into the virtual machine, in
+    /// place of reading one from disk (see [`crate::netboot`]).
+    ///
+    /// # Parameters
+    ///
+    /// - `addr`: Address to bind and listen for the incoming kernel image on.
+    /// - `cmdline`: Kernel command-line, passed through in case the received image is a bzImage.
+    ///
+    /// # Returns
+    ///
+    /// Upon successful completion, this method returns the entry point of the program that was
+    /// loaded into the virtual machine. Otherwise, it returns an error.
+    ///
+    pub fn load_kernel_netboot(&mut self, addr: SocketAddr, cmdline: &str) -> Result<u64> {
+        trace!("load_kernel_netboot(): {}", addr);
+        crate::timer!("vm_load_kernel_netboot");
+        let entry: u64 = self.vmem.load_kernel_netboot(addr, cmdline)?;
         Ok(entry)
     }
 
@@ -149,11 +264,68 @@ impl MicroVm {
         Ok(())
     }
 
+    ///
+    /// # Description
+    ///
+    /// Sets the kernel command-line of the virtual machine, letting users configure the guest
+    /// kernel (console selection, debug flags, ...) without recompiling it.
+    ///
+    /// # Parameters
+    ///
+    /// - `cmdline`: Command-line string.
+    ///
+    /// # Returns
+    ///
+    /// Upon successful completion, this method returns empty. Otherwise, it returns an error.
+    ///
+    pub fn set_cmdline(&mut self, cmdline: &str) -> Result<()> {
+        trace!("set_cmdline(): {:?}", cmdline);
+        let addr: u64 = config::CMDLINE_BASE as u64;
+        self.vmem.load_cmdline(addr, cmdline)?;
+        self.cmdline = Some(addr);
+        Ok(())
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Registers a memory-mapped device over `[base, base + len)` of the guest physical address
+    /// space. Accesses inside that range are routed to `read_fn`/`write_fn` instead of being
+    /// forwarded to the guest's RAM, unblocking devices (timers, interrupt controllers, ...) that
+    /// cannot be expressed through a fixed I/O port.
+    ///
+    /// # Parameters
+    ///
+    /// - `base`: Guest physical address at which the device is mapped.
+    /// - `len`: Size, in bytes, of the device's range.
+    /// - `read_fn`: Closure invoked on a read from the device's range.
+    /// - `write_fn`: Closure invoked on a write to the device's range.
+    ///
+    /// # Returns
+    ///
+    /// Upon successful completion, this method returns empty. Otherwise, it returns an error.
+    ///
+    pub fn register_mmio(
+        &mut self,
+        base: u64,
+        len: u64,
+        read_fn: Box<MmioReadFn>,
+        write_fn: Box<MmioWriteFn>,
+    ) -> Result<()> {
+        trace!("register_mmio(): base={:#010x}, len={}", base, len);
+        self.emulator.lock().unwrap().register_mmio(base, len, read_fn, write_fn)
+    }
+
     ///
     /// # Description
     ///
     /// Resets the virtual machine.
     ///
+    /// Every virtual processor's registers are primed with the same `rip`/`rax`/`rbx`/`rdi`, but
+    /// only the bootstrap processor (vcpu 0) is left running: `VirtualProcessor::reset` parks every
+    /// other one in `KVM_MP_STATE_UNINITIALIZED`, since this microvm does not model the startup-IPI
+    /// sequence a real platform would use to wake them.
+    ///
     /// # Parameters
     ///
     /// - `rip`: Entry point of the virtual machine.
@@ -165,6 +337,24 @@ impl MicroVm {
     pub fn reset(&mut self, rip: u64) -> Result<()> {
         trace!("reset(): {:#010x}", rip);
         crate::timer!("vm_reset");
+
+        let (rax, rbx, rdi): (u64, u64, u64) = self.reset_registers();
+
+        for vcpu in self.vcpus.iter_mut() {
+            vcpu.reset(rip, rax, rbx, rdi)?;
+        }
+
+        self.entry = Some(rip);
+
+        Ok(())
+    }
+
+    // Computes the `(rax, rbx, rdi)` register values that every virtual processor is reset to,
+    // encoding the initrd location/size and the kernel command-line address that this virtual
+    // machine was configured with. Factored out of `Self::reset` so that a live
+    // `VmRequest::Reset`, served by `Self::run` after the virtual processors have been handed off
+    // to their host threads, can recompute the same values without needing `&mut self`.
+    fn reset_registers(&self) -> (u64, u64, u64) {
         let rax: u64 = config::MICROVM_MAGIC as u64;
 
         // Encode initrd location and size:
@@ -176,7 +366,39 @@ impl MicroVm {
         };
         let rbx: u64 = (initrd_base & 0xfffff000) | ((initrd_size >> 12) & 0xfff);
 
-        self.vcpu.reset(rip, rax, rbx)
+        // Hand the kernel command-line address to the guest, if one was set.
+        let rdi: u64 = self.cmdline.unwrap_or(0);
+
+        (rax, rbx, rdi)
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Reboots the virtual machine, re-arming every virtual processor at the entry point that was
+    /// passed to the last call to [`Self::reset`]. This lets a guest-triggered reset (surfaced as
+    /// [`VirtualProcessorExitReason::Shutdown`] from [`Self::run`]) restart the machine instead of
+    /// tearing down the monitor.
+    ///
+    /// # Returns
+    ///
+    /// Upon successful completion, this method returns empty. Otherwise, it returns an error.
+    ///
+    pub fn reboot(&mut self) -> Result<()> {
+        trace!("reboot()");
+        crate::timer!("vm_reboot");
+
+        let rip: u64 = match self.entry {
+            Some(rip) => rip,
+            None => {
+                let reason: String =
+                    "cannot reboot a virtual machine that was never reset".to_string();
+                error!("reboot(): {}", reason);
+                return Err(anyhow::anyhow!(reason));
+            },
+        };
+
+        self.reset(rip)
     }
 
     ///
@@ -184,35 +406,532 @@ impl MicroVm {
     ///
     /// Runs the virtual machine.
     ///
+    /// One host thread is spawned per virtual processor, each looping on
+    /// [`VirtualProcessor::run`] and funneling PMIO/MMIO exits through the [`Emulator`] shared by
+    /// every thread. When any vCPU powers off (be it by a guest request or a run-time error), every
+    /// other vCPU thread is sent [`control::STOP_SIGNAL`] to unblock it from `KVM_RUN`, so that the
+    /// whole virtual machine shuts down together.
+    ///
+    /// If `gdb_addr` is set, the first virtual processor is held back from that thread pool: it is
+    /// paused on its reset `rip` and handed to a [`GdbStub`] bound to `gdb_addr`, which blocks this
+    /// call until a debugger attaches and later detaches, before the thread pool above starts.
+    ///
+    /// An extra host thread always runs alongside the vCPU thread pool, dispatching every
+    /// `VmRequest` decoded from a guest write to [`Self::VMM_PORT`] as well as any submitted by a
+    /// clone of the same sender held by a gateway peer (e.g. [`crate::http::HttpServer`]). If
+    /// `control_path` is set, a second extra host thread additionally serves a
+    /// [`ControlSocket`] bound to that path, letting an orchestrator pause, resume, reset, or
+    /// hot-attach event sources to the virtual machine over a Unix domain socket instead. If
+    /// `debug_addr` is set, a third extra host thread serves a [`Debugger`] bound to that address,
+    /// letting a developer inspect the guest's memory over a separate TCP control port for
+    /// bring-up and crash triage. Every such thread is joined together with the vCPU threads once a
+    /// `VmRequest::Exit` is received or the virtual machine shuts down on its own.
+    ///
+    /// # Parameters
+    ///
+    /// - `gdb_addr`: Address to serve a GDB Remote Serial Protocol stub on before running the
+    ///   virtual machine, letting a debugger attach to the first virtual processor from its reset
+    ///   state. If `None`, the virtual machine runs immediately.
+    /// - `control_path`: Path of the Unix domain socket to serve a runtime control channel on. If
+    ///   `None`, no control channel is served.
+    /// - `debug_addr`: Address to serve a [`Debugger`] control port on, alongside the vCPU threads.
+    ///   If `None`, no debugger control port is served.
+    ///
     /// # Returns
     ///
     /// Upon successful completion, this method returns empty. Otherwise, it returns an error.
     ///
-    pub fn run(&mut self) -> Result<()> {
-        trace!("run()");
+    pub fn run(
+        &mut self,
+        gdb_addr: Option<SocketAddr>,
+        control_path: Option<String>,
+        debug_addr: Option<SocketAddr>,
+    ) -> Result<()> {
+        trace!(
+            "run(): gdb_addr={:?}, control_path={:?}, debug_addr={:?}",
+            gdb_addr,
+            control_path,
+            debug_addr
+        );
         crate::timer!("vm_run");
 
-        // Run the virtual processor until it goes offline.
-        while self.vcpu.is_online() {
-            let exit_context: VirtualProcessorExitContext = self.vcpu.run()?;
-
-            // Parse exit reason.
-            match exit_context.reason() {
-                // The guest requested to access an I/O port.
-                VirtualProcessorExitReason::PmioAccess => {
-                    crate::timer!("vm_run_pmio_access");
-                    if self.emulator.handle_pmio_access(exit_context)? == false {
-                        self.vcpu.poweroff();
+        Self::register_stop_handler()?;
+
+        if let Some(gdb_addr) = gdb_addr {
+            crate::timer!("vm_run_gdbstub");
+            let vcpu: &mut VirtualProcessor = self
+                .vcpus
+                .get_mut(0)
+                .ok_or_else(|| anyhow::anyhow!("cannot debug a virtual machine with no vcpus"))?;
+            GdbStub::bind(gdb_addr)?.serve(vcpu, &self.vmem, &self.emulator)?;
+        }
+
+        // Set once any vCPU thread leaves its run loop, so that the others stop retrying after
+        // being interrupted by `control::STOP_SIGNAL` rather than re-entering `KVM_RUN`.
+        let stop: Arc<AtomicBool> = Arc::new(AtomicBool::new(false));
+        // Set by a `VmRequest::Pause` and cleared by a `VmRequest::Resume`; every vCPU thread waits
+        // on the paired condition variable while it is set.
+        let paused: Arc<(Mutex<bool>, Condvar)> = Arc::new((Mutex::new(false), Condvar::new()));
+        // Host threads backing each vCPU, so that the one that triggers the shutdown (or a control
+        // request) can signal every other one.
+        let threads: Arc<Mutex<Vec<::libc::pthread_t>>> =
+            Arc::new(Mutex::new(Vec::with_capacity(self.vcpus.len())));
+
+        // Every virtual processor, shared with the control threads below so that a
+        // `VmRequest::Reset` can reach it after ownership would otherwise have moved into its vCPU
+        // thread's closure.
+        let vcpus: Vec<Arc<Mutex<VirtualProcessor>>> =
+            self.vcpus.drain(..).map(|vcpu| Arc::new(Mutex::new(vcpu))).collect();
+
+        // `(rip, rax, rbx, rdi)` that a `VmRequest::Reset` re-arms every virtual processor to,
+        // shared by every source of a `VmRequest` below. By this point `self.entry` is always set,
+        // since every caller resets or restores the virtual machine before calling `Self::run`.
+        let reset_args: (u64, u64, u64, u64) = match self.entry {
+            Some(rip) => {
+                let (rax, rbx, rdi): (u64, u64, u64) = self.reset_registers();
+                (rip, rax, rbx, rdi)
+            },
+            None => {
+                let reason: String =
+                    "cannot run a virtual machine that was never reset".to_string();
+                error!("run(): {}", reason);
+                return Err(anyhow::anyhow!(reason));
+            },
+        };
+
+        let mut handles: Vec<thread::JoinHandle<Result<()>>> = Vec::with_capacity(vcpus.len());
+        for vcpu in vcpus.iter().cloned() {
+            let emulator: Arc<Mutex<Emulator>> = self.emulator.clone();
+            let stop: Arc<AtomicBool> = stop.clone();
+            let paused: Arc<(Mutex<bool>, Condvar)> = paused.clone();
+            let threads: Arc<Mutex<Vec<::libc::pthread_t>>> = threads.clone();
+
+            handles.push(thread::spawn(move || -> Result<()> {
+                threads.lock().unwrap().push(unsafe { ::libc::pthread_self() });
+
+                // Run the loop body in its own closure so that every exit path — the `while`
+                // loop's normal fallthrough, and every `?`/`return Err(...)` below — funnels
+                // through the same `result` variable, and the stop-broadcast bookkeeping after it
+                // always runs, instead of only on the happy path.
+                let result: Result<()> = (|| {
+                    // Run the virtual processor until it, or another vCPU, takes it offline.
+                    while vcpu.lock().unwrap().is_online() && !stop.load(Ordering::SeqCst) {
+                        // Block here for as long as a `VmRequest::Pause` is in effect, waking up on a
+                        // `VmRequest::Resume` or on `stop` being set.
+                        {
+                            let (lock, cvar): &(Mutex<bool>, Condvar) = &*paused;
+                            let mut guard = lock.lock().unwrap();
+                            while *guard && !stop.load(Ordering::SeqCst) {
+                                guard = cvar.wait(guard).unwrap();
+                            }
+                        }
+                        if stop.load(Ordering::SeqCst) {
+                            break;
+                        }
+
+                        let exit_context: VirtualProcessorExitContext = vcpu.lock().unwrap().run()?;
+
+                        // Parse exit reason.
+                        match exit_context.reason() {
+                            // Unblocked by `control::STOP_SIGNAL`; loop back around to re-check `stop`
+                            // and the pause flag.
+                            VirtualProcessorExitReason::Interrupted => continue,
+
+                            // The guest requested to access an I/O port.
+                            VirtualProcessorExitReason::PmioAccess => {
+                                crate::timer!("vm_run_pmio_access");
+                                let resume: bool =
+                                    emulator.lock().unwrap().handle_pmio_access(exit_context)?;
+                                if !resume {
+                                    vcpu.lock().unwrap().poweroff();
+                                }
+                            },
+
+                            // The guest requested to access a memory-mapped device.
+                            VirtualProcessorExitReason::MmioAccess => {
+                                crate::timer!("vm_run_mmio_access");
+                                let resume: bool =
+                                    emulator.lock().unwrap().handle_mmio_access(exit_context)?;
+                                if !resume {
+                                    vcpu.lock().unwrap().poweroff();
+                                }
+                            },
+
+                            // The guest halted or shut itself down (e.g. triple fault): take the vCPU
+                            // offline and let the post-loop bookkeeping below broadcast the stop signal
+                            // to the others, so the whole virtual machine winds down together.
+                            VirtualProcessorExitReason::Halt | VirtualProcessorExitReason::Shutdown => {
+                                vcpu.lock().unwrap().poweroff();
+                            },
+
+                            // The virtual processor failed to enter guest mode: propagate the captured
+                            // hardware fault details as an error.
+                            VirtualProcessorExitReason::FailEntry => match exit_context {
+                                VirtualProcessorExitContext::FailEntry(reason, cpu) => {
+                                    return Err(anyhow::anyhow!(
+                                        "vcpu failed to enter guest mode (reason={:#018x}, cpu={})",
+                                        reason,
+                                        cpu
+                                    ));
+                                },
+                                _ => unreachable!("fail entry reason without a matching exit context"),
+                            },
+
+                            // Guest-debug exit with no debugger attached (e.g. a leftover single-step
+                            // trap from a `GdbStub` that detached without resuming to completion):
+                            // there is nothing to report it to, so just keep the vCPU running.
+                            VirtualProcessorExitReason::Debug => {
+                                warn!("run(): unexpected guest-debug exit with no debugger attached");
+                            },
+
+                            // The hypervisor reported an internal error.
+                            VirtualProcessorExitReason::InternalError => {
+                                return Err(anyhow::anyhow!("vcpu internal error"));
+                            },
+
+                            // Virtual machine exited due to an unknown reason.
+                            VirtualProcessorExitReason::Unknown => {
+                                return Err(anyhow::anyhow!("unknown exit reason"));
+                            },
+                        }
+                    }
+
+                    Ok(())
+                })();
+
+                // This vCPU is going offline, whether cleanly or because `result` carries an
+                // error: make sure every other one (and the control thread, if any) stops too, so
+                // a real error on one vCPU can never leave the others (and `Self::run`'s join loop
+                // below) waiting forever on a sibling that was never told to stop.
+                stop.store(true, Ordering::SeqCst);
+                paused.1.notify_all();
+                control::broadcast_stop(&threads);
+
+                result
+            }));
+        }
+
+        // Drains `VmRequest`s decoded from a guest write to `Self::VMM_PORT` (see
+        // `crate::kvm::emulator::Emulator::handle_pmio_access`) and from any gateway peer holding a
+        // clone of the same sender, carrying each out with the exact same logic `ControlSocket`
+        // uses below. Unlike `ControlSocket`, there is no reply path back to either source, so a
+        // `VmResponse::Err` is only logged.
+        {
+            crate::timer!("vm_run_gateway_control");
+            let partition: Arc<VirtualPartition> = self.partition.clone();
+            let vcpus: Vec<Arc<Mutex<VirtualProcessor>>> = vcpus.clone();
+            let stop: Arc<AtomicBool> = stop.clone();
+            let paused: Arc<(Mutex<bool>, Condvar)> = paused.clone();
+            let threads: Arc<Mutex<Vec<::libc::pthread_t>>> = threads.clone();
+            let control_rx: mpsc::Receiver<control::VmRequest> = self
+                .control_rx
+                .take()
+                .ok_or_else(|| anyhow::anyhow!("gateway control channel already taken"))?;
+
+            handles.push(thread::spawn(move || -> Result<()> {
+                // Wait with a timeout instead of blocking in `recv()`, so that `stop` is noticed
+                // even when no further request arrives: `emulator` keeps a sender alive.
+                loop {
+                    let request: control::VmRequest =
+                        match control_rx.recv_timeout(::std::time::Duration::from_millis(50)) {
+                            Ok(request) => request,
+                            Err(mpsc::RecvTimeoutError::Timeout) => {
+                                if stop.load(Ordering::SeqCst) {
+                                    break;
+                                }
+                                continue;
+                            },
+                            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+                        };
+                    let exit_requested: bool = matches!(request, control::VmRequest::Exit);
+                    let response: control::VmResponse = control::dispatch(
+                        request,
+                        &partition,
+                        &vcpus,
+                        reset_args,
+                        &stop,
+                        &paused,
+                        &threads,
+                    );
+                    if let control::VmResponse::Err(reason) = response {
+                        warn!("run(): gateway control request has failed: {}", reason);
                     }
-                },
+                    if exit_requested || stop.load(Ordering::SeqCst) {
+                        break;
+                    }
+                }
+                Ok(())
+            }));
+        }
+
+        if let Some(control_path) = control_path {
+            crate::timer!("vm_run_control");
+            let partition: Arc<VirtualPartition> = self.partition.clone();
+            let vcpus: Vec<Arc<Mutex<VirtualProcessor>>> = vcpus.clone();
+            let stop: Arc<AtomicBool> = stop.clone();
+            let paused: Arc<(Mutex<bool>, Condvar)> = paused.clone();
+            let threads: Arc<Mutex<Vec<::libc::pthread_t>>> = threads.clone();
+
+            handles.push(thread::spawn(move || -> Result<()> {
+                let control: ControlSocket = ControlSocket::bind(&control_path)?;
+                control.serve(&partition, &vcpus, reset_args, &stop, &paused, &threads)
+            }));
+        }
+
+        if let Some(debug_addr) = debug_addr {
+            crate::timer!("vm_run_debug");
+            // `VirtualMemory::ptr()` and `VirtualMemory::size()` describe the main RAM region as a
+            // raw host pointer, which is not `Send`; round-trip it through a `usize` to cross the
+            // thread boundary, then cast it back before handing it to `Debugger::new`.
+            let destination: usize = self.vmem.ptr() as usize;
+            let size: usize = self.vmem.size();
+
+            handles.push(thread::spawn(move || -> Result<()> {
+                let mut debugger: Debugger =
+                    unsafe { Debugger::new(destination as *mut std::ffi::c_void, size) };
+                debugger.run(debug_addr)
+            }));
+        }
+
+        for handle in handles {
+            handle
+                .join()
+                .map_err(|_| anyhow::anyhow!("vcpu thread panicked"))??;
+        }
+
+        // Every vCPU thread, and every control thread sharing its `Arc`, has now joined, so each
+        // `Arc<Mutex<VirtualProcessor>>` above has exactly one owner left: this one. Reclaim the
+        // virtual processors into `self.vcpus` so that `Self::snapshot` can still serialize their
+        // final state once the virtual machine has shut down.
+        self.vcpus = vcpus
+            .into_iter()
+            .map(|vcpu| {
+                Arc::try_unwrap(vcpu)
+                    .map_err(|_| anyhow::anyhow!("vcpu still shared after its thread joined"))
+                    .map(|mutex| mutex.into_inner().unwrap())
+            })
+            .collect::<Result<Vec<VirtualProcessor>>>()?;
+
+        Ok(())
+    }
+
+    /// Installs a no-op handler for [`control::STOP_SIGNAL`], so that delivering it to a vCPU
+    /// thread blocked in `KVM_RUN` interrupts the ioctl instead of terminating the process.
+    fn register_stop_handler() -> Result<()> {
+        extern "C" fn handler(_signum: ::libc::c_int) {}
+
+        let ret: ::libc::sighandler_t =
+            unsafe { ::libc::signal(control::STOP_SIGNAL, handler as ::libc::sighandler_t) };
+        if ret == ::libc::SIG_ERR {
+            let reason: String = "failed to register vcpu stop signal handler".to_string();
+            error!("register_stop_handler(): {}", reason);
+            return Err(anyhow::anyhow!(reason));
+        }
+
+        Ok(())
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Freezes the current state of the virtual machine to `path`, so that it may later be
+    /// resumed with [`Self::restore`], possibly on another host.
+    ///
+    /// The on-disk format is a small header (magic, version, memory size, vcpu count, register
+    /// blob length, incremental flag, initrd location) followed by one online-flag-plus-register
+    /// blob per virtual processor, in `vcpu_count` order, and then a memory image. Passing
+    /// `incremental = true` writes only the pages that changed since the last call that queried
+    /// the dirty log (the previous [`Self::snapshot`] call, full or incremental, or, for the first
+    /// one, the creation of the virtual machine), instead of a full copy of guest memory; see
+    /// [`crate::kvm::vmem::VirtualMemory::get_dirty_log`].
+    ///
+    /// Callers driving this from a running virtual machine (e.g. a live `VmRequest` served by
+    /// [`Self::run`]) must pause every vCPU first, and must drain any in-flight gateway `Message`
+    /// still sitting in the guest's input queue before doing so — the I/O port emulation does not
+    /// itself persist queued messages, so one left in flight across a snapshot/restore cycle would
+    /// be silently lost.
+    ///
+    /// # Parameters
+    ///
+    /// - `path`: Path of the file to write the snapshot to.
+    /// - `incremental`: Whether to write only the pages that changed since the log was last
+    ///   queried, rather than a full memory image.
+    ///
+    /// # Returns
+    ///
+    /// Upon successful completion, this method returns empty. Otherwise, it returns an error.
+    ///
+    pub fn snapshot(&self, path: &str, incremental: bool) -> Result<()> {
+        trace!("snapshot(): path={}, incremental={}", path, incremental);
+        crate::timer!("vm_snapshot");
 
-                // Virtual machine exited due to an unknown reason.
-                VirtualProcessorExitReason::Unknown => {
-                    return Err(anyhow::anyhow!("unknown exit reason"));
-                },
+        let states: Vec<(bool, VirtualProcessorState)> = self
+            .vcpus
+            .iter()
+            .map(|vcpu| Ok((vcpu.is_online(), vcpu.save_state()?)))
+            .collect::<Result<Vec<(bool, VirtualProcessorState)>>>()?;
+        let register_blob_len: u64 = mem::size_of::<VirtualProcessorState>() as u64;
+
+        let (initrd_base, initrd_size): (u64, u64) = match self.initrd {
+            Some((base, size)) => (base, size as u64),
+            None => (0, 0),
+        };
+
+        let mut file: File = File::create(path)?;
+        file.write_all(&SNAPSHOT_MAGIC.to_le_bytes())?;
+        file.write_all(&SNAPSHOT_VERSION.to_le_bytes())?;
+        file.write_all(&(self.vmem.size() as u64).to_le_bytes())?;
+        file.write_all(&(states.len() as u64).to_le_bytes())?;
+        file.write_all(&register_blob_len.to_le_bytes())?;
+        file.write_all(&[incremental as u8])?;
+        file.write_all(&initrd_base.to_le_bytes())?;
+        file.write_all(&initrd_size.to_le_bytes())?;
+
+        for (online, state) in &states {
+            let register_blob: &[u8] = unsafe {
+                ::std::slice::from_raw_parts(
+                    state as *const VirtualProcessorState as *const u8,
+                    mem::size_of::<VirtualProcessorState>(),
+                )
+            };
+            file.write_all(&[*online as u8])?;
+            file.write_all(register_blob)?;
+        }
+
+        if incremental {
+            let dirty_log: Vec<u64> = self.vmem.get_dirty_log()?;
+            self.vmem.dump_dirty(&mut file, &dirty_log)?;
+        } else {
+            // Clear the dirty log, so that the next incremental snapshot carries only the pages
+            // written after this full one.
+            self.vmem.get_dirty_log()?;
+            self.vmem.dump(&mut file)?;
+        }
+
+        Ok(())
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Restores the virtual machine from a snapshot previously written by [`Self::snapshot`].
+    /// The virtual machine must have been created with the same memory size as the snapshot.
+    /// Also sets [`Self::entry`] from the restored bootstrap processor's `rip`, the same as
+    /// [`Self::reset`] would have, so that [`Self::run`] and [`Self::reboot`] work on a restored
+    /// virtual machine exactly as they would on one that was freshly reset.
+    ///
+    /// # Parameters
+    ///
+    /// - `path`: Path of the snapshot file to restore from.
+    ///
+    /// # Returns
+    ///
+    /// Upon successful completion, this method returns empty. Otherwise, it returns an error.
+    ///
+    pub fn restore(&mut self, path: &str) -> Result<()> {
+        trace!("restore(): path={}", path);
+        crate::timer!("vm_restore");
+
+        let mut file: File = File::open(path)?;
+
+        let mut u32_buf: [u8; 4] = [0; 4];
+        let mut u64_buf: [u8; 8] = [0; 8];
+
+        file.read_exact(&mut u32_buf)?;
+        let magic: u32 = u32::from_le_bytes(u32_buf);
+        if magic != SNAPSHOT_MAGIC {
+            let reason: String = format!("not a MicroVM snapshot (magic={:#010x})", magic);
+            error!("restore(): {}", reason);
+            return Err(anyhow::anyhow!(reason));
+        }
+
+        file.read_exact(&mut u32_buf)?;
+        let version: u32 = u32::from_le_bytes(u32_buf);
+        if version != SNAPSHOT_VERSION {
+            let reason: String = format!("unsupported snapshot version (version={})", version);
+            error!("restore(): {}", reason);
+            return Err(anyhow::anyhow!(reason));
+        }
+
+        file.read_exact(&mut u64_buf)?;
+        let memory_size: u64 = u64::from_le_bytes(u64_buf);
+        if memory_size as usize != self.vmem.size() {
+            let reason: String = format!(
+                "snapshot memory size does not match virtual machine (snapshot={}, vm={})",
+                memory_size,
+                self.vmem.size()
+            );
+            error!("restore(): {}", reason);
+            return Err(anyhow::anyhow!(reason));
+        }
+
+        file.read_exact(&mut u64_buf)?;
+        let vcpu_count: u64 = u64::from_le_bytes(u64_buf);
+        if vcpu_count as usize != self.vcpus.len() {
+            let reason: String = format!(
+                "snapshot vcpu count does not match virtual machine (snapshot={}, vm={})",
+                vcpu_count,
+                self.vcpus.len()
+            );
+            error!("restore(): {}", reason);
+            return Err(anyhow::anyhow!(reason));
+        }
+
+        file.read_exact(&mut u64_buf)?;
+        let register_blob_len: u64 = u64::from_le_bytes(u64_buf);
+        if register_blob_len as usize != mem::size_of::<VirtualProcessorState>() {
+            let reason: String = "snapshot register blob has unexpected size".to_string();
+            error!("restore(): {}", reason);
+            return Err(anyhow::anyhow!(reason));
+        }
+
+        let mut flags: [u8; 1] = [0; 1];
+        file.read_exact(&mut flags)?;
+        let incremental: bool = flags[0] != 0;
+
+        file.read_exact(&mut u64_buf)?;
+        let initrd_base: u64 = u64::from_le_bytes(u64_buf);
+        file.read_exact(&mut u64_buf)?;
+        let initrd_size: u64 = u64::from_le_bytes(u64_buf);
+
+        for (index, vcpu) in self.vcpus.iter_mut().enumerate() {
+            let mut online_flag: [u8; 1] = [0; 1];
+            file.read_exact(&mut online_flag)?;
+            let online: bool = online_flag[0] != 0;
+
+            let mut state: VirtualProcessorState = vcpu.save_state()?;
+            let state_bytes: &mut [u8] = unsafe {
+                ::std::slice::from_raw_parts_mut(
+                    &mut state as *mut VirtualProcessorState as *mut u8,
+                    mem::size_of::<VirtualProcessorState>(),
+                )
+            };
+            file.read_exact(state_bytes)?;
+            vcpu.load_state(&state)?;
+            vcpu.set_online(online);
+
+            // `Self::run`'s `reset_args` and `Self::reboot` both require `self.entry` to be set,
+            // the same as a fresh `Self::reset` would have set it; mirror that here using the
+            // bootstrap processor's restored `rip`, so a guest-triggered reboot after `-restore`
+            // re-arms every vCPU at the point the snapshot was taken at, instead of wherever it
+            // last happened to be when the snapshot was written.
+            if index == 0 {
+                self.entry = Some(state.regs.rip);
             }
         }
 
+        self.initrd = if initrd_size > 0 {
+            Some((initrd_base, initrd_size as usize))
+        } else {
+            None
+        };
+
+        if incremental {
+            self.vmem.load_dirty(&mut file)?;
+        } else {
+            self.vmem.load(&mut file)?;
+        }
+
         Ok(())
     }
 }
@@ -13,12 +13,18 @@ pub const PROGRAM_NAME: &str = "microvm";
 /// Default memory size.
 pub const DEFAULT_MEMORY_SIZE: usize = 128 * 1024 * 1024;
 
+/// Default number of virtual processors.
+pub const DEFAULT_VCPU_COUNT: usize = 1;
+
 /// Magic value that identifies the virtual machine monitor.
 pub const MICROVM_MAGIC: u32 = 0x0c00ffee;
 
 /// Base address of the RAM disk.
 pub const INITRD_BASE: usize = 0x00800000;
 
+/// Base address of the kernel command-line blob.
+pub const CMDLINE_BASE: usize = 0x00020000;
+
 /// I/O port that is connected to the standard output of the virtual machine.
 pub const STDOUT_PORT: u16 = 0xe9;
 
@@ -0,0 +1,428 @@
+// Copyright(c) The Maintainers of Nanvix.
+// Licensed under the MIT License.
+
+//!
+//! # Windows Hypervisor Platform Dispatch Table
+//!
+//! `WinHvPlatform.dll`/`WinHvEmulation.dll` are only present, and only loadable, on Windows
+//! editions/SKUs that shipped the Windows Hypervisor Platform and that have it enabled (the
+//! "Windows Hypervisor Platform" optional feature). Every other [`crate::mshv`] module used to
+//! call into these two DLLs through the `windows` crate's link-time imports, which means the
+//! whole process fails to start - with an unhelpful loader error - anywhere the feature is
+//! missing or disabled.
+//!
+//! This module instead resolves every entry point this backend needs with `LoadLibraryW`/
+//! `GetProcAddress` the first time [`dispatch`] is called, caching the result so the cost is paid
+//! once. If either DLL, or any symbol in it, cannot be resolved, [`dispatch`] returns
+//! [`WhpDispatch::UNAVAILABLE_MESSAGE`] instead of crashing, so a caller (ultimately [`crate::main`]) can
+//! report a clean diagnostic and exit.
+//!
+
+//==================================================================================================
+// Imports
+//==================================================================================================
+
+use ::anyhow::Result;
+use ::std::sync::OnceLock;
+use ::windows::{
+    core::{
+        s,
+        w,
+        HRESULT,
+        PCSTR,
+    },
+    Win32::System::{
+        Hypervisor::{
+            WHV_EMULATOR_CALLBACKS,
+            WHV_EMULATOR_STATUS,
+            WHV_PARTITION_HANDLE,
+            WHV_PARTITION_PROPERTY_CODE,
+            WHV_REGISTER_NAME,
+            WHV_REGISTER_VALUE,
+            WHV_MEMORY_ACCESS_CONTEXT,
+            WHV_TRANSLATE_GVA_FLAGS,
+            WHV_TRANSLATE_GVA_RESULT,
+            WHV_VP_EXIT_CONTEXT,
+            WHV_X64_IO_PORT_ACCESS_CONTEXT,
+        },
+        LibraryLoader::{
+            GetProcAddress,
+            LoadLibraryW,
+        },
+    },
+};
+
+//==================================================================================================
+// Function Pointer Types
+//==================================================================================================
+
+/// One function pointer type alias per entry point this backend resolves, named after the export
+/// it is bound to. Signatures mirror the prototypes `windows-rs` generates for the same APIs, so
+/// that swapping a link-time call (`Hypervisor::WHvFoo(...)`) for a dispatch-table one
+/// (`dispatch()?.WHvFoo(...)`) is a drop-in replacement at every call site.
+type FnWHvCreatePartition = unsafe extern "system" fn(Partition: *mut WHV_PARTITION_HANDLE) -> HRESULT;
+type FnWHvSetPartitionProperty = unsafe extern "system" fn(
+    Partition: WHV_PARTITION_HANDLE,
+    PropertyCode: WHV_PARTITION_PROPERTY_CODE,
+    PropertyBuffer: *const ::std::ffi::c_void,
+    PropertyBufferSizeInBytes: u32,
+) -> HRESULT;
+type FnWHvSetupPartition = unsafe extern "system" fn(Partition: WHV_PARTITION_HANDLE) -> HRESULT;
+type FnWHvDeletePartition = unsafe extern "system" fn(Partition: WHV_PARTITION_HANDLE) -> HRESULT;
+type FnWHvMapGpaRange = unsafe extern "system" fn(
+    Partition: WHV_PARTITION_HANDLE,
+    ProcessBuffer: *const ::std::ffi::c_void,
+    GuestAddress: u64,
+    SizeInBytes: u64,
+    Flags: u32,
+) -> HRESULT;
+type FnWHvUnmapGpaRange = unsafe extern "system" fn(
+    Partition: WHV_PARTITION_HANDLE,
+    GuestAddress: u64,
+    SizeInBytes: u64,
+) -> HRESULT;
+type FnWHvCreateVirtualProcessor =
+    unsafe extern "system" fn(Partition: WHV_PARTITION_HANDLE, VpIndex: u32, Flags: u32) -> HRESULT;
+type FnWHvDeleteVirtualProcessor =
+    unsafe extern "system" fn(Partition: WHV_PARTITION_HANDLE, VpIndex: u32) -> HRESULT;
+type FnWHvSetVirtualProcessorRegisters = unsafe extern "system" fn(
+    Partition: WHV_PARTITION_HANDLE,
+    VpIndex: u32,
+    RegisterNames: *const WHV_REGISTER_NAME,
+    RegisterCount: u32,
+    RegisterValues: *const WHV_REGISTER_VALUE,
+) -> HRESULT;
+type FnWHvGetVirtualProcessorRegisters = unsafe extern "system" fn(
+    Partition: WHV_PARTITION_HANDLE,
+    VpIndex: u32,
+    RegisterNames: *const WHV_REGISTER_NAME,
+    RegisterCount: u32,
+    RegisterValues: *mut WHV_REGISTER_VALUE,
+) -> HRESULT;
+type FnWHvRunVirtualProcessor = unsafe extern "system" fn(
+    Partition: WHV_PARTITION_HANDLE,
+    VpIndex: u32,
+    ExitContext: *mut ::std::ffi::c_void,
+    ExitContextSizeInBytes: u32,
+) -> HRESULT;
+type FnWHvTranslateGva = unsafe extern "system" fn(
+    Partition: WHV_PARTITION_HANDLE,
+    VpIndex: u32,
+    Gva: u64,
+    TranslateFlags: WHV_TRANSLATE_GVA_FLAGS,
+    TranslationResult: *mut WHV_TRANSLATE_GVA_RESULT,
+    Gpa: *mut u64,
+) -> HRESULT;
+type FnWHvEmulatorCreateEmulator = unsafe extern "system" fn(
+    Callbacks: *const WHV_EMULATOR_CALLBACKS,
+    Emulator: *mut *mut ::std::ffi::c_void,
+) -> HRESULT;
+type FnWHvEmulatorTryIoEmulation = unsafe extern "system" fn(
+    Emulator: *mut ::std::ffi::c_void,
+    Context: *const ::std::ffi::c_void,
+    VpContext: *const WHV_VP_EXIT_CONTEXT,
+    IoInstructionContext: *const WHV_X64_IO_PORT_ACCESS_CONTEXT,
+    EmulatorReturnStatus: *mut WHV_EMULATOR_STATUS,
+) -> HRESULT;
+type FnWHvEmulatorTryMmioEmulation = unsafe extern "system" fn(
+    Emulator: *mut ::std::ffi::c_void,
+    Context: *const ::std::ffi::c_void,
+    VpContext: *const WHV_VP_EXIT_CONTEXT,
+    MemoryAccessContext: *const WHV_MEMORY_ACCESS_CONTEXT,
+    EmulatorReturnStatus: *mut WHV_EMULATOR_STATUS,
+) -> HRESULT;
+
+//==================================================================================================
+// Structures
+//==================================================================================================
+
+/// A resolved table of `WinHvPlatform.dll`/`WinHvEmulation.dll` entry points. Obtained through
+/// [`dispatch`], never constructed directly.
+#[allow(non_snake_case)]
+pub struct WhpDispatch {
+    WHvCreatePartition: FnWHvCreatePartition,
+    WHvSetPartitionProperty: FnWHvSetPartitionProperty,
+    WHvSetupPartition: FnWHvSetupPartition,
+    WHvDeletePartition: FnWHvDeletePartition,
+    WHvMapGpaRange: FnWHvMapGpaRange,
+    WHvUnmapGpaRange: FnWHvUnmapGpaRange,
+    WHvCreateVirtualProcessor: FnWHvCreateVirtualProcessor,
+    WHvDeleteVirtualProcessor: FnWHvDeleteVirtualProcessor,
+    WHvSetVirtualProcessorRegisters: FnWHvSetVirtualProcessorRegisters,
+    WHvGetVirtualProcessorRegisters: FnWHvGetVirtualProcessorRegisters,
+    WHvRunVirtualProcessor: FnWHvRunVirtualProcessor,
+    WHvTranslateGva: FnWHvTranslateGva,
+    WHvEmulatorCreateEmulator: FnWHvEmulatorCreateEmulator,
+    WHvEmulatorTryIoEmulation: FnWHvEmulatorTryIoEmulation,
+    WHvEmulatorTryMmioEmulation: FnWHvEmulatorTryMmioEmulation,
+}
+
+#[allow(non_snake_case)]
+impl WhpDispatch {
+    /// Error surfaced by [`dispatch`] whenever either DLL, or any symbol inside it, fails to
+    /// resolve - covering both "this Windows edition/SKU never shipped WHP" and "WHP is present
+    /// but the optional feature is turned off".
+    const UNAVAILABLE_MESSAGE: &'static str =
+        "Windows Hypervisor Platform not available / not enabled";
+
+    fn load() -> Result<Self> {
+        trace!("load(): resolving WinHvPlatform.dll / WinHvEmulation.dll");
+
+        let platform = unsafe { LoadLibraryW(w!("WinHvPlatform.dll")) }
+            .map_err(|_| anyhow::anyhow!(Self::UNAVAILABLE_MESSAGE))?;
+        let emulation = unsafe { LoadLibraryW(w!("WinHvEmulation.dll")) }
+            .map_err(|_| anyhow::anyhow!(Self::UNAVAILABLE_MESSAGE))?;
+
+        // Resolves `$name` out of `$module` and transmutes it into `$ty`, bailing out with
+        // `Self::UNAVAILABLE_MESSAGE` rather than panicking if the export is missing.
+        macro_rules! resolve {
+            ($module:expr, $name:literal, $ty:ty) => {{
+                let proc = unsafe { GetProcAddress($module, PCSTR(s!($name).as_ptr())) }
+                    .ok_or_else(|| anyhow::anyhow!(Self::UNAVAILABLE_MESSAGE))?;
+                unsafe { ::std::mem::transmute::<_, $ty>(proc) }
+            }};
+        }
+
+        Ok(Self {
+            WHvCreatePartition: resolve!(platform, "WHvCreatePartition", FnWHvCreatePartition),
+            WHvSetPartitionProperty: resolve!(
+                platform,
+                "WHvSetPartitionProperty",
+                FnWHvSetPartitionProperty
+            ),
+            WHvSetupPartition: resolve!(platform, "WHvSetupPartition", FnWHvSetupPartition),
+            WHvDeletePartition: resolve!(platform, "WHvDeletePartition", FnWHvDeletePartition),
+            WHvMapGpaRange: resolve!(platform, "WHvMapGpaRange", FnWHvMapGpaRange),
+            WHvUnmapGpaRange: resolve!(platform, "WHvUnmapGpaRange", FnWHvUnmapGpaRange),
+            WHvCreateVirtualProcessor: resolve!(
+                platform,
+                "WHvCreateVirtualProcessor",
+                FnWHvCreateVirtualProcessor
+            ),
+            WHvDeleteVirtualProcessor: resolve!(
+                platform,
+                "WHvDeleteVirtualProcessor",
+                FnWHvDeleteVirtualProcessor
+            ),
+            WHvSetVirtualProcessorRegisters: resolve!(
+                platform,
+                "WHvSetVirtualProcessorRegisters",
+                FnWHvSetVirtualProcessorRegisters
+            ),
+            WHvGetVirtualProcessorRegisters: resolve!(
+                platform,
+                "WHvGetVirtualProcessorRegisters",
+                FnWHvGetVirtualProcessorRegisters
+            ),
+            WHvRunVirtualProcessor: resolve!(
+                platform,
+                "WHvRunVirtualProcessor",
+                FnWHvRunVirtualProcessor
+            ),
+            WHvTranslateGva: resolve!(platform, "WHvTranslateGva", FnWHvTranslateGva),
+            WHvEmulatorCreateEmulator: resolve!(
+                emulation,
+                "WHvEmulatorCreateEmulator",
+                FnWHvEmulatorCreateEmulator
+            ),
+            WHvEmulatorTryIoEmulation: resolve!(
+                emulation,
+                "WHvEmulatorTryIoEmulation",
+                FnWHvEmulatorTryIoEmulation
+            ),
+            WHvEmulatorTryMmioEmulation: resolve!(
+                emulation,
+                "WHvEmulatorTryMmioEmulation",
+                FnWHvEmulatorTryMmioEmulation
+            ),
+        })
+    }
+
+    pub fn create_partition(&self) -> Result<WHV_PARTITION_HANDLE> {
+        let mut partition: WHV_PARTITION_HANDLE = WHV_PARTITION_HANDLE::default();
+        unsafe { (self.WHvCreatePartition)(&mut partition) }.ok()?;
+        Ok(partition)
+    }
+
+    pub fn set_partition_property(
+        &self,
+        partition: WHV_PARTITION_HANDLE,
+        property_code: WHV_PARTITION_PROPERTY_CODE,
+        property: *const ::std::ffi::c_void,
+        size: u32,
+    ) -> Result<()> {
+        unsafe { (self.WHvSetPartitionProperty)(partition, property_code, property, size) }.ok()?;
+        Ok(())
+    }
+
+    pub fn setup_partition(&self, partition: WHV_PARTITION_HANDLE) -> Result<()> {
+        unsafe { (self.WHvSetupPartition)(partition) }.ok()?;
+        Ok(())
+    }
+
+    pub fn delete_partition(&self, partition: WHV_PARTITION_HANDLE) -> Result<()> {
+        unsafe { (self.WHvDeletePartition)(partition) }.ok()?;
+        Ok(())
+    }
+
+    pub fn map_gpa_range(
+        &self,
+        partition: WHV_PARTITION_HANDLE,
+        process_buffer: *const ::std::ffi::c_void,
+        guest_address: u64,
+        size: u64,
+        flags: u32,
+    ) -> Result<()> {
+        unsafe { (self.WHvMapGpaRange)(partition, process_buffer, guest_address, size, flags) }
+            .ok()?;
+        Ok(())
+    }
+
+    pub fn unmap_gpa_range(
+        &self,
+        partition: WHV_PARTITION_HANDLE,
+        guest_address: u64,
+        size: u64,
+    ) -> Result<()> {
+        unsafe { (self.WHvUnmapGpaRange)(partition, guest_address, size) }.ok()?;
+        Ok(())
+    }
+
+    pub fn create_virtual_processor(
+        &self,
+        partition: WHV_PARTITION_HANDLE,
+        vp_index: u32,
+        flags: u32,
+    ) -> Result<()> {
+        unsafe { (self.WHvCreateVirtualProcessor)(partition, vp_index, flags) }.ok()?;
+        Ok(())
+    }
+
+    pub fn delete_virtual_processor(
+        &self,
+        partition: WHV_PARTITION_HANDLE,
+        vp_index: u32,
+    ) -> Result<()> {
+        unsafe { (self.WHvDeleteVirtualProcessor)(partition, vp_index) }.ok()?;
+        Ok(())
+    }
+
+    pub fn set_virtual_processor_registers(
+        &self,
+        partition: WHV_PARTITION_HANDLE,
+        vp_index: u32,
+        names: *const WHV_REGISTER_NAME,
+        count: u32,
+        values: *const WHV_REGISTER_VALUE,
+    ) -> Result<()> {
+        unsafe {
+            (self.WHvSetVirtualProcessorRegisters)(partition, vp_index, names, count, values)
+        }
+        .ok()?;
+        Ok(())
+    }
+
+    pub fn get_virtual_processor_registers(
+        &self,
+        partition: WHV_PARTITION_HANDLE,
+        vp_index: u32,
+        names: *const WHV_REGISTER_NAME,
+        count: u32,
+        values: *mut WHV_REGISTER_VALUE,
+    ) -> Result<()> {
+        unsafe {
+            (self.WHvGetVirtualProcessorRegisters)(partition, vp_index, names, count, values)
+        }
+        .ok()?;
+        Ok(())
+    }
+
+    pub fn run_virtual_processor(
+        &self,
+        partition: WHV_PARTITION_HANDLE,
+        vp_index: u32,
+        exit_context: *mut ::std::ffi::c_void,
+        exit_context_size: u32,
+    ) -> Result<()> {
+        unsafe {
+            (self.WHvRunVirtualProcessor)(partition, vp_index, exit_context, exit_context_size)
+        }
+        .ok()?;
+        Ok(())
+    }
+
+    pub fn translate_gva(
+        &self,
+        partition: WHV_PARTITION_HANDLE,
+        vp_index: u32,
+        gva: u64,
+        flags: WHV_TRANSLATE_GVA_FLAGS,
+        result: *mut WHV_TRANSLATE_GVA_RESULT,
+        gpa: *mut u64,
+    ) -> Result<()> {
+        unsafe { (self.WHvTranslateGva)(partition, vp_index, gva, flags, result, gpa) }.ok()?;
+        Ok(())
+    }
+
+    pub fn emulator_create_emulator(
+        &self,
+        callbacks: *const WHV_EMULATOR_CALLBACKS,
+        emulator: *mut *mut ::std::ffi::c_void,
+    ) -> Result<()> {
+        unsafe { (self.WHvEmulatorCreateEmulator)(callbacks, emulator) }.ok()?;
+        Ok(())
+    }
+
+    pub fn emulator_try_io_emulation(
+        &self,
+        emulator: *mut ::std::ffi::c_void,
+        context: *const ::std::ffi::c_void,
+        vp_context: *const WHV_VP_EXIT_CONTEXT,
+        io_context: *const WHV_X64_IO_PORT_ACCESS_CONTEXT,
+    ) -> Result<WHV_EMULATOR_STATUS> {
+        let mut status: WHV_EMULATOR_STATUS = WHV_EMULATOR_STATUS::default();
+        unsafe {
+            (self.WHvEmulatorTryIoEmulation)(emulator, context, vp_context, io_context, &mut status)
+        }
+        .ok()?;
+        Ok(status)
+    }
+
+    pub fn emulator_try_mmio_emulation(
+        &self,
+        emulator: *mut ::std::ffi::c_void,
+        context: *const ::std::ffi::c_void,
+        vp_context: *const WHV_VP_EXIT_CONTEXT,
+        memory_context: *const WHV_MEMORY_ACCESS_CONTEXT,
+    ) -> Result<WHV_EMULATOR_STATUS> {
+        let mut status: WHV_EMULATOR_STATUS = WHV_EMULATOR_STATUS::default();
+        unsafe {
+            (self.WHvEmulatorTryMmioEmulation)(
+                emulator,
+                context,
+                vp_context,
+                memory_context,
+                &mut status,
+            )
+        }
+        .ok()?;
+        Ok(status)
+    }
+}
+
+//==================================================================================================
+// Standalone Functions
+//==================================================================================================
+
+static DISPATCH: OnceLock<::std::result::Result<WhpDispatch, String>> = OnceLock::new();
+
+/// Returns the process-wide [`WhpDispatch`], resolving it on first use. Every [`crate::mshv`]
+/// module that used to call `Hypervisor::WHv*` directly should go through this instead, so that a
+/// host missing the Windows Hypervisor Platform fails with
+/// [`WhpDispatch::UNAVAILABLE_MESSAGE`] instead of refusing to even start the process.
+pub fn dispatch() -> Result<&'static WhpDispatch> {
+    DISPATCH
+        .get_or_init(|| WhpDispatch::load().map_err(|e| e.to_string()))
+        .as_ref()
+        .map_err(|e| anyhow::anyhow!(e.clone()))
+}
@@ -6,7 +6,7 @@
 //==================================================================================================
 
 use crate::mshv::{
-    partition::VirtualPartition,
+    dispatch,
     vcpu::{
         MshvRegisters,
         VirtualExitProcessorContext,
@@ -15,11 +15,13 @@ use crate::mshv::{
 };
 use ::anyhow::Result;
 use ::std::{
+    cell::Cell,
     fmt::{
         self,
         Formatter,
     },
     ptr,
+    rc::Rc,
     slice,
 };
 use ::windows::{
@@ -147,28 +149,208 @@ impl fmt::Display for MshvEmulatorStatus {
     }
 }
 
+//==================================================================================================
+// Port I/O Devices
+//==================================================================================================
+
+///
+/// # Description
+///
+/// A virtual device mapped onto a range of the guest's port I/O space, owned by an [`Emulator`]'s
+/// registry. Replaces the fixed `input`/`output` closures that [`io_port_emulator`] used to
+/// dispatch by hand, so that a new VMM service (an RTC, a debug console, a structured hypercall
+/// device, ...) can be plugged in without touching the emulation core.
+///
+pub trait PortIoDevice {
+    /// Services a read of `size` bytes from `port`, returning the value read.
+    fn read(&mut self, port: u16, size: u32) -> Result<u32>;
+
+    /// Services a write of `size` bytes of `value` to `port`.
+    fn write(&mut self, port: u16, size: u32, value: u32) -> Result<()>;
+}
+
+///
+/// # Description
+///
+/// A [`PortIoDevice`], registered over a range of the guest's port I/O space.
+///
+struct PortIoRegistration {
+    /// First port the device owns.
+    base: u16,
+    /// Number of consecutive ports the device owns.
+    len: u16,
+    /// Device instance.
+    device: Box<dyn PortIoDevice>,
+}
+
+///
+/// # Description
+///
+/// A virtual device mapped onto a range of the guest's physical address space, owned by an
+/// [`Emulator`]'s registry, symmetric to [`PortIoDevice`] on the port I/O side. This is the
+/// prerequisite for exposing any MMIO device (virtio, the local APIC page, a framebuffer) to the
+/// guest through [`mmio_emulator`].
+///
+pub trait MmioDevice {
+    /// Services a read of `data.len()` bytes at `offset` into the device's registered range.
+    fn read(&mut self, offset: u64, data: &mut [u8]) -> Result<()>;
+
+    /// Services a write of `data` at `offset` into the device's registered range.
+    fn write(&mut self, offset: u64, data: &[u8]) -> Result<()>;
+}
+
+///
+/// # Description
+///
+/// An [`MmioDevice`], registered over a range of the guest's physical address space.
+///
+struct MmioRegistration {
+    /// Guest physical address at which the device is mapped.
+    base: u64,
+    /// Size, in bytes, of the device's range.
+    len: u64,
+    /// Device instance.
+    device: Box<dyn MmioDevice>,
+}
+
+///
+/// # Description
+///
+/// Device backing the guest's stdin port: a read invokes `input`, which is expected to
+/// stash the bytes it read wherever the caller's convention keeps them, and to report their count
+/// back as the value [`io_port_emulator`] writes into the guest's accumulator.
+///
+pub struct StdinDevice {
+    input: Box<dyn FnMut(u32) -> Result<u32>>,
+}
+
+impl StdinDevice {
+    pub fn new(input: Box<dyn FnMut(u32) -> Result<u32>>) -> Self {
+        Self { input }
+    }
+}
+
+impl PortIoDevice for StdinDevice {
+    fn read(&mut self, _port: u16, size: u32) -> Result<u32> {
+        (self.input)(size)
+    }
+
+    fn write(&mut self, port: u16, _size: u32, _value: u32) -> Result<()> {
+        anyhow::bail!("write to read-only port (port={:#06x})", port)
+    }
+}
+
+///
+/// # Description
+///
+/// Device backing the guest's stdout port: a write hands `value` to `output`.
+///
+pub struct StdoutDevice {
+    output: Box<dyn FnMut(u32) -> Result<()>>,
+}
+
+impl StdoutDevice {
+    pub fn new(output: Box<dyn FnMut(u32) -> Result<()>>) -> Self {
+        Self { output }
+    }
+}
+
+impl PortIoDevice for StdoutDevice {
+    fn read(&mut self, port: u16, _size: u32) -> Result<u32> {
+        anyhow::bail!("read from write-only port (port={:#06x})", port)
+    }
+
+    fn write(&mut self, _port: u16, _size: u32, value: u32) -> Result<()> {
+        (self.output)(value)
+    }
+}
+
+///
+/// # Description
+///
+/// Device backing the guest's hypercall port: a write asks the virtual processor to
+/// power off. A [`PortIoDevice`] has no direct access to the [`VirtualProcessor`] it is wired to,
+/// so the request is recorded in [`Self::poweroff_requested`] instead, for [`io_port_emulator`] to
+/// act on once it has dispatched the write.
+///
+pub struct HypercallDevice {
+    poweroff_requested: Rc<Cell<bool>>,
+}
+
+impl HypercallDevice {
+    pub fn new(poweroff_requested: Rc<Cell<bool>>) -> Self {
+        Self { poweroff_requested }
+    }
+}
+
+impl PortIoDevice for HypercallDevice {
+    fn read(&mut self, port: u16, _size: u32) -> Result<u32> {
+        anyhow::bail!("read from write-only port (port={:#06x})", port)
+    }
+
+    fn write(&mut self, _port: u16, _size: u32, _value: u32) -> Result<()> {
+        self.poweroff_requested.set(true);
+        Ok(())
+    }
+}
+
 //==================================================================================================
 // Public Structures
 //==================================================================================================
 
 pub struct Emulator {
     handle: *mut std::ffi::c_void,
-    input: Box<dyn FnMut(u32) -> Result<()>>,
-    output: Box<dyn FnMut(u32) -> Result<()>>,
+    /// Devices registered over the guest's port I/O space, via [`Self::new`].
+    devices: Vec<PortIoRegistration>,
+    /// Devices registered over the guest's physical address space, via [`Self::new`].
+    mmio_devices: Vec<MmioRegistration>,
+    /// Shared with whichever registered device backs the guest's hypercall port (e.g.
+    /// [`HypercallDevice`]), so that [`io_port_emulator`] can tell a request to power the vCPU off
+    /// apart from an ordinary device write, without the [`PortIoDevice`] trait needing to expose
+    /// the [`VirtualProcessor`] it has no business touching.
+    poweroff_requested: Rc<Cell<bool>>,
 }
 
 impl Emulator {
     pub fn new(
-        input: Box<dyn FnMut(u32) -> Result<()>>,
-        output: Box<dyn FnMut(u32) -> Result<()>>,
+        poweroff_requested: Rc<Cell<bool>>,
+        devices: Vec<(u16, u16, Box<dyn PortIoDevice>)>,
+        mmio_devices: Vec<(u64, u64, Box<dyn MmioDevice>)>,
     ) -> Result<Self> {
         let mut handle: *mut std::ffi::c_void = ptr::null_mut();
-        unsafe { Hypervisor::WHvEmulatorCreateEmulator(&CALLBACKS, &mut handle)? };
+        dispatch::dispatch()?.emulator_create_emulator(&CALLBACKS, &mut handle)?;
+
+        let mut registrations: Vec<PortIoRegistration> = devices
+            .into_iter()
+            .map(|(base, len, device)| PortIoRegistration { base, len, device })
+            .collect();
+        registrations.sort_by_key(|registration| registration.base);
+
+        let mut mmio_registrations: Vec<MmioRegistration> = mmio_devices
+            .into_iter()
+            .map(|(base, len, device)| MmioRegistration { base, len, device })
+            .collect();
+        mmio_registrations.sort_by_key(|registration| registration.base);
 
         Ok(Self {
             handle,
-            input,
-            output,
+            devices: registrations,
+            mmio_devices: mmio_registrations,
+            poweroff_requested,
+        })
+    }
+
+    /// Finds the index of the device, if any, that owns `port`.
+    fn find_port(&self, port: u16) -> Option<usize> {
+        self.devices.iter().position(|registration| {
+            port >= registration.base && port < registration.base + registration.len
+        })
+    }
+
+    /// Finds the index of the device, if any, whose range covers `gpa`.
+    fn find_mmio(&self, gpa: u64) -> Option<usize> {
+        self.mmio_devices.iter().position(|registration| {
+            gpa >= registration.base && gpa < registration.base + registration.len
         })
     }
 
@@ -180,20 +362,46 @@ impl Emulator {
         // let context = partition as *const _ as *const std::ffi::c_void;
         let handle: *mut std::ffi::c_void = self.handle;
         let context = MshvEmulatorContext::new(self, vcpu);
-        unsafe {
-            let status: WHV_EMULATOR_STATUS = Hypervisor::WHvEmulatorTryIoEmulation(
-                handle,
-                context.as_ptr(),
-                exit_context.vp_context() as *const WHV_VP_EXIT_CONTEXT,
-                exit_context.pmio_context(),
-            )?;
-
-            if MshvEmulatorStatus::from(status) != MshvEmulatorStatus::EmulationSuccessful {
-                return Err(anyhow::anyhow!(
-                    "failed to emulate pmio access (status={})",
-                    MshvEmulatorStatus::from(status)
-                ));
-            }
+        let status: WHV_EMULATOR_STATUS = dispatch::dispatch()?.emulator_try_io_emulation(
+            handle,
+            context.as_ptr(),
+            exit_context.vp_context() as *const WHV_VP_EXIT_CONTEXT,
+            exit_context.pmio_context(),
+        )?;
+
+        if MshvEmulatorStatus::from(status) != MshvEmulatorStatus::EmulationSuccessful {
+            return Err(anyhow::anyhow!(
+                "failed to emulate pmio access (status={})",
+                MshvEmulatorStatus::from(status)
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Services a [`crate::mshv::vcpu::VirtualProcessorExitReason::MmioAccess`] exit, symmetric to
+    /// [`Self::handle_pmio_access`]: hands the exit off to `WHvEmulatorTryMmioEmulation`, which
+    /// decodes the faulting instruction and dispatches to [`mmio_emulator`] for every byte it
+    /// touches.
+    pub fn handle_mmio_access(
+        &mut self,
+        vcpu: &mut VirtualProcessor,
+        exit_context: VirtualExitProcessorContext,
+    ) -> Result<()> {
+        let handle: *mut std::ffi::c_void = self.handle;
+        let context = MshvEmulatorContext::new(self, vcpu);
+        let status: WHV_EMULATOR_STATUS = dispatch::dispatch()?.emulator_try_mmio_emulation(
+            handle,
+            context.as_ptr(),
+            exit_context.vp_context() as *const WHV_VP_EXIT_CONTEXT,
+            exit_context.mmio_context(),
+        )?;
+
+        if MshvEmulatorStatus::from(status) != MshvEmulatorStatus::EmulationSuccessful {
+            return Err(anyhow::anyhow!(
+                "failed to emulate mmio access (status={})",
+                MshvEmulatorStatus::from(status)
+            ));
         }
 
         Ok(())
@@ -216,59 +424,112 @@ extern "system" fn io_port_emulator(
         let size = (*ioaccess).AccessSize;
         let direction = (*ioaccess).Direction;
 
+        let index: usize = match emulator.find_port(port) {
+            Some(index) => index,
+            None => {
+                return HRESULT(1);
+            },
+        };
+        let registration: &mut PortIoRegistration = &mut emulator.devices[index];
+
         match direction {
-            0 => match port {
-                VirtualPartition::STDIN_PORT => {
-                    if let Err(_) = (emulator.input)(size as u32) {
-                        return HRESULT(1);
-                    }
-                },
-                _ => {
+            0 => match registration.device.read(port, size) {
+                Ok(value) => (*ioaccess).Data = value,
+                Err(_) => {
                     return HRESULT(1);
                 },
             },
-            1 => match port {
-                VirtualPartition::STDOUT_PORT => {
-                    if let Err(_) = (emulator.output)((*ioaccess).Data) {
-                        return HRESULT(1);
-                    }
-                },
-                VirtualPartition::HYPERCALL_PORT => {
-                    vcpu.poweroff();
-                },
-                _ => {
+            1 => {
+                if let Err(_) = registration.device.write(port, size, (*ioaccess).Data) {
                     return HRESULT(1);
-                },
+                }
             },
             _ => {
                 return HRESULT(1);
             },
         }
+
+        if emulator.poweroff_requested.replace(false) {
+            vcpu.poweroff();
+        }
     }
 
     HRESULT(0)
 }
 
-#[allow(unused)]
+/// Dispatches a memory-mapped access to the owning registered [`MmioDevice`], symmetric to
+/// [`io_port_emulator`] on the port I/O side.
 extern "system" fn mmio_emulator(
     context: *const std::ffi::c_void,
     mmioaccess: *mut Hypervisor::WHV_EMULATOR_MEMORY_ACCESS_INFO,
 ) -> HRESULT {
-    // TODO: implement this functionality, if required.
+    unsafe {
+        let context: &mut MshvEmulatorContext = MshvEmulatorContext::from_raw(context);
+        let emulator: &mut Emulator = context.emulator;
+        let gpa: u64 = (*mmioaccess).GpaAddress;
+        let size: usize = (*mmioaccess).AccessSize as usize;
+        let direction = (*mmioaccess).Direction;
+
+        let index: usize = match emulator.find_mmio(gpa) {
+            Some(index) => index,
+            None => {
+                return HRESULT(1);
+            },
+        };
+        let registration: &mut MmioRegistration = &mut emulator.mmio_devices[index];
+        let offset: u64 = gpa - registration.base;
+
+        match direction {
+            0 => {
+                if let Err(_) = registration
+                    .device
+                    .read(offset, &mut (*mmioaccess).Data[..size])
+                {
+                    return HRESULT(1);
+                }
+            },
+            1 => {
+                if let Err(_) = registration
+                    .device
+                    .write(offset, &(*mmioaccess).Data[..size])
+                {
+                    return HRESULT(1);
+                }
+            },
+            _ => {
+                return HRESULT(1);
+            },
+        }
+    }
 
-    HRESULT(1)
+    HRESULT(0)
 }
 
-#[allow(unused)]
+/// Reads back the vCPU's registers via [`VirtualProcessor::get_registers`], so the emulator can
+/// decode instructions that depend on current register state (e.g. a `rep movs` needing `rcx`).
 extern "system" fn get_virtual_processor_registers(
     context: *const std::ffi::c_void,
     names: *const Hypervisor::WHV_REGISTER_NAME,
     name_count: u32,
     values: *mut Hypervisor::WHV_REGISTER_VALUE,
 ) -> HRESULT {
-    // TODO: implement this functionality, if required.
+    unsafe {
+        let context: &mut MshvEmulatorContext = MshvEmulatorContext::from_raw(context);
+        let vcpu: &VirtualProcessor = context.vcpu;
+        let names: &[Hypervisor::WHV_REGISTER_NAME] =
+            slice::from_raw_parts(names, name_count as usize);
 
-    HRESULT(1)
+        match vcpu.get_registers(names) {
+            Ok(read) => {
+                ptr::copy_nonoverlapping(read.as_ptr(), values, read.len());
+            },
+            Err(_) => {
+                return HRESULT(1);
+            },
+        }
+    };
+
+    HRESULT(0)
 }
 
 extern "system" fn set_virtual_processor_registers(
@@ -292,7 +553,8 @@ extern "system" fn set_virtual_processor_registers(
     HRESULT(0)
 }
 
-#[allow(unused)]
+/// Resolves a guest virtual address into a guest physical address via
+/// [`VirtualProcessor::translate_gva`], so the emulator can walk guest page tables.
 extern "system" fn translate_gva_page(
     context: *const std::ffi::c_void,
     gva: u64,
@@ -300,6 +562,21 @@ extern "system" fn translate_gva_page(
     translationresult: *mut Hypervisor::WHV_TRANSLATE_GVA_RESULT_CODE,
     gpa: *mut u64,
 ) -> HRESULT {
+    unsafe {
+        let context: &mut MshvEmulatorContext = MshvEmulatorContext::from_raw(context);
+        let vcpu: &VirtualProcessor = context.vcpu;
+
+        match vcpu.translate_gva(gva, translateflags) {
+            Ok((result_code, translated_gpa)) => {
+                *translationresult = result_code;
+                *gpa = translated_gpa;
+            },
+            Err(_) => {
+                return HRESULT(1);
+            },
+        }
+    }
+
     HRESULT(0)
 }
 
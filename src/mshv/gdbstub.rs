@@ -0,0 +1,431 @@
+// Copyright(c) The Maintainers of Nanvix.
+// Licensed under the MIT License.
+
+//!
+//! # GDB Remote Serial Protocol Stub
+//!
+//! This module exposes a [`VirtualProcessor`] over the GDB Remote Serial Protocol (RSP), so that
+//! `gdb`/`lldb` can attach to a running guest for bring-up and crash triage. It speaks just enough
+//! of the protocol to read general-purpose and segment registers, write general-purpose ones (a
+//! write to a segment register is accepted and ignored), read/write guest memory
+//! through a [`VirtualMemory`], single-step, continue, and set/clear breakpoints.
+//!
+
+//==================================================================================================
+// Imports
+//==================================================================================================
+
+use crate::mshv::{
+    partition::VirtualPartition,
+    vcpu::{
+        MshvRegisters,
+        VirtualProcessor,
+        VirtualProcessorExitReason,
+    },
+    vmem::VirtualMemory,
+};
+use ::anyhow::Result;
+use ::std::{
+    io::{
+        Read,
+        Write,
+    },
+    net::{
+        TcpListener,
+        TcpStream,
+    },
+    sync::{
+        Arc,
+        Mutex,
+    },
+};
+use ::windows::Win32::System::Hypervisor;
+
+//==================================================================================================
+// Constants
+//==================================================================================================
+
+/// Vector of the breakpoint exception (#BP), used both to arm the partition's exception exit
+/// bitmap and to recognize a software breakpoint trap on the way back out.
+const VECTOR_BP: u8 = 3;
+/// Vector of the debug exception (#DB), raised by the trap flag after a single step and by
+/// hardware (debug register) breakpoints.
+const VECTOR_DB: u8 = 1;
+
+/// Opcode of the `int3` instruction that software breakpoints are patched in with.
+const INT3: u8 = 0xcc;
+
+/// Registers exposed over the wire, in the order expected by `gdb`'s built-in `i386` target
+/// (used whenever a stub does not advertise a `target.xml`): general-purpose registers, `eip`,
+/// `eflags`, then the segment selectors.
+const WIRE_REGISTERS: [Hypervisor::WHV_REGISTER_NAME; 16] = [
+    Hypervisor::WHvX64RegisterRax,
+    Hypervisor::WHvX64RegisterRcx,
+    Hypervisor::WHvX64RegisterRdx,
+    Hypervisor::WHvX64RegisterRbx,
+    Hypervisor::WHvX64RegisterRsp,
+    Hypervisor::WHvX64RegisterRbp,
+    Hypervisor::WHvX64RegisterRsi,
+    Hypervisor::WHvX64RegisterRdi,
+    Hypervisor::WHvX64RegisterRip,
+    Hypervisor::WHvX64RegisterRflags,
+    Hypervisor::WHvX64RegisterCs,
+    Hypervisor::WHvX64RegisterSs,
+    Hypervisor::WHvX64RegisterDs,
+    Hypervisor::WHvX64RegisterEs,
+    Hypervisor::WHvX64RegisterFs,
+    Hypervisor::WHvX64RegisterGs,
+];
+/// Index, within [`WIRE_REGISTERS`], of the first segment register. Segment registers carry a
+/// full descriptor cache on Hyper-V, so a selector value received from `gdb` alone is not enough
+/// to reconstruct one; writes to these registers are accepted but ignored (see [`GdbStub::write_registers`]).
+const FIRST_SEGMENT_REGISTER: usize = 10;
+
+//==================================================================================================
+// Structures
+//==================================================================================================
+
+/// A software breakpoint that is currently patched into guest memory.
+struct Breakpoint {
+    addr: u64,
+    original_byte: u8,
+}
+
+///
+/// A GDB Remote Serial Protocol server for a single [`VirtualProcessor`].
+///
+pub struct GdbStub {
+    listener: TcpListener,
+    breakpoints: Vec<Breakpoint>,
+}
+
+//==================================================================================================
+// Implementations
+//==================================================================================================
+
+impl GdbStub {
+    pub fn bind(partition: Arc<Mutex<VirtualPartition>>, port: u16) -> Result<Self> {
+        trace!("bind(): port={}", port);
+
+        let listener: TcpListener = TcpListener::bind(("127.0.0.1", port))?;
+
+        // Trap #BP (software breakpoints) and #DB (single-step, hardware breakpoints) out to us
+        // instead of letting the partition inject them back into the guest.
+        partition
+            .lock()
+            .unwrap()
+            .set_exception_exit_bitmap((1 << VECTOR_BP) | (1 << VECTOR_DB))?;
+
+        Ok(Self {
+            listener,
+            breakpoints: Vec::new(),
+        })
+    }
+
+    /// Blocks waiting for a debugger to connect, then serves it until it detaches. The vCPU is
+    /// halted for the whole duration of the connection except while explicitly resumed by a
+    /// `continue`/`step` request, so stop/step requests are always serialized against execution.
+    pub fn serve(&mut self, vcpu: &VirtualProcessor, vmem: &VirtualMemory) -> Result<()> {
+        let (mut stream, addr) = self.listener.accept()?;
+        trace!("serve(): debugger attached (addr={:?})", addr);
+
+        loop {
+            let packet: Vec<u8> = match Self::read_packet(&mut stream)? {
+                Some(packet) => packet,
+                // Connection closed without a `D`etach.
+                None => return Ok(()),
+            };
+
+            match self.dispatch(&mut stream, &packet, vcpu, vmem)? {
+                Dispatch::Continue => continue,
+                Dispatch::Detach => {
+                    self.remove_all_breakpoints(vmem)?;
+                    return Ok(());
+                },
+            }
+        }
+    }
+
+    fn dispatch(
+        &mut self,
+        stream: &mut TcpStream,
+        packet: &[u8],
+        vcpu: &VirtualProcessor,
+        vmem: &VirtualMemory,
+    ) -> Result<Dispatch> {
+        let reply: String = match packet.first() {
+            Some(b'?') => "S05".to_string(),
+            Some(b'g') => self.read_registers(vcpu)?,
+            Some(b'G') => {
+                self.write_registers(vcpu, &packet[1..])?;
+                "OK".to_string()
+            },
+            Some(b'm') => self.read_memory(vmem, &packet[1..])?,
+            Some(b'M') => {
+                self.write_memory(vmem, &packet[1..])?;
+                "OK".to_string()
+            },
+            Some(b'Z') => {
+                self.set_breakpoint(vmem, &packet[1..])?;
+                "OK".to_string()
+            },
+            Some(b'z') => {
+                self.clear_breakpoint(vmem, &packet[1..])?;
+                "OK".to_string()
+            },
+            Some(b's') => {
+                self.resume(vcpu, vmem, true)?;
+                "S05".to_string()
+            },
+            Some(b'c') => {
+                self.resume(vcpu, vmem, false)?;
+                "S05".to_string()
+            },
+            Some(b'D') => {
+                Self::write_packet(stream, "OK")?;
+                return Ok(Dispatch::Detach);
+            },
+            // Unsupported command: an empty reply tells `gdb` the feature is not implemented.
+            _ => String::new(),
+        };
+
+        Self::write_packet(stream, &reply)?;
+        Ok(Dispatch::Continue)
+    }
+
+    /// Resumes the vCPU, either for a single instruction (`step`) or until the next breakpoint
+    /// or single-step trap (`continue`).
+    fn resume(&self, vcpu: &VirtualProcessor, vmem: &VirtualMemory, step: bool) -> Result<()> {
+        self.set_trap_flag(vcpu, step)?;
+
+        loop {
+            let exit = vcpu.run()?;
+            match exit.reason() {
+                VirtualProcessorExitReason::Exception => {
+                    let vector: u8 = exit.exception_context().ExceptionType.0 as u8;
+                    if vector == VECTOR_BP {
+                        self.rewind_past_breakpoint(vcpu, vmem)?;
+                    }
+                    return Ok(());
+                },
+                // The guest halted or triple-faulted; the monitor's run loop (not this debugger)
+                // is responsible for tearing the partition down. Stop resuming so the caller
+                // observes it instead of spinning on a dead vCPU.
+                VirtualProcessorExitReason::Halt | VirtualProcessorExitReason::Shutdown => {
+                    return Ok(());
+                },
+                // Unlike PMIO, CPUID/MSR exits need a result written back before the guest can
+                // make progress.
+                VirtualProcessorExitReason::Cpuid => {
+                    vcpu.handle_cpuid_exit(&exit)?;
+                    if step {
+                        return Ok(());
+                    }
+                    continue;
+                },
+                VirtualProcessorExitReason::MsrAccess => {
+                    vcpu.handle_msr_exit(&exit)?;
+                    if step {
+                        return Ok(());
+                    }
+                    continue;
+                },
+                // Any other exit (e.g. PMIO) does not concern the debugger; keep running.
+                VirtualProcessorExitReason::PmioAccess | VirtualProcessorExitReason::Unknown => {
+                    if step {
+                        return Ok(());
+                    }
+                    continue;
+                },
+            }
+        }
+    }
+
+    /// Sets or clears the trap flag (bit 8 of `rflags`) so that the next instruction raises #DB.
+    fn set_trap_flag(&self, vcpu: &VirtualProcessor, enabled: bool) -> Result<()> {
+        let names: [Hypervisor::WHV_REGISTER_NAME; 1] = [Hypervisor::WHvX64RegisterRflags];
+        let mut values = vcpu.get_registers(&names)?;
+        let rflags: u64 = unsafe { values[0].Reg64 };
+        values[0].Reg64 = if enabled { rflags | (1 << 8) } else { rflags & !(1 << 8) };
+        vcpu.set_registers(&MshvRegisters {
+            names: &names,
+            values: &values,
+        })
+    }
+
+    /// After an `int3` trap, `rip` points one byte past the breakpoint; rewind it so the guest
+    /// resumes on the original instruction.
+    fn rewind_past_breakpoint(&self, vcpu: &VirtualProcessor, _vmem: &VirtualMemory) -> Result<()> {
+        let names: [Hypervisor::WHV_REGISTER_NAME; 1] = [Hypervisor::WHvX64RegisterRip];
+        let mut values = vcpu.get_registers(&names)?;
+        unsafe {
+            values[0].Reg64 -= 1;
+        }
+        vcpu.set_registers(&MshvRegisters {
+            names: &names,
+            values: &values,
+        })
+    }
+
+    fn read_registers(&self, vcpu: &VirtualProcessor) -> Result<String> {
+        let values = vcpu.get_registers(&WIRE_REGISTERS)?;
+        let mut hex: String = String::new();
+        for (i, value) in values.iter().enumerate() {
+            let word: u32 = if i >= FIRST_SEGMENT_REGISTER {
+                unsafe { value.Segment.Selector as u32 }
+            } else {
+                unsafe { value.Reg64 as u32 }
+            };
+            hex.push_str(&encode_hex(&word.to_le_bytes()));
+        }
+        Ok(hex)
+    }
+
+    fn write_registers(&self, vcpu: &VirtualProcessor, hex: &[u8]) -> Result<()> {
+        let bytes: Vec<u8> = decode_hex(std::str::from_utf8(hex)?)?;
+
+        // Segment registers carry a descriptor cache on Hyper-V that a bare selector cannot
+        // reconstruct, so only the general-purpose/rip/rflags prefix is writable.
+        let names: &[Hypervisor::WHV_REGISTER_NAME] = &WIRE_REGISTERS[..FIRST_SEGMENT_REGISTER];
+        let mut values = vcpu.get_registers(names)?;
+        for (i, value) in values.iter_mut().enumerate() {
+            if let Some(word) = bytes.get(i * 4..i * 4 + 4) {
+                value.Reg64 = u32::from_le_bytes(word.try_into()?) as u64;
+            }
+        }
+
+        vcpu.set_registers(&MshvRegisters { names, values: &values })
+    }
+
+    fn read_memory(&self, vmem: &VirtualMemory, args: &[u8]) -> Result<String> {
+        let (addr, len) = Self::parse_addr_len(args)?;
+        let mut data: Vec<u8> = vec![0; len];
+        vmem.read_bytes(addr, &mut data)?;
+        Ok(encode_hex(&data))
+    }
+
+    fn write_memory(&self, vmem: &VirtualMemory, args: &[u8]) -> Result<()> {
+        let args: &str = std::str::from_utf8(args)?;
+        let (header, data) = args.split_once(':').ok_or_else(|| anyhow::anyhow!("malformed M packet"))?;
+        let (addr, len) = Self::parse_addr_len(header.as_bytes())?;
+        let bytes: Vec<u8> = decode_hex(data)?;
+        if bytes.len() != len {
+            anyhow::bail!("M packet length mismatch");
+        }
+        vmem.write_bytes(addr, &bytes)
+    }
+
+    /// `Z`/`z` packets are `<type>,<addr>,<kind>`. Type `0` is a software breakpoint; types `1`-`4`
+    /// are hardware watch/breakpoints, which this stub does not support and rejects.
+    fn set_breakpoint(&mut self, vmem: &VirtualMemory, args: &[u8]) -> Result<()> {
+        let addr: u64 = Self::parse_breakpoint(args)?;
+        if self.breakpoints.iter().any(|bp| bp.addr == addr) {
+            return Ok(());
+        }
+        let mut original_byte: [u8; 1] = [0];
+        vmem.read_bytes(addr, &mut original_byte)?;
+        vmem.write_bytes(addr, &[INT3])?;
+        self.breakpoints.push(Breakpoint {
+            addr,
+            original_byte: original_byte[0],
+        });
+        Ok(())
+    }
+
+    fn clear_breakpoint(&mut self, vmem: &VirtualMemory, args: &[u8]) -> Result<()> {
+        let addr: u64 = Self::parse_breakpoint(args)?;
+        if let Some(i) = self.breakpoints.iter().position(|bp| bp.addr == addr) {
+            let bp: Breakpoint = self.breakpoints.remove(i);
+            vmem.write_bytes(bp.addr, &[bp.original_byte])?;
+        }
+        Ok(())
+    }
+
+    fn remove_all_breakpoints(&mut self, vmem: &VirtualMemory) -> Result<()> {
+        for bp in self.breakpoints.drain(..) {
+            vmem.write_bytes(bp.addr, &[bp.original_byte])?;
+        }
+        Ok(())
+    }
+
+    fn parse_breakpoint(args: &[u8]) -> Result<u64> {
+        let args: &str = std::str::from_utf8(args)?;
+        let mut parts = args.splitn(3, ',');
+        let kind: &str = parts.next().ok_or_else(|| anyhow::anyhow!("malformed Z/z packet"))?;
+        if kind != "0" {
+            anyhow::bail!("unsupported breakpoint kind (kind={})", kind);
+        }
+        let addr: &str = parts.next().ok_or_else(|| anyhow::anyhow!("malformed Z/z packet"))?;
+        Ok(u64::from_str_radix(addr, 16)?)
+    }
+
+    fn parse_addr_len(args: &[u8]) -> Result<(u64, usize)> {
+        let args: &str = std::str::from_utf8(args)?;
+        let (addr, len) = args.split_once(',').ok_or_else(|| anyhow::anyhow!("malformed m/M packet"))?;
+        Ok((u64::from_str_radix(addr, 16)?, usize::from_str_radix(len, 16)?))
+    }
+
+    /// Reads one `$<data>#<checksum>` packet, acknowledging it with `+`. Returns `None` if the
+    /// peer closed the connection.
+    fn read_packet(stream: &mut TcpStream) -> Result<Option<Vec<u8>>> {
+        let mut byte: [u8; 1] = [0];
+        loop {
+            if stream.read(&mut byte)? == 0 {
+                return Ok(None);
+            }
+            if byte[0] == b'$' {
+                break;
+            }
+            // Ignore ack/nack bytes and anything else preceding the next packet.
+        }
+
+        let mut data: Vec<u8> = Vec::new();
+        loop {
+            if stream.read(&mut byte)? == 0 {
+                anyhow::bail!("connection closed mid-packet");
+            }
+            if byte[0] == b'#' {
+                break;
+            }
+            data.push(byte[0]);
+        }
+        // Consume the two-byte checksum; we trust the kernel-level TCP stream's own integrity.
+        let mut checksum: [u8; 2] = [0; 2];
+        stream.read_exact(&mut checksum)?;
+
+        stream.write_all(b"+")?;
+        Ok(Some(data))
+    }
+
+    /// Writes `payload` out as a `$<payload>#<checksum>` packet.
+    fn write_packet(stream: &mut TcpStream, payload: &str) -> Result<()> {
+        let checksum: u8 = payload.bytes().fold(0u8, |acc, b| acc.wrapping_add(b));
+        write!(stream, "${}#{:02x}", payload, checksum)?;
+        stream.flush()?;
+        Ok(())
+    }
+}
+
+/// Outcome of dispatching one packet, telling [`GdbStub::serve`] whether to keep serving the
+/// current connection.
+enum Dispatch {
+    Continue,
+    Detach,
+}
+
+/// Encodes `bytes` as lowercase hex, the wire format that the RSP uses for register and memory
+/// contents.
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Decodes a hex string produced by [`encode_hex`] (or sent by `gdb`) back into bytes.
+fn decode_hex(hex: &str) -> Result<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        anyhow::bail!("odd-length hex string");
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| Ok(u8::from_str_radix(&hex[i..i + 2], 16)?))
+        .collect()
+}
@@ -7,7 +7,11 @@
 //! This module provides the backend implementation of MicroVM for Microsoft Hyper-V.
 //!
 
+pub mod dispatch;
 pub mod emulator;
+pub mod gdbstub;
 pub mod partition;
+pub mod smp;
+pub mod snapshot;
 pub mod vcpu;
 pub mod vmem;
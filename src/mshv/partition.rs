@@ -5,13 +5,12 @@
 // Imports
 //==================================================================================================
 
+use crate::mshv::dispatch;
 use ::anyhow::Result;
-use ::windows::Win32::System::{
-    Hypervisor,
-    Hypervisor::{
-        WHV_PARTITION_HANDLE,
-        WHV_PARTITION_PROPERTY,
-    },
+use ::windows::Win32::System::Hypervisor::{
+    self,
+    WHV_PARTITION_HANDLE,
+    WHV_PARTITION_PROPERTY,
 };
 
 //==================================================================================================
@@ -20,6 +19,7 @@ use ::windows::Win32::System::{
 
 pub struct VirtualPartition {
     partition: WHV_PARTITION_HANDLE,
+    ncpus: u32,
 }
 
 impl VirtualPartition {
@@ -27,41 +27,90 @@ impl VirtualPartition {
     pub const STDIN_PORT: u16 = 0xe9;
     pub const HYPERCALL_PORT: u16 = 0x604;
 
-    pub fn new() -> Result<Self> {
-        let ncpus = 1;
+    pub fn new(ncpus: u32) -> Result<Self> {
         trace!("new(): ncpus={:?}", ncpus);
 
-        let partition: Hypervisor::WHV_PARTITION_HANDLE =
-            unsafe { Hypervisor::WHvCreatePartition()? };
+        let partition: Hypervisor::WHV_PARTITION_HANDLE = dispatch::dispatch()?.create_partition()?;
 
         let mut property: Hypervisor::WHV_PARTITION_PROPERTY = WHV_PARTITION_PROPERTY::default();
-        property.ProcessorCount = ncpus as u32;
+        property.ProcessorCount = ncpus;
 
         // Setup partition property.
-        unsafe {
-            Hypervisor::WHvSetPartitionProperty(
-                partition,
-                Hypervisor::WHvPartitionPropertyCodeProcessorCount,
-                &property as *const _ as *const std::ffi::c_void,
-                std::mem::size_of::<WHV_PARTITION_PROPERTY>() as u32,
-            )?
-        };
-
-        unsafe { Hypervisor::WHvSetupPartition(partition)? };
-
-        Ok(Self { partition })
+        dispatch::dispatch()?.set_partition_property(
+            partition,
+            Hypervisor::WHvPartitionPropertyCodeProcessorCount,
+            &property as *const _ as *const std::ffi::c_void,
+            std::mem::size_of::<WHV_PARTITION_PROPERTY>() as u32,
+        )?;
+
+        // Enable the extended VM exits that this VMM's run loop actually handles: CPUID and MSR
+        // accesses (see `VirtualProcessor::handle_cpuid_exit`/`handle_msr_exit`) and exceptions
+        // (bits 0, 1 and 2), the last of which the GDB stub's exception exit bitmap relies on. I/O
+        // port accesses exit without being enabled here.
+        let mut property: Hypervisor::WHV_PARTITION_PROPERTY = WHV_PARTITION_PROPERTY::default();
+        property.ExtendedVmExits = Hypervisor::WHV_EXTENDED_VM_EXITS::default();
+        property.ExtendedVmExits.AsUINT64 = (1 << 0) | (1 << 1) | (1 << 2);
+
+        dispatch::dispatch()?.set_partition_property(
+            partition,
+            Hypervisor::WHvPartitionPropertyCodeExtendedVmExits,
+            &property as *const _ as *const std::ffi::c_void,
+            std::mem::size_of::<WHV_PARTITION_PROPERTY>() as u32,
+        )?;
+
+        // Request local APIC emulation, which real (non-single-core) guests rely on to receive
+        // timer interrupts and to start secondary vCPUs via INIT/SIPI.
+        let mut property: Hypervisor::WHV_PARTITION_PROPERTY = WHV_PARTITION_PROPERTY::default();
+        property.LocalApicEmulationMode = Hypervisor::WHvX64LocalApicEmulationModeXApic;
+
+        dispatch::dispatch()?.set_partition_property(
+            partition,
+            Hypervisor::WHvPartitionPropertyCodeLocalApicEmulationMode,
+            &property as *const _ as *const std::ffi::c_void,
+            std::mem::size_of::<WHV_PARTITION_PROPERTY>() as u32,
+        )?;
+
+        dispatch::dispatch()?.setup_partition(partition)?;
+
+        Ok(Self { partition, ncpus })
     }
 
     pub fn into_raw(&self) -> WHV_PARTITION_HANDLE {
         self.partition
     }
+
+    /// Returns the number of virtual processors that this partition was configured with, so that
+    /// the run loop knows how many [`crate::mshv::vcpu::VirtualProcessor`]s to create and
+    /// dispatch.
+    pub fn ncpus(&self) -> u32 {
+        self.ncpus
+    }
+
+    /// Arms the partition to exit to user-level on the exceptions set in `bitmap` (one bit per
+    /// vector, e.g. `1 << 1` for #DB and `1 << 3` for #BP), instead of injecting them straight back
+    /// into the guest. Used by the gdbstub to intercept breakpoints and single-step traps.
+    pub fn set_exception_exit_bitmap(&self, bitmap: u64) -> Result<()> {
+        trace!("set_exception_exit_bitmap(): bitmap={:#010x}", bitmap);
+
+        let mut property: Hypervisor::WHV_PARTITION_PROPERTY = WHV_PARTITION_PROPERTY::default();
+        property.ExceptionExitBitmap = bitmap;
+
+        dispatch::dispatch()?.set_partition_property(
+            self.partition,
+            Hypervisor::WHvPartitionPropertyCodeExceptionExitBitmap,
+            &property as *const _ as *const std::ffi::c_void,
+            std::mem::size_of::<WHV_PARTITION_PROPERTY>() as u32,
+        )?;
+
+        Ok(())
+    }
 }
 
 impl Drop for VirtualPartition {
     fn drop(&mut self) {
         trace!("delete partition");
-        unsafe {
-            Hypervisor::WHvDeletePartition(self.partition).unwrap();
+        if let Ok(dispatch) = dispatch::dispatch() {
+            dispatch.delete_partition(self.partition).unwrap();
         }
     }
 }
@@ -0,0 +1,243 @@
+// Copyright(c) The Maintainers of Nanvix.
+// Licensed under the MIT License.
+
+//!
+//! # Symmetric Multiprocessing
+//!
+//! This module brings up every [`VirtualProcessor`] of a [`VirtualPartition`] on its own host
+//! thread, sharing one [`VirtualMemory`]. Unlike [`crate::microvm::MicroVm`] (the `kvm` backend's
+//! run loop, which resets every vCPU to the same `rip` and does not model bring-up), this follows
+//! the INIT/SIPI protocol real x86 platforms use: [`VirtualMachine::BSP`] starts running at the
+//! kernel's entry point while every application processor stays parked until a guest write to
+//! [`VirtualPartition::HYPERCALL_PORT`] hands it a startup vector.
+//!
+
+//==================================================================================================
+// Imports
+//==================================================================================================
+
+use crate::{
+    config,
+    mshv::{
+        emulator::Emulator,
+        partition::VirtualPartition,
+        vcpu::{
+            ProcessorMode,
+            VirtualExitProcessorContext,
+            VirtualProcessor,
+            VirtualProcessorExitReason,
+        },
+        vmem::VirtualMemory,
+    },
+};
+use ::anyhow::Result;
+use ::std::{
+    cell::Cell,
+    rc::Rc,
+    sync::{
+        atomic::{
+            AtomicBool,
+            AtomicU64,
+            Ordering,
+        },
+        Arc,
+        Mutex,
+    },
+    thread::{
+        self,
+        JoinHandle,
+    },
+    time::Duration,
+};
+
+//==================================================================================================
+// Structures
+//==================================================================================================
+
+/// Startup state for one application processor: parked (not [`Self::online`]) until
+/// [`VirtualMachine::handle_hypercall_exit`] observes a startup IPI naming it, at which point
+/// [`Self::entry`] carries the vector it should resume at.
+struct ApSlot {
+    online: AtomicBool,
+    entry: AtomicU64,
+}
+
+impl ApSlot {
+    fn parked() -> Self {
+        Self {
+            online: AtomicBool::new(false),
+            entry: AtomicU64::new(0),
+        }
+    }
+}
+
+/// A symmetric multiprocessing virtual machine: one [`VirtualProcessor`] per host thread, all
+/// sharing one [`VirtualPartition`] and [`VirtualMemory`].
+pub struct VirtualMachine {
+    vmem: Arc<VirtualMemory>,
+    vcpus: Vec<VirtualProcessor>,
+    ap_slots: Arc<Vec<ApSlot>>,
+}
+
+impl VirtualMachine {
+    /// Processor index of the bootstrap processor: the only one [`Self::reset`] starts running
+    /// immediately. Every other index is an application processor that waits for a startup IPI.
+    pub const BSP: u32 = 0;
+
+    /// Interval at which a parked application processor thread wakes up to re-check
+    /// [`ApSlot::online`], since nothing pokes it directly (see [`Self::run`]).
+    const AP_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+    /// Packs a startup IPI word for the guest to write to [`VirtualPartition::HYPERCALL_PORT`]:
+    /// the low byte carries the target processor index, the rest carries the entry point's page
+    /// number (`entry >> 12`), mirroring the vector-to-address shift of a real-mode SIPI.
+    pub fn encode_sipi(target: u32, entry: u64) -> u64 {
+        (target as u64 & 0xff) | ((entry >> 12) << 8)
+    }
+
+    fn decode_sipi(word: u64) -> (u32, u64) {
+        ((word & 0xff) as u32, (word >> 8) << 12)
+    }
+
+    /// Creates a partition with `vcpu_count` virtual processors (at least
+    /// [`config::DEFAULT_VCPU_COUNT`]) and a [`VirtualMemory`] of `memory_size` bytes shared by
+    /// all of them.
+    pub fn new(memory_size: usize, vcpu_count: u32) -> Result<Self> {
+        let vcpu_count: u32 = vcpu_count.max(config::DEFAULT_VCPU_COUNT as u32);
+        trace!("new(): memory_size={}, vcpu_count={}", memory_size, vcpu_count);
+
+        let partition: Arc<Mutex<VirtualPartition>> =
+            Arc::new(Mutex::new(VirtualPartition::new(vcpu_count)?));
+        let vmem: Arc<VirtualMemory> =
+            Arc::new(VirtualMemory::new(partition.clone(), memory_size)?);
+
+        let mut vcpus: Vec<VirtualProcessor> = Vec::with_capacity(vcpu_count as usize);
+        let mut ap_slots: Vec<ApSlot> = Vec::with_capacity(vcpu_count as usize);
+        for index in 0..vcpu_count {
+            vcpus.push(VirtualProcessor::new(partition.clone(), index)?);
+            ap_slots.push(ApSlot::parked());
+        }
+
+        Ok(Self {
+            vmem,
+            vcpus,
+            ap_slots: Arc::new(ap_slots),
+        })
+    }
+
+    pub fn vmem(&self) -> &VirtualMemory {
+        &self.vmem
+    }
+
+    /// Resets [`Self::BSP`] to run at `entry`. Every application processor is left powered off
+    /// (see [`VirtualProcessor::poweroff`]) instead of being reset, so it does not start executing
+    /// until [`Self::run`] sees a startup IPI name it.
+    pub fn reset(&mut self, entry: u64) -> Result<()> {
+        trace!("reset(): entry={:#010x}", entry);
+
+        for (index, vcpu) in self.vcpus.iter_mut().enumerate() {
+            if index as u32 == Self::BSP {
+                vcpu.reset(&self.vmem, ProcessorMode::Long, entry)?;
+            } else {
+                vcpu.poweroff();
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Runs every virtual processor on its own host thread until all of them power off.
+    ///
+    /// The bootstrap processor starts executing immediately. Every application processor thread
+    /// instead polls its [`ApSlot`] (see [`Self::AP_POLL_INTERVAL`]) until a startup IPI sets it
+    /// online, resets itself at the vector it was given, then joins the same dispatch loop as the
+    /// bootstrap processor.
+    pub fn run(mut self) -> Result<()> {
+        trace!("run()");
+
+        let mut handles: Vec<JoinHandle<Result<()>>> = Vec::with_capacity(self.vcpus.len());
+
+        for (index, mut vcpu) in self.vcpus.drain(..).enumerate() {
+            let vmem: Arc<VirtualMemory> = self.vmem.clone();
+            let ap_slots: Arc<Vec<ApSlot>> = self.ap_slots.clone();
+            let is_ap: bool = index as u32 != Self::BSP;
+
+            handles.push(thread::spawn(move || -> Result<()> {
+                if is_ap {
+                    while !ap_slots[index].online.load(Ordering::Acquire) {
+                        thread::sleep(Self::AP_POLL_INTERVAL);
+                    }
+                    let entry: u64 = ap_slots[index].entry.load(Ordering::Acquire);
+                    vcpu.power_on();
+                    vcpu.reset(&vmem, ProcessorMode::Long, entry)?;
+                }
+
+                // No device is registered against this vCPU's emulator: this run loop only
+                // exists to carry a guest through INIT/SIPI bring-up, not to host MMIO devices,
+                // so a real MMIO access still fails, but via `Emulator::handle_mmio_access`'s own
+                // error rather than the blanket `Unknown` exit reason below.
+                let mut emulator: Emulator =
+                    Emulator::new(Rc::new(Cell::new(false)), Vec::new(), Vec::new())?;
+
+                while vcpu.is_online() {
+                    let exit: VirtualExitProcessorContext = vcpu.run()?;
+
+                    match exit.reason() {
+                        VirtualProcessorExitReason::Halt
+                        | VirtualProcessorExitReason::Shutdown => vcpu.poweroff(),
+                        VirtualProcessorExitReason::Cpuid => vcpu.handle_cpuid_exit(&exit)?,
+                        VirtualProcessorExitReason::MsrAccess => vcpu.handle_msr_exit(&exit)?,
+                        VirtualProcessorExitReason::PmioAccess => {
+                            Self::handle_hypercall_exit(&vcpu, &exit, &ap_slots)?;
+                        },
+                        VirtualProcessorExitReason::MmioAccess => {
+                            emulator.handle_mmio_access(&mut vcpu, exit)?;
+                        },
+                        VirtualProcessorExitReason::Exception => vcpu.poweroff(),
+                        VirtualProcessorExitReason::Unknown => {
+                            return Err(anyhow::anyhow!("unknown exit reason"));
+                        },
+                    }
+                }
+
+                Ok(())
+            }));
+        }
+
+        for handle in handles {
+            handle
+                .join()
+                .map_err(|_| anyhow::anyhow!("vcpu thread panicked"))??;
+        }
+
+        Ok(())
+    }
+
+    /// Services a [`VirtualProcessorExitReason::PmioAccess`] exit on
+    /// [`VirtualPartition::HYPERCALL_PORT`] as a startup IPI: decodes the target processor index
+    /// and entry point off the written word (see [`Self::encode_sipi`]) and wakes that
+    /// processor's thread, which is parked in [`Self::run`]. Writes to any other port are
+    /// acknowledged without effect, since this monitor does not model legacy PMIO devices.
+    fn handle_hypercall_exit(
+        vcpu: &VirtualProcessor,
+        exit: &VirtualExitProcessorContext,
+        ap_slots: &Arc<Vec<ApSlot>>,
+    ) -> Result<()> {
+        let ctx = exit.pmio_context();
+
+        if ctx.PortNumber == VirtualPartition::HYPERCALL_PORT {
+            let (target, entry) = Self::decode_sipi(ctx.Rax);
+            if let Some(slot) = ap_slots.get(target as usize) {
+                trace!(
+                    "handle_hypercall_exit(): sipi target={} entry={:#010x}",
+                    target,
+                    entry
+                );
+                slot.entry.store(entry, Ordering::Release);
+                slot.online.store(true, Ordering::Release);
+            }
+        }
+
+        vcpu.ack_pmio_exit(exit)
+    }
+}
@@ -0,0 +1,110 @@
+// Copyright(c) The Maintainers of Nanvix.
+// Licensed under the MIT License.
+
+//!
+//! # Snapshotting
+//!
+//! This module defines the versioned on-disk header that ties together a
+//! [`crate::mshv::vcpu::VirtualProcessor::snapshot`] register blob with the metadata needed to
+//! resume it: the guest memory dump left by [`crate::mshv::vmem::VirtualMemory::snapshot`] is kept
+//! in its own file, named alongside this header.
+//!
+
+//==================================================================================================
+// Imports
+//==================================================================================================
+
+use crate::config;
+use ::anyhow::Result;
+use ::std::{
+    fs::File,
+    io::{
+        BufReader,
+        BufWriter,
+        Read,
+        Write,
+    },
+};
+
+//==================================================================================================
+// Constants
+//==================================================================================================
+
+/// Version of [`SnapshotHeader`]'s on-disk layout, bumped whenever a field is added, removed, or
+/// reordered.
+const SNAPSHOT_VERSION: u32 = 1;
+
+//==================================================================================================
+// Structures
+//==================================================================================================
+
+/// Versioned header describing a paused guest, so it can be resumed later or cloned. Fields are
+/// written little-endian, in declaration order, prefixed with [`config::MICROVM_MAGIC`] and
+/// [`SNAPSHOT_VERSION`].
+pub struct SnapshotHeader {
+    /// Size, in bytes, of the guest memory dump that [`crate::mshv::vmem::VirtualMemory::snapshot`]
+    /// wrote alongside this header.
+    pub memory_size: u64,
+    /// Guest physical address the vCPU should resume (or reset) at.
+    pub entry_point: u64,
+    /// [`crate::mshv::vcpu::VirtualProcessor::snapshot`]'s register blob.
+    pub registers: Vec<u8>,
+}
+
+impl SnapshotHeader {
+    /// Writes this header out to `path`.
+    pub fn save(&self, path: &str) -> Result<()> {
+        trace!("save(): path={}", path);
+
+        let mut writer: BufWriter<File> = BufWriter::new(File::create(path)?);
+
+        writer.write_all(&config::MICROVM_MAGIC.to_le_bytes())?;
+        writer.write_all(&SNAPSHOT_VERSION.to_le_bytes())?;
+        writer.write_all(&self.memory_size.to_le_bytes())?;
+        writer.write_all(&self.entry_point.to_le_bytes())?;
+        writer.write_all(&(self.registers.len() as u64).to_le_bytes())?;
+        writer.write_all(&self.registers)?;
+
+        Ok(())
+    }
+
+    /// Reads a header back from `path`, rejecting it if the magic or version does not match.
+    pub fn load(path: &str) -> Result<Self> {
+        trace!("load(): path={}", path);
+
+        let mut reader: BufReader<File> = BufReader::new(File::open(path)?);
+
+        let mut u32_buf: [u8; 4] = [0; 4];
+        let mut u64_buf: [u8; 8] = [0; 8];
+
+        reader.read_exact(&mut u32_buf)?;
+        let magic: u32 = u32::from_le_bytes(u32_buf);
+        if magic != config::MICROVM_MAGIC {
+            anyhow::bail!("not a microvm snapshot (magic={:#010x})", magic);
+        }
+
+        reader.read_exact(&mut u32_buf)?;
+        let version: u32 = u32::from_le_bytes(u32_buf);
+        if version != SNAPSHOT_VERSION {
+            anyhow::bail!("unsupported snapshot version (version={})", version);
+        }
+
+        reader.read_exact(&mut u64_buf)?;
+        let memory_size: u64 = u64::from_le_bytes(u64_buf);
+
+        reader.read_exact(&mut u64_buf)?;
+        let entry_point: u64 = u64::from_le_bytes(u64_buf);
+
+        reader.read_exact(&mut u64_buf)?;
+        let registers_len: u64 = u64::from_le_bytes(u64_buf);
+
+        let mut registers: Vec<u8> = vec![0; registers_len as usize];
+        reader.read_exact(&mut registers)?;
+
+        Ok(Self {
+            memory_size,
+            entry_point,
+            registers,
+        })
+    }
+}
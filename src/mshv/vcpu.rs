@@ -1,7 +1,11 @@
 // Copyright(c) The Maintainers of Nanvix.
 // Licensed under the MIT License.
 
-use crate::mshv::partition::VirtualPartition;
+use crate::mshv::{
+    dispatch,
+    partition::VirtualPartition,
+    vmem::VirtualMemory,
+};
 use ::anyhow::Result;
 use ::windows::Win32::System::{
     Hypervisor,
@@ -12,12 +16,34 @@ use ::windows::Win32::System::{
 };
 use std::{
     cell::RefCell,
+    collections::HashMap,
     mem,
-    rc::Rc,
+    sync::{
+        Arc,
+        Mutex,
+    },
 };
 
 pub enum VirtualProcessorExitReason {
+    /// The guest executed `hlt`. A real run loop should stop scheduling this vCPU until an
+    /// interrupt wakes it back up.
+    Halt,
+    /// The guest triple-faulted (or hit some other unrecoverable condition). A real run loop
+    /// should tear the partition down; [`VirtualExitProcessorContext::vp_context`] carries the
+    /// faulting `rip`/`cs` for diagnostics.
+    Shutdown,
     PmioAccess,
+    /// The guest accessed a memory-mapped I/O range. Serviced the same way as [`Self::PmioAccess`],
+    /// via the emulator's `WHvEmulatorTryMmioEmulation`, but against
+    /// [`crate::mshv::emulator::Emulator`]'s `MmioDevice` registry instead of its `PortIoDevice`
+    /// one.
+    MmioAccess,
+    /// The guest executed `cpuid`. [`VirtualProcessor::handle_cpuid_exit`] synthesizes a result.
+    Cpuid,
+    /// The guest executed `rdmsr`/`wrmsr`. [`VirtualProcessor::handle_msr_exit`] services it
+    /// against a small per-vCPU shadow table.
+    MsrAccess,
+    Exception,
     Unknown,
 }
 
@@ -35,14 +61,54 @@ impl VirtualExitProcessorContext {
         unsafe { &self.context.Anonymous.IoPortAccess }
     }
 
+    pub fn mmio_context(&self) -> &Hypervisor::WHV_MEMORY_ACCESS_CONTEXT {
+        unsafe { &self.context.Anonymous.MemoryAccess }
+    }
+
+    pub fn exception_context(&self) -> &Hypervisor::WHV_VP_EXCEPTION_CONTEXT {
+        unsafe { &self.context.Anonymous.VpException }
+    }
+
+    pub fn cpuid_context(&self) -> &Hypervisor::WHV_X64_CPUID_ACCESS_CONTEXT {
+        unsafe { &self.context.Anonymous.CpuidAccess }
+    }
+
+    pub fn msr_context(&self) -> &Hypervisor::WHV_X64_MSR_ACCESS_CONTEXT {
+        unsafe { &self.context.Anonymous.MsrAccess }
+    }
+
     pub fn reason(&self) -> VirtualProcessorExitReason {
         match self.context.ExitReason {
+            Hypervisor::WHvRunVpExitReasonX64Halt => VirtualProcessorExitReason::Halt,
+            Hypervisor::WHvRunVpExitReasonUnrecoverableException => {
+                VirtualProcessorExitReason::Shutdown
+            },
             Hypervisor::WHvRunVpExitReasonX64IoPortAccess => VirtualProcessorExitReason::PmioAccess,
+            Hypervisor::WHvRunVpExitReasonMemoryAccess => VirtualProcessorExitReason::MmioAccess,
+            Hypervisor::WHvRunVpExitReasonX64Cpuid => VirtualProcessorExitReason::Cpuid,
+            Hypervisor::WHvRunVpExitReasonX64MsrAccess => VirtualProcessorExitReason::MsrAccess,
+            Hypervisor::WHvRunVpExitReasonException => VirtualProcessorExitReason::Exception,
             _ => VirtualProcessorExitReason::Unknown,
         }
     }
 }
 
+/// Processor mode that [`VirtualProcessor::reset`] programs the vCPU into, so that both 32-bit
+/// and 64-bit guests can be launched from the same entry point.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ProcessorMode {
+    /// 16-bit real mode, the processor's power-on default: segment/control registers are left
+    /// untouched, matching what [`VirtualProcessor::reset`] always did before this mode became
+    /// selectable.
+    Real,
+    /// 32-bit protected mode, paging disabled: flat 4 GiB code/data segments, suitable for a
+    /// 32-bit ELF entry point.
+    Protected,
+    /// 64-bit long mode: flat code/data segments plus an identity-mapped page table that
+    /// [`VirtualProcessor::reset`] builds in guest memory, suitable for a 64-bit ELF entry point.
+    Long,
+}
+
 pub struct MshvRegisters<'a> {
     pub names: &'a [Hypervisor::WHV_REGISTER_NAME],
     pub values: &'a [Hypervisor::WHV_REGISTER_VALUE],
@@ -52,15 +118,78 @@ pub struct MshvRegisters<'a> {
 // MshvVirtualProcessor
 //==================================================================================================
 
-pub struct VirtualProcessor(Rc<RefCell<VirtualPartition>>, u32, bool);
+pub struct VirtualProcessor(
+    Arc<Mutex<VirtualPartition>>,
+    u32,
+    bool,
+    RefCell<HashMap<u32, u64>>,
+);
 
 impl VirtualProcessor {
-    pub fn new(partition: Rc<RefCell<VirtualPartition>>, index: u32) -> Result<Self> {
+    /// CPUID leaf, in the hypervisor-reserved range (`0x4000_0000`-`0x4000_00ff`), at which
+    /// [`Self::handle_cpuid_exit`] reports this monitor's signature in `eax`.
+    const CPUID_LEAF_SIGNATURE: u32 = 0x4000_0000;
+
+    /// Signature reported at [`Self::CPUID_LEAF_SIGNATURE`]; the same value [`Self::reset`] seeds
+    /// `rax` with, so a running guest can query it without waiting for a reset.
+    const CPUID_SIGNATURE: u32 = 0x0c0ffee;
+
+    /// Guest physical address at which [`Self::reset`] builds the flat GDT used to enter
+    /// [`ProcessorMode::Protected`]/[`ProcessorMode::Long`]. Left untouched in
+    /// [`ProcessorMode::Real`].
+    const GDT_BASE: u64 = 0x1000;
+
+    /// Guest physical address at which [`Self::reset`] builds the identity-mapped page table used
+    /// to enter [`ProcessorMode::Long`]. Immediately follows [`Self::GDT_BASE`], which is one page
+    /// long at most.
+    const PAGE_TABLE_BASE: u64 = 0x2000;
+
+    /// Number of gibibytes identity-mapped at [`Self::PAGE_TABLE_BASE`] using 2 MiB pages. This
+    /// only needs to cover the kernel/initrd/bootstrap data the guest touches before it sets up
+    /// its own page tables, not the whole of guest memory.
+    const IDENTITY_MAP_GIB: u64 = 1;
+
+    /// Selector of the flat code segment that [`Self::reset`] installs into `cs` for
+    /// [`ProcessorMode::Protected`]/[`ProcessorMode::Long`].
+    const SELECTOR_CODE: u16 = 0x08;
+
+    /// Selector of the flat data segment that [`Self::reset`] installs into `ds`/`es`/`fs`/`gs`/
+    /// `ss` for [`ProcessorMode::Protected`]/[`ProcessorMode::Long`].
+    const SELECTOR_DATA: u16 = 0x10;
+
+    /// Full architectural register set that [`Self::snapshot`]/[`Self::restore`] archive, in the
+    /// fixed order their on-disk blob serializes values at.
+    const SNAPSHOT_REGISTERS: [Hypervisor::WHV_REGISTER_NAME; 22] = [
+        Hypervisor::WHvX64RegisterRax,
+        Hypervisor::WHvX64RegisterRcx,
+        Hypervisor::WHvX64RegisterRdx,
+        Hypervisor::WHvX64RegisterRbx,
+        Hypervisor::WHvX64RegisterRsp,
+        Hypervisor::WHvX64RegisterRbp,
+        Hypervisor::WHvX64RegisterRsi,
+        Hypervisor::WHvX64RegisterRdi,
+        Hypervisor::WHvX64RegisterRip,
+        Hypervisor::WHvX64RegisterRflags,
+        Hypervisor::WHvX64RegisterCs,
+        Hypervisor::WHvX64RegisterDs,
+        Hypervisor::WHvX64RegisterEs,
+        Hypervisor::WHvX64RegisterFs,
+        Hypervisor::WHvX64RegisterGs,
+        Hypervisor::WHvX64RegisterSs,
+        Hypervisor::WHvX64RegisterGdtr,
+        Hypervisor::WHvX64RegisterIdtr,
+        Hypervisor::WHvX64RegisterCr0,
+        Hypervisor::WHvX64RegisterCr3,
+        Hypervisor::WHvX64RegisterCr4,
+        Hypervisor::WHvX64RegisterEfer,
+    ];
+
+    pub fn new(partition: Arc<Mutex<VirtualPartition>>, index: u32) -> Result<Self> {
         trace!("new(): index={:?}", index);
-        let p = partition.borrow().into_raw();
-        unsafe { Hypervisor::WHvCreateVirtualProcessor(p, index, 0)? };
+        let p = partition.lock().unwrap().into_raw();
+        dispatch::dispatch()?.create_virtual_processor(p, index, 0)?;
 
-        Ok(Self(partition, index, true))
+        Ok(Self(partition, index, true, RefCell::new(HashMap::new())))
     }
 
     pub fn is_online(&self) -> bool {
@@ -72,8 +201,26 @@ impl VirtualProcessor {
         self.2 = false;
     }
 
-    pub fn reset(&self, entry: u64) -> Result<()> {
-        trace!("reset(): entry={:#010x}", entry);
+    /// Brings this processor back online after [`Self::poweroff`], without otherwise touching its
+    /// register state. Used by [`crate::mshv::smp::VirtualMachine`] to start an application
+    /// processor that [`crate::mshv::smp::VirtualMachine::reset`] left parked, once a startup IPI
+    /// names it.
+    pub fn power_on(&mut self) {
+        trace!("power_on()");
+        self.2 = true;
+    }
+
+    /// Resets the vCPU into `mode`, with `rip` set to `entry`.
+    ///
+    /// Beyond the eight GP registers, `rip` and `rflags` that every mode gets, [`ProcessorMode::
+    /// Protected`] and [`ProcessorMode::Long`] also program flat 4 GiB segments (`cs`/`ds`/`es`/
+    /// `fs`/`gs`/`ss`), a GDT backing them in guest memory (see [`Self::GDT_BASE`]) and `cr0`
+    /// (`PE`). [`ProcessorMode::Long`] additionally builds an identity-mapped page table in guest
+    /// memory (see [`Self::PAGE_TABLE_BASE`]) and points `cr3`/`cr4`/`efer` at it to enter long
+    /// mode. `idtr` is left pointing at an empty table in every mode, since this monitor does not
+    /// model interrupt delivery into the guest.
+    pub fn reset(&self, vmem: &VirtualMemory, mode: ProcessorMode, entry: u64) -> Result<()> {
+        trace!("reset(): mode={:?}, entry={:#010x}", mode as u32, entry);
 
         const REGISTERS_COUNT: usize = 10;
 
@@ -136,48 +283,396 @@ impl VirtualProcessor {
         // Set registers.
         self.set_registers(&registers)?;
 
-        Ok(())
+        if mode == ProcessorMode::Real {
+            return Ok(());
+        }
+
+        self.build_gdt(vmem, mode)?;
+
+        let long_mode: bool = mode == ProcessorMode::Long;
+        let mut names: Vec<Hypervisor::WHV_REGISTER_NAME> = vec![
+            Hypervisor::WHvX64RegisterCs,
+            Hypervisor::WHvX64RegisterDs,
+            Hypervisor::WHvX64RegisterEs,
+            Hypervisor::WHvX64RegisterFs,
+            Hypervisor::WHvX64RegisterGs,
+            Hypervisor::WHvX64RegisterSs,
+            Hypervisor::WHvX64RegisterGdtr,
+            Hypervisor::WHvX64RegisterIdtr,
+            Hypervisor::WHvX64RegisterCr0,
+        ];
+        let mut values: Vec<Hypervisor::WHV_REGISTER_VALUE> = vec![
+            Self::segment_register(Self::SELECTOR_CODE, true, long_mode),
+            Self::segment_register(Self::SELECTOR_DATA, false, false),
+            Self::segment_register(Self::SELECTOR_DATA, false, false),
+            Self::segment_register(Self::SELECTOR_DATA, false, false),
+            Self::segment_register(Self::SELECTOR_DATA, false, false),
+            Self::segment_register(Self::SELECTOR_DATA, false, false),
+            Self::table_register(Self::GDT_BASE, 2 * 8 - 1),
+            Self::table_register(0, 0),
+            Self::control_register(if long_mode { 0x8000_0001 } else { 0x1 }),
+        ];
+
+        if long_mode {
+            let cr3: u64 = self.build_identity_page_table(vmem)?;
+            names.push(Hypervisor::WHvX64RegisterCr3);
+            values.push(Self::control_register(cr3));
+            names.push(Hypervisor::WHvX64RegisterCr4);
+            values.push(Self::control_register(0x20)); // PAE
+            names.push(Hypervisor::WHvX64RegisterEfer);
+            values.push(Self::control_register(0x500)); // LME | LMA
+        }
+
+        self.set_registers(&MshvRegisters {
+            names: &names,
+            values: &values,
+        })
+    }
+
+    /// Builds a minimal flat GDT (null, [`Self::SELECTOR_CODE`], [`Self::SELECTOR_DATA`]) at
+    /// [`Self::GDT_BASE`], with the code descriptor's `L`/`D` bits set according to `mode`.
+    fn build_gdt(&self, vmem: &VirtualMemory, mode: ProcessorMode) -> Result<()> {
+        let long_mode: bool = mode == ProcessorMode::Long;
+
+        // Access byte: present, ring 0, non-system, executable, readable/writable.
+        let code_access: u8 = 0x9a;
+        let data_access: u8 = 0x92;
+        // Flags (limit is in 4 KiB units, so granularity is always set): D/B set for 32-bit
+        // protected mode and for every data segment, L set instead for 64-bit code.
+        let code_flags: u8 = if long_mode { 0xa } else { 0xc };
+        let data_flags: u8 = 0xc;
+
+        let gdt: [u64; 3] = [
+            0,
+            Self::gdt_descriptor(0, 0xfffff, code_access, code_flags),
+            Self::gdt_descriptor(0, 0xfffff, data_access, data_flags),
+        ];
+
+        let mut bytes: [u8; 24] = [0; 24];
+        for (i, entry) in gdt.iter().enumerate() {
+            bytes[i * 8..i * 8 + 8].copy_from_slice(&entry.to_le_bytes());
+        }
+
+        vmem.write_bytes(Self::GDT_BASE, &bytes)
+    }
+
+    /// Packs a single 8-byte x86 segment descriptor.
+    fn gdt_descriptor(base: u32, limit: u32, access: u8, flags: u8) -> u64 {
+        (limit as u64 & 0xffff)
+            | ((base as u64 & 0xffffff) << 16)
+            | ((access as u64) << 40)
+            | (((limit as u64 >> 16) & 0xf) << 48)
+            | ((flags as u64 & 0xf) << 52)
+            | (((base as u64 >> 24) & 0xff) << 56)
+    }
+
+    /// Builds an identity-mapped page table tree ([`Self::IDENTITY_MAP_GIB`] GiB, in 2 MiB pages)
+    /// at [`Self::PAGE_TABLE_BASE`] and returns its PML4 address, suitable for loading into `cr3`.
+    fn build_identity_page_table(&self, vmem: &VirtualMemory) -> Result<u64> {
+        const PRESENT: u64 = 1 << 0;
+        const WRITABLE: u64 = 1 << 1;
+        const PAGE_SIZE: u64 = 1 << 7;
+
+        let pml4_addr: u64 = Self::PAGE_TABLE_BASE;
+        let pdpt_addr: u64 = pml4_addr + 0x1000;
+        let pd_addr: u64 = pdpt_addr + 0x1000;
+
+        let pml4: [u64; 512] = {
+            let mut table: [u64; 512] = [0; 512];
+            table[0] = pdpt_addr | PRESENT | WRITABLE;
+            table
+        };
+        vmem.write_bytes(pml4_addr, unsafe {
+            ::std::slice::from_raw_parts(pml4.as_ptr() as *const u8, 4096)
+        })?;
+
+        let num_pds: u64 = Self::IDENTITY_MAP_GIB;
+        let pdpt: [u64; 512] = {
+            let mut table: [u64; 512] = [0; 512];
+            for i in 0..num_pds {
+                table[i as usize] = (pd_addr + i * 0x1000) | PRESENT | WRITABLE;
+            }
+            table
+        };
+        vmem.write_bytes(pdpt_addr, unsafe {
+            ::std::slice::from_raw_parts(pdpt.as_ptr() as *const u8, 4096)
+        })?;
+
+        for i in 0..num_pds {
+            let mut pd: [u64; 512] = [0; 512];
+            for (j, entry) in pd.iter_mut().enumerate() {
+                let phys: u64 = i * 0x4000_0000 + j as u64 * 0x20_0000;
+                *entry = phys | PRESENT | WRITABLE | PAGE_SIZE;
+            }
+            vmem.write_bytes(pd_addr + i * 0x1000, unsafe {
+                ::std::slice::from_raw_parts(pd.as_ptr() as *const u8, 4096)
+            })?;
+        }
+
+        Ok(pml4_addr)
+    }
+
+    /// Builds a flat segment register value: `base = 0`, `limit = 4 GiB` (4 KiB granularity).
+    /// `executable` selects a code vs. data segment type; `long_mode` sets the code segment's `L`
+    /// bit instead of `D`, as required when it will be loaded into `cs` in long mode.
+    fn segment_register(
+        selector: u16,
+        executable: bool,
+        long_mode: bool,
+    ) -> Hypervisor::WHV_REGISTER_VALUE {
+        let mut value: Hypervisor::WHV_REGISTER_VALUE = Hypervisor::WHV_REGISTER_VALUE::default();
+
+        value.Segment.Base = 0;
+        value.Segment.Limit = 0xffff_ffff;
+        value.Segment.Selector = selector;
+
+        // Present(7) | NonSystemSegment(4) | Granularity(15) | Default/Long(14/13) |
+        // SegmentType(0..3): execute/read(0xb) for code, read/write(0x3) for data.
+        let mut attributes: u16 = (1 << 7) | (1 << 4) | (1 << 15);
+        attributes |= if executable { 0xb } else { 0x3 };
+        if executable && long_mode {
+            attributes |= 1 << 13;
+        } else {
+            attributes |= 1 << 14;
+        }
+        value.Segment.Anonymous.Attributes = attributes;
+
+        value
+    }
+
+    /// Builds a table register (`gdtr`/`idtr`) value.
+    fn table_register(base: u64, limit: u16) -> Hypervisor::WHV_REGISTER_VALUE {
+        let mut value: Hypervisor::WHV_REGISTER_VALUE = Hypervisor::WHV_REGISTER_VALUE::default();
+        value.Table.Base = base;
+        value.Table.Limit = limit;
+        value
+    }
+
+    /// Builds a plain 64-bit register value, for `cr0`/`cr3`/`cr4`/`efer`.
+    fn control_register(value: u64) -> Hypervisor::WHV_REGISTER_VALUE {
+        let mut reg: Hypervisor::WHV_REGISTER_VALUE = Hypervisor::WHV_REGISTER_VALUE::default();
+        reg.Reg64 = value;
+        reg
     }
 
     pub fn set_registers<'a>(&self, registers: &'a MshvRegisters) -> Result<()> {
         // Set registers.
-        unsafe {
-            let p: WHV_PARTITION_HANDLE = self.0.borrow().into_raw();
-            Hypervisor::WHvSetVirtualProcessorRegisters(
-                p,
-                0,
-                registers.names.as_ptr(),
-                registers.names.len() as u32,
-                registers.values.as_ptr(),
-            )?
-        };
+        let p: WHV_PARTITION_HANDLE = self.0.lock().unwrap().into_raw();
+        dispatch::dispatch()?.set_virtual_processor_registers(
+            p,
+            self.1,
+            registers.names.as_ptr(),
+            registers.names.len() as u32,
+            registers.values.as_ptr(),
+        )?;
 
         Ok(())
     }
 
+    pub fn get_registers(
+        &self,
+        names: &[Hypervisor::WHV_REGISTER_NAME],
+    ) -> Result<Vec<Hypervisor::WHV_REGISTER_VALUE>> {
+        let mut values: Vec<Hypervisor::WHV_REGISTER_VALUE> =
+            vec![Hypervisor::WHV_REGISTER_VALUE::default(); names.len()];
+
+        let p: WHV_PARTITION_HANDLE = self.0.lock().unwrap().into_raw();
+        dispatch::dispatch()?.get_virtual_processor_registers(
+            p,
+            self.1,
+            names.as_ptr(),
+            names.len() as u32,
+            values.as_mut_ptr(),
+        )?;
+
+        Ok(values)
+    }
+
     pub fn run(&self) -> Result<VirtualExitProcessorContext> {
         // Run virtual processor.
         let mut exit_context = VirtualExitProcessorContext::default();
 
-        unsafe {
-            let p: WHV_PARTITION_HANDLE = self.0.borrow().into_raw();
-            Hypervisor::WHvRunVirtualProcessor(
-                p,
-                0,
-                &mut exit_context.context as *mut _ as *mut std::ffi::c_void,
-                mem::size_of::<WHV_RUN_VP_EXIT_CONTEXT>() as u32,
-            )?
-        };
+        let p: WHV_PARTITION_HANDLE = self.0.lock().unwrap().into_raw();
+        dispatch::dispatch()?.run_virtual_processor(
+            p,
+            self.1,
+            &mut exit_context.context as *mut _ as *mut std::ffi::c_void,
+            mem::size_of::<WHV_RUN_VP_EXIT_CONTEXT>() as u32,
+        )?;
 
         Ok(exit_context)
     }
+
+    /// Translates a guest virtual address into a guest physical address by walking the guest's
+    /// own page tables, via `WHvTranslateGva`. Backs the emulator's `translate_gva_page` callback,
+    /// so that instruction emulation can resolve a memory operand even when paging is enabled.
+    pub fn translate_gva(
+        &self,
+        gva: u64,
+        flags: Hypervisor::WHV_TRANSLATE_GVA_FLAGS,
+    ) -> Result<(Hypervisor::WHV_TRANSLATE_GVA_RESULT_CODE, u64)> {
+        let mut result: Hypervisor::WHV_TRANSLATE_GVA_RESULT =
+            Hypervisor::WHV_TRANSLATE_GVA_RESULT::default();
+        let mut gpa: u64 = 0;
+
+        let p: WHV_PARTITION_HANDLE = self.0.lock().unwrap().into_raw();
+        dispatch::dispatch()?.translate_gva(p, self.1, gva, flags, &mut result, &mut gpa)?;
+
+        Ok((result.ResultCode, gpa))
+    }
+
+    /// Services a [`VirtualProcessorExitReason::Cpuid`] exit: synthesizes a result (querying
+    /// [`Self::CPUID_LEAF_SIGNATURE`] returns [`Self::CPUID_SIGNATURE`]; every other leaf falls
+    /// back to whatever the hypervisor would have returned by default) and writes it into the
+    /// guest's `rax`/`rbx`/`rcx`/`rdx`, then advances `rip` past the `cpuid` instruction.
+    pub fn handle_cpuid_exit(&self, exit: &VirtualExitProcessorContext) -> Result<()> {
+        let ctx: &Hypervisor::WHV_X64_CPUID_ACCESS_CONTEXT = exit.cpuid_context();
+
+        let (rax, rbx, rcx, rdx): (u64, u64, u64, u64) =
+            if ctx.Rax as u32 == Self::CPUID_LEAF_SIGNATURE {
+                (Self::CPUID_SIGNATURE as u64, 0, 0, 0)
+            } else {
+                (
+                    ctx.DefaultResultRax,
+                    ctx.DefaultResultRbx,
+                    ctx.DefaultResultRcx,
+                    ctx.DefaultResultRdx,
+                )
+            };
+
+        self.set_exit_registers(
+            exit,
+            &[
+                (Hypervisor::WHvX64RegisterRax, rax),
+                (Hypervisor::WHvX64RegisterRbx, rbx),
+                (Hypervisor::WHvX64RegisterRcx, rcx),
+                (Hypervisor::WHvX64RegisterRdx, rdx),
+            ],
+        )
+    }
+
+    /// Services a [`VirtualProcessorExitReason::MsrAccess`] exit against a small per-vCPU shadow
+    /// table: `wrmsr` stashes the written value, `rdmsr` reads back whatever was last stashed (or
+    /// `0` for an MSR that was never written). Either way, `rip` is advanced past the instruction.
+    pub fn handle_msr_exit(&self, exit: &VirtualExitProcessorContext) -> Result<()> {
+        let ctx: &Hypervisor::WHV_X64_MSR_ACCESS_CONTEXT = exit.msr_context();
+        let msr: u32 = ctx.MsrNumber;
+        let is_write: bool = unsafe { ctx.AccessInfo.AsUINT32 } & 0x1 != 0;
+
+        if is_write {
+            let value: u64 = (ctx.Rax & 0xffff_ffff) | (ctx.Rdx << 32);
+            trace!(
+                "handle_msr_exit(): wrmsr msr={:#010x} value={:#018x}",
+                msr,
+                value
+            );
+            self.3.borrow_mut().insert(msr, value);
+            self.set_exit_registers(exit, &[])
+        } else {
+            let value: u64 = *self.3.borrow().get(&msr).unwrap_or(&0);
+            trace!(
+                "handle_msr_exit(): rdmsr msr={:#010x} value={:#018x}",
+                msr,
+                value
+            );
+            self.set_exit_registers(
+                exit,
+                &[
+                    (Hypervisor::WHvX64RegisterRax, value & 0xffff_ffff),
+                    (Hypervisor::WHvX64RegisterRdx, value >> 32),
+                ],
+            )
+        }
+    }
+
+    /// Writes `regs` into the vCPU's registers, then advances `rip` past the instruction that
+    /// caused `exit`, as required after servicing a CPUID/MSR exit by hand.
+    fn set_exit_registers(
+        &self,
+        exit: &VirtualExitProcessorContext,
+        regs: &[(Hypervisor::WHV_REGISTER_NAME, u64)],
+    ) -> Result<()> {
+        let mut names: Vec<Hypervisor::WHV_REGISTER_NAME> =
+            regs.iter().map(|(name, _)| *name).collect();
+        let mut values: Vec<Hypervisor::WHV_REGISTER_VALUE> = regs
+            .iter()
+            .map(|(_, value)| {
+                let mut reg: Hypervisor::WHV_REGISTER_VALUE =
+                    Hypervisor::WHV_REGISTER_VALUE::default();
+                reg.Reg64 = *value;
+                reg
+            })
+            .collect();
+
+        names.push(Hypervisor::WHvX64RegisterRip);
+        let mut rip: Hypervisor::WHV_REGISTER_VALUE = Hypervisor::WHV_REGISTER_VALUE::default();
+        rip.Reg64 = exit.vp_context().Rip + exit.vp_context().InstructionLength() as u64;
+        values.push(rip);
+
+        self.set_registers(&MshvRegisters {
+            names: &names,
+            values: &values,
+        })
+    }
+
+    /// Advances `rip` past the `in`/`out` instruction that caused `exit`, without otherwise
+    /// touching any register. Used to acknowledge a [`VirtualProcessorExitReason::PmioAccess`]
+    /// exit that a caller has already serviced by hand (e.g. [`crate::mshv::smp::VirtualMachine`]
+    /// decoding a startup IPI off [`VirtualPartition::HYPERCALL_PORT`]) and that therefore does
+    /// not need any register written back, unlike [`Self::handle_cpuid_exit`]/
+    /// [`Self::handle_msr_exit`].
+    pub fn ack_pmio_exit(&self, exit: &VirtualExitProcessorContext) -> Result<()> {
+        self.set_exit_registers(exit, &[])
+    }
+
+    /// Reads [`Self::SNAPSHOT_REGISTERS`] into a little-endian blob (each entry's raw
+    /// `WHV_REGISTER_VALUE` bytes, back to back, in array order), suitable for archiving in a
+    /// [`crate::mshv::snapshot::SnapshotHeader`] and replaying later via [`Self::restore`].
+    pub fn snapshot(&self) -> Result<Vec<u8>> {
+        trace!("snapshot()");
+
+        let values: Vec<Hypervisor::WHV_REGISTER_VALUE> = self.get_registers(&Self::SNAPSHOT_REGISTERS)?;
+
+        let value_size: usize = mem::size_of::<Hypervisor::WHV_REGISTER_VALUE>();
+        let mut blob: Vec<u8> = Vec::with_capacity(values.len() * value_size);
+        for value in &values {
+            blob.extend_from_slice(unsafe {
+                ::std::slice::from_raw_parts(value as *const _ as *const u8, value_size)
+            });
+        }
+
+        Ok(blob)
+    }
+
+    /// Writes a blob produced by [`Self::snapshot`] back into [`Self::SNAPSHOT_REGISTERS`],
+    /// resuming a paused guest (or cloning one) from where it was archived.
+    pub fn restore(&self, blob: &[u8]) -> Result<()> {
+        trace!("restore()");
+
+        let value_size: usize = mem::size_of::<Hypervisor::WHV_REGISTER_VALUE>();
+        if blob.len() != Self::SNAPSHOT_REGISTERS.len() * value_size {
+            anyhow::bail!("malformed register blob (len={})", blob.len());
+        }
+
+        let values: Vec<Hypervisor::WHV_REGISTER_VALUE> = blob
+            .chunks_exact(value_size)
+            .map(|chunk| unsafe {
+                ::std::ptr::read_unaligned(chunk.as_ptr() as *const Hypervisor::WHV_REGISTER_VALUE)
+            })
+            .collect();
+
+        self.set_registers(&MshvRegisters {
+            names: &Self::SNAPSHOT_REGISTERS,
+            values: &values,
+        })
+    }
 }
 
 impl Drop for VirtualProcessor {
     fn drop(&mut self) {
-        unsafe {
-            let p: WHV_PARTITION_HANDLE = self.0.borrow().into_raw();
-            Hypervisor::WHvDeleteVirtualProcessor(p, self.1).unwrap();
+        if let Ok(dispatch) = dispatch::dispatch() {
+            let p: WHV_PARTITION_HANDLE = self.0.lock().unwrap().into_raw();
+            dispatch.delete_virtual_processor(p, self.1).unwrap();
         }
     }
 }
@@ -7,12 +7,25 @@
 
 use std::{
     cell::RefCell,
-    rc::Rc,
+    fs::File,
+    io::{
+        BufReader,
+        BufWriter,
+        Read,
+        Write,
+    },
+    sync::{
+        Arc,
+        Mutex,
+    },
 };
 
 use crate::{
     elf,
-    mshv::partition::VirtualPartition,
+    mshv::{
+        dispatch,
+        partition::VirtualPartition,
+    },
     pal::FileMapping,
 };
 use ::anyhow::Result;
@@ -21,10 +34,37 @@ use ::windows::Win32::System::{
     Memory,
 };
 
-pub struct VirtualMemory(Rc<RefCell<VirtualPartition>>, *mut std::ffi::c_void, usize);
+/// Size, in bytes, of a guest page frame. Matches the granularity that the balloon device's
+/// inflate/deflate protocol exchanges page frame numbers at.
+pub const PAGE_SIZE: u64 = 4096;
+
+/// Host-observable state of the memory balloon device.
+#[derive(Debug, Clone, Copy)]
+pub struct BalloonStats {
+    /// Memory currently backing the guest, i.e. the total size minus reclaimed pages.
+    pub actual_size: u64,
+    /// Size that the host last asked the balloon to shrink the guest down to.
+    pub target_size: u64,
+    /// Number of pages currently discarded on the host's behalf.
+    pub pages_reclaimed: u64,
+}
+
+/// Bookkeeping for the memory balloon device. Held behind a [`RefCell`] so that inflate/deflate
+/// can be driven through a shared `&VirtualMemory`, consistently with `read_bytes`/`write_bytes`.
+struct BalloonState {
+    target_size: u64,
+    reclaimed_pages: std::collections::HashSet<u64>,
+}
+
+pub struct VirtualMemory(
+    Arc<Mutex<VirtualPartition>>,
+    *mut std::ffi::c_void,
+    usize,
+    RefCell<BalloonState>,
+);
 
 impl VirtualMemory {
-    pub fn new(partition: Rc<RefCell<VirtualPartition>>, size: usize) -> Result<Self> {
+    pub fn new(partition: Arc<Mutex<VirtualPartition>>, size: usize) -> Result<Self> {
         let ptr: *mut std::ffi::c_void = unsafe {
             Memory::VirtualAlloc(
                 None,
@@ -35,19 +75,23 @@ impl VirtualMemory {
         };
 
         trace!("new()");
-        unsafe {
-            Hypervisor::WHvMapGpaRange(
-                partition.borrow().into_raw(),
-                ptr,
-                0,
-                size as u64,
-                Hypervisor::WHvMapGpaRangeFlagRead
-                    | Hypervisor::WHvMapGpaRangeFlagWrite
-                    | Hypervisor::WHvMapGpaRangeFlagExecute,
-            )?
+        let flags = Hypervisor::WHvMapGpaRangeFlagRead
+            | Hypervisor::WHvMapGpaRangeFlagWrite
+            | Hypervisor::WHvMapGpaRangeFlagExecute;
+        dispatch::dispatch()?.map_gpa_range(
+            partition.lock().unwrap().into_raw(),
+            ptr,
+            0,
+            size as u64,
+            flags.0 as u32,
+        )?;
+
+        let balloon: BalloonState = BalloonState {
+            target_size: size as u64,
+            reclaimed_pages: std::collections::HashSet::new(),
         };
 
-        Ok(Self(partition, ptr, size))
+        Ok(Self(partition, ptr, size, RefCell::new(balloon)))
     }
 
     pub fn load(&self, filename: &str) -> Result<u64> {
@@ -57,13 +101,147 @@ impl VirtualMemory {
 
         Ok(entry)
     }
+
+    /// Reads `data.len()` bytes out of guest memory starting at the guest physical address `addr`.
+    pub fn read_bytes(&self, addr: u64, data: &mut [u8]) -> Result<()> {
+        if addr as usize + data.len() > self.2 {
+            let reason: String = format!("invalid memory access (addr={:#010x})", addr);
+            error!("read_bytes(): {}", reason);
+            return Err(anyhow::anyhow!(reason));
+        }
+
+        unsafe {
+            std::ptr::copy_nonoverlapping(
+                (self.1 as *const u8).add(addr as usize),
+                data.as_mut_ptr(),
+                data.len(),
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Writes `data` into guest memory starting at the guest physical address `addr`.
+    pub fn write_bytes(&self, addr: u64, data: &[u8]) -> Result<()> {
+        if addr as usize + data.len() > self.2 {
+            let reason: String = format!("invalid memory access (addr={:#010x})", addr);
+            error!("write_bytes(): {}", reason);
+            return Err(anyhow::anyhow!(reason));
+        }
+
+        unsafe {
+            std::ptr::copy_nonoverlapping(
+                data.as_ptr(),
+                (self.1 as *mut u8).add(addr as usize),
+                data.len(),
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Sets the size, in bytes, that the balloon device should shrink guest memory down to. Does
+    /// not reclaim anything by itself; the guest's balloon driver is expected to notice and hand
+    /// back page frames via [`Self::inflate`] until [`Self::balloon_stats`] reports `actual_size`
+    /// at or below `bytes`.
+    pub fn set_target_size(&self, bytes: u64) {
+        trace!("set_target_size(): bytes={}", bytes);
+        self.3.borrow_mut().target_size = bytes.min(self.2 as u64);
+    }
+
+    /// Reclaims the page frames that the guest balloon driver relinquished. The pages are
+    /// discarded (their content becomes undefined) so that the host can reuse the physical memory
+    /// backing them; they are not unmapped from the partition, so the guest may still touch them
+    /// without faulting, it will simply observe undefined content.
+    pub fn inflate(&self, pfns: &[u64]) -> Result<()> {
+        trace!("inflate(): pages={}", pfns.len());
+
+        for &pfn in pfns {
+            let addr: u64 = pfn * PAGE_SIZE;
+            if (addr + PAGE_SIZE) as usize > self.2 {
+                let reason: String = format!("invalid page frame number (pfn={})", pfn);
+                error!("inflate(): {}", reason);
+                return Err(anyhow::anyhow!(reason));
+            }
+
+            // DiscardVirtualMemory() returns a WIN32_ERROR code directly, rather than following
+            // the BOOL-plus-GetLastError() convention of most of the Memory APIs used elsewhere.
+            let ptr: *mut std::ffi::c_void = unsafe { (self.1 as *mut u8).add(addr as usize) as *mut _ };
+            let ret: u32 = unsafe { Memory::DiscardVirtualMemory(ptr, PAGE_SIZE as usize) };
+            if ret != 0 {
+                let reason: String = format!("failed to discard page (pfn={}, ret={})", pfn, ret);
+                error!("inflate(): {}", reason);
+                return Err(anyhow::anyhow!(reason));
+            }
+
+            self.3.borrow_mut().reclaimed_pages.insert(pfn);
+        }
+
+        Ok(())
+    }
+
+    /// Returns the page frames previously given to [`Self::inflate`] to the guest. Discarded
+    /// pages are re-faulted in lazily by the guest's own next access, so this only updates our
+    /// bookkeeping.
+    pub fn deflate(&self, pfns: &[u64]) {
+        trace!("deflate(): pages={}", pfns.len());
+
+        let mut state = self.3.borrow_mut();
+        for pfn in pfns {
+            state.reclaimed_pages.remove(pfn);
+        }
+    }
+
+    /// Copies the committed guest RAM range (`self.1..self.1+self.2`) out to `path`, verbatim and
+    /// uncompressed. Paired with [`Self::restore`] so a paused guest's memory can be archived in a
+    /// [`crate::mshv::snapshot::SnapshotHeader`] and mapped back in later.
+    pub fn snapshot(&self, path: &str) -> Result<()> {
+        trace!("snapshot(): path={}", path);
+
+        let bytes: &[u8] = unsafe { ::std::slice::from_raw_parts(self.1 as *const u8, self.2) };
+        let mut writer: BufWriter<File> = BufWriter::new(File::create(path)?);
+        writer.write_all(bytes)?;
+
+        Ok(())
+    }
+
+    /// Re-maps a `size`-byte [`VirtualMemory`] onto `partition` and fills it from a file
+    /// previously written by [`Self::snapshot`]. Registers are untouched here; replay them
+    /// afterwards via [`crate::mshv::vcpu::VirtualProcessor::restore`].
+    pub fn restore(partition: Arc<Mutex<VirtualPartition>>, size: usize, path: &str) -> Result<Self> {
+        trace!("restore(): path={}, size={}", path, size);
+
+        let vmem: Self = Self::new(partition, size)?;
+
+        let mut reader: BufReader<File> = BufReader::new(File::open(path)?);
+        let mut bytes: Vec<u8> = vec![0; size];
+        reader.read_exact(&mut bytes)?;
+        vmem.write_bytes(0, &bytes)?;
+
+        Ok(vmem)
+    }
+
+    /// Reports the current state of the balloon device.
+    pub fn balloon_stats(&self) -> BalloonStats {
+        let state = self.3.borrow();
+        let pages_reclaimed: u64 = state.reclaimed_pages.len() as u64;
+
+        BalloonStats {
+            actual_size: self.2 as u64 - pages_reclaimed * PAGE_SIZE,
+            target_size: state.target_size,
+            pages_reclaimed,
+        }
+    }
 }
 
 impl Drop for VirtualMemory {
     fn drop(&mut self) {
-        unsafe {
-            Hypervisor::WHvUnmapGpaRange(self.0.borrow().into_raw(), self.1 as u64, self.2 as u64)
+        if let Ok(dispatch) = dispatch::dispatch() {
+            dispatch
+                .unmap_gpa_range(self.0.lock().unwrap().into_raw(), self.1 as u64, self.2 as u64)
                 .unwrap();
+        }
+        unsafe {
             if let Err(e) = Memory::VirtualFree(self.1, 0, Memory::MEM_RELEASE) {
                 error!("failed to free memory: {:?}", e);
             }
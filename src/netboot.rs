@@ -0,0 +1,168 @@
+// Copyright(c) The Maintainers of Nanvix.
+// Licensed under the MIT License.
+
+//!
+//! # Network Boot
+//!
+//! This module lets a guest image be pushed over TCP instead of being read from a file on disk,
+//! mirroring the `szl` netboot flow from `zynq-rs`: a developer starts the virtual machine
+//! listening on a socket, then streams a kernel image to it, getting a "push a kernel and run"
+//! workflow without rebuilding a disk image for every iteration.
+//!
+
+//==================================================================================================
+// Imports
+//==================================================================================================
+
+use crate::{
+    bzimage,
+    elf,
+};
+use ::anyhow::Result;
+use ::std::{
+    io::Read,
+    net::{
+        SocketAddr,
+        TcpListener,
+        TcpStream,
+    },
+};
+
+//==================================================================================================
+// Constants
+//==================================================================================================
+
+/// Magic value that identifies a netboot image transfer.
+const NETBOOT_MAGIC: u32 = 0x4f4f_424e;
+
+//==================================================================================================
+// Standalone Functions
+//==================================================================================================
+
+///
+/// # Description
+///
+/// Binds `addr`, accepts a single connection, and reads a netboot image off it.
+///
+/// The wire format is a small framed header followed by the image itself:
+///
+/// - `magic`: `u32`, little-endian, must equal [`NETBOOT_MAGIC`].
+/// - `length`: `u32`, little-endian, size in bytes of the image that follows.
+/// - `crc32`: `u32`, little-endian, CRC-32 (IEEE 802.3) of the image, checked before the image is
+///   handed off to the caller so that a corrupt transfer is never booted.
+/// - `image`: `length` bytes.
+///
+/// # Parameters
+///
+/// - `addr`: Address to bind and listen on.
+///
+/// # Returns
+///
+/// Upon successful completion, this function returns the image that was received. Otherwise, it
+/// returns an error.
+///
+pub fn fetch(addr: SocketAddr) -> Result<Vec<u8>> {
+    trace!("fetch(): addr={}", addr);
+    crate::timer!("netboot_fetch");
+
+    let listener: TcpListener = TcpListener::bind(addr)?;
+    let (mut stream, peer): (TcpStream, SocketAddr) = listener.accept()?;
+    trace!("fetch(): accepted connection from {}", peer);
+
+    let mut u32_buf: [u8; 4] = [0; 4];
+
+    stream.read_exact(&mut u32_buf)?;
+    let magic: u32 = u32::from_le_bytes(u32_buf);
+    if magic != NETBOOT_MAGIC {
+        let reason: String = format!("invalid netboot magic (magic={:#010x})", magic);
+        error!("fetch(): {}", reason);
+        return Err(anyhow::anyhow!(reason));
+    }
+
+    stream.read_exact(&mut u32_buf)?;
+    let length: u32 = u32::from_le_bytes(u32_buf);
+
+    stream.read_exact(&mut u32_buf)?;
+    let expected_crc: u32 = u32::from_le_bytes(u32_buf);
+
+    let mut image: Vec<u8> = vec![0; length as usize];
+    stream.read_exact(&mut image)?;
+
+    let actual_crc: u32 = crc32(&image);
+    if actual_crc != expected_crc {
+        let reason: String = format!(
+            "netboot image CRC mismatch (expected={:#010x}, actual={:#010x})",
+            expected_crc, actual_crc
+        );
+        error!("fetch(): {}", reason);
+        return Err(anyhow::anyhow!(reason));
+    }
+
+    trace!("fetch(): received {} bytes", image.len());
+
+    Ok(image)
+}
+
+///
+/// # Description
+///
+/// Fetches a guest image over the network (see [`fetch`]) and loads it into memory, dispatching to
+/// [`elf::load`] or [`bzimage::load`] depending on what the received bytes look like.
+///
+/// # Parameters
+///
+/// - `destination`: Base address, in memory, of the guest's RAM.
+/// - `addr`: Address to bind and listen on.
+/// - `max_offset`: Maximum offset, relative to `destination`, available to the guest.
+/// - `memory_size`: Size, in bytes, of the guest's RAM, passed through to [`bzimage::load`].
+/// - `cmdline`: Kernel command-line, passed through to [`bzimage::load`].
+///
+/// # Returns
+///
+/// Upon successful completion, this function returns a tuple containing the entry point, the first
+/// address, and the size of the program that was loaded into memory. Otherwise, it returns an
+/// error.
+///
+/// # Safety
+///
+/// This function is unsafe because it manipulates raw pointers and is up to the caller to ensure
+/// that the following conditions are met:
+///
+/// - The `destination` address is valid.
+/// - The `max_offset` is valid.
+///
+pub unsafe fn load(
+    destination: *mut std::ffi::c_void,
+    addr: SocketAddr,
+    max_offset: usize,
+    memory_size: u64,
+    cmdline: &str,
+) -> Result<(usize, usize, usize)> {
+    let image: Vec<u8> = fetch(addr)?;
+
+    if bzimage::is_bzimage(image.as_ptr(), image.len()) {
+        bzimage::load(
+            destination,
+            image.as_ptr(),
+            image.len(),
+            max_offset,
+            memory_size,
+            cmdline,
+        )
+    } else {
+        elf::load(destination, image.as_ptr(), max_offset, elf::EM_386)
+    }
+}
+
+// Computes the CRC-32 (IEEE 802.3) checksum of `data`.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc: u32 = 0xffff_ffff;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask: u32 = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    !crc
+}
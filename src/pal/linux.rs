@@ -12,10 +12,117 @@ use ::std::ptr;
 // Structures
 //==================================================================================================
 
+/// Selects how [`FileMapping::mmap_with_mode`] opens and protects its backing file.
+#[derive(Debug, Clone, Copy)]
+pub enum MmapMode {
+    /// Guest reads only; writes are rejected by the kernel. The default used by
+    /// [`FileMapping::mmap`].
+    ReadOnly,
+    /// Writes are visible to, and persisted back into, the underlying file.
+    ReadWrite,
+    /// Writes are visible to this mapping only and are never written back to the underlying
+    /// file, so a guest can run against a shared base image while keeping its writes private.
+    CopyOnWrite,
+}
+
+/// An access-pattern hint for [`FileMapping::advise`]/[`FileMapping::advise_range`].
+#[derive(Debug, Clone, Copy)]
+pub enum MmapAdvice {
+    /// Pages are expected to be accessed in sequential order; the kernel should read ahead
+    /// aggressively.
+    Sequential,
+    /// Pages are expected to be accessed in no particular order; the kernel should not bother
+    /// reading ahead.
+    Random,
+    /// Pages are expected to be needed soon; the kernel should start reading them in now, so a
+    /// later access does not block on I/O.
+    WillNeed,
+    /// Pages are not expected to be needed again soon; the kernel may evict them early.
+    DontNeed,
+}
+
+/// Builds up a [`FileMapping`] over a file, mirroring `memmap2::MmapOptions`: every setter
+/// consumes and returns `self`, and [`MmapOptions::map`] performs the actual mapping once all
+/// options are set.
+#[derive(Debug, Clone, Copy)]
+pub struct MmapOptions {
+    /// Byte offset, into the file, at which the mapping starts. Defaults to `0`.
+    offset: u64,
+    /// Length, in bytes, of the mapping. Defaults to the rest of the file past `offset`.
+    len: Option<usize>,
+    mode: MmapMode,
+    advice: MmapAdvice,
+}
+
+impl MmapOptions {
+    /// Creates a new builder, mapping the whole file read-only with [`MmapAdvice::Sequential`]
+    /// unless overridden.
+    pub fn new() -> Self {
+        Self {
+            offset: 0,
+            len: None,
+            mode: MmapMode::ReadOnly,
+            advice: MmapAdvice::Sequential,
+        }
+    }
+
+    /// Sets the byte offset, into the file, at which the mapping starts.
+    pub fn offset(mut self, offset: u64) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    /// Sets the length, in bytes, of the mapping. If unset, the mapping runs to the end of the
+    /// file.
+    pub fn len(mut self, len: usize) -> Self {
+        self.len = Some(len);
+        self
+    }
+
+    /// Sets the [`MmapMode`] to open and protect the mapping with.
+    pub fn mode(mut self, mode: MmapMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Sets the [`MmapAdvice`] to apply to the mapping once it is created.
+    pub fn advice(mut self, advice: MmapAdvice) -> Self {
+        self.advice = advice;
+        self
+    }
+
+    /// Maps `filename` according to the options set so far.
+    pub fn map(self, filename: &str) -> Result<FileMapping> {
+        let mapping: FileMapping =
+            FileMapping::mmap_range(filename, self.mode, self.offset, self.len)?;
+        mapping.advise(self.advice)?;
+        Ok(mapping)
+    }
+}
+
+impl Default for MmapOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 pub struct FileMapping {
+    /// Backing file descriptor, or `-1` for an anonymous mapping that owns no file.
     fd: ::libc::c_int,
+    /// Base of the underlying `mmap(2)` region, which may start up to a page before [`Self::ptr`]
+    /// when the mapping was created with a non-page-aligned `offset` (see
+    /// [`MmapOptions::offset`]); used to `munmap(2)` the region in [`Drop`].
+    base: *mut ::libc::c_void,
+    /// Length, in bytes, of the `mmap(2)` region starting at [`Self::base`].
+    map_len: usize,
+    /// Pointer callers see via [`Self::ptr`]/[`Self::ptr_mut`]: `base` plus the alignment delta.
     ptr: *mut ::libc::c_void,
+    /// Size, in bytes, of the range requested by the caller, starting at [`Self::ptr`].
     size: usize,
+    /// Number of bytes, starting at `ptr`, that have actually been committed (i.e. backed by
+    /// `PROT_READ | PROT_WRITE` pages), as opposed to merely reserved. Always equal to `size` for
+    /// a file-backed mapping, since the whole file is committed up front.
+    committed: usize,
 }
 
 //==================================================================================================
@@ -23,15 +130,47 @@ pub struct FileMapping {
 //==================================================================================================
 
 impl FileMapping {
-    /// Maps a file into memory.
+    /// Maps a file into memory, read-only. Equivalent to
+    /// `Self::mmap_with_mode(filename, MmapMode::ReadOnly)`.
     pub fn mmap(filename: &str) -> Result<Self> {
-        trace!("mmap(): filename={}", filename);
+        Self::mmap_with_mode(filename, MmapMode::ReadOnly)
+    }
+
+    /// Maps a file into memory under the given [`MmapMode`]. Equivalent to
+    /// `MmapOptions::new().mode(mode).map(filename)`.
+    pub fn mmap_with_mode(filename: &str, mode: MmapMode) -> Result<Self> {
+        MmapOptions::new().mode(mode).map(filename)
+    }
+
+    /// Maps a file into memory under the given [`MmapMode`] and [`MmapAdvice`]. Equivalent to
+    /// `MmapOptions::new().mode(mode).advice(advice).map(filename)`.
+    pub fn mmap_with_options(filename: &str, mode: MmapMode, advice: MmapAdvice) -> Result<Self> {
+        MmapOptions::new().mode(mode).advice(advice).map(filename)
+    }
+
+    /// Maps `[offset, offset + len)` of `filename` (or `[offset, EOF)` if `len` is `None`),
+    /// rounding `offset` down to the system's page-alignment boundary as `mmap(2)` requires, and
+    /// adjusting [`Self::ptr`] back up by the resulting delta so callers still see exactly the
+    /// range they asked for.
+    fn mmap_range(filename: &str, mode: MmapMode, offset: u64, len: Option<usize>) -> Result<Self> {
+        trace!(
+            "mmap_range(): filename={}, mode={:?}, offset={:#x}, len={:?}",
+            filename,
+            mode,
+            offset,
+            len
+        );
+
+        let oflag: ::libc::c_int = match mode {
+            MmapMode::ReadOnly => ::libc::O_RDONLY,
+            MmapMode::ReadWrite | MmapMode::CopyOnWrite => ::libc::O_RDWR,
+        };
 
         // Open the file.
         let fd: i32 = unsafe {
             let filename: std::ffi::CString = ::std::ffi::CString::new(filename)?;
             let filename: &[u8] = filename.as_bytes_with_nul();
-            ::libc::open(filename.as_ptr() as *const ::libc::c_char, ::libc::O_RDONLY)
+            ::libc::open(filename.as_ptr() as *const ::libc::c_char, oflag)
         };
 
         if fd < 0 {
@@ -39,7 +178,7 @@ impl FileMapping {
         }
 
         // Get file size.
-        let size: usize = unsafe {
+        let file_size: u64 = unsafe {
             let mut stat: ::libc::stat = ::std::mem::zeroed();
             if ::libc::fstat(fd, &mut stat) < 0 {
                 if ::libc::close(fd) < 0 {
@@ -47,15 +186,55 @@ impl FileMapping {
                 }
                 anyhow::bail!("failed to get file size");
             }
-            stat.st_size as usize
+            stat.st_size as u64
+        };
+
+        let size: usize = match len {
+            Some(len) => len,
+            None => file_size.saturating_sub(offset) as usize,
+        };
+
+        if offset.checked_add(size as u64).map(|end| end > file_size).unwrap_or(true) {
+            unsafe {
+                if ::libc::close(fd) < 0 {
+                    warn!("failed to close file");
+                }
+            }
+            anyhow::bail!(
+                "requested range exceeds file size (offset={:#x}, len={:#x}, file_size={:#x})",
+                offset,
+                size,
+                file_size
+            );
+        }
+
+        // `mmap(2)` requires the file offset to be a multiple of the page size, so round it down
+        // and map the extra bytes this uncovers too; `delta` is how far `ptr` must be nudged back
+        // up from `base` to land exactly on the offset the caller asked for.
+        let page_size: u64 = unsafe { ::libc::sysconf(::libc::_SC_PAGESIZE) as u64 };
+        let aligned_offset: u64 = offset - (offset % page_size);
+        let delta: usize = (offset - aligned_offset) as usize;
+        let map_len: usize = size + delta;
+
+        let (prot, flags): (::libc::c_int, ::libc::c_int) = match mode {
+            MmapMode::ReadOnly => (::libc::PROT_READ, ::libc::MAP_PRIVATE),
+            MmapMode::ReadWrite => (::libc::PROT_READ | ::libc::PROT_WRITE, ::libc::MAP_SHARED),
+            MmapMode::CopyOnWrite => (::libc::PROT_READ | ::libc::PROT_WRITE, ::libc::MAP_PRIVATE),
         };
 
         // Map the file.
-        let ptr: *mut std::ffi::c_void = unsafe {
-            ::libc::mmap(ptr::null_mut(), size, ::libc::PROT_READ, ::libc::MAP_PRIVATE, fd, 0)
+        let base: *mut std::ffi::c_void = unsafe {
+            ::libc::mmap(
+                ptr::null_mut(),
+                map_len,
+                prot,
+                flags,
+                fd,
+                aligned_offset as ::libc::off_t,
+            )
         };
 
-        if ptr == ::libc::MAP_FAILED {
+        if base == ::libc::MAP_FAILED {
             unsafe {
                 if ::libc::close(fd) < 0 {
                     warn!("failed to close file");
@@ -64,25 +243,187 @@ impl FileMapping {
             anyhow::bail!("failed to map file");
         }
 
-        Ok(Self { fd, size, ptr })
+        let ptr: *mut ::libc::c_void = unsafe { base.add(delta) };
+
+        Ok(Self {
+            fd,
+            base,
+            map_len,
+            ptr,
+            size,
+            committed: size,
+        })
+    }
+
+    /// Applies `advice` to the whole mapping. Equivalent to
+    /// `self.advise_range(0, self.committed(), advice)`.
+    pub fn advise(&self, advice: MmapAdvice) -> Result<()> {
+        self.advise_range(0, self.committed, advice)
+    }
+
+    /// Applies `advice` to `[offset, offset + len)` via `madvise(2)`, letting the VMM prefetch (or
+    /// deprioritize) just the part of the mapping it knows it is about to (or will not) touch,
+    /// e.g. the kernel/initrd region of a larger disk image.
+    ///
+    /// # Parameters
+    ///
+    /// - `offset`: Byte offset, relative to [`FileMapping::ptr`], at which the range starts.
+    /// - `len`: Length, in bytes, of the range.
+    /// - `advice`: Access-pattern hint to apply to the range.
+    ///
+    /// # Returns
+    ///
+    /// Upon successful completion, this method returns empty. Otherwise, it returns an error.
+    ///
+    pub fn advise_range(&self, offset: usize, len: usize, advice: MmapAdvice) -> Result<()> {
+        trace!(
+            "advise_range(): offset={:#x}, len={:#x}, advice={:?}",
+            offset,
+            len,
+            advice
+        );
+
+        let madvice: ::libc::c_int = match advice {
+            MmapAdvice::Sequential => ::libc::MADV_SEQUENTIAL,
+            MmapAdvice::Random => ::libc::MADV_RANDOM,
+            MmapAdvice::WillNeed => ::libc::MADV_WILLNEED,
+            MmapAdvice::DontNeed => ::libc::MADV_DONTNEED,
+        };
+
+        let base: *mut ::libc::c_void = unsafe { self.ptr.add(offset) };
+        if unsafe { ::libc::madvise(base, len, madvice) } < 0 {
+            anyhow::bail!("failed to advise mapping");
+        }
+
+        Ok(())
+    }
+
+    /// Persists dirty pages of a [`MmapMode::ReadWrite`] mapping back to the underlying file via
+    /// `msync(2)`, then flushes the file descriptor itself via `fsync(2)`. A no-op for
+    /// [`MmapMode::CopyOnWrite`] mappings, since their writes are never shared with the file in
+    /// the first place; an error for an anonymous mapping, since it has no file to flush to.
+    pub fn flush(&self) -> Result<()> {
+        trace!("flush()");
+
+        if self.fd < 0 {
+            anyhow::bail!("cannot flush an anonymous mapping");
+        }
+
+        if unsafe { ::libc::msync(self.ptr, self.committed, ::libc::MS_SYNC) } < 0 {
+            anyhow::bail!("failed to sync mapping");
+        }
+
+        if unsafe { ::libc::fsync(self.fd) } < 0 {
+            anyhow::bail!("failed to sync file");
+        }
+
+        Ok(())
+    }
+
+    /// Reserves `reserve` bytes of virtual address space, without backing any of it with physical
+    /// pages, so that [`FileMapping::grow`] can later commit pages in place without ever moving
+    /// the base pointer. This is meant to back guest RAM, which must be writable and grow over
+    /// time.
+    pub fn anonymous(reserve: usize) -> Result<Self> {
+        trace!("anonymous(): reserve={:#x}", reserve);
+
+        let ptr: *mut ::libc::c_void = unsafe {
+            ::libc::mmap(
+                ptr::null_mut(),
+                reserve,
+                ::libc::PROT_NONE,
+                ::libc::MAP_PRIVATE | ::libc::MAP_ANONYMOUS | ::libc::MAP_NORESERVE,
+                -1,
+                0,
+            )
+        };
+
+        if ptr == ::libc::MAP_FAILED {
+            anyhow::bail!("failed to reserve anonymous mapping");
+        }
+
+        Ok(Self {
+            fd: -1,
+            base: ptr,
+            map_len: reserve,
+            ptr,
+            size: reserve,
+            committed: 0,
+        })
+    }
+
+    /// Commits pages so that `[0, new_size)` is backed by read-write memory, without moving the
+    /// base pointer returned by [`FileMapping::ptr`]/[`FileMapping::ptr_mut`]. Shrinking (i.e.
+    /// `new_size <= committed`) is a no-op; `new_size` must not exceed the range reserved by
+    /// [`FileMapping::anonymous`].
+    pub fn grow(&mut self, new_size: usize) -> Result<()> {
+        trace!(
+            "grow(): new_size={:#x}, committed={:#x}",
+            new_size,
+            self.committed
+        );
+
+        if new_size <= self.committed {
+            return Ok(());
+        }
+
+        if new_size > self.size {
+            anyhow::bail!(
+                "cannot grow beyond the reserved range (new_size={:#x}, reserved={:#x})",
+                new_size,
+                self.size
+            );
+        }
+
+        let base: *mut ::libc::c_void = unsafe { self.ptr.add(self.committed) };
+        let additional: usize = new_size - self.committed;
+
+        let ptr: *mut ::libc::c_void = unsafe {
+            ::libc::mmap(
+                base,
+                additional,
+                ::libc::PROT_READ | ::libc::PROT_WRITE,
+                ::libc::MAP_FIXED | ::libc::MAP_PRIVATE | ::libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        };
+
+        if ptr == ::libc::MAP_FAILED {
+            anyhow::bail!("failed to commit additional pages");
+        }
+
+        self.committed = new_size;
+
+        Ok(())
     }
 
     pub fn ptr(&self) -> *const u8 {
         self.ptr as *const u8
     }
 
+    pub fn ptr_mut(&mut self) -> *mut u8 {
+        self.ptr as *mut u8
+    }
+
     pub fn size(&self) -> usize {
         self.size
     }
+
+    /// Returns the number of bytes, starting at [`FileMapping::ptr`], that are currently backed by
+    /// read-write memory.
+    pub fn committed(&self) -> usize {
+        self.committed
+    }
 }
 
 impl Drop for FileMapping {
     fn drop(&mut self) {
         unsafe {
-            if ::libc::munmap(self.ptr, self.size) < 0 {
+            if ::libc::munmap(self.base, self.map_len) < 0 {
                 warn!("failed to unmap file");
             }
-            if ::libc::close(self.fd) < 0 {
+            if self.fd >= 0 && ::libc::close(self.fd) < 0 {
                 warn!("failed to close file");
             }
         }
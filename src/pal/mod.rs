@@ -14,9 +14,15 @@
 #[cfg(target_os = "linux")]
 mod linux;
 
+#[cfg(target_os = "windows")]
+mod windows;
+
 //==================================================================================================
 // Exports
 //==================================================================================================
 
 #[cfg(target_os = "linux")]
 pub use linux::*;
+
+#[cfg(target_os = "windows")]
+pub use windows::*;
@@ -6,10 +6,7 @@
 //==================================================================================================
 
 use ::anyhow::Result;
-use ::std::{
-    ptr,
-    usize,
-};
+use ::std::ptr;
 use ::windows::{
     core::HSTRING,
     Win32::{
@@ -19,6 +16,7 @@ use ::windows::{
             FileSystem,
             FileSystem::{
                 FILE_ATTRIBUTE_NORMAL,
+                FILE_FLAG_RANDOM_ACCESS,
                 FILE_FLAG_SEQUENTIAL_SCAN,
                 FILE_SHARE_READ,
                 OPEN_EXISTING,
@@ -27,10 +25,23 @@ use ::windows::{
         System::{
             Memory,
             Memory::{
+                FILE_MAP_ACCESS,
+                FILE_MAP_COPY,
                 FILE_MAP_READ,
+                FILE_MAP_WRITE,
                 MEMORY_MAPPED_VIEW_ADDRESS,
+                MEM_COMMIT,
+                MEM_RELEASE,
+                MEM_RESERVE,
+                PAGE_NOACCESS,
                 PAGE_READONLY,
+                PAGE_READWRITE,
+                PAGE_WRITECOPY,
+                WIN32_MEMORY_RANGE_ENTRY,
             },
+            SystemInformation,
+            SystemInformation::SYSTEM_INFO,
+            Threading,
         },
     },
 };
@@ -39,67 +50,456 @@ use ::windows::{
 // Structures
 //==================================================================================================
 
+/// Selects how [`FileMapping::mmap_with_mode`] opens and protects its backing file. Mirrors
+/// [`super::linux::MmapMode`].
+#[derive(Debug, Clone, Copy)]
+pub enum MmapMode {
+    /// Guest reads only; writes are rejected by the kernel. The default used by
+    /// [`FileMapping::mmap`].
+    ReadOnly,
+    /// Writes are visible to, and persisted back into, the underlying file.
+    ReadWrite,
+    /// Writes are visible to this mapping only and are never written back to the underlying
+    /// file, so a guest can run against a shared base image while keeping its writes private.
+    CopyOnWrite,
+}
+
+/// An access-pattern hint for [`FileMapping::mmap_with_options`]/[`FileMapping::advise`]/
+/// [`FileMapping::advise_range`]. Mirrors [`super::linux::MmapAdvice`].
+#[derive(Debug, Clone, Copy)]
+pub enum MmapAdvice {
+    /// Pages are expected to be accessed in sequential order; chooses `FILE_FLAG_SEQUENTIAL_SCAN`
+    /// at open time. The default used by [`FileMapping::mmap`]/[`FileMapping::mmap_with_mode`].
+    Sequential,
+    /// Pages are expected to be accessed in no particular order; chooses `FILE_FLAG_RANDOM_ACCESS`
+    /// at open time.
+    Random,
+    /// Pages are expected to be needed soon; drives `PrefetchVirtualMemory` to start reading them
+    /// in now, so a later access does not block on I/O.
+    WillNeed,
+    /// Pages are not expected to be needed again soon; drives `DiscardVirtualMemory` so Windows
+    /// may evict them early.
+    DontNeed,
+}
+
+/// Builds up a [`FileMapping`] over a file, mirroring `memmap2::MmapOptions`: every setter
+/// consumes and returns `self`, and [`MmapOptions::map`] performs the actual mapping once all
+/// options are set.
+#[derive(Debug, Clone, Copy)]
+pub struct MmapOptions {
+    /// Byte offset, into the file, at which the mapping starts. Defaults to `0`.
+    offset: u64,
+    /// Length, in bytes, of the mapping. Defaults to the rest of the file past `offset`.
+    len: Option<usize>,
+    mode: MmapMode,
+    advice: MmapAdvice,
+}
+
+impl MmapOptions {
+    /// Creates a new builder, mapping the whole file read-only with [`MmapAdvice::Sequential`]
+    /// unless overridden.
+    pub fn new() -> Self {
+        Self {
+            offset: 0,
+            len: None,
+            mode: MmapMode::ReadOnly,
+            advice: MmapAdvice::Sequential,
+        }
+    }
+
+    /// Sets the byte offset, into the file, at which the mapping starts.
+    pub fn offset(mut self, offset: u64) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    /// Sets the length, in bytes, of the mapping. If unset, the mapping runs to the end of the
+    /// file.
+    pub fn len(mut self, len: usize) -> Self {
+        self.len = Some(len);
+        self
+    }
+
+    /// Sets the [`MmapMode`] to open and protect the mapping with.
+    pub fn mode(mut self, mode: MmapMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Sets the [`MmapAdvice`] to apply to the mapping once it is created.
+    pub fn advice(mut self, advice: MmapAdvice) -> Self {
+        self.advice = advice;
+        self
+    }
+
+    /// Maps `filename` according to the options set so far.
+    pub fn map(self, filename: &str) -> Result<FileMapping> {
+        FileMapping::mmap_range(filename, self.mode, self.advice, self.offset, self.len)
+    }
+}
+
+impl Default for MmapOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A file, or an anonymous range of virtual address space, mapped into the process. Mirrors the
+/// Linux backend's [`super::linux::FileMapping`], so that the rest of the VMM can go through
+/// [`crate::pal::FileMapping`] without caring which backend it was built against.
 pub struct FileMapping {
+    /// Handle to the backing file, or invalid for an anonymous mapping that owns no file.
     fd: HANDLE,
+    /// Handle to the file mapping object, or invalid for an anonymous mapping.
     file_mapping: HANDLE,
+    /// Base of the mapped view returned by `MapViewOfFile`, or of the reserved virtual range for
+    /// an anonymous mapping. May start up to an allocation-granularity unit before [`Self::ptr`]
+    /// when the mapping was created with a non-aligned `offset` (see [`MmapOptions::offset`]);
+    /// used to `UnmapViewOfFile`/`VirtualFree` the view in [`Drop`].
     file_view: MEMORY_MAPPED_VIEW_ADDRESS,
+    /// Pointer callers see via [`Self::ptr`]/[`Self::ptr_mut`]: `file_view` plus the alignment
+    /// delta.
+    ptr: *mut ::std::ffi::c_void,
+    /// Size, in bytes, of the range requested by the caller, starting at [`Self::ptr`].
+    size: usize,
+    /// Number of bytes, starting at [`Self::ptr`], that have actually been committed (i.e. backed
+    /// by `PAGE_READWRITE` pages), as opposed to merely reserved. Always equal to `size` for a
+    /// file-backed mapping, since the whole file is committed up front.
+    committed: usize,
 }
 
+//==================================================================================================
+// Implementations
+//==================================================================================================
+
 impl FileMapping {
+    /// Maps a file into memory, read-only. Equivalent to
+    /// `Self::mmap_with_mode(filename, MmapMode::ReadOnly)`.
     pub fn mmap(filename: &str) -> Result<Self> {
-        trace!("opening file");
+        Self::mmap_with_mode(filename, MmapMode::ReadOnly)
+    }
+
+    /// Maps a file into memory under the given [`MmapMode`], with the default
+    /// [`MmapAdvice::Sequential`] access pattern. Equivalent to
+    /// `MmapOptions::new().mode(mode).map(filename)`.
+    pub fn mmap_with_mode(filename: &str, mode: MmapMode) -> Result<Self> {
+        MmapOptions::new().mode(mode).map(filename)
+    }
+
+    /// Maps a file into memory under the given [`MmapMode`] and [`MmapAdvice`]. Equivalent to
+    /// `MmapOptions::new().mode(mode).advice(advice).map(filename)`.
+    pub fn mmap_with_options(filename: &str, mode: MmapMode, advice: MmapAdvice) -> Result<Self> {
+        MmapOptions::new().mode(mode).advice(advice).map(filename)
+    }
+
+    /// Maps `[offset, offset + len)` of `filename` (or `[offset, EOF)` if `len` is `None`),
+    /// rounding `offset` down to the system's allocation-granularity boundary as `MapViewOfFile`
+    /// requires, and adjusting [`Self::ptr`] back up by the resulting delta so callers still see
+    /// exactly the range they asked for.
+    fn mmap_range(
+        filename: &str,
+        mode: MmapMode,
+        advice: MmapAdvice,
+        offset: u64,
+        len: Option<usize>,
+    ) -> Result<Self> {
+        trace!(
+            "mmap_range(): filename={}, mode={:?}, advice={:?}, offset={:#x}, len={:?}",
+            filename,
+            mode,
+            advice,
+            offset,
+            len
+        );
         let lp_file_name = &HSTRING::from(filename);
 
-        // Open the file.
+        let generic_access: u32 = match mode {
+            MmapMode::ReadOnly => Foundation::GENERIC_READ.0,
+            MmapMode::ReadWrite | MmapMode::CopyOnWrite => {
+                Foundation::GENERIC_READ.0 | Foundation::GENERIC_WRITE.0
+            },
+        };
+
+        let access_flags: FileSystem::FILE_FLAGS_AND_ATTRIBUTES = match advice {
+            MmapAdvice::Random => FILE_FLAG_RANDOM_ACCESS,
+            MmapAdvice::Sequential | MmapAdvice::WillNeed | MmapAdvice::DontNeed => {
+                FILE_FLAG_SEQUENTIAL_SCAN
+            },
+        };
+
+        // Open the file. Other readers may still open it concurrently in every mode; this
+        // process is simply the only one allowed to hold a writable handle to it.
         let fd: HANDLE = unsafe {
             FileSystem::CreateFileW(
                 lp_file_name,
-                Foundation::GENERIC_READ.0,
+                generic_access,
                 FILE_SHARE_READ,
                 None,
                 OPEN_EXISTING,
-                FILE_ATTRIBUTE_NORMAL | FILE_FLAG_SEQUENTIAL_SCAN,
+                FILE_ATTRIBUTE_NORMAL | access_flags,
                 HANDLE(ptr::null_mut()),
             )?
         };
 
-        trace!("getting file size");
         // Get file size.
-        let file_size = unsafe {
-            let mut file_size = 0;
+        let file_size: u64 = unsafe {
+            let mut file_size: i64 = 0;
             FileSystem::GetFileSizeEx(fd, &mut file_size)?;
-            file_size
+            file_size as u64
         };
 
-        trace!("file size: {}", file_size);
+        let size: usize = match len {
+            Some(len) => len,
+            None => file_size.saturating_sub(offset) as usize,
+        };
+
+        if offset.checked_add(size as u64).map(|end| end > file_size).unwrap_or(true) {
+            unsafe {
+                if let Err(e) = Foundation::CloseHandle(fd) {
+                    warn!("failed to close file (error={:?})", e);
+                }
+            }
+            anyhow::bail!(
+                "requested range exceeds file size (offset={:#x}, len={:#x}, file_size={:#x})",
+                offset,
+                size,
+                file_size
+            );
+        }
+
+        // `MapViewOfFile` requires the file offset to be a multiple of the system's allocation
+        // granularity, so round it down and map the extra bytes this uncovers too; `delta` is how
+        // far `ptr` must be nudged back up from `file_view` to land exactly on the offset the
+        // caller asked for.
+        let granularity: u64 = unsafe {
+            let mut info: SYSTEM_INFO = ::std::mem::zeroed();
+            SystemInformation::GetSystemInfo(&mut info);
+            info.dwAllocationGranularity as u64
+        };
+        let aligned_offset: u64 = offset - (offset % granularity);
+        let delta: usize = (offset - aligned_offset) as usize;
+        let map_len: usize = size + delta;
+
+        let page_protect: Memory::PAGE_PROTECTION_FLAGS = match mode {
+            MmapMode::ReadOnly => PAGE_READONLY,
+            MmapMode::ReadWrite => PAGE_READWRITE,
+            MmapMode::CopyOnWrite => PAGE_WRITECOPY,
+        };
 
-        trace!("mapping file");
         // Map the file.
         let file_mapping: HANDLE =
-            unsafe { Memory::CreateFileMappingW(fd, None, PAGE_READONLY, 0, 0, None)? };
+            unsafe { Memory::CreateFileMappingW(fd, None, page_protect, 0, 0, None)? };
+
+        let map_access: u32 = match mode {
+            MmapMode::ReadOnly => FILE_MAP_READ.0,
+            MmapMode::ReadWrite => FILE_MAP_WRITE.0,
+            MmapMode::CopyOnWrite => FILE_MAP_COPY.0,
+        };
 
-        trace!("viewing file {:?}", file_mapping);
+        // Map file.
+        let file_view: MEMORY_MAPPED_VIEW_ADDRESS = unsafe {
+            Memory::MapViewOfFile(
+                file_mapping,
+                FILE_MAP_ACCESS(map_access),
+                (aligned_offset >> 32) as u32,
+                (aligned_offset & 0xFFFF_FFFF) as u32,
+                map_len,
+            )
+        };
 
-        // Map file
-        let file_view =
-            unsafe { Memory::MapViewOfFile(file_mapping, FILE_MAP_READ, 0, 0, file_size as usize) };
+        let ptr: *mut ::std::ffi::c_void = unsafe { file_view.Value.add(delta) };
 
-        Ok(Self {
+        let mapping: Self = Self {
             fd,
             file_mapping,
             file_view,
+            ptr,
+            size,
+            committed: size,
+        };
+
+        if matches!(advice, MmapAdvice::WillNeed) {
+            mapping.advise(advice)?;
+        }
+
+        Ok(mapping)
+    }
+
+    /// Applies `advice` to the whole mapping. Equivalent to
+    /// `self.advise_range(0, self.committed(), advice)`.
+    pub fn advise(&self, advice: MmapAdvice) -> Result<()> {
+        self.advise_range(0, self.committed, advice)
+    }
+
+    /// Applies `advice` to `[offset, offset + len)`, letting the VMM prefetch (or deprioritize)
+    /// just the part of the mapping it knows it is about to (or will not) touch, e.g. the
+    /// kernel/initrd region of a larger disk image.
+    ///
+    /// [`MmapAdvice::Sequential`]/[`MmapAdvice::Random`] have no effect here: on Windows they are
+    /// only applied at open time, via [`FileMapping::mmap_with_options`], since they select which
+    /// flag `CreateFileW` is called with.
+    ///
+    /// # Parameters
+    ///
+    /// - `offset`: Byte offset, relative to [`FileMapping::ptr`], at which the range starts.
+    /// - `len`: Length, in bytes, of the range.
+    /// - `advice`: Access-pattern hint to apply to the range.
+    ///
+    /// # Returns
+    ///
+    /// Upon successful completion, this method returns empty. Otherwise, it returns an error.
+    ///
+    pub fn advise_range(&self, offset: usize, len: usize, advice: MmapAdvice) -> Result<()> {
+        trace!(
+            "advise_range(): offset={:#x}, len={:#x}, advice={:?}",
+            offset,
+            len,
+            advice
+        );
+
+        let base: *mut ::std::ffi::c_void = unsafe { self.ptr.add(offset) };
+
+        match advice {
+            MmapAdvice::WillNeed => {
+                let entry: WIN32_MEMORY_RANGE_ENTRY = WIN32_MEMORY_RANGE_ENTRY {
+                    VirtualAddress: base,
+                    NumberOfBytes: len,
+                };
+                unsafe {
+                    Memory::PrefetchVirtualMemory(Threading::GetCurrentProcess(), &[entry], 0)?;
+                }
+            },
+            MmapAdvice::DontNeed => unsafe {
+                Memory::DiscardVirtualMemory(base, len)?;
+            },
+            // No open-view equivalent; see this method's doc comment.
+            MmapAdvice::Sequential | MmapAdvice::Random => {},
+        }
+
+        Ok(())
+    }
+
+    /// Persists dirty pages of a [`MmapMode::ReadWrite`] mapping back to the underlying file via
+    /// `FlushViewOfFile`, then flushes the file handle itself via `FlushFileBuffers`. A no-op for
+    /// [`MmapMode::CopyOnWrite`] mappings, since their writes are never shared with the file in
+    /// the first place; an error for an anonymous mapping, since it has no file to flush to.
+    pub fn flush(&self) -> Result<()> {
+        trace!("flush()");
+
+        if self.file_mapping.is_invalid() {
+            anyhow::bail!("cannot flush an anonymous mapping");
+        }
+
+        unsafe {
+            Memory::FlushViewOfFile(self.file_view.Value, 0)?;
+            FileSystem::FlushFileBuffers(self.fd)?;
+        }
+
+        Ok(())
+    }
+
+    /// Reserves `reserve` bytes of virtual address space, without backing any of it with physical
+    /// pages, so that [`FileMapping::grow`] can later commit pages in place without ever moving
+    /// the base pointer. This is meant to back guest RAM, which must be writable and grow over
+    /// time.
+    ///
+    /// Reserved via `VirtualAlloc(MEM_RESERVE)` rather than `CreateFileMappingW` over
+    /// `INVALID_HANDLE_VALUE`: both approaches produce an unbacked, file-less range, but
+    /// `VirtualAlloc` is what [`FileMapping::grow`] already uses to commit `PAGE_READWRITE` pages
+    /// into it later, so there is only one allocator to reason about.
+    pub fn anonymous(reserve: usize) -> Result<Self> {
+        trace!("anonymous(): reserve={:#x}", reserve);
+
+        let file_view: MEMORY_MAPPED_VIEW_ADDRESS = unsafe {
+            MEMORY_MAPPED_VIEW_ADDRESS {
+                Value: Memory::VirtualAlloc(None, reserve, MEM_RESERVE, PAGE_NOACCESS),
+            }
+        };
+
+        if file_view.Value.is_null() {
+            anyhow::bail!("failed to reserve anonymous mapping");
+        }
+
+        Ok(Self {
+            fd: HANDLE(ptr::null_mut()),
+            file_mapping: HANDLE(ptr::null_mut()),
+            file_view,
+            ptr: file_view.Value,
+            size: reserve,
+            committed: 0,
         })
     }
 
+    /// Commits pages so that `[0, new_size)` is backed by read-write memory, without moving the
+    /// base pointer returned by [`FileMapping::ptr`]/[`FileMapping::ptr_mut`]. Shrinking (i.e.
+    /// `new_size <= committed`) is a no-op; `new_size` must not exceed the range reserved by
+    /// [`FileMapping::anonymous`].
+    pub fn grow(&mut self, new_size: usize) -> Result<()> {
+        trace!(
+            "grow(): new_size={:#x}, committed={:#x}",
+            new_size,
+            self.committed
+        );
+
+        if new_size <= self.committed {
+            return Ok(());
+        }
+
+        if new_size > self.size {
+            anyhow::bail!(
+                "cannot grow beyond the reserved range (new_size={:#x}, reserved={:#x})",
+                new_size,
+                self.size
+            );
+        }
+
+        let base: *mut ::std::ffi::c_void = unsafe { self.ptr.add(self.committed) };
+        let additional: usize = new_size - self.committed;
+
+        let ptr: *mut ::std::ffi::c_void =
+            unsafe { Memory::VirtualAlloc(Some(base), additional, MEM_COMMIT, PAGE_READWRITE) };
+
+        if ptr.is_null() {
+            anyhow::bail!("failed to commit additional pages");
+        }
+
+        self.committed = new_size;
+
+        Ok(())
+    }
+
     pub fn ptr(&self) -> *const u8 {
-        self.file_view.Value as *const u8
+        self.ptr as *const u8
+    }
+
+    pub fn ptr_mut(&mut self) -> *mut u8 {
+        self.ptr as *mut u8
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Returns the number of bytes, starting at [`FileMapping::ptr`], that are currently backed by
+    /// read-write memory.
+    pub fn committed(&self) -> usize {
+        self.committed
     }
 }
 
 impl Drop for FileMapping {
     fn drop(&mut self) {
         unsafe {
-            trace!("unmapping file");
+            // An anonymous mapping has no file mapping object backing it: it was reserved
+            // (and possibly grown) directly with `VirtualAlloc`, so it must be torn down with
+            // `VirtualFree` instead of `UnmapViewOfFile`.
+            if self.file_mapping.is_invalid() {
+                if let Err(e) = Memory::VirtualFree(self.file_view.Value, 0, MEM_RELEASE) {
+                    warn!("failed to release anonymous mapping (error={:?})", e);
+                }
+                return;
+            }
+
             if let Err(e) = Memory::UnmapViewOfFile(self.file_view) {
                 warn!("failed to unmap view of file (error={:?})", e);
             }
@@ -0,0 +1,459 @@
+// Copyright(c) The Maintainers of Nanvix.
+// Licensed under the MIT License.
+
+//!
+//! # Profiler
+//!
+//! This module provides [`Profiler`], a thread-local scope-timing tree built by the
+//! [`crate::timer!`] macro. A dump is written whenever the thread-local instance is dropped (i.e.,
+//! at thread/process exit), in one of two formats — a flat CSV (the original format, one row per
+//! scope) or Chrome Trace Event JSON (one object per scope *invocation*, loadable straight into a
+//! flamegraph/trace viewer) — to one of three sinks: stderr, a file, or an HTTP endpoint. Both are
+//! configurable at runtime via [`set_format`]/[`set_sink`], ahead of whatever scopes are being
+//! timed; [`Profiler::drop`] cannot take parameters, so there is no way to pick them after the
+//! fact.
+//!
+
+//==================================================================================================
+// Lint Exceptions
+//==================================================================================================
+
+// Not all functions are used.
+#![allow(dead_code)]
+
+//==================================================================================================
+// Imports
+//==================================================================================================
+
+use ::anyhow::Result;
+use ::std::{
+    cell::RefCell,
+    fs::File,
+    io::Write,
+    net::{SocketAddr, TcpStream},
+    rc::Rc,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Instant,
+};
+
+//==================================================================================================
+// Structures
+//==================================================================================================
+
+thread_local!(
+    /// Global thread-local instance of the profiler.
+    pub static PROFILER: RefCell<Profiler> = RefCell::new(Profiler::new())
+);
+
+thread_local!(
+    /// Small, stable numeric id for the current thread, used as the `tid` of every Chrome Trace
+    /// Event emitted by this thread's [`Profiler`]. `std::thread::ThreadId` has no public numeric
+    /// accessor, so we mint our own.
+    static TID: u64 = next_tid()
+);
+
+/// Backing counter for [`TID`].
+static TID_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Mints the next thread id, for [`TID`].
+fn next_tid() -> u64 {
+    TID_COUNTER.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Where a [`Profiler`] writes its dump on [`Drop`].
+pub enum ProfilerSink {
+    /// Write to the standard error stream.
+    Stderr,
+    /// Write to the file at this path, overwriting it if it already exists.
+    File(String),
+    /// POST the dump to the HTTP server listening at this address (the same `<host>:<port>`
+    /// address accepted by [`crate::args::Args::take_sockaddr`]). This assumes the listener on the
+    /// other end is prepared to accept an unsolicited `POST /profile` request; the stock
+    /// [`crate::http::HttpServer`] is not, so this sink is only useful alongside a custom listener.
+    Http(String),
+}
+
+impl Default for ProfilerSink {
+    fn default() -> Self {
+        Self::Stderr
+    }
+}
+
+/// Serialization format for a [`Profiler`] dump.
+pub enum ProfilerFormat {
+    /// Flat CSV, one row per scope, aggregated across all of its invocations.
+    Csv,
+    /// Chrome Trace Event JSON, one `ph: "X"` (complete event) object per scope invocation.
+    ChromeTrace,
+}
+
+impl Default for ProfilerFormat {
+    fn default() -> Self {
+        Self::Csv
+    }
+}
+
+/// Internal representation of scopes as a tree. This tracks a single profiling block of code in
+/// relationship to other profiled blocks.
+struct Scope {
+    /// Name of the scope.
+    name: &'static str,
+    /// Parent scope in the tree. Root scopes have no parent.
+    pred: Option<Rc<RefCell<Scope>>>,
+    /// Child scopes in the tree.
+    succs: Vec<Rc<RefCell<Scope>>>,
+    /// How often has this scope been visited?
+    num_calls: usize,
+    /// In total, how much time has been spent in this scope?
+    duration_sum: u128,
+    /// `(ts, dur)` pairs, in microseconds since [`Profiler::epoch`], one per invocation. Only this
+    /// lets [`Profiler::write_chrome_trace`] emit per-call events instead of just the aggregates
+    /// above.
+    events: Vec<(u128, u128)>,
+}
+
+/// A guard that is created when entering a scope and dropped when leaving it.
+pub struct Guard {
+    enter_time: Instant,
+}
+
+/// A `Profiler` stores the scope tree and keeps track of the currently active scope.
+///
+/// Note that there is a global thread-local instance of `Profiler` in
+/// [`PROFILER`](static@PROFILER), so it is not possible to manually create an instance of
+/// `Profiler`.
+pub struct Profiler {
+    roots: Vec<Rc<RefCell<Scope>>>,
+    current: Option<Rc<RefCell<Scope>>>,
+    /// Instant that every recorded event's `ts` is relative to.
+    epoch: Instant,
+    sink: ProfilerSink,
+    format: ProfilerFormat,
+}
+
+//==================================================================================================
+// Associated Functions
+//==================================================================================================
+
+impl Scope {
+    fn new(name: &'static str, pred: Option<Rc<RefCell<Scope>>>) -> Scope {
+        Scope {
+            name,
+            pred,
+            succs: Vec::new(),
+            num_calls: 0,
+            duration_sum: 0,
+            events: Vec::new(),
+        }
+    }
+
+    fn get_name(&self) -> &'static str {
+        self.name
+    }
+
+    fn get_pred(&self) -> &Option<Rc<RefCell<Scope>>> {
+        &self.pred
+    }
+
+    fn get_succs(&self) -> &Vec<Rc<RefCell<Scope>>> {
+        &self.succs
+    }
+
+    fn add_succ(&mut self, succ: Rc<RefCell<Scope>>) {
+        self.succs.push(succ.clone())
+    }
+
+    fn get_duration_sum(&self) -> u128 {
+        self.duration_sum
+    }
+
+    /// Enter this scope. Returns a [`Guard`] that should be dropped when leaving the scope.
+    #[inline]
+    fn enter(&mut self) -> Guard {
+        Guard::enter()
+    }
+
+    /// Leave this scope, having been entered at `ts` microseconds since the owning
+    /// [`Profiler::epoch`] and having lasted `duration` microseconds. Called automatically by the
+    /// [`Guard`] instance.
+    #[inline]
+    fn leave(&mut self, ts: u128, duration: u128) {
+        self.num_calls += 1;
+        self.duration_sum += duration;
+        self.events.push((ts, duration));
+    }
+
+    /// Writes this scope and its descendants as CSV rows.
+    fn write_csv_recursive<W: Write>(
+        &self,
+        out: &mut W,
+        total_duration: u128,
+        depth: usize,
+    ) -> Result<()> {
+        let total_duration_secs = total_duration as f64;
+        let duration_sum_secs = self.duration_sum as f64;
+        let pred_sum_secs = self.pred.clone().map_or(total_duration_secs, |pred| {
+            pred.borrow().duration_sum as f64
+        });
+        let percent_time = duration_sum_secs / pred_sum_secs * 100.0;
+
+        let mut markers = String::from("+");
+        for _ in 0..depth {
+            markers.push('+');
+        }
+        writeln!(
+            out,
+            "{},{},{:.2},{:.2}",
+            format_args!("{},{}", markers, self.name),
+            self.num_calls,
+            percent_time,
+            duration_sum_secs / (self.num_calls as f64),
+        )?;
+
+        for succ in &self.succs {
+            succ.borrow()
+                .write_csv_recursive(out, total_duration, depth + 1)?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes one Chrome Trace Event object per invocation of this scope and its descendants.
+    fn write_chrome_trace_recursive<W: Write>(
+        &self,
+        out: &mut W,
+        pid: u32,
+        tid: u64,
+        first: &mut bool,
+    ) -> Result<()> {
+        for (ts, dur) in &self.events {
+            if !*first {
+                write!(out, ",")?;
+            }
+            *first = false;
+
+            write!(
+                out,
+                r#"{{"ph":"X","name":{:?},"ts":{},"dur":{},"pid":{},"tid":{}}}"#,
+                self.name, ts, dur, pid, tid
+            )?;
+        }
+
+        for succ in &self.succs {
+            succ.borrow()
+                .write_chrome_trace_recursive(out, pid, tid, first)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Guard {
+    #[inline]
+    fn enter() -> Self {
+        Self {
+            enter_time: Instant::now(),
+        }
+    }
+}
+
+impl Profiler {
+    fn new() -> Profiler {
+        Profiler {
+            roots: Vec::new(),
+            current: None,
+            epoch: Instant::now(),
+            sink: ProfilerSink::default(),
+            format: ProfilerFormat::default(),
+        }
+    }
+
+    /// Create and enter a synchronous scope. Returns a [`Guard`] that should be dropped upon
+    /// leaving the scope.
+    ///
+    /// Usually, this method will be called by the [`crate::timer!`] macro, so it does not need to
+    /// be used directly.
+    #[inline]
+    pub fn sync_scope(&mut self, name: &'static str) -> Guard {
+        let scope = self.get_scope(name);
+        self.enter_scope(scope)
+    }
+
+    /// Looks up the scope at the root level using the name, creating a new one if not found.
+    fn get_root_scope(&mut self, name: &'static str) -> Rc<RefCell<Scope>> {
+        let existing_root = self
+            .roots
+            .iter()
+            .find(|root| root.borrow().get_name() == name)
+            .cloned();
+
+        existing_root.unwrap_or_else(|| {
+            let new_scope: Scope = Scope::new(name, None);
+            let succ = Rc::new(RefCell::new(new_scope));
+            self.roots.push(succ.clone());
+            succ
+        })
+    }
+
+    /// Look up the scope using the name.
+    fn get_scope(&mut self, name: &'static str) -> Rc<RefCell<Scope>> {
+        if let Some(current) = self.current.as_ref() {
+            let existing_succ = current
+                .borrow()
+                .get_succs()
+                .iter()
+                .find(|succ| succ.borrow().get_name() == name)
+                .cloned();
+
+            existing_succ.unwrap_or_else(|| {
+                let new_scope: Scope = Scope::new(name, Some(current.clone()));
+                let succ = Rc::new(RefCell::new(new_scope));
+                current.borrow_mut().add_succ(succ.clone());
+                succ
+            })
+        } else {
+            self.get_root_scope(name)
+        }
+    }
+
+    /// Actually enter a scope.
+    fn enter_scope(&mut self, scope: Rc<RefCell<Scope>>) -> Guard {
+        let guard = scope.borrow_mut().enter();
+        self.current = Some(scope);
+        guard
+    }
+
+    /// Leave the current scope, having been entered at `ts` microseconds since [`Self::epoch`] and
+    /// having lasted `duration` microseconds.
+    #[inline]
+    fn leave_scope(&mut self, ts: u128, duration: u128) {
+        self.current = if let Some(current) = self.current.as_ref() {
+            current.borrow_mut().leave(ts, duration);
+            current.borrow().get_pred().as_ref().cloned()
+        } else {
+            error!("leave_scope(): called while not in any scope");
+            None
+        };
+    }
+
+    /// Writes a flat CSV dump, one row per scope, to `out`.
+    fn write_csv<W: Write>(&self, out: &mut W) -> Result<()> {
+        let total_duration: u128 = self
+            .roots
+            .iter()
+            .map(|root| root.borrow().get_duration_sum())
+            .sum();
+
+        writeln!(
+            out,
+            "call_depth,function_name,num_calls,percent_time,microsecs_per_call"
+        )?;
+        for root in self.roots.iter() {
+            root.borrow().write_csv_recursive(out, total_duration, 0)?;
+        }
+        out.flush()?;
+        Ok(())
+    }
+
+    /// Writes a Chrome Trace Event JSON array, one object per scope invocation, to `out`.
+    fn write_chrome_trace<W: Write>(&self, out: &mut W) -> Result<()> {
+        let pid: u32 = std::process::id();
+        let tid: u64 = TID.with(|tid| *tid);
+        let mut first: bool = true;
+
+        write!(out, "[")?;
+        for root in self.roots.iter() {
+            root.borrow()
+                .write_chrome_trace_recursive(out, pid, tid, &mut first)?;
+        }
+        write!(out, "]")?;
+        out.flush()?;
+        Ok(())
+    }
+
+    /// Serializes the dump, in [`Self::format`], to `out`.
+    fn write_to<W: Write>(&self, out: &mut W) -> Result<()> {
+        match self.format {
+            ProfilerFormat::Csv => self.write_csv(out),
+            ProfilerFormat::ChromeTrace => self.write_chrome_trace(out),
+        }
+    }
+
+    /// Connects to `addr` and POSTs the dump to it.
+    fn write_to_http(&self, addr: &str) -> Result<()> {
+        let sockaddr: SocketAddr = addr.parse()?;
+
+        let mut body: Vec<u8> = Vec::new();
+        self.write_to(&mut body)?;
+
+        let content_type: &str = match self.format {
+            ProfilerFormat::Csv => "text/csv",
+            ProfilerFormat::ChromeTrace => "application/json",
+        };
+
+        let mut stream: TcpStream = TcpStream::connect(sockaddr)?;
+        write!(
+            stream,
+            "POST /profile HTTP/1.1\r\nHost: {}\r\nContent-Type: {}\r\nContent-Length: \
+             {}\r\nConnection: close\r\n\r\n",
+            addr,
+            content_type,
+            body.len()
+        )?;
+        stream.write_all(&body)?;
+        Ok(())
+    }
+
+    /// Writes the dump to [`Self::sink`], in [`Self::format`].
+    fn dump(&self) -> Result<()> {
+        match &self.sink {
+            ProfilerSink::Stderr => self.write_to(&mut std::io::stderr()),
+            ProfilerSink::File(path) => self.write_to(&mut File::create(path)?),
+            ProfilerSink::Http(addr) => self.write_to_http(addr),
+        }
+    }
+}
+
+//==================================================================================================
+// Standalone Functions
+//==================================================================================================
+
+/// Selects the sink that the calling thread's [`Profiler`] writes its dump to. Only takes effect
+/// for dumps written after this call, so it must run before the thread that owns this `Profiler`
+/// exits.
+pub fn set_sink(sink: ProfilerSink) {
+    PROFILER.with(|p| p.borrow_mut().sink = sink);
+}
+
+/// Selects the format that the calling thread's [`Profiler`] serializes its dump with.
+pub fn set_format(format: ProfilerFormat) {
+    PROFILER.with(|p| p.borrow_mut().format = format);
+}
+
+//==================================================================================================
+// Trait Implementations
+//==================================================================================================
+
+impl Drop for Profiler {
+    fn drop(&mut self) {
+        if let Err(e) = self.dump() {
+            error!("profiler: failed to write dump (error={:?})", e);
+        }
+    }
+}
+
+impl Drop for Guard {
+    #[inline]
+    fn drop(&mut self) {
+        let duration: u128 = self.enter_time.elapsed().as_micros();
+        PROFILER.with(|p| {
+            let ts: u128 = {
+                let profiler = p.borrow();
+                self.enter_time
+                    .saturating_duration_since(profiler.epoch)
+                    .as_micros()
+            };
+            p.borrow_mut().leave_scope(ts, duration);
+        });
+    }
+}
@@ -0,0 +1,243 @@
+// Copyright(c) The Maintainers of Nanvix.
+// Licensed under the MIT License.
+
+//!
+//! # Seccomp Sandbox
+//!
+//! This module provides a minimal seccomp-BPF sandbox for the virtual machine monitor process,
+//! modeled after crosvm's compiled-in seccomp policies. [`install`] installs a filter that
+//! allowlists only the syscalls the run loop needs (KVM `ioctl`s, the I/O channels to the guest,
+//! and the handful of syscalls `libstd` itself relies on for threading and memory management) and
+//! kills the process on anything else.
+//!
+//! Note that a seccomp-BPF filter only applies to the thread that installs it and to threads
+//! spawned afterwards; it is not retroactively applied to already-running threads, and every
+//! thread that is itself going to spawn more threads (the main thread spawning the vCPU and
+//! control-socket threads, the I/O thread spawning its own gateway threads) needs `clone`/`clone3`
+//! allowlisted for that spawn to succeed. `main::run_vmm` installs the filter on the main thread
+//! after all of its own setup that needs a broader syscall surface has finished (opening files,
+//! creating KVM file descriptors) but before `MicroVm::run` spawns any guest-facing thread, and
+//! `main::main` installs it again on the I/O thread, right before it starts serving the guest
+//! (see [`ALLOWED_SYSCALLS`] for the extra setup syscalls that second install point requires),
+//! since that thread runs the guest-facing message parser the sandbox exists to contain.
+//!
+
+//==================================================================================================
+// Imports
+//==================================================================================================
+
+use ::anyhow::Result;
+
+//==================================================================================================
+// Constants
+//==================================================================================================
+
+/// Syscalls required by the run loop: KVM `ioctl`s, the I/O channels to the guest (`read`/`write`
+/// for files, `writev` for scatter-gather writes, `recvmsg`/`sendmsg` for the gateway), the
+/// syscalls `libstd`'s threading/allocator/signal-handling machinery relies on (`futex`,
+/// `mmap`/`munmap`, `mprotect`, `rt_sigreturn`, `rt_sigprocmask`), `clone`/`clone3` and
+/// `set_robust_list` to spawn the vCPU, control-socket and I/O threads themselves (the filter is
+/// installed on the thread that then has to create all of the others), `exit` and `exit_group` to
+/// let threads and the process terminate normally, and the syscalls the I/O thread's own setup
+/// needs now that [`install`] is also called from inside it (see `main::main`): `openat` and
+/// `newfstatat`/`close` to open the `-vm-stdin`/`-vm-stdout` files, and
+/// `socket`/`bind`/`listen`/`accept4`/`setsockopt`/`connect`/`shutdown` for the HTTP gateway.
+#[cfg(target_arch = "x86_64")]
+const ALLOWED_SYSCALLS: &[i64] = &[
+    ::libc::SYS_ioctl,
+    ::libc::SYS_read,
+    ::libc::SYS_write,
+    ::libc::SYS_writev,
+    ::libc::SYS_recvmsg,
+    ::libc::SYS_sendmsg,
+    ::libc::SYS_futex,
+    ::libc::SYS_mmap,
+    ::libc::SYS_munmap,
+    ::libc::SYS_mprotect,
+    ::libc::SYS_rt_sigreturn,
+    ::libc::SYS_rt_sigprocmask,
+    ::libc::SYS_clone,
+    ::libc::SYS_clone3,
+    ::libc::SYS_set_robust_list,
+    ::libc::SYS_exit,
+    ::libc::SYS_exit_group,
+    ::libc::SYS_openat,
+    ::libc::SYS_newfstatat,
+    ::libc::SYS_close,
+    ::libc::SYS_socket,
+    ::libc::SYS_bind,
+    ::libc::SYS_listen,
+    ::libc::SYS_accept4,
+    ::libc::SYS_setsockopt,
+    ::libc::SYS_connect,
+    ::libc::SYS_shutdown,
+];
+
+/// Same syscall set as [`ALLOWED_SYSCALLS`] above, for the `aarch64` target.
+#[cfg(target_arch = "aarch64")]
+const ALLOWED_SYSCALLS: &[i64] = &[
+    ::libc::SYS_ioctl,
+    ::libc::SYS_read,
+    ::libc::SYS_write,
+    ::libc::SYS_writev,
+    ::libc::SYS_recvmsg,
+    ::libc::SYS_sendmsg,
+    ::libc::SYS_futex,
+    ::libc::SYS_mmap,
+    ::libc::SYS_munmap,
+    ::libc::SYS_mprotect,
+    ::libc::SYS_rt_sigreturn,
+    ::libc::SYS_rt_sigprocmask,
+    ::libc::SYS_clone,
+    ::libc::SYS_clone3,
+    ::libc::SYS_set_robust_list,
+    ::libc::SYS_exit,
+    ::libc::SYS_exit_group,
+    ::libc::SYS_openat,
+    ::libc::SYS_newfstatat,
+    ::libc::SYS_close,
+    ::libc::SYS_socket,
+    ::libc::SYS_bind,
+    ::libc::SYS_listen,
+    ::libc::SYS_accept4,
+    ::libc::SYS_setsockopt,
+    ::libc::SYS_connect,
+    ::libc::SYS_shutdown,
+];
+
+/// Audit architecture constant that [`ALLOWED_SYSCALLS`] was compiled for. The filter rejects any
+/// syscall made under a different personality/architecture (e.g. a 32-bit compatibility syscall
+/// on a 64-bit process), since the syscall numbers in [`ALLOWED_SYSCALLS`] are only meaningful for
+/// this one.
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: u32 = ::libc::AUDIT_ARCH_X86_64;
+
+/// See [`AUDIT_ARCH`] above.
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: u32 = ::libc::AUDIT_ARCH_AARCH64;
+
+/// Byte offset of the `nr` field in `struct seccomp_data`.
+const SECCOMP_DATA_NR_OFFSET: u32 = 0;
+
+/// Byte offset of the `arch` field in `struct seccomp_data`.
+const SECCOMP_DATA_ARCH_OFFSET: u32 = 4;
+
+//==================================================================================================
+// Standalone Functions
+//==================================================================================================
+
+///
+/// # Description
+///
+/// Installs a seccomp-BPF filter on the calling thread that allowlists [`ALLOWED_SYSCALLS`] and
+/// kills the process (`SECCOMP_RET_KILL_PROCESS`) on any other syscall. The filter is inherited by
+/// every thread spawned after this call returns.
+///
+/// # Returns
+///
+/// Upon successful completion, this function returns empty. Otherwise, it returns an error.
+///
+pub fn install() -> Result<()> {
+    trace!("install()");
+    crate::timer!("seccomp_install");
+
+    let program: Vec<::libc::sock_filter> = build_filter();
+
+    // Disallow acquiring new privileges from this point on, as required by the kernel before a
+    // seccomp filter may be installed by an unprivileged process.
+    if unsafe { ::libc::prctl(::libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } != 0 {
+        let reason: String =
+            format!("failed to set no_new_privs (error={})", ::std::io::Error::last_os_error());
+        error!("install(): {}", reason);
+        anyhow::bail!(reason);
+    }
+
+    let fprog: ::libc::sock_fprog = ::libc::sock_fprog {
+        len: program.len() as u16,
+        filter: program.as_ptr() as *mut ::libc::sock_filter,
+    };
+
+    if unsafe {
+        ::libc::prctl(
+            ::libc::PR_SET_SECCOMP,
+            ::libc::SECCOMP_MODE_FILTER,
+            &fprog as *const ::libc::sock_fprog,
+        )
+    } != 0
+    {
+        let reason: String =
+            format!("failed to install seccomp filter (error={})", ::std::io::Error::last_os_error());
+        error!("install(): {}", reason);
+        anyhow::bail!(reason);
+    }
+
+    Ok(())
+}
+
+///
+/// # Description
+///
+/// Builds the BPF program installed by [`install`]: reject any syscall made under an unexpected
+/// architecture, then allow every syscall in [`ALLOWED_SYSCALLS`], killing the process on
+/// anything else.
+///
+/// # Returns
+///
+/// The BPF program, as a sequence of `sock_filter` instructions.
+///
+fn build_filter() -> Vec<::libc::sock_filter> {
+    let mut program: Vec<::libc::sock_filter> = Vec::with_capacity(4 + ALLOWED_SYSCALLS.len());
+
+    // Reject anything that was not made under the expected architecture: a match skips over the
+    // kill instruction that immediately follows, a mismatch falls straight through into it.
+    program.push(bpf_stmt(
+        (::libc::BPF_LD | ::libc::BPF_W | ::libc::BPF_ABS) as u16,
+        SECCOMP_DATA_ARCH_OFFSET,
+    ));
+    program.push(bpf_jump((::libc::BPF_JMP | ::libc::BPF_JEQ | ::libc::BPF_K) as u16, AUDIT_ARCH, 1, 0));
+    program.push(bpf_stmt(
+        (::libc::BPF_RET | ::libc::BPF_K) as u16,
+        ::libc::SECCOMP_RET_KILL_PROCESS,
+    ));
+
+    // Load the syscall number once, then check it against every allowed syscall. A match jumps
+    // forward to the `ALLOW` instruction right after the last check; a miss on every check falls
+    // through to the final `KILL_PROCESS` instruction.
+    program.push(bpf_stmt(
+        (::libc::BPF_LD | ::libc::BPF_W | ::libc::BPF_ABS) as u16,
+        SECCOMP_DATA_NR_OFFSET,
+    ));
+
+    let n: usize = ALLOWED_SYSCALLS.len();
+    for (i, syscall) in ALLOWED_SYSCALLS.iter().enumerate() {
+        let is_last: bool = i == n - 1;
+        let jt: u8 = if is_last { 0 } else { (n - i - 1) as u8 };
+        let jf: u8 = if is_last { 1 } else { 0 };
+        program.push(bpf_jump(
+            (::libc::BPF_JMP | ::libc::BPF_JEQ | ::libc::BPF_K) as u16,
+            *syscall as u32,
+            jt,
+            jf,
+        ));
+    }
+
+    program.push(bpf_stmt((::libc::BPF_RET | ::libc::BPF_K) as u16, ::libc::SECCOMP_RET_ALLOW));
+    program.push(bpf_stmt((::libc::BPF_RET | ::libc::BPF_K) as u16, ::libc::SECCOMP_RET_KILL_PROCESS));
+
+    program
+}
+
+/// Builds a non-jumping BPF instruction (`BPF_STMT`).
+fn bpf_stmt(code: u16, k: u32) -> ::libc::sock_filter {
+    ::libc::sock_filter {
+        code,
+        jt: 0,
+        jf: 0,
+        k,
+    }
+}
+
+/// Builds a conditional-jump BPF instruction (`BPF_JUMP`).
+fn bpf_jump(code: u16, k: u32, jt: u8, jf: u8) -> ::libc::sock_filter {
+    ::libc::sock_filter { code, jt, jf, k }
+}
@@ -0,0 +1,456 @@
+// Copyright(c) The Maintainers of Nanvix.
+// Licensed under the MIT License.
+
+//!
+//! # Shared Filesystem Device
+//!
+//! This module exposes a host directory to the guest, modeled on the virtio-fs approach: the
+//! guest issues FUSE-style requests (`LOOKUP`/`GETATTR`/`OPEN`/`READ`/`WRITE`/`READDIR`) and the
+//! device serves them directly off the host filesystem rooted at a given path, tagged with a
+//! name the guest uses to mount it. This gives a persistent, writable host-filesystem path into
+//! the microVM without rebuilding the initrd for every change.
+//!
+//! Full virtio-fs runs the FUSE wire format over a virtqueue, with requests and payloads placed
+//! anywhere in guest memory via descriptor chains. This codebase does not yet give MMIO device
+//! callbacks access to guest memory (see [`crate::microvm::MmioReadFn`]/
+//! [`crate::microvm::MmioWriteFn`], which only see the device's own register range), so this
+//! device instead exposes a simplified register-file protocol: the guest stages a request's
+//! arguments (opcode, handle, offset, length, path) and, for writes, payload bytes into fixed
+//! registers, then triggers dispatch by writing [`VirtioFsDevice::REG_OPCODE`]. This is a
+//! deliberate, honestly-scoped simplification of the wire format, not a re-implementation of it.
+//!
+
+//==================================================================================================
+// Imports
+//==================================================================================================
+
+use crate::microvm::MicroVm;
+use ::anyhow::Result;
+use ::std::{
+    collections::HashMap,
+    fs,
+    io::{Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+//==================================================================================================
+// Constants
+//==================================================================================================
+
+/// Size, in bytes, of [`VirtioFsDevice::REG_PATH`].
+const PATH_BUF_SIZE: usize = 256;
+
+/// Size, in bytes, of [`VirtioFsDevice::REG_DATA`].
+const DATA_BUF_SIZE: usize = 4096;
+
+/// Opcode for a `LOOKUP` request: resolves a path and reports whether it exists.
+const OP_LOOKUP: u32 = 1;
+/// Opcode for a `GETATTR` request: reports size/mode/kind for a path.
+const OP_GETATTR: u32 = 2;
+/// Opcode for an `OPEN` request: opens a path and returns a handle.
+const OP_OPEN: u32 = 3;
+/// Opcode for a `READ` request: reads from an open handle at a given offset.
+const OP_READ: u32 = 4;
+/// Opcode for a `WRITE` request: writes to an open handle at a given offset.
+const OP_WRITE: u32 = 5;
+/// Opcode for a `READDIR` request: lists directory entries starting at a given index.
+const OP_READDIR: u32 = 6;
+
+/// Request completed successfully.
+const STATUS_OK: u32 = 0;
+/// The requested path does not exist.
+const STATUS_NOT_FOUND: u32 = 1;
+/// The requested path is not a directory.
+const STATUS_NOT_A_DIRECTORY: u32 = 2;
+/// `HANDLE` does not refer to a handle returned by a prior `OPEN`.
+const STATUS_INVALID_HANDLE: u32 = 3;
+/// `PATH` escapes the shared directory (e.g. via `..`) or is not valid UTF-8.
+const STATUS_INVALID_PATH: u32 = 4;
+/// The host filesystem returned an I/O error.
+const STATUS_IO_ERROR: u32 = 5;
+/// The request or opcode was not recognized.
+const STATUS_INVALID_REQUEST: u32 = 6;
+
+//==================================================================================================
+// Structures
+//==================================================================================================
+
+///
+/// # Description
+///
+/// A shared-directory device that exposes a host directory to the guest under a mount tag, over
+/// the simplified register-file protocol described in the module documentation.
+///
+pub struct VirtioFsDevice {
+    /// Tag that the guest mounts this device by.
+    tag: String,
+    /// Host directory that this device exposes.
+    root: PathBuf,
+    /// Open file handles, keyed by the handle returned to the guest by [`OP_OPEN`].
+    handles: HashMap<u32, fs::File>,
+    /// Next handle to hand out.
+    next_handle: u32,
+    /// Last opcode written to [`Self::REG_OPCODE`].
+    opcode: u32,
+    /// Result of the last dispatched request.
+    status: u32,
+    /// Handle argument/result register.
+    handle: u32,
+    /// Offset argument register, low 32 bits.
+    offset_lo: u32,
+    /// Offset argument register, high 32 bits.
+    offset_hi: u32,
+    /// Length argument/result register.
+    length: u32,
+    /// Path argument register, staged as a NUL-terminated relative path.
+    path: [u8; PATH_BUF_SIZE],
+    /// Payload register, staged for `WRITE` requests and filled in for `READ`/`READDIR`/
+    /// `GETATTR` responses.
+    data: [u8; DATA_BUF_SIZE],
+}
+
+impl VirtioFsDevice {
+    /// Size, in bytes, of the MMIO window that a [`VirtioFsDevice`] occupies.
+    pub const MMIO_LEN: u64 = Self::REG_DATA + DATA_BUF_SIZE as u64;
+
+    /// Opcode register. Writing here dispatches the request named by the written value, using
+    /// whatever of [`Self::REG_HANDLE`], [`Self::REG_OFFSET_LO`]/[`Self::REG_OFFSET_HI`], [`Self::REG_LENGTH`] and
+    /// [`Self::REG_PATH`]/[`Self::REG_DATA`] it needs; reading back returns the last opcode
+    /// dispatched.
+    const REG_OPCODE: u64 = 0x00;
+    /// Status register, set by the last dispatched request. `0` on success, see the `STATUS_*`
+    /// constants otherwise.
+    const REG_STATUS: u64 = 0x04;
+    /// Handle register, read/write.
+    const REG_HANDLE: u64 = 0x08;
+    /// Offset register, low 32 bits, read/write.
+    const REG_OFFSET_LO: u64 = 0x10;
+    /// Offset register, high 32 bits, read/write.
+    const REG_OFFSET_HI: u64 = 0x14;
+    /// Length register, read/write.
+    const REG_LENGTH: u64 = 0x18;
+    /// Path register: a NUL-terminated path, relative to the shared directory, read/write.
+    const REG_PATH: u64 = 0x20;
+    /// Data register: the request/response payload, read/write.
+    const REG_DATA: u64 = Self::REG_PATH + PATH_BUF_SIZE as u64;
+
+    ///
+    /// # Description
+    ///
+    /// Creates a new shared-directory device.
+    ///
+    /// # Parameters
+    ///
+    /// - `tag`: Tag that the guest mounts this device by.
+    /// - `root`: Host directory to expose.
+    ///
+    /// # Returns
+    ///
+    /// Upon successful completion, this method returns the device that was created. Otherwise, it
+    /// returns an error.
+    ///
+    pub fn new(tag: &str, root: &str) -> Result<Self> {
+        trace!("new(): tag={}, root={}", tag, root);
+
+        let root: PathBuf = fs::canonicalize(root)?;
+        if !root.is_dir() {
+            anyhow::bail!("shared directory is not a directory (root={:?})", root);
+        }
+
+        Ok(Self {
+            tag: tag.to_string(),
+            root,
+            handles: HashMap::new(),
+            next_handle: 1,
+            opcode: 0,
+            status: STATUS_OK,
+            handle: 0,
+            offset_lo: 0,
+            offset_hi: 0,
+            length: 0,
+            path: [0; PATH_BUF_SIZE],
+            data: [0; DATA_BUF_SIZE],
+        })
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Registers this device with `microvm` at `base`, consuming it. The device is shared behind
+    /// an `Arc<Mutex<...>>` so that the read and write closures handed to
+    /// [`MicroVm::register_mmio`] can both reach it.
+    ///
+    /// # Parameters
+    ///
+    /// - `microvm`: Virtual machine to register the device with.
+    /// - `base`: Guest physical address at which the device is mapped.
+    ///
+    /// # Returns
+    ///
+    /// Upon successful completion, this method returns empty. Otherwise, it returns an error.
+    ///
+    pub fn attach(self, microvm: &mut MicroVm, base: u64) -> Result<()> {
+        trace!("attach(): tag={}, base={:#010x}", self.tag, base);
+
+        let device: Arc<Mutex<VirtioFsDevice>> = Arc::new(Mutex::new(self));
+
+        let read_device: Arc<Mutex<VirtioFsDevice>> = device.clone();
+        let read_fn = move |offset: u64, buf: &mut [u8]| -> Result<()> {
+            read_device.lock().unwrap().mmio_read(offset, buf)
+        };
+
+        let write_device: Arc<Mutex<VirtioFsDevice>> = device;
+        let write_fn = move |offset: u64, value: u32, size: usize| -> Result<()> {
+            write_device.lock().unwrap().mmio_write(offset, value, size)
+        };
+
+        microvm.register_mmio(base, Self::MMIO_LEN, Box::new(read_fn), Box::new(write_fn))
+    }
+
+    /// Returns the combined 64-bit value of [`Self::offset_lo`]/[`Self::offset_hi`].
+    fn offset(&self) -> u64 {
+        ((self.offset_hi as u64) << 32) | self.offset_lo as u64
+    }
+
+    /// Handles a read of `buf.len()` bytes at `offset` within this device's MMIO window.
+    fn mmio_read(&mut self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        let value: &[u8] = match offset {
+            Self::REG_OPCODE => &self.opcode.to_le_bytes(),
+            Self::REG_STATUS => &self.status.to_le_bytes(),
+            Self::REG_HANDLE => &self.handle.to_le_bytes(),
+            Self::REG_OFFSET_LO => &self.offset_lo.to_le_bytes(),
+            Self::REG_OFFSET_HI => &self.offset_hi.to_le_bytes(),
+            Self::REG_LENGTH => &self.length.to_le_bytes(),
+            _ if (Self::REG_PATH..Self::REG_DATA).contains(&offset) => {
+                let start: usize = (offset - Self::REG_PATH) as usize;
+                &self.path[start..]
+            }
+            _ if (Self::REG_DATA..Self::MMIO_LEN).contains(&offset) => {
+                let start: usize = (offset - Self::REG_DATA) as usize;
+                &self.data[start..]
+            }
+            _ => anyhow::bail!(
+                "read from unmapped virtiofs register (offset={:#x})",
+                offset
+            ),
+        };
+
+        let len: usize = buf.len().min(value.len());
+        buf[..len].copy_from_slice(&value[..len]);
+
+        Ok(())
+    }
+
+    /// Handles a write of `size` bytes of `value` at `offset` within this device's MMIO window.
+    fn mmio_write(&mut self, offset: u64, value: u32, size: usize) -> Result<()> {
+        match offset {
+            Self::REG_OPCODE => {
+                self.opcode = value;
+                self.status = self.dispatch(value);
+            }
+            Self::REG_HANDLE => self.handle = value,
+            Self::REG_OFFSET_LO => self.offset_lo = value,
+            Self::REG_OFFSET_HI => self.offset_hi = value,
+            Self::REG_LENGTH => self.length = value,
+            _ if (Self::REG_PATH..Self::REG_DATA).contains(&offset) => {
+                let start: usize = (offset - Self::REG_PATH) as usize;
+                let bytes: [u8; 4] = value.to_le_bytes();
+                let len: usize = size.min(self.path.len() - start);
+                self.path[start..start + len].copy_from_slice(&bytes[..len]);
+            }
+            _ if (Self::REG_DATA..Self::MMIO_LEN).contains(&offset) => {
+                let start: usize = (offset - Self::REG_DATA) as usize;
+                let bytes: [u8; 4] = value.to_le_bytes();
+                let len: usize = size.min(self.data.len() - start);
+                self.data[start..start + len].copy_from_slice(&bytes[..len]);
+            }
+            _ => anyhow::bail!("write to unmapped virtiofs register (offset={:#x})", offset),
+        }
+
+        Ok(())
+    }
+
+    /// Dispatches the request named by `opcode`, returning the status to latch into
+    /// [`Self::REG_STATUS`].
+    fn dispatch(&mut self, opcode: u32) -> u32 {
+        let result: Result<(), u32> = match opcode {
+            OP_LOOKUP | OP_GETATTR => self.do_getattr(),
+            OP_OPEN => self.do_open(),
+            OP_READ => self.do_read(),
+            OP_WRITE => self.do_write(),
+            OP_READDIR => self.do_readdir(),
+            _ => Err(STATUS_INVALID_REQUEST),
+        };
+
+        match result {
+            Ok(()) => STATUS_OK,
+            Err(status) => status,
+        }
+    }
+
+    /// Resolves [`Self::path`] to a host path under [`Self::root`], rejecting paths that escape
+    /// it.
+    fn resolve_path(&self) -> Result<PathBuf, u32> {
+        let nul: usize = self
+            .path
+            .iter()
+            .position(|&b| b == 0)
+            .unwrap_or(self.path.len());
+        let relative: &str =
+            std::str::from_utf8(&self.path[..nul]).map_err(|_| STATUS_INVALID_PATH)?;
+
+        let mut resolved: PathBuf = self.root.clone();
+        for component in Path::new(relative).components() {
+            use std::path::Component;
+            match component {
+                Component::Normal(part) => resolved.push(part),
+                Component::CurDir => {}
+                _ => return Err(STATUS_INVALID_PATH),
+            }
+        }
+
+        Ok(resolved)
+    }
+
+    /// Serves `LOOKUP`/`GETATTR`: writes `(size: u64, mode: u32, is_dir: u8)` to
+    /// [`Self::REG_DATA`] and the encoded length to [`Self::REG_LENGTH`]. `mode` is the host's
+    /// Unix mode bits, or `0` on hosts that have none.
+    fn do_getattr(&mut self) -> Result<(), u32> {
+        let path: PathBuf = self.resolve_path()?;
+        let metadata: fs::Metadata = fs::metadata(&path).map_err(|_| STATUS_NOT_FOUND)?;
+
+        #[cfg(unix)]
+        let mode: u32 = ::std::os::unix::fs::PermissionsExt::mode(&metadata.permissions());
+        #[cfg(not(unix))]
+        let mode: u32 = 0;
+
+        let mut encoded: [u8; 13] = [0; 13];
+        encoded[0..8].copy_from_slice(&metadata.len().to_le_bytes());
+        encoded[8..12].copy_from_slice(&mode.to_le_bytes());
+        encoded[12] = metadata.is_dir() as u8;
+
+        self.data[..encoded.len()].copy_from_slice(&encoded);
+        self.length = encoded.len() as u32;
+
+        Ok(())
+    }
+
+    /// Serves `OPEN`: opens [`Self::path`] for reading and writing, latching the resulting handle
+    /// in [`Self::REG_HANDLE`].
+    fn do_open(&mut self) -> Result<(), u32> {
+        let path: PathBuf = self.resolve_path()?;
+        let file: fs::File = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&path)
+            .map_err(|_| STATUS_NOT_FOUND)?;
+
+        let handle: u32 = self.next_handle;
+        self.next_handle += 1;
+        self.handles.insert(handle, file);
+        self.handle = handle;
+
+        Ok(())
+    }
+
+    /// Serves `READ`: reads up to `min(length, DATA_BUF_SIZE)` bytes from `handle` at `offset`
+    /// into [`Self::REG_DATA`], latching the number of bytes actually read in
+    /// [`Self::REG_LENGTH`].
+    fn do_read(&mut self) -> Result<(), u32> {
+        let file: &mut fs::File = self
+            .handles
+            .get_mut(&self.handle)
+            .ok_or(STATUS_INVALID_HANDLE)?;
+
+        let requested: usize = (self.length as usize).min(DATA_BUF_SIZE);
+        file.seek(SeekFrom::Start(self.offset()))
+            .map_err(|_| STATUS_IO_ERROR)?;
+        let read: usize = file
+            .read(&mut self.data[..requested])
+            .map_err(|_| STATUS_IO_ERROR)?;
+
+        self.length = read as u32;
+
+        Ok(())
+    }
+
+    /// Serves `WRITE`: writes the first `length` bytes of [`Self::REG_DATA`] to `handle` at
+    /// `offset`, latching the number of bytes actually written in [`Self::REG_LENGTH`].
+    fn do_write(&mut self) -> Result<(), u32> {
+        let file: &mut fs::File = self
+            .handles
+            .get_mut(&self.handle)
+            .ok_or(STATUS_INVALID_HANDLE)?;
+
+        let requested: usize = (self.length as usize).min(DATA_BUF_SIZE);
+        file.seek(SeekFrom::Start(self.offset()))
+            .map_err(|_| STATUS_IO_ERROR)?;
+        let written: usize = file
+            .write(&self.data[..requested])
+            .map_err(|_| STATUS_IO_ERROR)?;
+
+        self.length = written as u32;
+
+        Ok(())
+    }
+
+    /// Serves `READDIR`: lists entries of [`Self::path`] starting at index [`Self::offset`],
+    /// packing as many NUL-terminated names as fit into [`Self::REG_DATA`], and latches the
+    /// number of bytes written in [`Self::REG_LENGTH`].
+    fn do_readdir(&mut self) -> Result<(), u32> {
+        let path: PathBuf = self.resolve_path()?;
+        if !path.is_dir() {
+            return Err(STATUS_NOT_A_DIRECTORY);
+        }
+
+        let entries: fs::ReadDir = fs::read_dir(&path).map_err(|_| STATUS_IO_ERROR)?;
+        let names: Vec<String> = entries
+            .filter_map(|entry| entry.ok())
+            .skip(self.offset() as usize)
+            .map(|entry| entry.file_name().to_string_lossy().into_owned())
+            .collect();
+
+        let mut written: usize = 0;
+        for name in names {
+            let bytes: &[u8] = name.as_bytes();
+            if written + bytes.len() + 1 > DATA_BUF_SIZE {
+                break;
+            }
+            self.data[written..written + bytes.len()].copy_from_slice(bytes);
+            self.data[written + bytes.len()] = 0;
+            written += bytes.len() + 1;
+        }
+
+        self.length = written as u32;
+
+        Ok(())
+    }
+}
+
+//==================================================================================================
+// Standalone Functions
+//==================================================================================================
+
+///
+/// # Description
+///
+/// Parses a `-fs <tag>:<host-path>` argument value into its tag and host path.
+///
+/// # Parameters
+///
+/// - `arg`: Argument value, in `<tag>:<host-path>` form.
+///
+/// # Returns
+///
+/// Upon successful completion, this method returns the tag and host path. Otherwise, it returns an
+/// error.
+///
+pub fn parse_fs_arg(arg: &str) -> Result<(String, String)> {
+    match arg.split_once(':') {
+        Some((tag, path)) if !tag.is_empty() && !path.is_empty() => {
+            Ok((tag.to_string(), path.to_string()))
+        }
+        _ => anyhow::bail!("invalid -fs argument '{}', expected <tag>:<host-path>", arg),
+    }
+}